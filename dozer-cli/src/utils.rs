@@ -70,6 +70,8 @@ pub fn get_checkpoint_options(config: &Config) -> CheckpointOptions {
     CheckpointOptions {
         data_storage: app.data_storage.clone(),
         record_store: app.record_store,
+        spill_path: None,
+        schema_versions: Default::default(),
     }
 }
 
@@ -100,6 +102,7 @@ pub fn get_executor_options(config: &Config) -> ExecutorOptions {
                 .unwrap_or_else(default_enable_app_checkpoints),
         },
         checkpoint_factory_options: get_checkpoint_factory_options(config),
+        ..Default::default()
     }
 }
 
@@ -0,0 +1,420 @@
+use std::collections::{BTreeMap, HashMap};
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer, StoreRecord};
+use dozer_storage::RocksdbMap;
+use dozer_types::bincode;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::ordered_float::OrderedFloat;
+use dozer_types::serde::{Deserialize, Serialize};
+use dozer_types::types::{
+    Field, FieldDefinition, FieldType, Operation, Record, Schema, SourceDefinition,
+};
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::epoch::Epoch;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::{PortHandle, Processor, ProcessorFactory, StateBackend, StateEnvironment};
+use crate::DEFAULT_PORT_HANDLE;
+
+/// Maintains the count, sum, min and max of a field per group key, in a `RocksdbMap`.
+///
+/// Every operation on `DEFAULT_PORT_HANDLE` retracts the contribution of its old record and applies its new one,
+/// then the changed aggregates are sent as inserts, updates or deletes on `DEFAULT_PORT_HANDLE`.
+/// A group's aggregate is deleted once its last record is.
+///
+/// Output records have the fields `group`, `count`, `sum`, `min` and `max`, keyed by `group`.
+/// `sum` is a float. Null values count towards `count` only. Every distinct value of a group is kept to retract `min` and `max`.
+#[derive(Debug)]
+pub struct AggregateProcessorFactory {
+    group_by: usize,
+    value: usize,
+}
+
+impl AggregateProcessorFactory {
+    /// Groups by the field at index `group_by` and aggregates the field at index `value`.
+    pub fn new(group_by: usize, value: usize) -> Self {
+        Self { group_by, value }
+    }
+}
+
+impl ProcessorFactory for AggregateProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        let input = &input_schemas[&DEFAULT_PORT_HANDLE];
+        let field = |index: usize| {
+            input
+                .fields
+                .get(index)
+                .ok_or_else(|| format!("Aggregate field {index} is out of range"))
+        };
+        let group = field(self.group_by)?;
+        let value = field(self.value)?;
+        let definition = |name: &str, typ, nullable| {
+            FieldDefinition::new(name.to_string(), typ, nullable, SourceDefinition::Dynamic)
+        };
+        Ok(Schema::default()
+            .field(definition("group", group.typ, group.nullable), true)
+            .field(definition("count", FieldType::UInt, false), false)
+            .field(definition("sum", FieldType::Float, false), false)
+            .field(definition("min", value.typ, true), false)
+            .field(definition("max", value.typ, true), false)
+            .clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        unreachable!("The executor calls build_with_state")
+    }
+
+    fn type_name(&self) -> String {
+        "Aggregate".to_owned()
+    }
+
+    fn id(&self) -> String {
+        "Aggregate".to_owned()
+    }
+
+    fn build_with_state(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+        state: StateEnvironment,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        let StateEnvironment::RocksDb(path) = state else {
+            return Err("Aggregate processor state must be kept in RocksDB".into());
+        };
+        Ok(Box::new(AggregateProcessor {
+            group_by: self.group_by,
+            value: self.value,
+            groups: RocksdbMap::create(&path, Default::default())?,
+        }))
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::RocksDb
+    }
+}
+
+/// The contributions of a group's records.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+struct GroupState {
+    count: u64,
+    sum: f64,
+    /// How many records have each non null value.
+    values: BTreeMap<Field, u64>,
+}
+
+impl GroupState {
+    fn apply(&mut self, value: &Field, retract: bool) -> Result<(), BoxedError> {
+        if retract {
+            self.count = self
+                .count
+                .checked_sub(1)
+                .ok_or("Retracted a record that wasn't aggregated")?;
+        } else {
+            self.count += 1;
+        }
+        if *value == Field::Null {
+            return Ok(());
+        }
+        let number = value
+            .to_float()
+            .ok_or_else(|| format!("Cannot aggregate {value:?}"))?;
+        if retract {
+            self.sum -= number;
+            let remaining = self
+                .values
+                .get_mut(value)
+                .ok_or("Retracted a value that wasn't aggregated")?;
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.values.remove(value);
+            }
+        } else {
+            self.sum += number;
+            *self.values.entry(value.clone()).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn to_record(&self, group: Field) -> Option<Record> {
+        if self.count == 0 {
+            return None;
+        }
+        let bound =
+            |value: Option<(&Field, _)>| value.map_or(Field::Null, |(value, _)| value.clone());
+        Some(Record::new(vec![
+            group,
+            Field::UInt(self.count),
+            Field::Float(OrderedFloat(self.sum)),
+            bound(self.values.first_key_value()),
+            bound(self.values.last_key_value()),
+        ]))
+    }
+}
+
+#[derive(Debug)]
+struct AggregateProcessor {
+    group_by: usize,
+    value: usize,
+    /// Bincode encoded `GroupState` of every group, keyed by the bincode encoded group key.
+    groups: RocksdbMap<Vec<u8>, Vec<u8>>,
+}
+
+impl AggregateProcessor {
+    fn load(&self, key: &[u8]) -> Result<GroupState, BoxedError> {
+        Ok(match self.groups.get(key)? {
+            Some(state) => bincode::deserialize(&state)?,
+            None => GroupState::default(),
+        })
+    }
+}
+
+impl Processor for AggregateProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BoxedError> {
+        Ok(self.groups.flush()?)
+    }
+
+    fn snapshot_state(&self, dir: &std::path::Path) -> Result<(), BoxedError> {
+        Ok(self.groups.checkpoint(dir)?)
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        let (old, new) = match op.load(record_store)? {
+            Operation::Insert { new } => (None, Some(new)),
+            Operation::Delete { old } => (Some(old), None),
+            Operation::Update { old, new } => (Some(old), Some(new)),
+        };
+
+        // Contributions of the operation to each group, in order.
+        let mut changes: Vec<(Field, Vec<(Field, bool)>)> = vec![];
+        for (record, retract) in [(old, true), (new, false)] {
+            let Some(record) = record else {
+                continue;
+            };
+            let group = record.values[self.group_by].clone();
+            let value = record.values[self.value].clone();
+            match changes.iter_mut().find(|(key, _)| *key == group) {
+                Some((_, contributions)) => contributions.push((value, retract)),
+                None => changes.push((group, vec![(value, retract)])),
+            }
+        }
+
+        for (group, contributions) in changes {
+            let key = bincode::serialize(&group)?;
+            let mut state = self.load(&key)?;
+            let before = state.to_record(group.clone());
+            for (value, retract) in &contributions {
+                state.apply(value, *retract)?;
+            }
+            let after = state.to_record(group);
+            if state.count == 0 {
+                self.groups.remove(&key)?;
+            } else {
+                self.groups.insert(&key, &bincode::serialize(&state)?)?;
+            }
+
+            let op = match (before, after) {
+                (None, Some(new)) => ProcessorOperation::Insert {
+                    new: record_store.create_record(&new)?,
+                },
+                (Some(old), None) => ProcessorOperation::Delete {
+                    old: record_store.create_record(&old)?,
+                },
+                (Some(old), Some(new)) if old != new => ProcessorOperation::Update {
+                    old: record_store.create_record(&old)?,
+                    new: record_store.create_record(&new)?,
+                },
+                _ => continue,
+            };
+            fw.send(op, DEFAULT_PORT_HANDLE);
+        }
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct TestForwarder {
+        sent: Vec<ProcessorOperation>,
+    }
+
+    impl ProcessorChannelForwarder for TestForwarder {
+        fn send(&mut self, op: ProcessorOperation, _port: PortHandle) {
+            self.sent.push(op);
+        }
+    }
+
+    fn record(id: i64, group: &str, value: Field) -> Record {
+        Record::new(vec![
+            Field::Int(id),
+            Field::String(group.to_string()),
+            value,
+        ])
+    }
+
+    /// Aggregates of `records` computed from scratch, keyed by group.
+    fn baseline(records: &HashMap<i64, Record>) -> HashMap<Field, Record> {
+        let mut states = HashMap::<Field, GroupState>::new();
+        for record in records.values() {
+            states
+                .entry(record.values[1].clone())
+                .or_default()
+                .apply(&record.values[2], false)
+                .unwrap();
+        }
+        states
+            .into_iter()
+            .map(|(group, state)| (group.clone(), state.to_record(group).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn aggregate_matches_baseline() {
+        let state_dir = TempDir::new("aggregate_matches_baseline").unwrap();
+        let factory = AggregateProcessorFactory::new(1, 2);
+        let mut processor = factory
+            .build_with_state(
+                HashMap::new(),
+                HashMap::new(),
+                &ProcessorRecordStoreDeserializer::new(Default::default()).unwrap(),
+                None,
+                StateEnvironment::RocksDb(state_dir.path().to_path_buf()),
+            )
+            .unwrap();
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let mut fw = TestForwarder::default();
+
+        let ops = vec![
+            Operation::Insert {
+                new: record(1, "a", Field::Int(5)),
+            },
+            Operation::Insert {
+                new: record(2, "a", Field::Int(3)),
+            },
+            Operation::Insert {
+                new: record(3, "b", Field::Int(10)),
+            },
+            Operation::Insert {
+                new: record(4, "b", Field::Null),
+            },
+            // Lowers the max of "a".
+            Operation::Update {
+                old: record(1, "a", Field::Int(5)),
+                new: record(1, "a", Field::Int(1)),
+            },
+            // Moves a record from "b" to "a".
+            Operation::Update {
+                old: record(3, "b", Field::Int(10)),
+                new: record(3, "a", Field::Int(7)),
+            },
+            Operation::Delete {
+                old: record(2, "a", Field::Int(3)),
+            },
+            Operation::Insert {
+                new: record(5, "c", Field::Int(2)),
+            },
+            // Removes the last record of "c".
+            Operation::Delete {
+                old: record(5, "c", Field::Int(2)),
+            },
+        ];
+
+        let mut records = HashMap::new();
+        let mut emitted = HashMap::new();
+        for op in ops {
+            match &op {
+                Operation::Insert { new } | Operation::Update { new, .. } => {
+                    records.insert(new.values[0].as_int().unwrap(), new.clone());
+                }
+                Operation::Delete { old } => {
+                    records.remove(&old.values[0].as_int().unwrap());
+                }
+            }
+            let op = ProcessorOperation::new(&op, &record_store).unwrap();
+            processor
+                .process(DEFAULT_PORT_HANDLE, &record_store, op, &mut fw)
+                .unwrap();
+
+            for op in fw.sent.drain(..) {
+                match op.load(&record_store).unwrap() {
+                    Operation::Insert { new } => {
+                        assert!(emitted.insert(new.values[0].clone(), new).is_none());
+                    }
+                    Operation::Update { old, new } => {
+                        assert_eq!(emitted.get(&old.values[0]), Some(&old));
+                        emitted.insert(new.values[0].clone(), new);
+                    }
+                    Operation::Delete { old } => {
+                        assert_eq!(emitted.remove(&old.values[0]), Some(old));
+                    }
+                }
+            }
+            assert_eq!(emitted, baseline(&records));
+        }
+
+        assert_eq!(
+            emitted[&Field::String("a".to_string())],
+            Record::new(vec![
+                Field::String("a".to_string()),
+                Field::UInt(2),
+                Field::Float(OrderedFloat(8.0)),
+                Field::Int(1),
+                Field::Int(7),
+            ])
+        );
+        assert_eq!(
+            emitted[&Field::String("b".to_string())],
+            Record::new(vec![
+                Field::String("b".to_string()),
+                Field::UInt(1),
+                Field::Float(OrderedFloat(0.0)),
+                Field::Null,
+                Field::Null,
+            ])
+        );
+    }
+}
@@ -1,13 +1,29 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use daggy::petgraph::visit::{IntoNodeIdentifiers, IntoNodeReferences};
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
+use dozer_storage::lmdb_storage::LmdbEnvironmentManager;
+use dozer_types::errors::internal::BoxedError;
 use dozer_types::node::NodeHandle;
+use dozer_types::types::Schema;
 
 use crate::{
+    channels::ProcessorChannelForwarder,
     checkpoint::OptionCheckpoint,
     dag_schemas::{DagHaveSchemas, DagSchemas, EdgeType},
+    epoch::Epoch,
     errors::ExecutionError,
-    node::{Processor, Sink, Source, SourceState},
+    executor_operation::ProcessorOperation,
+    node::{
+        PortHandle, Processor, ProcessorFactory, Sink, Source, SourceState, StateBackend,
+        StateEnvironment,
+    },
     NodeKind as DagNodeKind,
 };
 
@@ -24,13 +40,163 @@ pub struct NodeType {
 /// Node kind, source, processor or sink. Source has a checkpoint to start from.
 pub enum NodeKind {
     Source {
-        source: Box<dyn Source>,
+        /// Shared so the executor can query its progress while it runs.
+        source: Arc<dyn Source>,
         last_checkpoint: SourceState,
     },
-    Processor(Box<dyn Processor>),
+    Processor {
+        processor: Box<dyn Processor>,
+        rebuild: ProcessorRebuild,
+    },
     Sink(Box<dyn Sink>),
 }
 
+/// What's needed to build a processor again, after it panics under `SupervisionPolicy::RestartFromCheckpoint`.
+#[derive(Debug)]
+pub struct ProcessorRebuild {
+    handle: NodeHandle,
+    factory: Arc<dyn ProcessorFactory>,
+    input_schemas: HashMap<PortHandle, Schema>,
+    output_schemas: HashMap<PortHandle, Schema>,
+    state_dir: PathBuf,
+    /// The epoch of the checkpoint the processor was built from, and its data in it, until taken by the executor.
+    checkpoint_epoch: Option<u64>,
+    checkpoint_data: Option<Vec<u8>>,
+}
+
+impl ProcessorRebuild {
+    pub fn factory(&self) -> &Arc<dyn ProcessorFactory> {
+        &self.factory
+    }
+
+    /// Takes the epoch of the checkpoint the processor was built from, and its data in it.
+    pub fn take_checkpoint(&mut self) -> (Option<u64>, Option<Vec<u8>>) {
+        (self.checkpoint_epoch, self.checkpoint_data.take())
+    }
+
+    /// Snapshots the processor's state storage as committed at epoch `epoch_id`, which writes a checkpoint, see [`restore_state`].
+    ///
+    /// Keeps the snapshots of the last `retention` checkpoints and of the one that may not be durable yet, or all of them if `retention` is 0.
+    pub fn snapshot_state(
+        &self,
+        processor: &dyn Processor,
+        epoch_id: u64,
+        retention: usize,
+    ) -> Result<(), ExecutionError> {
+        if self.factory.state_backend() == StateBackend::Memory {
+            return Ok(());
+        }
+        let snapshots_dir = state_snapshots_dir(&self.state_dir, &self.handle);
+        let snapshot_dir = state_snapshot_dir(&self.state_dir, &self.handle, epoch_id);
+        // Written aside and renamed, so a crash can't leave a partial snapshot.
+        let temp_dir = snapshot_dir.with_extension("tmp");
+        remove_dir_if_exists(&temp_dir)?;
+        remove_dir_if_exists(&snapshot_dir)?;
+        std::fs::create_dir_all(&snapshots_dir)
+            .map_err(|e| ExecutionError::FileSystemError(snapshots_dir.clone(), e))?;
+        processor
+            .snapshot_state(&temp_dir)
+            .map_err(|error| ExecutionError::StateSnapshot {
+                node: self.handle.clone(),
+                error,
+            })?;
+        if !temp_dir.exists() {
+            // The processor keeps nothing in its storage.
+            return Ok(());
+        }
+        std::fs::rename(&temp_dir, &snapshot_dir)
+            .map_err(|e| ExecutionError::FileSystemError(snapshot_dir, e))?;
+
+        if retention > 0 {
+            let epochs = snapshot_epochs(&snapshots_dir)?;
+            for epoch in epochs.iter().rev().skip(retention + 1) {
+                remove_dir_if_exists(&state_snapshot_dir(&self.state_dir, &self.handle, *epoch))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the processor from its data in the checkpoint of epoch `checkpoint_epoch`, as if it was loaded with `record_store`,
+    /// on its state storage restored to that checkpoint, see [`restore_state`].
+    ///
+    /// The processor built before must have been dropped, so it doesn't hold the storage open.
+    pub fn build_from_checkpoint(
+        &self,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_epoch: Option<u64>,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, ExecutionError> {
+        restore_state(
+            self.factory.state_backend(),
+            &self.state_dir,
+            &self.handle,
+            checkpoint_epoch,
+        )?;
+        self.build_with(record_store, checkpoint_data)?
+            .map_err(|error| ExecutionError::NodeBuild {
+                node: self.handle.clone(),
+                error,
+            })
+    }
+
+    /// Builds the processor on the storage its state backend persisted, passing `checkpoint_data` as if it was loaded from a checkpoint with `record_store`.
+    pub fn build_from_checkpoint_data(
+        &self,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Vec<u8>,
+    ) -> Result<Box<dyn Processor>, ExecutionError> {
+        self.build_with(record_store, Some(checkpoint_data))?
+            .map_err(|error| ExecutionError::NodeBuild {
+                node: self.handle.clone(),
+                error,
+            })
+    }
+
+    /// Fails if the state can't be provisioned, or returns what the factory returned.
+    fn build_with(
+        &self,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Result<Box<dyn Processor>, BoxedError>, ExecutionError> {
+        let state = provision_state(self.factory.state_backend(), &self.state_dir, &self.handle)?;
+        Ok(self.factory.build_with_state(
+            self.input_schemas.clone(),
+            self.output_schemas.clone(),
+            record_store,
+            checkpoint_data,
+            state,
+        ))
+    }
+}
+
+/// Stands in for a processor while it's rebuilt, so the old one can release its state storage first.
+#[derive(Debug)]
+pub(crate) struct ReplacedProcessor;
+
+impl Processor for ReplacedProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        unreachable!("replaced before it's used")
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        _record_store: &ProcessorRecordStore,
+        _op: ProcessorOperation,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        unreachable!("replaced before it's used")
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        unreachable!("replaced before it's used")
+    }
+}
+
 /// Builder DAG builds all the sources, processors and sinks.
 /// It also asks each source if its possible to start from the given checkpoint.
 /// If not possible, it resets metadata and updates the checkpoint.
@@ -43,6 +209,7 @@ impl BuilderDag {
     pub async fn new(
         checkpoint: &OptionCheckpoint,
         dag_schemas: DagSchemas,
+        state_dir: &Path,
     ) -> Result<Self, ExecutionError> {
         // Collect input output schemas.
         let mut input_schemas = HashMap::new();
@@ -85,34 +252,59 @@ impl BuilderDag {
                                 .remove(&node_index)
                                 .expect("we collected all output schemas"),
                         )
-                        .map_err(ExecutionError::Factory)?;
+                        .map_err(|error| ExecutionError::NodeBuild {
+                            node: node.handle.clone(),
+                            error,
+                        })?;
 
                     Ok::<_, ExecutionError>(NodeType {
                         handle: node.handle,
                         kind: NodeKind::Source {
-                            source,
+                            source: source.into(),
                             last_checkpoint,
                         },
                     })
                 }
-                DagNodeKind::Processor(processor) => {
-                    let processor = processor
-                        .build(
-                            input_schemas
-                                .remove(&node_index)
-                                .expect("we collected all input schemas"),
-                            output_schemas
-                                .remove(&node_index)
-                                .expect("we collected all output schemas"),
+                DagNodeKind::Processor(factory) => {
+                    let factory: Arc<dyn ProcessorFactory> = factory.into();
+                    restore_state(
+                        factory.state_backend(),
+                        state_dir,
+                        &node.handle,
+                        checkpoint.epoch_id(),
+                    )?;
+                    let state = provision_state(factory.state_backend(), state_dir, &node.handle)?;
+                    let processor_data = checkpoint_data
+                        .remove(&node_index)
+                        .expect("we collected all processor checkpoint data");
+                    let rebuild = ProcessorRebuild {
+                        handle: node.handle.clone(),
+                        factory: factory.clone(),
+                        input_schemas: input_schemas
+                            .remove(&node_index)
+                            .expect("we collected all input schemas"),
+                        output_schemas: output_schemas
+                            .remove(&node_index)
+                            .expect("we collected all output schemas"),
+                        state_dir: state_dir.to_path_buf(),
+                        checkpoint_epoch: checkpoint.epoch_id(),
+                        checkpoint_data: processor_data.clone(),
+                    };
+                    let processor = factory
+                        .build_with_state(
+                            rebuild.input_schemas.clone(),
+                            rebuild.output_schemas.clone(),
                             checkpoint.record_store(),
-                            checkpoint_data
-                                .remove(&node_index)
-                                .expect("we collected all processor checkpoint data"),
+                            processor_data,
+                            state,
                         )
-                        .map_err(ExecutionError::Factory)?;
+                        .map_err(|error| ExecutionError::NodeBuild {
+                            node: node.handle.clone(),
+                            error,
+                        })?;
                     Ok(NodeType {
                         handle: node.handle,
-                        kind: NodeKind::Processor(processor),
+                        kind: NodeKind::Processor { processor, rebuild },
                     })
                 }
                 DagNodeKind::Sink(sink) => {
@@ -122,7 +314,10 @@ impl BuilderDag {
                                 .remove(&node_index)
                                 .expect("we collected all input schemas"),
                         )
-                        .map_err(ExecutionError::Factory)?;
+                        .map_err(|error| ExecutionError::NodeBuild {
+                            node: node.handle.clone(),
+                            error,
+                        })?;
                     Ok(NodeType {
                         handle: node.handle,
                         kind: NodeKind::Sink(sink),
@@ -142,4 +337,181 @@ impl BuilderDag {
     pub fn into_graph(self) -> daggy::Dag<NodeType, EdgeType> {
         self.graph
     }
+
+    /// Builds processor `handle` again, passing it `checkpoint_data` instead of what it was built from.
+    ///
+    /// The processor is rebuilt on the storage its state backend persisted. Fails if `handle` isn't a processor.
+    pub fn restore_processor(
+        &mut self,
+        handle: &NodeHandle,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Vec<u8>,
+    ) -> Result<(), ExecutionError> {
+        let node = self
+            .graph
+            .node_weights_mut()
+            .find(|node| &node.handle == handle);
+        let Some(NodeType {
+            kind: NodeKind::Processor { processor, rebuild },
+            ..
+        }) = node
+        else {
+            return Err(ExecutionError::NodeNotFound(handle.clone()));
+        };
+        // The built processor may hold its state storage open.
+        drop(std::mem::replace(processor, Box::new(ReplacedProcessor)));
+        *processor = rebuild.build_from_checkpoint_data(record_store, checkpoint_data)?;
+        Ok(())
+    }
+}
+
+/// Provisions the storage for a processor's state backend, under `state_dir/<node handle>`.
+fn provision_state(
+    backend: StateBackend,
+    state_dir: &Path,
+    node_handle: &NodeHandle,
+) -> Result<StateEnvironment, ExecutionError> {
+    Ok(match backend {
+        StateBackend::RocksDb => {
+            StateEnvironment::RocksDb(create_node_state_dir(state_dir, node_handle)?)
+        }
+        StateBackend::Lmdb => {
+            let node_dir = create_node_state_dir(state_dir, node_handle)?;
+            StateEnvironment::Lmdb(LmdbEnvironmentManager::create_rw(
+                &node_dir,
+                "state",
+                Default::default(),
+            )?)
+        }
+        StateBackend::Memory => StateEnvironment::Memory,
+    })
+}
+
+/// Checks that the state storage of processor `node_handle` can be restored to the checkpoint of epoch `checkpoint_epoch`,
+/// which it can if it's empty or the processor snapshotted it at that epoch.
+pub(crate) fn check_state_restorable(
+    backend: StateBackend,
+    state_dir: &Path,
+    node_handle: &NodeHandle,
+    checkpoint_epoch: u64,
+) -> Result<(), ExecutionError> {
+    if backend == StateBackend::Memory
+        || state_snapshot_dir(state_dir, node_handle, checkpoint_epoch).exists()
+        || is_empty_dir(&state_dir.join(node_handle.to_string()))?
+    {
+        return Ok(());
+    }
+    Err(ExecutionError::ProcessorStateNotRestorable {
+        node: node_handle.clone(),
+        checkpoint_epoch,
+    })
+}
+
+/// Restores the state storage of processor `node_handle` to the checkpoint it resumes from, of epoch `checkpoint_epoch`,
+/// from the snapshot taken when the processor committed that epoch. Snapshots of later epochs are deleted with the epochs.
+///
+/// Storage left with no checkpoint to resume from is cleared with its snapshots, so the processor starts empty like the rest of the pipeline.
+/// Storage that's empty is provisioned empty, like for a processor added to the pipeline.
+pub(crate) fn restore_state(
+    backend: StateBackend,
+    state_dir: &Path,
+    node_handle: &NodeHandle,
+    checkpoint_epoch: Option<u64>,
+) -> Result<(), ExecutionError> {
+    if backend == StateBackend::Memory {
+        return Ok(());
+    }
+    let node_dir = state_dir.join(node_handle.to_string());
+    let snapshots_dir = state_snapshots_dir(state_dir, node_handle);
+    let Some(checkpoint_epoch) = checkpoint_epoch else {
+        remove_dir_if_exists(&node_dir)?;
+        return remove_dir_if_exists(&snapshots_dir);
+    };
+    check_state_restorable(backend, state_dir, node_handle, checkpoint_epoch)?;
+
+    let snapshot_dir = state_snapshot_dir(state_dir, node_handle, checkpoint_epoch);
+    if snapshot_dir.exists() {
+        remove_dir_if_exists(&node_dir)?;
+        copy_dir(&snapshot_dir, &node_dir)?;
+    }
+    if snapshots_dir.exists() {
+        for epoch in snapshot_epochs(&snapshots_dir)? {
+            if epoch > checkpoint_epoch {
+                remove_dir_if_exists(&state_snapshot_dir(state_dir, node_handle, epoch))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The directory holding the snapshots of the state storage of processor `node_handle`, one for every checkpoint epoch.
+fn state_snapshots_dir(state_dir: &Path, node_handle: &NodeHandle) -> PathBuf {
+    state_dir.join(format!("{node_handle}.snapshots"))
+}
+
+fn state_snapshot_dir(state_dir: &Path, node_handle: &NodeHandle, epoch_id: u64) -> PathBuf {
+    state_snapshots_dir(state_dir, node_handle).join(format!("{epoch_id:020}"))
+}
+
+/// The epochs of the snapshots in `snapshots_dir`, in increasing order. Partially written snapshots are skipped.
+fn snapshot_epochs(snapshots_dir: &Path) -> Result<Vec<u64>, ExecutionError> {
+    let entries = std::fs::read_dir(snapshots_dir)
+        .map_err(|e| ExecutionError::FileSystemError(snapshots_dir.to_path_buf(), e))?;
+    let mut epochs = vec![];
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| ExecutionError::FileSystemError(snapshots_dir.to_path_buf(), e))?;
+        if let Some(epoch) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            epochs.push(epoch);
+        }
+    }
+    epochs.sort_unstable();
+    Ok(epochs)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), ExecutionError> {
+    std::fs::create_dir_all(to)
+        .map_err(|e| ExecutionError::FileSystemError(to.to_path_buf(), e))?;
+    let entries = std::fs::read_dir(from)
+        .map_err(|e| ExecutionError::FileSystemError(from.to_path_buf(), e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| ExecutionError::FileSystemError(from.to_path_buf(), e))?;
+        let (from, to) = (entry.path(), to.join(entry.file_name()));
+        if from.is_dir() {
+            copy_dir(&from, &to)?;
+        } else {
+            std::fs::copy(&from, &to).map_err(|e| ExecutionError::FileSystemError(to, e))?;
+        }
+    }
+    Ok(())
+}
+
+fn is_empty_dir(dir: &Path) -> Result<bool, ExecutionError> {
+    match std::fs::read_dir(dir) {
+        Ok(mut entries) => Ok(entries.next().is_none()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(ExecutionError::FileSystemError(dir.to_path_buf(), e)),
+    }
+}
+
+fn remove_dir_if_exists(dir: &Path) -> Result<(), ExecutionError> {
+    match std::fs::remove_dir_all(dir) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ExecutionError::FileSystemError(dir.to_path_buf(), e)),
+    }
+}
+
+fn create_node_state_dir(
+    state_dir: &Path,
+    node_handle: &NodeHandle,
+) -> Result<PathBuf, ExecutionError> {
+    let node_dir = state_dir.join(node_handle.to_string());
+    std::fs::create_dir_all(&node_dir)
+        .map_err(|e| ExecutionError::FileSystemError(node_dir.clone(), e))?;
+    Ok(node_dir)
 }
@@ -1,12 +1,39 @@
 use crate::errors::ExecutionError;
-use crate::executor_operation::ProcessorOperation;
+use crate::executor_operation::{OperationContext, OperationPriority, ProcessorOperation};
 use crate::node::PortHandle;
 use core::marker::{Send, Sync};
 use core::result::Result;
 use dozer_types::models::ingestion_types::IngestionMessage;
+use std::time::SystemTime;
 
 pub trait SourceChannelForwarder: Send + Sync {
     fn send(&mut self, message: IngestionMessage, port: PortHandle) -> Result<(), ExecutionError>;
+
+    /// Like `send`, also passing when the event happened at the source and the headers to attach to the operation.
+    ///
+    /// The context reaches processors and sinks unchanged, see [`OperationContext`], except for `timestamps.processing_time`,
+    /// which is stamped when the operation is ingested.
+    fn send_with_context(
+        &mut self,
+        message: IngestionMessage,
+        port: PortHandle,
+        _context: OperationContext,
+    ) -> Result<(), ExecutionError> {
+        self.send(message, port)
+    }
+
+    /// Promises that operations sent on `port` from now on have event times at or after `watermark`.
+    ///
+    /// Downstream processors and sinks are told once the watermarks of all their inputs pass a time,
+    /// so windowing nodes know when to close windows. Watermarks not later than the last one sent on the port are dropped.
+    /// Forwarders that don't carry watermarks drop them all.
+    fn send_watermark(
+        &mut self,
+        _watermark: SystemTime,
+        _port: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        Ok(())
+    }
 }
 
 pub trait ProcessorChannelForwarder {
@@ -15,4 +42,36 @@ pub trait ProcessorChannelForwarder {
     /// We must panic instead of returning an error because this method will be called by `Processor::process`,
     /// which only returns recoverable errors.
     fn send(&mut self, op: ProcessorOperation, port: PortHandle);
+
+    /// Sends several operations in order, like calling `send` for each, but with less overhead per operation.
+    fn send_all(&mut self, ops: Vec<ProcessorOperation>, port: PortHandle) {
+        for op in ops {
+            self.send(op, port);
+        }
+    }
+
+    /// Like `send`, letting `High` priority operations jump ahead of the ones already queued downstream.
+    fn send_with_priority(
+        &mut self,
+        op: ProcessorOperation,
+        port: PortHandle,
+        _priority: OperationPriority,
+    ) {
+        self.send(op, port)
+    }
+
+    /// Like `send`, with `context` instead of the inherited one, for operations that were held back.
+    fn send_with_context(
+        &mut self,
+        op: ProcessorOperation,
+        port: PortHandle,
+        _context: OperationContext,
+    ) {
+        self.send(op, port)
+    }
+
+    /// The context operations sent now inherit, which is that of the operation being processed.
+    fn context(&self) -> OperationContext {
+        Default::default()
+    }
 }
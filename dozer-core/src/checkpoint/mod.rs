@@ -1,12 +1,27 @@
-use std::{collections::HashMap, num::NonZeroUsize, ops::Deref, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    num::NonZeroUsize,
+    ops::Deref,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use dozer_log::{
     camino::{Utf8Path, Utf8PathBuf},
     replication::create_data_storage,
     storage::{self, Object, Queue, Storage},
-    tokio::task::JoinHandle,
+    tokio::{
+        self,
+        sync::{mpsc, oneshot},
+        task::JoinHandle,
+    },
+};
+use dozer_recordstore::{
+    ProcessorRecordStore, ProcessorRecordStoreDeserializer, SchemaVersions, StoreRecord,
 };
-use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer, StoreRecord};
 use dozer_types::{
     bincode,
     log::{error, info},
@@ -19,6 +34,7 @@ use dozer_types::{
 use tempdir::TempDir;
 
 use crate::errors::ExecutionError;
+use crate::Dag;
 
 #[derive(Debug)]
 pub struct CheckpointFactory {
@@ -26,6 +42,48 @@ pub struct CheckpointFactory {
     prefix: String,
     record_store: Arc<ProcessorRecordStore>,
     state: Mutex<CheckpointWriterFactoryState>,
+    /// Set when the executor is aborted. Checkpoint writers dropped afterwards don't write anything.
+    aborted: Arc<AtomicBool>,
+    /// Receives every uploaded record store slice, if there's an `on_checkpoint` callback.
+    checkpoint_notifier: Option<mpsc::UnboundedSender<(oneshot::Receiver<String>, SourceStates)>>,
+    /// Number of checkpoints whose processor states are kept, 0 to keep all.
+    retention: usize,
+}
+
+/// Called with the source consistency of every checkpoint once it's durable.
+pub type CheckpointCallback = Arc<dyn Fn(HashMap<NodeHandle, Consistency>) + Send + Sync>;
+
+/// The state a checkpoint has of a source's tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Consistency {
+    /// All tables are in the same state.
+    FullyConsistent(TableState),
+    /// Tables are in different states. Maps every state to its tables, ordered by name.
+    PartiallyConsistent(HashMap<TableState, Vec<String>>),
+}
+
+/// Returns the consistency of every source in `source_states`.
+pub fn source_consistency(source_states: &SourceStates) -> HashMap<NodeHandle, Consistency> {
+    source_states
+        .iter()
+        .map(|(node_handle, tables)| {
+            let mut states = HashMap::<TableState, Vec<String>>::new();
+            for (table_name, state) in tables {
+                states.entry(*state).or_default().push(table_name.clone());
+            }
+            let consistency = if states.len() <= 1 {
+                Consistency::FullyConsistent(
+                    states.into_keys().next().unwrap_or(TableState::NotStarted),
+                )
+            } else {
+                for table_names in states.values_mut() {
+                    table_names.sort();
+                }
+                Consistency::PartiallyConsistent(states)
+            };
+            (node_handle.clone(), consistency)
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone)]
@@ -48,6 +106,8 @@ struct Checkpoint {
     processor_prefix: String,
     epoch_id: u64,
     source_states: SourceStates,
+    /// Processor prefixes of every record store slice, oldest first.
+    processor_prefixes: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -58,10 +118,63 @@ pub struct OptionCheckpoint {
     checkpoint: Option<Checkpoint>,
 }
 
+/// A source table whose state differs between two checkpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointDiff {
+    pub node_handle: NodeHandle,
+    pub table_name: String,
+    /// State in `self`, `None` if the table isn't in `self`'s checkpoint.
+    pub before: Option<TableState>,
+    /// State in `other`, `None` if the table isn't in `other`'s checkpoint.
+    pub after: Option<TableState>,
+}
+
+/// What [`OptionCheckpoint::verify_integrity`] found in the checkpoint storage.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IntegrityReport {
+    /// Number of record store slices in storage.
+    pub num_slices: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    pub fn is_consistent(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// The loaded checkpoint has a different state for a source table than the last record store slice in storage.
+    OffsetMismatch {
+        node_handle: NodeHandle,
+        table_name: String,
+        recorded: TableState,
+        /// `None` if the table isn't in the last slice, or there's no slice.
+        stored: Option<TableState>,
+    },
+    /// A record store slice has an earlier offset for a source table than the slice before it.
+    OffsetRegressed {
+        node_handle: NodeHandle,
+        table_name: String,
+        epoch_id: u64,
+    },
+    /// A record store slice can't be decoded.
+    CorruptedSlice { key: String },
+    /// A processor or record writer state whose epoch has no record store slice, so it's never restored.
+    OrphanedState { key: String },
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct CheckpointOptions {
     pub data_storage: DataStorage,
     pub record_store: RecordStore,
+    /// Where a RocksDB record store keeps its files, instead of the system temp dir.
+    ///
+    /// Checkpoint data is still written to the checkpoint dir. Usually the same as `ExecutorOptions::spill_path`.
+    pub spill_path: Option<PathBuf>,
+    /// The record schema versions. Records restored from checkpoints written with older versions are migrated to the current one.
+    pub schema_versions: Arc<SchemaVersions>,
 }
 
 impl OptionCheckpoint {
@@ -70,9 +183,27 @@ impl OptionCheckpoint {
         options: CheckpointOptions,
     ) -> Result<Self, ExecutionError> {
         let (storage, prefix) =
-            create_data_storage(options.data_storage, checkpoint_dir.to_string()).await?;
-        let (record_store, checkpoint) =
-            read_record_store_slices(&*storage, &prefix, options.record_store).await?;
+            create_data_storage(options.data_storage.clone(), checkpoint_dir.to_string()).await?;
+        Self::with_storage(storage, prefix, options).await
+    }
+
+    /// Opens the checkpoint under `prefix` in `storage`, which can be any object storage, like a remote key-value store.
+    ///
+    /// `options.data_storage` is ignored. Checkpoints written through the returned checkpoint also go to `storage`,
+    /// so passing a storage with the same objects again restores the latest of them.
+    pub async fn with_storage(
+        storage: Box<dyn Storage>,
+        prefix: String,
+        options: CheckpointOptions,
+    ) -> Result<Self, ExecutionError> {
+        let (record_store, checkpoint) = read_record_store_slices(
+            &*storage,
+            &prefix,
+            options.record_store,
+            options.spill_path,
+            options.schema_versions,
+        )
+        .await?;
         if let Some(checkpoint) = &checkpoint {
             info!(
                 "Restored record store from {}th checkpoint, last epoch id is {}, processor states are stored in {}",
@@ -88,6 +219,103 @@ impl OptionCheckpoint {
         })
     }
 
+    /// Opens checkpoint `epoch_id` in `checkpoint_dir`, deleting every newer checkpoint from storage.
+    ///
+    /// Fails if there's no checkpoint `epoch_id`, in which case nothing is deleted.
+    pub async fn rollback(
+        checkpoint_dir: String,
+        options: CheckpointOptions,
+        epoch_id: u64,
+    ) -> Result<Self, ExecutionError> {
+        let (storage, prefix) =
+            create_data_storage(options.data_storage.clone(), checkpoint_dir.clone()).await?;
+        let record_store_prefix = record_store_prefix(&prefix);
+        let formatted_epoch_id = format!("{:020}", epoch_id);
+        let mut found = false;
+        let mut newer_keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let objects = storage
+                .list_objects(prefix.clone(), continuation_token)
+                .await?;
+            for object in objects.objects {
+                // Both record store slices and processor states are named after their epoch id, formatted to the same width.
+                let path = AsRef::<Utf8Path>::as_ref(&object.key);
+                if let Ok(slice_epoch_id) = path.strip_prefix(&record_store_prefix) {
+                    found |= slice_epoch_id.as_str() == formatted_epoch_id;
+                    if slice_epoch_id.as_str() > formatted_epoch_id.as_str() {
+                        newer_keys.push(object.key);
+                    }
+                } else if let Some(state_epoch_id) = path
+                    .strip_prefix(&prefix)
+                    .ok()
+                    .and_then(|path| path.components().next())
+                {
+                    if state_epoch_id.as_str() > formatted_epoch_id.as_str() {
+                        newer_keys.push(object.key);
+                    }
+                }
+            }
+
+            continuation_token = objects.continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        if !found {
+            return Err(ExecutionError::CheckpointNotFound(epoch_id));
+        }
+        if !newer_keys.is_empty() {
+            info!(
+                "Rolling back to checkpoint {epoch_id}, deleting {} newer objects",
+                newer_keys.len()
+            );
+            storage.delete_objects(newer_keys).await?;
+        }
+        Self::new(checkpoint_dir, options).await
+    }
+
+    /// Returns the checkpointed table states of every source in `dag`, which is where the sources resume from.
+    ///
+    /// Only reads the latest record store slice, not the record store or processor states, so it's cheap to call
+    /// before starting an executor, for example to coordinate with external systems.
+    /// Sources that aren't in the checkpoint, or when there's no checkpoint, aren't in the result.
+    pub async fn source_offsets(
+        dag: &Dag,
+        checkpoint_dir: String,
+        data_storage: DataStorage,
+    ) -> Result<SourceStates, ExecutionError> {
+        let (storage, prefix) = create_data_storage(data_storage, checkpoint_dir).await?;
+        let record_store_prefix = record_store_prefix(&prefix);
+        let mut last_key = None;
+        let mut continuation_token = None;
+        loop {
+            let objects = storage
+                .list_objects(record_store_prefix.to_string(), continuation_token)
+                .await?;
+            if let Some(object) = objects.objects.into_iter().last() {
+                last_key = Some(object.key);
+            }
+            continuation_token = objects.continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        let Some(last_key) = last_key else {
+            return Ok(SourceStates::new());
+        };
+        let data = storage.download_object(last_key).await?;
+        let mut source_states = bincode::deserialize::<RecordStoreSlice>(&data)
+            .map_err(ExecutionError::CorruptedCheckpoint)?
+            .source_states;
+        Ok(dag
+            .sources()
+            .filter_map(|(handle, _)| source_states.remove_entry(handle))
+            .collect())
+    }
+
     pub fn storage(&self) -> &dyn Storage {
         &*self.storage
     }
@@ -106,6 +334,13 @@ impl OptionCheckpoint {
             .map_or(0, |checkpoint| checkpoint.num_slices.get())
     }
 
+    /// The epoch id of the loaded checkpoint, which can be passed to [`OptionCheckpoint::rollback`].
+    pub fn epoch_id(&self) -> Option<u64> {
+        self.checkpoint
+            .as_ref()
+            .map(|checkpoint| checkpoint.epoch_id)
+    }
+
     pub fn next_epoch_id(&self) -> u64 {
         self.checkpoint
             .as_ref()
@@ -142,6 +377,226 @@ impl OptionCheckpoint {
         Ok(Some(result))
     }
 
+    /// Returns the source tables whose state changed from `self` to `other`, ordered by node handle and table name.
+    ///
+    /// A missing checkpoint is treated as having no source states.
+    pub fn diff(&self, other: &OptionCheckpoint) -> Vec<CheckpointDiff> {
+        let empty = SourceStates::new();
+        let before = self
+            .checkpoint
+            .as_ref()
+            .map_or(&empty, |checkpoint| &checkpoint.source_states);
+        let after = other
+            .checkpoint
+            .as_ref()
+            .map_or(&empty, |checkpoint| &checkpoint.source_states);
+
+        let get = |source_states: &SourceStates, node_handle: &NodeHandle, table_name: &str| {
+            source_states
+                .get(node_handle)
+                .and_then(|tables| tables.get(table_name))
+                .copied()
+        };
+
+        let mut seen = HashSet::new();
+        let mut result = vec![];
+        for (node_handle, tables) in before.iter().chain(after.iter()) {
+            for table_name in tables.keys() {
+                if !seen.insert((node_handle, table_name)) {
+                    continue;
+                }
+                let diff = CheckpointDiff {
+                    node_handle: node_handle.clone(),
+                    table_name: table_name.clone(),
+                    before: get(before, node_handle, table_name),
+                    after: get(after, node_handle, table_name),
+                };
+                if diff.before != diff.after {
+                    result.push(diff);
+                }
+            }
+        }
+        result.sort_by(|a, b| {
+            (a.node_handle.to_string(), &a.table_name)
+                .cmp(&(b.node_handle.to_string(), &b.table_name))
+        });
+        result
+    }
+
+    /// Re-reads the checkpoint storage and cross-checks it with the checkpoint loaded in `self`.
+    ///
+    /// Inconsistencies are listed in the report. Only failing to read the storage is an error.
+    pub async fn verify_integrity(&self) -> Result<IntegrityReport, ExecutionError> {
+        let record_store_prefix = record_store_prefix(&self.prefix);
+        let mut report = IntegrityReport::default();
+        let mut epochs = HashSet::new();
+        let mut state_keys = vec![];
+        let mut last_source_states: Option<SourceStates> = None;
+        let mut continuation_token = None;
+        loop {
+            let objects = self
+                .storage
+                .list_objects(self.prefix.clone(), continuation_token)
+                .await?;
+            for object in objects.objects {
+                let epoch_id = AsRef::<Utf8Path>::as_ref(&object.key)
+                    .strip_prefix(&record_store_prefix)
+                    .ok()
+                    .map(|epoch_id| epoch_id.to_string());
+                let Some(epoch_id) = epoch_id else {
+                    state_keys.push(object.key);
+                    continue;
+                };
+                report.num_slices += 1;
+
+                let data = self.storage.download_object(object.key.clone()).await?;
+                let (Ok(slice), Ok(epoch_number)) = (
+                    bincode::deserialize::<RecordStoreSlice>(&data),
+                    epoch_id.parse::<u64>(),
+                ) else {
+                    report
+                        .issues
+                        .push(IntegrityIssue::CorruptedSlice { key: object.key });
+                    continue;
+                };
+                if let Some(previous) = &last_source_states {
+                    report
+                        .issues
+                        .extend(regressed_tables(previous, &slice.source_states).map(
+                            |(node_handle, table_name)| IntegrityIssue::OffsetRegressed {
+                                node_handle: node_handle.clone(),
+                                table_name: table_name.clone(),
+                                epoch_id: epoch_number,
+                            },
+                        ));
+                }
+                epochs.insert(epoch_id);
+                last_source_states = Some(slice.source_states);
+            }
+
+            continuation_token = objects.continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            for (node_handle, tables) in &checkpoint.source_states {
+                for (table_name, recorded) in tables {
+                    let stored = last_source_states
+                        .as_ref()
+                        .and_then(|source_states| source_states.get(node_handle))
+                        .and_then(|tables| tables.get(table_name))
+                        .copied();
+                    if stored != Some(*recorded) {
+                        report.issues.push(IntegrityIssue::OffsetMismatch {
+                            node_handle: node_handle.clone(),
+                            table_name: table_name.clone(),
+                            recorded: *recorded,
+                            stored,
+                        });
+                    }
+                }
+            }
+        }
+
+        for key in state_keys {
+            let epoch_id = AsRef::<Utf8Path>::as_ref(&key)
+                .strip_prefix(&self.prefix)
+                .ok()
+                .and_then(|path| path.components().next())
+                .map(|epoch_id| epoch_id.as_str().to_string());
+            if !epoch_id.map_or(false, |epoch_id| epochs.contains(&epoch_id)) {
+                report.issues.push(IntegrityIssue::OrphanedState { key });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Moves the state of node `old` in the latest checkpoint to `new`, so a DAG with the node renamed resumes it.
+    ///
+    /// The latest record store slice is rewritten with the renamed source state, and the node's processor and record writer
+    /// states are moved. Older checkpoints keep the old name, so a DAG rolled back to one of them doesn't resume the renamed node.
+    /// Does nothing if there's no checkpoint. Fails if `old` has no state in the checkpoint, or `new` already has.
+    pub async fn rename_node(
+        &mut self,
+        old: &NodeHandle,
+        new: NodeHandle,
+    ) -> Result<(), ExecutionError> {
+        let Some(checkpoint) = self.checkpoint.as_mut() else {
+            return Ok(());
+        };
+
+        // Record writer keys are the processor key followed by `-` and the port name.
+        let old_key = processor_key(&checkpoint.processor_prefix, old);
+        let new_key = processor_key(&checkpoint.processor_prefix, &new);
+        let is_node_key = |key: &str, node_key: &str| {
+            key.strip_prefix(node_key)
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with('-'))
+        };
+        let mut old_keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let objects = self
+                .storage
+                .list_objects(
+                    format!("{}/", checkpoint.processor_prefix),
+                    continuation_token,
+                )
+                .await?;
+            for object in objects.objects {
+                if is_node_key(&object.key, &new_key) {
+                    return Err(ExecutionError::DuplicateNodeHandle(new));
+                }
+                if is_node_key(&object.key, &old_key) {
+                    old_keys.push(object.key);
+                }
+            }
+            continuation_token = objects.continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        if checkpoint.source_states.contains_key(&new) {
+            return Err(ExecutionError::DuplicateNodeHandle(new));
+        }
+        if old_keys.is_empty() && !checkpoint.source_states.contains_key(old) {
+            return Err(ExecutionError::NodeNotFound(old.clone()));
+        }
+
+        for key in &old_keys {
+            let data = self.storage.download_object(key.clone()).await?;
+            let renamed = format!("{new_key}{}", &key[old_key.len()..]);
+            self.storage.put_object(renamed, data).await?;
+        }
+
+        // The rewritten slice is what a restart reads, so it's written after the states it refers to.
+        if let Some(source_state) = checkpoint.source_states.remove(old) {
+            let key = record_store_prefix(&self.prefix)
+                .join(format!("{:020}", checkpoint.epoch_id))
+                .into_string();
+            let data = self.storage.download_object(key.clone()).await?;
+            let mut slice = bincode::deserialize::<RecordStoreSlice>(&data)
+                .map_err(ExecutionError::CorruptedCheckpoint)?;
+            if let Some(slice_state) = slice.source_states.remove(old) {
+                slice.source_states.insert(new.clone(), slice_state);
+            }
+            let data =
+                bincode::serialize(&slice).expect("Record store slice should be serializable");
+            self.storage.put_object(key, data).await?;
+            checkpoint.source_states.insert(new.clone(), source_state);
+        }
+
+        if !old_keys.is_empty() {
+            self.storage.delete_objects(old_keys).await?;
+        }
+        info!(
+            "Renamed node {old} to {new} in checkpoint {}",
+            checkpoint.epoch_id
+        );
+        Ok(())
+    }
+
     pub async fn load_processor_data(
         &self,
         node_handle: &NodeHandle,
@@ -181,6 +636,11 @@ impl CheckpointFactory {
         let record_store = checkpoint.record_store.into_record_store();
         let state = Mutex::new(CheckpointWriterFactoryState {
             next_record_index: record_store.num_records(),
+            last_written_epoch: None,
+            retained_processor_prefixes: checkpoint
+                .checkpoint
+                .map(|checkpoint| checkpoint.processor_prefixes.into())
+                .unwrap_or_default(),
         });
 
         Ok((
@@ -189,6 +649,9 @@ impl CheckpointFactory {
                 prefix: checkpoint.prefix,
                 record_store: Arc::new(record_store),
                 state,
+                aborted: Arc::new(AtomicBool::new(false)),
+                checkpoint_notifier: None,
+                retention: 0,
             },
             worker,
         ))
@@ -198,13 +661,52 @@ impl CheckpointFactory {
         &self.queue
     }
 
+    /// The prefix of all checkpoint objects in storage.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
     pub fn record_store(&self) -> &Arc<ProcessorRecordStore> {
         &self.record_store
     }
 
+    /// The newest epoch whose checkpoint is complete and queued for upload.
+    pub fn last_written_epoch(&self) -> Option<u64> {
+        self.state.lock().last_written_epoch
+    }
+
+    pub fn aborted(&self) -> &Arc<AtomicBool> {
+        &self.aborted
+    }
+
+    /// Calls `on_checkpoint` after every checkpoint is uploaded, in order. Must be called in a tokio runtime.
+    ///
+    /// The record store slice is the last object of a checkpoint to be written, so the checkpoint is durable once it's uploaded.
+    pub fn set_on_checkpoint(&mut self, on_checkpoint: CheckpointCallback) {
+        let (sender, mut receiver) =
+            mpsc::unbounded_channel::<(oneshot::Receiver<String>, SourceStates)>();
+        tokio::spawn(async move {
+            while let Some((uploaded, source_states)) = receiver.recv().await {
+                if uploaded.await.is_ok() {
+                    on_checkpoint(source_consistency(&source_states));
+                }
+            }
+        });
+        self.checkpoint_notifier = Some(sender);
+    }
+
+    /// Keeps the processor states of the last `retention` checkpoints, deleting older ones after every checkpoint. 0 keeps all.
+    ///
+    /// Record store slices are always kept, because every checkpoint needs all slices before it.
+    pub fn set_retention(&mut self, retention: usize) {
+        self.retention = retention;
+    }
+
     fn write_record_store_slice(
         &self,
+        epoch_id: u64,
         key: String,
+        processor_prefix: String,
         source_states: SourceStates,
     ) -> Result<(), ExecutionError> {
         let mut state = self.state.lock();
@@ -213,14 +715,43 @@ impl CheckpointFactory {
         state.next_record_index = next_record_index;
         drop(state);
 
+        let notified_source_states = self
+            .checkpoint_notifier
+            .as_ref()
+            .map(|_| source_states.clone());
         let data = bincode::serialize(&RecordStoreSlice {
             source_states,
             data,
         })
         .expect("Record store slice should be serializable");
-        self.queue
+        let uploaded = self
+            .queue
             .upload_object(key, data)
             .map_err(|_| ExecutionError::CheckpointWriterThreadPanicked)?;
+        self.state.lock().last_written_epoch = Some(epoch_id);
+        if let (Some(notifier), Some(source_states)) =
+            (&self.checkpoint_notifier, notified_source_states)
+        {
+            // The notifier only quits with the runtime.
+            let _ = notifier.send((uploaded, source_states));
+        }
+
+        if self.retention > 0 {
+            let mut state = self.state.lock();
+            state
+                .retained_processor_prefixes
+                .push_back(processor_prefix);
+            while state.retained_processor_prefixes.len() > self.retention {
+                let expired = state
+                    .retained_processor_prefixes
+                    .pop_front()
+                    .expect("longer than retention");
+                // Queued after the slice, so it's deleted once a newer checkpoint is durable.
+                self.queue
+                    .delete_prefix(format!("{expired}/"))
+                    .map_err(|_| ExecutionError::CheckpointWriterThreadPanicked)?;
+            }
+        }
         Ok(())
     }
 }
@@ -228,6 +759,9 @@ impl CheckpointFactory {
 #[derive(Debug)]
 struct CheckpointWriterFactoryState {
     next_record_index: usize,
+    last_written_epoch: Option<u64>,
+    /// Processor prefixes of the checkpoints that haven't been pruned, oldest first.
+    retained_processor_prefixes: VecDeque<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -241,6 +775,7 @@ struct RecordStoreSlice {
 #[derive(Debug)]
 pub struct CheckpointWriter {
     factory: Arc<CheckpointFactory>,
+    epoch_id: u64,
     record_store_key: String,
     source_states: Arc<SourceStates>,
     processor_prefix: String,
@@ -275,13 +810,14 @@ impl CheckpointWriter {
         source_states: Arc<SourceStates>,
     ) -> Self {
         // Format with `u64` max number of digits.
-        let epoch_id = format!("{:020}", epoch_id);
+        let formatted_epoch_id = format!("{:020}", epoch_id);
         let record_store_key = record_store_prefix(&factory.prefix)
-            .join(&epoch_id)
+            .join(&formatted_epoch_id)
             .into_string();
-        let processor_prefix = processor_prefix(&factory.prefix, &epoch_id);
+        let processor_prefix = processor_prefix(&factory.prefix, &formatted_epoch_id);
         Self {
             factory,
+            epoch_id,
             record_store_key,
             source_states,
             processor_prefix,
@@ -312,8 +848,14 @@ impl CheckpointWriter {
     }
 
     fn drop(&mut self) -> Result<(), ExecutionError> {
+        // Nodes may not have processed this epoch if the executor was aborted.
+        if self.factory.aborted.load(Ordering::SeqCst) {
+            return Ok(());
+        }
         self.factory.write_record_store_slice(
+            self.epoch_id,
             std::mem::take(&mut self.record_store_key),
+            self.processor_prefix.clone(),
             self.source_states.deref().clone(),
         )
     }
@@ -327,12 +869,38 @@ impl Drop for CheckpointWriter {
     }
 }
 
+/// Returns the tables whose offset in `current` is before their offset in `previous`.
+fn regressed_tables<'a>(
+    previous: &'a SourceStates,
+    current: &'a SourceStates,
+) -> impl Iterator<Item = (&'a NodeHandle, &'a String)> + 'a {
+    current.iter().flat_map(move |(node_handle, tables)| {
+        tables.iter().filter_map(move |(table_name, state)| {
+            let previous = previous
+                .get(node_handle)
+                .and_then(|tables| tables.get(table_name));
+            match (previous, state) {
+                (Some(TableState::Restartable(before)), TableState::Restartable(after))
+                    if after < before =>
+                {
+                    Some((node_handle, table_name))
+                }
+                _ => None,
+            }
+        })
+    })
+}
+
 async fn read_record_store_slices(
     storage: &dyn Storage,
     factory_prefix: &str,
     record_store: RecordStore,
+    spill_path: Option<PathBuf>,
+    schema_versions: Arc<SchemaVersions>,
 ) -> Result<(ProcessorRecordStoreDeserializer, Option<Checkpoint>), ExecutionError> {
-    let record_store = ProcessorRecordStoreDeserializer::new(record_store)?;
+    let record_store =
+        ProcessorRecordStoreDeserializer::new_in(record_store, spill_path.as_deref())?
+            .with_schema_versions(schema_versions);
     let record_store_prefix = record_store_prefix(factory_prefix);
 
     let mut last_checkpoint: Option<Checkpoint> = None;
@@ -375,11 +943,20 @@ async fn read_record_store_slices(
                     epoch_id,
                     source_states: record_store_slice.source_states,
                     processor_prefix,
+                    processor_prefixes: vec![],
                 });
             }
         }
 
         for object in objects.objects {
+            if let (Some(last_checkpoint), Ok(object_name)) = (
+                last_checkpoint.as_mut(),
+                AsRef::<Utf8Path>::as_ref(&object.key).strip_prefix(&record_store_prefix),
+            ) {
+                last_checkpoint
+                    .processor_prefixes
+                    .push(processor_prefix(factory_prefix, object_name.as_str()));
+            }
             info!("Loading {}", object.key);
             let data = storage.download_object(object.key).await?;
             let record_store_slice = bincode::deserialize::<RecordStoreSlice>(&data)
@@ -465,10 +1042,812 @@ pub mod serialize;
 mod tests {
     use super::*;
 
+    use std::sync::atomic::AtomicU64;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use dozer_log::storage::InMemoryStorage;
     use dozer_log::tokio;
 
+    use crate::epoch::EpochManagerOptions;
+    use crate::executor::{DagExecutor, ExecutorOptions};
+    use crate::node::StateBackend;
+    use crate::tests::processors::StateCountingProcessorFactory;
+    use crate::tests::sinks::{RecordingSinkFactory, SinkRecording, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::tests::{
+        checkpoint_every_commit, dir_entries, generator_to_materializing_dag,
+        generator_to_state_counting_dag,
+    };
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
     #[tokio::test]
     async fn checkpoint_writer_should_write_records() {
         create_checkpoint_factory_for_test(&[vec![Field::Int(0)]]).await;
     }
+
+    async fn write_checkpoint(checkpoint_dir: &str, epoch_id: u64, source_states: SourceStates) {
+        let checkpoint = OptionCheckpoint::new(checkpoint_dir.to_string(), Default::default())
+            .await
+            .unwrap();
+        let (checkpoint_factory, handle) = CheckpointFactory::new(checkpoint, Default::default())
+            .await
+            .unwrap();
+        let factory = Arc::new(checkpoint_factory);
+        // Writer must be dropped outside tokio context.
+        std::thread::spawn(move || {
+            drop(CheckpointWriter::new(
+                factory,
+                epoch_id,
+                Arc::new(source_states),
+            ))
+        })
+        .join()
+        .unwrap();
+        handle.await.unwrap();
+    }
+
+    fn source_states(source: &NodeHandle, txid: u64) -> SourceStates {
+        [(
+            source.clone(),
+            [(
+                "table".to_string(),
+                TableState::Restartable(OpIdentifier::new(txid, 0)),
+            )]
+            .into_iter()
+            .collect(),
+        )]
+        .into_iter()
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn diff_should_report_advanced_source_offset() {
+        let (temp_dir, empty) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap();
+        let source = NodeHandle::new(None, "source".to_string());
+
+        write_checkpoint(checkpoint_dir, 0, source_states(&source, 10)).await;
+        let before = OptionCheckpoint::new(checkpoint_dir.to_string(), Default::default())
+            .await
+            .unwrap();
+        write_checkpoint(checkpoint_dir, 1, source_states(&source, 25)).await;
+        let after = OptionCheckpoint::new(checkpoint_dir.to_string(), Default::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            before.diff(&after),
+            vec![CheckpointDiff {
+                node_handle: source.clone(),
+                table_name: "table".to_string(),
+                before: Some(TableState::Restartable(OpIdentifier::new(10, 0))),
+                after: Some(TableState::Restartable(OpIdentifier::new(25, 0))),
+            }]
+        );
+        assert_eq!(empty.diff(&before)[0].before, None);
+        assert!(after.diff(&after).is_empty());
+    }
+
+    #[tokio::test]
+    async fn verify_integrity_should_flag_offset_beyond_stored_slices() {
+        let (temp_dir, _) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap();
+        let source = NodeHandle::new(None, "source".to_string());
+
+        write_checkpoint(checkpoint_dir, 0, source_states(&source, 10)).await;
+        write_checkpoint(checkpoint_dir, 1, source_states(&source, 25)).await;
+        let checkpoint = OptionCheckpoint::new(checkpoint_dir.to_string(), Default::default())
+            .await
+            .unwrap();
+        let report = checkpoint.verify_integrity().await.unwrap();
+        assert_eq!(report.num_slices, 2);
+        assert!(report.is_consistent());
+
+        // Remove the last slice behind the loaded checkpoint's back.
+        let last_slice = record_store_prefix(&checkpoint.prefix)
+            .join(format!("{:020}", 1))
+            .into_string();
+        checkpoint
+            .storage()
+            .delete_objects(vec![last_slice])
+            .await
+            .unwrap();
+
+        let report = checkpoint.verify_integrity().await.unwrap();
+        assert_eq!(report.num_slices, 1);
+        assert_eq!(
+            report.issues,
+            vec![IntegrityIssue::OffsetMismatch {
+                node_handle: source,
+                table_name: "table".to_string(),
+                recorded: TableState::Restartable(OpIdentifier::new(25, 0)),
+                stored: Some(TableState::Restartable(OpIdentifier::new(10, 0))),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn retention_should_keep_newest_processor_states() {
+        let (temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+        let prefix = checkpoint.prefix.clone();
+        let (mut checkpoint_factory, handle) =
+            CheckpointFactory::new(checkpoint, Default::default())
+                .await
+                .unwrap();
+        checkpoint_factory.set_retention(2);
+        let factory = Arc::new(checkpoint_factory);
+        let processor = NodeHandle::new(None, "processor".to_string());
+
+        // Writer must be dropped outside tokio context.
+        let processor_clone = processor.clone();
+        std::thread::spawn(move || {
+            for epoch_id in 0..5 {
+                let writer = CheckpointWriter::new(factory.clone(), epoch_id, Default::default());
+                let mut object = writer.create_processor_object(&processor_clone).unwrap();
+                object.write(&[epoch_id as u8]).unwrap();
+                drop(object);
+                drop(writer);
+            }
+        })
+        .join()
+        .unwrap();
+        handle.await.unwrap();
+
+        let checkpoint = OptionCheckpoint::new(checkpoint_dir, Default::default())
+            .await
+            .unwrap();
+        let mut processor_keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let objects = checkpoint
+                .storage()
+                .list_objects(format!("{prefix}/"), continuation_token)
+                .await
+                .unwrap();
+            processor_keys.extend(
+                objects
+                    .objects
+                    .into_iter()
+                    .map(|object| object.key)
+                    .filter(|key| !key.starts_with(record_store_prefix(&prefix).as_str())),
+            );
+            continuation_token = objects.continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        processor_keys.sort();
+        assert_eq!(
+            processor_keys,
+            [3, 4]
+                .into_iter()
+                .map(|epoch_id| processor_key(
+                    &processor_prefix(&prefix, &format!("{epoch_id:020}")),
+                    &processor
+                ))
+                .collect::<Vec<_>>()
+        );
+
+        // Every record store slice is kept and the latest checkpoint is still loadable.
+        assert_eq!(checkpoint.checkpoint.as_ref().unwrap().num_slices.get(), 5);
+        assert_eq!(
+            checkpoint.load_processor_data(&processor).await.unwrap(),
+            Some(vec![4])
+        );
+    }
+
+    #[test]
+    fn source_consistency_groups_tables_by_state() {
+        let source = NodeHandle::new(None, "source".to_string());
+        let mut source_states = source_states(&source, 10);
+        assert_eq!(
+            source_consistency(&source_states)[&source],
+            Consistency::FullyConsistent(TableState::Restartable(OpIdentifier::new(10, 0)))
+        );
+
+        let tables = source_states.get_mut(&source).unwrap();
+        tables.insert("b".to_string(), TableState::NotStarted);
+        tables.insert("a".to_string(), TableState::NotStarted);
+        let Consistency::PartiallyConsistent(states) = &source_consistency(&source_states)[&source]
+        else {
+            panic!("Tables are in different states");
+        };
+        assert_eq!(states[&TableState::NotStarted], vec!["a", "b"]);
+        assert_eq!(
+            states[&TableState::Restartable(OpIdentifier::new(10, 0))],
+            vec!["table"]
+        );
+    }
+
+    /// Checkpoints every epoch of 10 operations.
+    fn checkpoint_every_10_operations() -> ExecutorOptions {
+        ExecutorOptions {
+            commit_sz: 10,
+            commit_time_threshold: Duration::from_secs(3600),
+            epoch_manager_options: EpochManagerOptions {
+                max_num_records_before_persist: 10,
+                enable_app_checkpoints: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn run_to_end(executor: DagExecutor) {
+        executor
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    /// The offset of the only table of `source_handle`.
+    fn source_offset(checkpoint: &OptionCheckpoint, source_handle: &NodeHandle) -> Option<u64> {
+        checkpoint
+            .get_source_state(source_handle)
+            .unwrap()
+            .map(|state| state.into_values().next().flatten().unwrap().txid)
+    }
+
+    fn sorted_generated_keys(from: u64, count: u64) -> Vec<Field> {
+        let mut keys = (from..from + count)
+            .map(|n| Field::String(format!("key_{n}")))
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn materialized_keys(recording: &Mutex<SinkRecording>) -> Vec<Field> {
+        let mut keys = recording
+            .lock()
+            .materialize()
+            .into_keys()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_on_checkpoint() {
+        let count: u64 = 100;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let (dag, _) = generator_to_materializing_dag(count);
+
+        let checkpoints = Arc::new(Mutex::new(vec![]));
+        let on_checkpoint_checkpoints = checkpoints.clone();
+        let options = ExecutorOptions {
+            on_checkpoint: Some(Arc::new(move |consistency| {
+                on_checkpoint_checkpoints.lock().push(consistency)
+            })),
+            ..checkpoint_every_10_operations()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        run_to_end(DagExecutor::new(dag, checkpoint, options).await.unwrap()).await;
+
+        // Callbacks run on this runtime once uploads complete.
+        let expected =
+            Consistency::FullyConsistent(TableState::Restartable(OpIdentifier::new(count, 0)));
+        let last_consistency = || {
+            checkpoints
+                .lock()
+                .last()
+                .and_then(|consistency| consistency.get(&source_handle).cloned())
+        };
+        for _ in 0..100 {
+            if last_consistency().as_ref() == Some(&expected) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(last_consistency(), Some(expected));
+        assert_eq!(checkpoints.lock().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_restart_from_checkpoint() {
+        let count: u64 = 50;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let (temp_dir, _) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+        let open = || OptionCheckpoint::new(checkpoint_dir.clone(), Default::default());
+        let offset =
+            |checkpoint: &OptionCheckpoint| source_offset(checkpoint, &source_handle).unwrap();
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        run_to_end(
+            DagExecutor::new(dag, open().await.unwrap(), checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+        let mid_run = open().await.unwrap();
+        let mid_run_id = mid_run.epoch_id().unwrap();
+        let mid_run_offset = offset(&mid_run);
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        run_to_end(
+            DagExecutor::new(dag, open().await.unwrap(), checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+        let latest = open().await.unwrap();
+        assert!(latest.epoch_id().unwrap() > mid_run_id);
+        assert!(offset(&latest) > mid_run_offset);
+
+        let (dag, recording) = generator_to_materializing_dag(count);
+        run_to_end(
+            DagExecutor::restart_from_checkpoint(
+                dag,
+                checkpoint_dir.clone(),
+                mid_run_id,
+                Default::default(),
+                checkpoint_every_10_operations(),
+            )
+            .await
+            .unwrap(),
+        )
+        .await;
+
+        // The source replayed from the earlier offset, and the newer checkpoints were replaced.
+        assert_eq!(
+            materialized_keys(&recording),
+            sorted_generated_keys(mid_run_offset + 1, count)
+        );
+        assert_eq!(offset(&open().await.unwrap()), mid_run_offset + count);
+
+        assert!(matches!(
+            DagExecutor::restart_from_checkpoint(
+                generator_to_materializing_dag(count).0,
+                checkpoint_dir,
+                u64::MAX,
+                Default::default(),
+                checkpoint_every_10_operations(),
+            )
+            .await,
+            Err(ExecutionError::CheckpointNotFound(u64::MAX))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_restart_from_checkpoint_with_processor_state() {
+        let count: u64 = 100;
+        let state_dir =
+            TempDir::new("test_run_dag_restart_from_checkpoint_with_processor_state").unwrap();
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let (temp_dir, _) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+        let open = || OptionCheckpoint::new(checkpoint_dir.clone(), Default::default());
+
+        let (dag, _) = generator_to_state_counting_dag(count);
+        run_to_end(
+            DagExecutor::new(
+                dag,
+                open().await.unwrap(),
+                checkpoint_every_commit(&state_dir),
+            )
+            .await
+            .unwrap(),
+        )
+        .await;
+        let mid_run_id = open().await.unwrap().epoch_id().unwrap();
+        let (dag, state_count) = generator_to_state_counting_dag(count);
+        run_to_end(
+            DagExecutor::new(
+                dag,
+                open().await.unwrap(),
+                checkpoint_every_commit(&state_dir),
+            )
+            .await
+            .unwrap(),
+        )
+        .await;
+        assert!(open().await.unwrap().epoch_id().unwrap() > mid_run_id);
+        assert_eq!(state_count.load(Ordering::SeqCst), count * 2);
+
+        // The state is rolled back with the checkpoint, so the replayed operations are counted once.
+        let (dag, state_count) = generator_to_state_counting_dag(count);
+        run_to_end(
+            DagExecutor::restart_from_checkpoint(
+                dag,
+                checkpoint_dir.clone(),
+                mid_run_id,
+                Default::default(),
+                checkpoint_every_commit(&state_dir),
+            )
+            .await
+            .unwrap(),
+        )
+        .await;
+        assert_eq!(state_count.load(Ordering::SeqCst), count * 2);
+
+        // Without a snapshot, the state can't be rolled back, which fails before the checkpoints are touched.
+        let latest_id = open().await.unwrap().epoch_id().unwrap();
+        std::fs::remove_dir_all(state_dir.path().join(format!("{proc_handle}.snapshots"))).unwrap();
+        let result = DagExecutor::restart_from_checkpoint(
+            generator_to_state_counting_dag(count).0,
+            checkpoint_dir,
+            mid_run_id,
+            Default::default(),
+            checkpoint_every_commit(&state_dir),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ExecutionError::ProcessorStateNotRestorable { node, checkpoint_epoch })
+                if node == proc_handle && checkpoint_epoch == mid_run_id
+        ));
+        assert_eq!(open().await.unwrap().epoch_id(), Some(latest_id));
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_checkpoint_epoch_increases_across_restarts() {
+        let count: u64 = 50;
+        // Returns the epoch the executor resumed from, and the one of the last checkpoint it wrote.
+        let run = |executor: DagExecutor| async move {
+            let resumed_epoch = executor.current_epoch();
+            let mut join_handle = executor
+                .start(Arc::new(AtomicBool::new(true)), Default::default())
+                .await
+                .unwrap();
+            while join_handle
+                .join_timeout(Duration::from_millis(10))
+                .unwrap()
+                .is_none()
+            {}
+            (resumed_epoch, join_handle.current_epoch())
+        };
+        let (temp_dir, _) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+        let open = || OptionCheckpoint::new(checkpoint_dir.clone(), Default::default());
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        let (resumed_epoch, first_epoch) =
+            run(
+                DagExecutor::new(dag, open().await.unwrap(), checkpoint_every_10_operations())
+                    .await
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(resumed_epoch, None);
+        let first_epoch = first_epoch.unwrap();
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        let (resumed_epoch, second_epoch) =
+            run(
+                DagExecutor::new(dag, open().await.unwrap(), checkpoint_every_10_operations())
+                    .await
+                    .unwrap(),
+            )
+            .await;
+        assert_eq!(resumed_epoch, Some(first_epoch));
+        assert!(second_epoch.unwrap() > first_epoch);
+        assert_eq!(open().await.unwrap().epoch_id(), second_epoch);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_restart_from_custom_checkpoint_storage() {
+        let count: u64 = 50;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        // Every checkpoint opened from a clone of `storage` sees the same objects.
+        let storage = InMemoryStorage::new();
+        let open = || {
+            OptionCheckpoint::with_storage(
+                Box::new(storage.clone()),
+                String::new(),
+                Default::default(),
+            )
+        };
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        run_to_end(
+            DagExecutor::new(dag, open().await.unwrap(), checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+        let stopped = open().await.unwrap();
+        assert!(stopped.verify_integrity().await.unwrap().is_consistent());
+        assert_eq!(source_offset(&stopped, &source_handle), Some(count));
+
+        let (dag, recording) = generator_to_materializing_dag(count);
+        run_to_end(
+            DagExecutor::new(dag, stopped, checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+
+        // The restarted source resumed right after the checkpointed offset.
+        assert_eq!(
+            materialized_keys(&recording),
+            sorted_generated_keys(count + 1, count)
+        );
+        let restarted = open().await.unwrap();
+        assert!(restarted.verify_integrity().await.unwrap().is_consistent());
+        assert_eq!(source_offset(&restarted, &source_handle), Some(2 * count));
+    }
+
+    #[tokio::test]
+    async fn test_source_offsets_reads_persisted_offsets() {
+        let count: u64 = 50;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let (temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+
+        // Nothing is persisted before the first run.
+        let (dag, _) = generator_to_materializing_dag(count);
+        let offsets =
+            OptionCheckpoint::source_offsets(&dag, checkpoint_dir.clone(), Default::default())
+                .await
+                .unwrap();
+        assert!(offsets.is_empty());
+
+        run_to_end(
+            DagExecutor::new(dag, checkpoint, checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        let offsets = OptionCheckpoint::source_offsets(&dag, checkpoint_dir, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            offsets,
+            HashMap::from([(
+                source_handle,
+                HashMap::from([(
+                    "generator".to_string(),
+                    TableState::Restartable(OpIdentifier::new(count, 0))
+                )])
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_resumes_renamed_source() {
+        let count: u64 = 50;
+        let storage = InMemoryStorage::new();
+        let open = || {
+            OptionCheckpoint::with_storage(
+                Box::new(storage.clone()),
+                String::new(),
+                Default::default(),
+            )
+        };
+        let old_handle = NodeHandle::new(None, 1.to_string());
+        let new_handle = NodeHandle::new(None, "renamed".to_string());
+
+        let (dag, _) = generator_to_materializing_dag(count);
+        run_to_end(
+            DagExecutor::new(dag, open().await.unwrap(), checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+        let mut checkpoint = open().await.unwrap();
+        assert_eq!(source_offset(&checkpoint, &old_handle), Some(count));
+        assert!(matches!(
+            checkpoint
+                .rename_node(&new_handle, old_handle.clone())
+                .await,
+            Err(ExecutionError::NodeNotFound(_))
+        ));
+        checkpoint
+            .rename_node(&old_handle, new_handle.clone())
+            .await
+            .unwrap();
+        assert_eq!(source_offset(&checkpoint, &new_handle), Some(count));
+
+        // The rename is persisted.
+        let checkpoint = open().await.unwrap();
+        assert_eq!(source_offset(&checkpoint, &old_handle), None);
+        assert_eq!(source_offset(&checkpoint, &new_handle), Some(count));
+        assert!(checkpoint.verify_integrity().await.unwrap().is_consistent());
+
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let latch = Arc::new(AtomicBool::new(true));
+        let sink = RecordingSinkFactory::new()
+            .record_ops()
+            .stop_after(count, latch.clone());
+        let recording = sink.recording();
+        let dag = DagBuilder::new()
+            .source(
+                new_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &new_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_to_end(
+            DagExecutor::new(dag, checkpoint, checkpoint_every_10_operations())
+                .await
+                .unwrap(),
+        )
+        .await;
+
+        // The renamed source resumed right after the offset checkpointed under its old handle.
+        assert_eq!(
+            materialized_keys(&recording),
+            sorted_generated_keys(count + 1, count)
+        );
+        assert_eq!(
+            source_offset(&open().await.unwrap(), &new_handle),
+            Some(2 * count)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_snapshot() {
+        let count: u64 = 100_000;
+        let source_latch = Arc::new(AtomicBool::new(true));
+        let sent = Arc::new(AtomicU64::new(0));
+        let sink = RecordingSinkFactory::new();
+        let received = sink.received();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, source_latch.clone(), false)
+                    .with_sent_counter(sent.clone()),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        // App checkpoints are disabled, so the snapshot is the only checkpoint.
+        let options = ExecutorOptions {
+            commit_sz: 1_000,
+            channel_buffer_sz: 100,
+            ..Default::default()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let running = Arc::new(AtomicBool::new(true));
+        let join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(running.clone(), Default::default())
+            .await
+            .unwrap();
+
+        while sent.load(Ordering::SeqCst) < 10_000 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let dest = TempDir::new("test_run_dag_snapshot").unwrap();
+        join_handle.snapshot(dest.path()).await.unwrap();
+
+        // The pipeline resumes after the snapshot.
+        while received.load(Ordering::SeqCst) < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        running.store(false, Ordering::SeqCst);
+        source_latch.store(false, Ordering::SeqCst);
+        join_handle.join().unwrap();
+
+        let snapshot = OptionCheckpoint::new(
+            dest.path().to_str().unwrap().to_string(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        assert!(snapshot.epoch_id().is_some());
+        assert!(snapshot.verify_integrity().await.unwrap().is_consistent());
+        let offset = source_offset(&snapshot, &source_handle).unwrap();
+        // Taken mid-run.
+        assert!(offset > 0 && offset < count, "offset {offset}");
+
+        // An executor can restart from it.
+        DagExecutor::new(
+            generator_to_materializing_dag(count).0,
+            snapshot,
+            Default::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_spill_path() {
+        let count: u64 = 1_000;
+        let running = Arc::new(AtomicBool::new(true));
+        let checkpoint_dir = TempDir::new("test_run_dag_with_spill_path_checkpoint").unwrap();
+        let spill_dir = TempDir::new("test_run_dag_with_spill_path_spill").unwrap();
+        let processor = StateCountingProcessorFactory::new(StateBackend::RocksDb);
+        let processed = processor.count();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, running.clone(), false),
+            )
+            .processor(proc_handle.clone(), processor)
+            .sink(sink_handle.clone(), RecordingSinkFactory::new())
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let checkpoint = OptionCheckpoint::new(
+            checkpoint_dir.path().to_str().unwrap().to_string(),
+            CheckpointOptions {
+                record_store: RecordStore::Rocksdb(Default::default()),
+                spill_path: Some(spill_dir.path().to_path_buf()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let options = ExecutorOptions {
+            spill_path: Some(spill_dir.path().to_path_buf()),
+            epoch_manager_options: EpochManagerOptions {
+                enable_app_checkpoints: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        while processed.load(Ordering::SeqCst) < count {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        // The stores only exist while the executor runs, so they're checked before stopping the source.
+        let spilled = dir_entries(spill_dir.path());
+        let is_store = |name: &String| {
+            name.starts_with("rocksdb_processor_record_store")
+                || name.starts_with("dozer_processor_state")
+        };
+        assert!(spilled
+            .iter()
+            .any(|name| name.starts_with("rocksdb_processor_record_store")));
+        assert!(spilled
+            .iter()
+            .any(|name| name.starts_with("dozer_processor_state")));
+        assert!(!dir_entries(checkpoint_dir.path()).iter().any(is_store));
+
+        running.store(false, Ordering::SeqCst);
+        join_handle.join().unwrap();
+
+        let metadata = dir_entries(checkpoint_dir.path());
+        assert!(!metadata.is_empty());
+        assert!(!metadata.iter().any(is_store));
+    }
 }
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use dozer_log::storage::Queue;
+use dozer_recordstore::ProcessorRecordStore;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::log::{info, warn};
+use dozer_types::node::{OpIdentifier, SourceStates};
+use dozer_types::types::Schema;
+
+use crate::epoch::Epoch;
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::node::{CommitDecision, PortHandle, Sink, SinkFactory, SinkPartitioning};
+use crate::partition::PartitionHasher;
+
+/// When a sink's circuit breaker opens, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerOptions {
+    /// Consecutive failed operations that open the breaker.
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before an operation is let through to test recovery.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerOptions {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CircuitState {
+    /// Operations are delivered to the sink.
+    Closed,
+    /// Delivery is paused until the cooldown ends.
+    Open { until: Instant },
+    /// The next operation tests whether the sink has recovered.
+    HalfOpen,
+}
+
+/// Wraps a sink factory, pausing delivery to its sinks after repeated failures.
+///
+/// While the breaker is open the sink node blocks instead of calling the sink, so the sources feeding it are paused by back pressure.
+/// Failed operations are still reported to the executor's error threshold.
+#[derive(Debug)]
+pub struct CircuitBreakerSinkFactory {
+    inner: Box<dyn SinkFactory>,
+    options: CircuitBreakerOptions,
+}
+
+impl CircuitBreakerSinkFactory {
+    pub fn new(inner: Box<dyn SinkFactory>, options: CircuitBreakerOptions) -> Self {
+        Self { inner, options }
+    }
+}
+
+impl SinkFactory for CircuitBreakerSinkFactory {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.inner.get_input_ports()
+    }
+
+    fn prepare(&self, input_schemas: HashMap<PortHandle, Schema>) -> Result<(), BoxedError> {
+        self.inner.prepare(input_schemas)
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Sink>, BoxedError> {
+        Ok(Box::new(CircuitBreakerSink::new(
+            self.inner.build(input_schemas)?,
+            self.options,
+        )))
+    }
+
+    fn partition_by(&self) -> Option<SinkPartitioning> {
+        self.inner.partition_by()
+    }
+
+    fn partition_hasher(&self) -> PartitionHasher {
+        self.inner.partition_hasher()
+    }
+}
+
+#[derive(Debug)]
+struct CircuitBreakerSink {
+    inner: Box<dyn Sink>,
+    options: CircuitBreakerOptions,
+    state: CircuitState,
+    consecutive_failures: u32,
+}
+
+impl CircuitBreakerSink {
+    fn new(inner: Box<dyn Sink>, options: CircuitBreakerOptions) -> Self {
+        Self {
+            inner,
+            options,
+            state: CircuitState::Closed,
+            consecutive_failures: 0,
+        }
+    }
+
+    /// Blocks until the breaker lets an operation through.
+    fn wait_until_closed(&mut self) {
+        if let CircuitState::Open { until } = self.state {
+            let now = Instant::now();
+            if until > now {
+                std::thread::sleep(until - now);
+            }
+            self.state = CircuitState::HalfOpen;
+        }
+    }
+
+    fn on_result(&mut self, result: Result<(), BoxedError>) -> Result<(), BoxedError> {
+        match &result {
+            Ok(()) => {
+                if self.state == CircuitState::HalfOpen {
+                    info!("Sink recovered, closing circuit breaker");
+                }
+                self.state = CircuitState::Closed;
+                self.consecutive_failures = 0;
+            }
+            Err(e) => {
+                self.consecutive_failures += 1;
+                if self.state == CircuitState::HalfOpen
+                    || self.consecutive_failures >= self.options.failure_threshold
+                {
+                    warn!(
+                        "Sink failed {} times in a row, pausing delivery for {:?}: {e}",
+                        self.consecutive_failures, self.options.cooldown
+                    );
+                    self.state = CircuitState::Open {
+                        until: Instant::now() + self.options.cooldown,
+                    };
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Sink for CircuitBreakerSink {
+    fn init(&mut self) -> Result<(), BoxedError> {
+        self.inner.init()
+    }
+
+    fn commit(&mut self, epoch_details: &Epoch) -> Result<(), BoxedError> {
+        self.inner.commit(epoch_details)
+    }
+
+    fn commit_with_decision(
+        &mut self,
+        epoch_details: &Epoch,
+    ) -> Result<CommitDecision, BoxedError> {
+        self.inner.commit_with_decision(epoch_details)
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<(), BoxedError> {
+        self.wait_until_closed();
+        let result = self.inner.process(from_port, record_store, op);
+        self.on_result(result)
+    }
+
+    fn process_with_context(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        context: &OperationContext,
+    ) -> Result<(), BoxedError> {
+        self.wait_until_closed();
+        let result = self
+            .inner
+            .process_with_context(from_port, record_store, op, context);
+        self.on_result(result)
+    }
+
+    fn persist(&mut self, queue: &Queue) -> Result<(), BoxedError> {
+        self.inner.persist(queue)
+    }
+
+    fn on_source_snapshotting_done(&mut self, connection_name: String) -> Result<(), BoxedError> {
+        self.inner.on_source_snapshotting_done(connection_name)
+    }
+
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), BoxedError> {
+        self.inner.on_watermark(watermark)
+    }
+
+    fn applied_source_states(&self) -> Option<SourceStates> {
+        self.inner.applied_source_states()
+    }
+
+    fn applied_through(&self) -> Option<OpIdentifier> {
+        self.inner.applied_through()
+    }
+
+    fn on_end_of_stream(&mut self) -> Result<(), BoxedError> {
+        self.inner.on_end_of_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dozer_types::parking_lot::Mutex;
+    use dozer_types::types::{Field, Record};
+
+    use super::*;
+
+    /// Fails the operations attempted in `failing`, recording when each attempt was made.
+    #[derive(Debug)]
+    struct FlakySink {
+        failing: std::ops::Range<usize>,
+        attempts: Arc<Mutex<Vec<Instant>>>,
+    }
+
+    impl Sink for FlakySink {
+        fn commit(&mut self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn process(
+            &mut self,
+            _from_port: PortHandle,
+            _record_store: &ProcessorRecordStore,
+            _op: ProcessorOperation,
+        ) -> Result<(), BoxedError> {
+            let mut attempts = self.attempts.lock();
+            let attempt = attempts.len();
+            attempts.push(Instant::now());
+            if self.failing.contains(&attempt) {
+                Err("sink unavailable".into())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn persist(&mut self, _queue: &Queue) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn on_source_snapshotting_done(
+            &mut self,
+            _connection_name: String,
+        ) -> Result<(), BoxedError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn circuit_breaker_pauses_failing_sink() {
+        const COOLDOWN: Duration = Duration::from_millis(100);
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let mut sink = CircuitBreakerSink::new(
+            Box::new(FlakySink {
+                failing: 5..10,
+                attempts: attempts.clone(),
+            }),
+            CircuitBreakerOptions {
+                failure_threshold: 3,
+                cooldown: COOLDOWN,
+            },
+        );
+
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let new = record_store
+            .create_record(&Record::new(vec![Field::UInt(0)]))
+            .unwrap();
+        let mut results = vec![];
+        for _ in 0..15 {
+            let op = ProcessorOperation::Insert { new: new.clone() };
+            results.push(sink.process(0, &record_store, op).is_ok());
+            if results.len() == 8 {
+                // The third failure in a row opened the breaker.
+                assert!(matches!(sink.state, CircuitState::Open { .. }));
+            }
+        }
+        assert_eq!(sink.state, CircuitState::Closed);
+
+        // Every operation is attempted, and failures are still reported.
+        let expected = (0..15).map(|i| !(5..10).contains(&i)).collect::<Vec<_>>();
+        assert_eq!(results, expected);
+
+        // Attempts made while the breaker was open waited out the cooldown. The others weren't delayed.
+        let attempts = attempts.lock();
+        for i in 1..attempts.len() {
+            let gap = attempts[i] - attempts[i - 1];
+            if (8..=10).contains(&i) {
+                assert!(gap >= COOLDOWN, "attempt {i} wasn't paused");
+            } else {
+                assert!(gap < COOLDOWN, "attempt {i} was paused");
+            }
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer, StoreRecord};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::indexmap::IndexMap;
+use dozer_types::types::Schema;
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::epoch::Epoch;
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::node::{PortHandle, Processor, ProcessorFactory, StateBackend};
+use crate::DEFAULT_PORT_HANDLE;
+
+/// Forwards only the net change to every primary key within a window, dropping the intermediate states.
+///
+/// Pending operations on the same key are combined: an insert followed by updates becomes one insert of the
+/// final record, and a delete cancels the pending updates, or the pending insert along with itself.
+/// Pending operations are sent in the order their keys first became pending, when the window closes and
+/// before every commit, so windows never span epochs and nothing is checkpointed.
+/// The input schema must have a primary key. An update that changes the primary key closes the window.
+#[derive(Debug)]
+pub struct CoalesceProcessorFactory {
+    window: CoalesceWindow,
+}
+
+/// When [`CoalesceProcessorFactory`] sends the pending operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoalesceWindow {
+    /// After receiving this many operations.
+    Count(usize),
+    /// On the first operation received this long after the window opened.
+    Time(Duration),
+}
+
+impl CoalesceProcessorFactory {
+    pub fn new(window: CoalesceWindow) -> Self {
+        Self { window }
+    }
+}
+
+impl ProcessorFactory for CoalesceProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        let schema = &input_schemas[&DEFAULT_PORT_HANDLE];
+        if schema.primary_index.is_empty() {
+            return Err("Coalescing requires an input schema with a primary key".into());
+        }
+        Ok(schema.clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        Ok(Box::new(CoalesceProcessor {
+            window: self.window,
+            primary_index: input_schemas[&DEFAULT_PORT_HANDLE].primary_index.clone(),
+            pending: IndexMap::new(),
+            num_received: 0,
+            opened_at: None,
+        }))
+    }
+
+    fn type_name(&self) -> String {
+        "Coalesce".to_owned()
+    }
+
+    fn id(&self) -> String {
+        "Coalesce".to_owned()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::Memory
+    }
+}
+
+#[derive(Debug)]
+struct CoalesceProcessor {
+    window: CoalesceWindow,
+    primary_index: Vec<usize>,
+    /// The net pending operation on every key, with the context of the latest operation on it.
+    pending: IndexMap<Vec<u8>, (ProcessorOperation, OperationContext)>,
+    /// Operations received since the window opened.
+    num_received: usize,
+    opened_at: Option<Instant>,
+}
+
+impl CoalesceProcessor {
+    fn flush(&mut self, fw: &mut dyn ProcessorChannelForwarder) {
+        for (_, (op, context)) in self.pending.drain(..) {
+            fw.send_with_context(op, DEFAULT_PORT_HANDLE, context);
+        }
+        self.num_received = 0;
+        self.opened_at = None;
+    }
+
+    fn is_window_closed(&self) -> bool {
+        match self.window {
+            CoalesceWindow::Count(count) => self.num_received >= count,
+            CoalesceWindow::Time(duration) => self
+                .opened_at
+                .map_or(false, |opened_at| opened_at.elapsed() >= duration),
+        }
+    }
+}
+
+/// Returns the net operation of `pending` followed by `op` on the same key, or `None` if they cancel out.
+fn combine(pending: ProcessorOperation, op: ProcessorOperation) -> Option<ProcessorOperation> {
+    use ProcessorOperation::{Delete, Insert, Update};
+    match (pending, op) {
+        (Insert { .. }, Update { new, .. }) => Some(Insert { new }),
+        (Insert { .. }, Delete { .. }) => None,
+        (Update { old, .. }, Update { new, .. }) => Some(Update { old, new }),
+        (Update { old, .. }, Delete { .. }) => Some(Delete { old }),
+        (Delete { old }, Insert { new }) => Some(Update { old, new }),
+        // Inconsistent with the pending operation, so there's nothing to combine with.
+        (_, op) => Some(op),
+    }
+}
+
+impl Processor for CoalesceProcessor {
+    fn before_commit(
+        &mut self,
+        _epoch_details: &Epoch,
+        _record_store: &ProcessorRecordStore,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.flush(fw);
+        Ok(())
+    }
+
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        let context = fw.context();
+        let key = |record| {
+            record_store
+                .load_record(record)
+                .map(|record| record.get_key(&self.primary_index))
+        };
+        let key = match &op {
+            ProcessorOperation::Insert { new } => key(new)?,
+            ProcessorOperation::Delete { old } => key(old)?,
+            ProcessorOperation::Update { old, new } => {
+                let new_key = key(new)?;
+                if key(old)? != new_key {
+                    self.flush(fw);
+                    fw.send(op, DEFAULT_PORT_HANDLE);
+                    return Ok(());
+                }
+                new_key
+            }
+        };
+
+        self.opened_at.get_or_insert_with(Instant::now);
+        self.num_received += 1;
+        match self.pending.shift_remove(&key) {
+            Some((pending, _)) => {
+                if let Some(op) = combine(pending, op) {
+                    self.pending.insert(key, (op, context));
+                }
+            }
+            None => {
+                self.pending.insert(key, (op, context));
+            }
+        }
+
+        if self.is_window_closed() {
+            self.flush(fw);
+        }
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        // Pending operations are sent before every commit.
+        Ok(())
+    }
+
+    fn on_port_closed(
+        &mut self,
+        _port: PortHandle,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.flush(fw);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use dozer_recordstore::ProcessorRecord;
+    use dozer_types::types::{Field, FieldType, Operation, Record};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct TestForwarder {
+        ops: Vec<ProcessorOperation>,
+    }
+
+    impl ProcessorChannelForwarder for TestForwarder {
+        fn send(&mut self, op: ProcessorOperation, _port: PortHandle) {
+            self.ops.push(op);
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::builder()
+            .field("id", FieldType::UInt, false)
+            .field("value", FieldType::UInt, false)
+            .primary_key([0])
+            .build()
+    }
+
+    struct Harness {
+        processor: Box<dyn Processor>,
+        record_store: ProcessorRecordStore,
+        fw: TestForwarder,
+    }
+
+    impl Harness {
+        fn new(window: CoalesceWindow) -> Self {
+            let processor = CoalesceProcessorFactory::new(window)
+                .build(
+                    [(DEFAULT_PORT_HANDLE, schema())].into_iter().collect(),
+                    HashMap::new(),
+                    &ProcessorRecordStoreDeserializer::new(Default::default()).unwrap(),
+                    None,
+                )
+                .unwrap();
+            Self {
+                processor,
+                record_store: ProcessorRecordStore::new(Default::default()).unwrap(),
+                fw: TestForwarder::default(),
+            }
+        }
+
+        fn record(&self, id: u64, value: u64) -> ProcessorRecord {
+            self.record_store
+                .create_record(&Record::new(vec![Field::UInt(id), Field::UInt(value)]))
+                .unwrap()
+        }
+
+        fn process(&mut self, op: ProcessorOperation) {
+            self.processor
+                .process(DEFAULT_PORT_HANDLE, &self.record_store, op, &mut self.fw)
+                .unwrap();
+        }
+
+        fn commit(&mut self) {
+            let epoch = Epoch::new(0, Default::default(), None, None, SystemTime::now());
+            self.processor
+                .before_commit(&epoch, &self.record_store, &mut self.fw)
+                .unwrap();
+        }
+
+        fn sent(&self) -> Vec<Operation> {
+            self.fw
+                .ops
+                .iter()
+                .map(|op| op.load(&self.record_store).unwrap())
+                .collect()
+        }
+    }
+
+    fn record(id: u64, value: u64) -> Record {
+        Record::new(vec![Field::UInt(id), Field::UInt(value)])
+    }
+
+    #[test]
+    fn coalesce_sends_final_state_of_rapid_updates() {
+        let mut harness = Harness::new(CoalesceWindow::Time(Duration::from_secs(3600)));
+        let new = harness.record(1, 0);
+        harness.process(ProcessorOperation::Insert { new });
+        harness.commit();
+
+        for value in 0..10 {
+            let old = harness.record(1, value);
+            let new = harness.record(1, value + 1);
+            harness.process(ProcessorOperation::Update { old, new });
+        }
+        // Still within the window.
+        assert_eq!(harness.fw.ops.len(), 1);
+        harness.commit();
+
+        assert_eq!(
+            harness.sent(),
+            vec![
+                Operation::Insert { new: record(1, 0) },
+                Operation::Update {
+                    old: record(1, 0),
+                    new: record(1, 10),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_delete_cancels_pending_operations() {
+        let mut harness = Harness::new(CoalesceWindow::Count(100));
+        let new = harness.record(1, 0);
+        harness.process(ProcessorOperation::Insert { new });
+        let (old, new) = (harness.record(1, 0), harness.record(1, 1));
+        harness.process(ProcessorOperation::Update { old, new });
+        let old = harness.record(1, 1);
+        harness.process(ProcessorOperation::Delete { old });
+
+        let (old, new) = (harness.record(2, 0), harness.record(2, 1));
+        harness.process(ProcessorOperation::Update { old, new });
+        let old = harness.record(2, 1);
+        harness.process(ProcessorOperation::Delete { old });
+        harness.commit();
+
+        // Key 1 was inserted and deleted within the window. Key 2's update is replaced by the delete.
+        assert_eq!(
+            harness.sent(),
+            vec![Operation::Delete { old: record(2, 0) }]
+        );
+    }
+
+    #[test]
+    fn coalesce_flushes_when_count_window_closes() {
+        let mut harness = Harness::new(CoalesceWindow::Count(3));
+        for value in 0..3 {
+            let (old, new) = (harness.record(1, value), harness.record(1, value + 1));
+            harness.process(ProcessorOperation::Update { old, new });
+        }
+
+        assert_eq!(
+            harness.sent(),
+            vec![Operation::Update {
+                old: record(1, 0),
+                new: record(1, 3),
+            }]
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use dozer_types::node::NodeHandle;
+
+use crate::edge_transform::EdgeTransform;
+use crate::errors::ExecutionError;
+use crate::node::{
+    PortHandle, ProcessorFactory, SinkFactory, SinkOptions, SourceFactory, TransformingSinkFactory,
+};
+use crate::{Dag, Edge, Endpoint};
+
+/// Builds a [`Dag`] by chaining nodes and edges.
+///
+/// Every step is validated when it's added. The first error is kept and returned from [`build`](Self::build),
+/// and later steps are ignored.
+#[derive(Debug, Default)]
+pub struct DagBuilder {
+    dag: Dag,
+    error: Option<ExecutionError>,
+}
+
+impl DagBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a source. Fails if `handle` already exists.
+    pub fn source(self, handle: NodeHandle, source: impl SourceFactory + 'static) -> Self {
+        self.add_node(handle, |dag, handle| {
+            dag.add_source(handle, Box::new(source));
+        })
+    }
+
+    /// Adds a processor. Fails if `handle` already exists.
+    pub fn processor(self, handle: NodeHandle, processor: impl ProcessorFactory + 'static) -> Self {
+        self.add_node(handle, |dag, handle| {
+            dag.add_processor(handle, Box::new(processor));
+        })
+    }
+
+    /// Adds a sink. Fails if `handle` already exists.
+    pub fn sink(self, handle: NodeHandle, sink: impl SinkFactory + 'static) -> Self {
+        self.add_node(handle, |dag, handle| {
+            dag.add_sink(handle, Box::new(sink));
+        })
+    }
+
+    /// Adds a sink, wrapped according to `options`. Fails if `handle` already exists.
+    pub fn sink_with_options(
+        self,
+        handle: NodeHandle,
+        sink: impl SinkFactory + 'static,
+        options: SinkOptions,
+    ) -> Self {
+        self.add_node(handle, |dag, handle| {
+            dag.add_sink_with_options(handle, Box::new(sink), options);
+        })
+    }
+
+    /// Adds a sink with output ports. Fails if `handle` already exists.
+    pub fn transforming_sink(
+        self,
+        handle: NodeHandle,
+        sink: impl TransformingSinkFactory + 'static,
+    ) -> Self {
+        self.add_node(handle, |dag, handle| {
+            dag.add_transforming_sink(handle, Box::new(sink));
+        })
+    }
+
+    /// Connects `from_port` of `from` to `to_port` of `to`.
+    ///
+    /// Fails if either node doesn't exist, either port is invalid, the edge already exists or it would create a cycle.
+    pub fn edge(
+        self,
+        from: &NodeHandle,
+        from_port: PortHandle,
+        to: &NodeHandle,
+        to_port: PortHandle,
+    ) -> Self {
+        self.add_edge(from, from_port, to, to_port, None)
+    }
+
+    /// Connects `from_port` of `from` to `to_port` of `to` like `edge`, filtering and mapping the operations sent through it with `transform`.
+    pub fn edge_with_transform(
+        self,
+        from: &NodeHandle,
+        from_port: PortHandle,
+        to: &NodeHandle,
+        to_port: PortHandle,
+        transform: EdgeTransform,
+    ) -> Self {
+        self.add_edge(from, from_port, to, to_port, Some(transform))
+    }
+
+    fn add_edge(
+        mut self,
+        from: &NodeHandle,
+        from_port: PortHandle,
+        to: &NodeHandle,
+        to_port: PortHandle,
+        transform: Option<EdgeTransform>,
+    ) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        let from = Endpoint::new(from.clone(), from_port);
+        let to = Endpoint::new(to.clone(), to_port);
+        let result = if !self.dag.contains_node(&from.node) {
+            Err(ExecutionError::NodeNotFound(from.node))
+        } else if !self.dag.contains_node(&to.node) {
+            Err(ExecutionError::NodeNotFound(to.node))
+        } else if self.dag.contains_edge(&from, &to) {
+            Err(ExecutionError::DuplicateEdge(Edge::new(from, to)))
+        } else if let Some(transform) = transform {
+            self.dag.connect_with_transform(from, to, transform)
+        } else {
+            self.dag.connect(from, to)
+        };
+        self.error = result.err();
+        self
+    }
+
+    /// Returns the built `Dag`, or the first error encountered.
+    pub fn build(self) -> Result<Dag, ExecutionError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.dag),
+        }
+    }
+
+    fn add_node(mut self, handle: NodeHandle, add: impl FnOnce(&mut Dag, NodeHandle)) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        if self.dag.contains_node(&handle) {
+            self.error = Some(ExecutionError::DuplicateNodeHandle(handle));
+        } else {
+            add(&mut self.dag, handle);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    use dozer_log::tokio;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::executor::DagExecutor;
+    use crate::tests::dag_base_run::NoopProcessorFactory;
+    use crate::tests::processors::ConnectivityTestProcessorFactory;
+    use crate::tests::sinks::{CountingSinkFactory, COUNTING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::DEFAULT_PORT_HANDLE;
+
+    use super::*;
+
+    fn source() -> GeneratorSourceFactory {
+        GeneratorSourceFactory::new(1, Arc::new(AtomicBool::new(true)), false)
+    }
+
+    #[test]
+    fn builder_rejects_duplicate_node() {
+        let handle = NodeHandle::new(None, "source".to_string());
+        let result = DagBuilder::new()
+            .source(handle.clone(), source())
+            .processor(handle, ConnectivityTestProcessorFactory)
+            .build();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::DuplicateNodeHandle(_))
+        ));
+    }
+
+    #[test]
+    fn builder_rejects_unknown_node() {
+        let source_handle = NodeHandle::new(None, "source".to_string());
+        let sink_handle = NodeHandle::new(None, "sink".to_string());
+        let result = DagBuilder::new()
+            .source(source_handle.clone(), source())
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                COUNTING_SINK_INPUT_PORT,
+            )
+            .build();
+        assert!(
+            matches!(result, Err(ExecutionError::NodeNotFound(handle)) if handle == sink_handle)
+        );
+    }
+
+    #[test]
+    fn builder_rejects_invalid_port_and_duplicate_edge() {
+        let source_handle = NodeHandle::new(None, "source".to_string());
+        let proc_handle = NodeHandle::new(None, "proc".to_string());
+        let builder = || {
+            DagBuilder::new()
+                .source(source_handle.clone(), source())
+                .processor(proc_handle.clone(), ConnectivityTestProcessorFactory)
+        };
+
+        let result = builder()
+            .edge(
+                &source_handle,
+                DEFAULT_PORT_HANDLE,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .build();
+        assert!(matches!(result, Err(ExecutionError::InvalidPortHandle(_))));
+
+        let result = builder()
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .build();
+        assert!(matches!(result, Err(ExecutionError::DuplicateEdge(_))));
+    }
+
+    #[test]
+    fn builder_stops_at_first_error() {
+        let handle = NodeHandle::new(None, "sink".to_string());
+        let latch = Arc::new(AtomicBool::new(true));
+        let result = DagBuilder::new()
+            .sink(handle.clone(), CountingSinkFactory::new(1, latch.clone()))
+            .sink(handle.clone(), CountingSinkFactory::new(1, latch))
+            .edge(&handle, 0, &handle, 0)
+            .build();
+        assert!(matches!(
+            result,
+            Err(ExecutionError::DuplicateNodeHandle(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_built_with_builder() {
+        let count: u64 = 1_000;
+
+        let source_handle = NodeHandle::new(Some(1), 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        let create_dag = |latch: Arc<AtomicBool>, use_builder: bool| {
+            if use_builder {
+                return DagBuilder::new()
+                    .source(
+                        source_handle.clone(),
+                        GeneratorSourceFactory::new(count, latch.clone(), false),
+                    )
+                    .processor(proc_handle.clone(), NoopProcessorFactory {})
+                    .sink(sink_handle.clone(), CountingSinkFactory::new(count, latch))
+                    .edge(
+                        &source_handle,
+                        GENERATOR_SOURCE_OUTPUT_PORT,
+                        &proc_handle,
+                        DEFAULT_PORT_HANDLE,
+                    )
+                    .edge(
+                        &proc_handle,
+                        DEFAULT_PORT_HANDLE,
+                        &sink_handle,
+                        COUNTING_SINK_INPUT_PORT,
+                    )
+                    .build()
+                    .unwrap();
+            }
+
+            let mut dag = Dag::new();
+            dag.add_source(
+                source_handle.clone(),
+                Box::new(GeneratorSourceFactory::new(count, latch.clone(), false)),
+            );
+            dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+            dag.add_sink(
+                sink_handle.clone(),
+                Box::new(CountingSinkFactory::new(count, latch)),
+            );
+            dag.connect(
+                Endpoint::new(source_handle.clone(), GENERATOR_SOURCE_OUTPUT_PORT),
+                Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+            )
+            .unwrap();
+            dag.connect(
+                Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+                Endpoint::new(sink_handle.clone(), COUNTING_SINK_INPUT_PORT),
+            )
+            .unwrap();
+            dag
+        };
+
+        let mut dag_infos = vec![];
+        for use_builder in [false, true] {
+            let latch = Arc::new(AtomicBool::new(true));
+            let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+            let executor = DagExecutor::new(
+                create_dag(latch, use_builder),
+                checkpoint,
+                Default::default(),
+            )
+            .await
+            .unwrap();
+            dag_infos.push(executor.dag().clone());
+            executor
+                .start(Arc::new(AtomicBool::new(true)), Default::default())
+                .await
+                .unwrap()
+                .join()
+                .unwrap();
+        }
+        assert_eq!(dag_infos[0], dag_infos[1]);
+    }
+}
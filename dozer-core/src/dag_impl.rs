@@ -1,9 +1,22 @@
-use daggy::petgraph::visit::{Bfs, EdgeRef, IntoEdges};
+use daggy::petgraph::visit::{Bfs, EdgeRef, IntoEdges, IntoNodeReferences};
+use daggy::petgraph::Direction;
 use daggy::Walker;
 use dozer_types::node::NodeHandle;
+use dozer_types::serde_json::Value;
 
+use crate::circuit_breaker::CircuitBreakerSinkFactory;
+use crate::dag_schemas;
+use crate::dedup::DedupSinkFactory;
+use crate::edge_transform::EdgeTransform;
 use crate::errors::ExecutionError;
-use crate::node::{PortHandle, ProcessorFactory, SinkFactory, SourceFactory};
+use crate::executor::DagNodeType;
+use crate::node::{
+    PortHandle, ProcessorFactory, SinkFactory, SinkOptions, SourceFactory, TransformingSinkFactory,
+};
+use crate::partitioned_processor::PartitionedProcessorFactory;
+use crate::partitioned_sink::PartitionedSinkFactory;
+use crate::projection::FieldProjection;
+use crate::transforming_sink::TransformingSinkProcessorFactory;
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display};
 
@@ -56,16 +69,25 @@ impl Display for NodeType {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 /// The edge type of the description DAG.
 pub struct EdgeType {
     pub from: PortHandle,
     pub to: PortHandle,
+    /// Applied to records sent through this edge, if any.
+    pub projection: Option<FieldProjection>,
+    /// Applied to operations sent through this edge, before `projection`, if any.
+    pub transform: Option<EdgeTransform>,
 }
 
 impl EdgeType {
     pub fn new(from: PortHandle, to: PortHandle) -> Self {
-        Self { from, to }
+        Self {
+            from,
+            to,
+            projection: None,
+            transform: None,
+        }
     }
 }
 
@@ -130,26 +152,109 @@ impl Dag {
     }
 
     /// Adds a processor. Panics if the `handle` exists in the `Dag`.
+    ///
+    /// If the processor factory returns `Some` from `partition_by`, the node runs a processor per partition.
     pub fn add_processor(
         &mut self,
         handle: NodeHandle,
         processor: Box<dyn ProcessorFactory>,
     ) -> daggy::NodeIndex {
+        let processor: Box<dyn ProcessorFactory> = match processor.partition_by() {
+            Some(partitioning) => {
+                Box::new(PartitionedProcessorFactory::new(processor, partitioning))
+            }
+            None => processor,
+        };
         self.add_node(handle, NodeKind::Processor(processor))
     }
 
     /// Adds a sink. Panics if the `handle` exists in the `Dag`.
+    ///
+    /// If the sink factory returns `Some` from `partition_by`, the node runs a sink per partition.
     pub fn add_sink(&mut self, handle: NodeHandle, sink: Box<dyn SinkFactory>) -> daggy::NodeIndex {
+        let sink: Box<dyn SinkFactory> = match sink.partition_by() {
+            Some(partitioning) => Box::new(PartitionedSinkFactory::new(sink, partitioning)),
+            None => sink,
+        };
         self.add_node(handle, NodeKind::Sink(sink))
     }
 
+    /// Adds a sink, wrapped according to `options`. Panics if the `handle` exists in the `Dag`.
+    pub fn add_sink_with_options(
+        &mut self,
+        handle: NodeHandle,
+        sink: Box<dyn SinkFactory>,
+        options: SinkOptions,
+    ) -> daggy::NodeIndex {
+        let sink: Box<dyn SinkFactory> = if options.dedup_by_primary_key {
+            Box::new(DedupSinkFactory::new(sink, options.dedup_capacity))
+        } else {
+            sink
+        };
+        let sink: Box<dyn SinkFactory> = match options.circuit_breaker {
+            Some(circuit_breaker) => {
+                Box::new(CircuitBreakerSinkFactory::new(sink, circuit_breaker))
+            }
+            None => sink,
+        };
+        self.add_sink(handle, sink)
+    }
+
+    /// Adds a sink with output ports. It runs as a processor node. Panics if the `handle` exists in the `Dag`.
+    pub fn add_transforming_sink(
+        &mut self,
+        handle: NodeHandle,
+        sink: Box<dyn TransformingSinkFactory>,
+    ) -> daggy::NodeIndex {
+        self.add_processor(handle, Box::new(TransformingSinkProcessorFactory(sink)))
+    }
+
     /// Adds an edge. Panics if there's already an edge from `from` to `to`.
     ///
     /// Returns an error if any of the port cannot be found or the edge would create a cycle.
     pub fn connect(&mut self, from: Endpoint, to: Endpoint) -> Result<(), ExecutionError> {
+        self.connect_with_projection(from, to, None)
+    }
+
+    /// Adds an edge like `connect`, projecting the records sent through it with `projection`.
+    ///
+    /// The input port of `to` receives the projected schema. The projection is validated against the output schema of `from` when the DAG is built.
+    pub fn connect_with_projection(
+        &mut self,
+        from: Endpoint,
+        to: Endpoint,
+        projection: Option<FieldProjection>,
+    ) -> Result<(), ExecutionError> {
+        self.connect_endpoints(from, to, projection, None)
+    }
+
+    /// Adds an edge like `connect`, filtering and mapping the operations sent through it with `transform`.
+    pub fn connect_with_transform(
+        &mut self,
+        from: Endpoint,
+        to: Endpoint,
+        transform: EdgeTransform,
+    ) -> Result<(), ExecutionError> {
+        self.connect_endpoints(from, to, None, Some(transform))
+    }
+
+    fn connect_endpoints(
+        &mut self,
+        from: Endpoint,
+        to: Endpoint,
+        projection: Option<FieldProjection>,
+        transform: Option<EdgeTransform>,
+    ) -> Result<(), ExecutionError> {
         let from_node_index = validate_endpoint(self, &from, PortDirection::Output)?;
         let to_node_index = validate_endpoint(self, &to, PortDirection::Input)?;
-        self.connect_with_index(from_node_index, from.port, to_node_index, to.port)
+        self.add_edge(
+            from_node_index,
+            from.port,
+            to_node_index,
+            to.port,
+            projection,
+            transform,
+        )
     }
 
     /// Adds an edge. Panics if there's already an edge from `from` to `to`.
@@ -161,13 +266,37 @@ impl Dag {
         output_port: PortHandle,
         to_node_index: daggy::NodeIndex,
         input_port: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        self.add_edge(
+            from_node_index,
+            output_port,
+            to_node_index,
+            input_port,
+            None,
+            None,
+        )
+    }
+
+    fn add_edge(
+        &mut self,
+        from_node_index: daggy::NodeIndex,
+        output_port: PortHandle,
+        to_node_index: daggy::NodeIndex,
+        input_port: PortHandle,
+        projection: Option<FieldProjection>,
+        transform: Option<EdgeTransform>,
     ) -> Result<(), ExecutionError> {
         validate_port_with_index(self, from_node_index, output_port, PortDirection::Output)?;
         validate_port_with_index(self, to_node_index, input_port, PortDirection::Input)?;
         let edge_index = self.graph.add_edge(
             from_node_index,
             to_node_index,
-            EdgeType::new(output_port, input_port),
+            EdgeType {
+                from: output_port,
+                to: input_port,
+                projection,
+                transform,
+            },
         )?;
 
         if !self.edge_indexes.insert(EdgeIndex {
@@ -184,7 +313,7 @@ impl Dag {
 
     /// Adds another whole `Dag` to `self`. Optionally under a namespace `ns`.
     pub fn merge(&mut self, ns: Option<u16>, other: Dag) {
-        let (other_nodes, _) = other.graph.into_graph().into_nodes_edges();
+        let (other_nodes, other_edges) = other.graph.into_graph().into_nodes_edges();
 
         // Insert nodes.
         let mut other_node_index_to_self_node_index = vec![];
@@ -197,16 +326,22 @@ impl Dag {
         }
 
         // Insert edges.
-        for other_edge_index in other.edge_indexes.into_iter() {
-            let self_from_node =
-                other_node_index_to_self_node_index[other_edge_index.from_node.index()];
-            let self_to_node =
-                other_node_index_to_self_node_index[other_edge_index.to_node.index()];
-            self.connect_with_index(
+        for other_edge in other_edges.into_iter() {
+            let self_from_node = other_node_index_to_self_node_index[other_edge.source().index()];
+            let self_to_node = other_node_index_to_self_node_index[other_edge.target().index()];
+            let EdgeType {
+                from,
+                to,
+                projection,
+                transform,
+            } = other_edge.weight;
+            self.add_edge(
                 self_from_node,
-                other_edge_index.output_port,
+                from,
                 self_to_node,
-                other_edge_index.input_port,
+                to,
+                projection,
+                transform,
             )
             .expect("BUG in DAG");
         }
@@ -255,6 +390,21 @@ impl Dag {
         })
     }
 
+    /// Returns the handles of the nodes of type `typ`, in the order they were added.
+    pub fn nodes_by_type(&self, typ: DagNodeType) -> Vec<&NodeHandle> {
+        self.nodes()
+            .filter(|node| {
+                matches!(
+                    (&node.kind, typ),
+                    (NodeKind::Source(_), DagNodeType::Source)
+                        | (NodeKind::Processor(_), DagNodeType::Processor)
+                        | (NodeKind::Sink(_), DagNodeType::Sink)
+                )
+            })
+            .map(|node| &node.handle)
+            .collect()
+    }
+
     /// Returns an iterator over all edge handles.
     pub fn edge_handles(&self) -> Vec<Edge> {
         let get_endpoint = |node_index: daggy::NodeIndex, port_handle| {
@@ -276,6 +426,27 @@ impl Dag {
             .collect()
     }
 
+    /// Returns if a node with `handle` exists.
+    pub fn contains_node(&self, handle: &NodeHandle) -> bool {
+        self.node_lookup_table.contains_key(handle)
+    }
+
+    /// Returns if there's an edge from `from` to `to`.
+    pub fn contains_edge(&self, from: &Endpoint, to: &Endpoint) -> bool {
+        let (Some(from_node), Some(to_node)) = (
+            self.node_lookup_table.get(&from.node),
+            self.node_lookup_table.get(&to.node),
+        ) else {
+            return false;
+        };
+        self.edge_indexes.contains(&EdgeIndex {
+            from_node: *from_node,
+            output_port: from.port,
+            to_node: *to_node,
+            input_port: to.port,
+        })
+    }
+
     /// Finds the node by its handle.
     pub fn node_kind_from_handle(&self, handle: &NodeHandle) -> &NodeKind {
         &self.graph[self.node_index(handle)].kind
@@ -309,6 +480,41 @@ impl Dag {
             .iter(self.graph.graph())
             .map(|node_index| &self.graph[node_index].handle)
     }
+
+    /// Checks that the DAG has a source, and that every node is reachable from a source and leads to a sink.
+    ///
+    /// Returns `ExecutionError::NoSourceNode` if there's no source, as nothing would ever run, and
+    /// `ExecutionError::UnreachableNode` for the first node that isn't connected, which usually means it wasn't wired.
+    pub fn validate(&self) -> Result<(), ExecutionError> {
+        if !self
+            .graph
+            .node_references()
+            .any(|(_, node)| matches!(node.kind, NodeKind::Source(_)))
+        {
+            return Err(ExecutionError::NoSourceNode);
+        }
+        let from_sources = self.reachable(
+            |kind| matches!(kind, NodeKind::Source(_)),
+            Direction::Outgoing,
+        );
+        let to_sinks = self.reachable(
+            |kind| matches!(kind, NodeKind::Sink(_)),
+            Direction::Incoming,
+        );
+        for (node_index, node) in self.graph.node_references() {
+            if !from_sources.contains(&node_index) || !to_sinks.contains(&node_index) {
+                return Err(ExecutionError::UnreachableNode {
+                    node: node.handle.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns a machine-readable description of every node's handle, type, ports and the schemas on those ports.
+    pub fn describe(&self) -> Result<Value, ExecutionError> {
+        dag_schemas::describe(self)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -331,6 +537,29 @@ impl Dag {
         node_index
     }
 
+    /// Returns the nodes reachable in `direction` from the nodes whose kind matches `is_start`, including them.
+    fn reachable(
+        &self,
+        is_start: impl Fn(&NodeKind) -> bool,
+        direction: Direction,
+    ) -> HashSet<daggy::NodeIndex> {
+        let mut stack = self
+            .graph
+            .node_references()
+            .filter(|(_, node)| is_start(&node.kind))
+            .map(|(node_index, _)| node_index)
+            .collect::<Vec<_>>();
+        let mut reached = stack.iter().copied().collect::<HashSet<_>>();
+        while let Some(node_index) = stack.pop() {
+            for neighbor in self.graph.graph().neighbors_directed(node_index, direction) {
+                if reached.insert(neighbor) {
+                    stack.push(neighbor);
+                }
+            }
+        }
+        reached
+    }
+
     fn node_index(&self, node_handle: &NodeHandle) -> daggy::NodeIndex {
         *self
             .node_lookup_table
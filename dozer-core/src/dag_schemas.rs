@@ -1,13 +1,21 @@
 use crate::errors::ExecutionError;
 use crate::{Dag, EdgeHavePorts, NodeKind};
 
-use crate::node::{OutputPortType, PortHandle};
+use crate::edge_transform::EdgeTransform;
+use crate::node::{Compression, OutputPortType, PortHandle};
+use crate::projection::FieldProjection;
+use crate::transport::EdgeTransport;
 use daggy::petgraph::graph::EdgeReference;
 use daggy::petgraph::visit::{EdgeRef, IntoEdges, IntoEdgesDirected, IntoNodeReferences, Topo};
 use daggy::petgraph::Direction;
 use daggy::{NodeIndex, Walker};
+use dozer_storage::errors::StorageError;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::errors::types::TypeError;
 use dozer_types::log::{error, info};
+use dozer_types::node::NodeHandle;
 use dozer_types::serde::{Deserialize, Serialize};
+use dozer_types::serde_json::{json, Value};
 use dozer_types::types::Schema;
 use std::collections::HashMap;
 use std::fmt::Debug;
@@ -20,8 +28,19 @@ use super::{EdgeType as DagEdgeType, NodeType};
 pub struct EdgeType {
     pub output_port: PortHandle,
     pub input_port: PortHandle,
+    /// Schema of the output port.
     pub schema: Schema,
     pub edge_kind: EdgeKind,
+    /// Applied to records sent through this edge, if any.
+    pub projection: Option<FieldProjection>,
+    /// Schema the input port receives, `schema` projected by `projection`.
+    pub input_schema: Schema,
+    /// Applied to operations sent through this edge, before `projection`, if any.
+    #[serde(skip)]
+    pub transform: Option<EdgeTransform>,
+    /// Creates the channels of this edge.
+    #[serde(skip)]
+    pub transport: EdgeTransport,
 }
 
 impl EdgeType {
@@ -34,8 +53,12 @@ impl EdgeType {
         Self {
             output_port,
             input_port,
+            input_schema: schema.clone(),
             schema,
             edge_kind,
+            projection: None,
+            transform: None,
+            transport: Default::default(),
         }
     }
 }
@@ -46,12 +69,16 @@ pub enum EdgeKind {
     FromSource {
         port_type: OutputPortType,
         port_name: String,
+        compression: Option<Compression>,
     },
     FromProcessor,
 }
 
 pub trait EdgeHaveSchema: EdgeHavePorts {
+    /// Schema of the output port.
     fn schema(&self) -> &Schema;
+    /// Schema of the input port.
+    fn input_schema(&self) -> &Schema;
 }
 
 impl EdgeHavePorts for EdgeType {
@@ -68,6 +95,10 @@ impl EdgeHaveSchema for EdgeType {
     fn schema(&self) -> &Schema {
         &self.schema
     }
+
+    fn input_schema(&self) -> &Schema {
+        &self.input_schema
+    }
 }
 
 #[derive(Debug)]
@@ -90,6 +121,7 @@ impl DagSchemas {
     /// Validate and populate the schemas, the resultant DAG will have the exact same structure as the input DAG,
     /// with validated schema information on the edges.
     pub fn new(dag: Dag) -> Result<Self, ExecutionError> {
+        dag.validate()?;
         validate_connectivity(&dag);
 
         match populate_schemas(dag.into_graph()) {
@@ -116,7 +148,7 @@ pub trait DagHaveSchemas {
 
         for edge in self.graph().edges_directed(node_index, Direction::Incoming) {
             let edge = edge.weight();
-            let schema = edge.schema();
+            let schema = edge.input_schema();
             schemas.insert(edge.input_port(), schema.clone());
         }
 
@@ -191,9 +223,22 @@ fn validate_connectivity(dag: &Dag) {
 fn populate_schemas(
     dag: daggy::Dag<NodeType, DagEdgeType>,
 ) -> Result<daggy::Dag<NodeType, EdgeType>, ExecutionError> {
+    let mut edges = resolve_edges(&dag, true)?;
+
+    Ok(dag.map_owned(
+        |_, node| node,
+        |edge, _| edges[edge.index()].take().expect("We traversed every edge"),
+    ))
+}
+
+/// Resolves the schema of every edge, indexed by edge index. Sinks are only prepared if `prepare_sinks` is set.
+fn resolve_edges(
+    dag: &daggy::Dag<NodeType, DagEdgeType>,
+    prepare_sinks: bool,
+) -> Result<Vec<Option<EdgeType>>, ExecutionError> {
     let mut edges = vec![None; dag.graph().edge_count()];
 
-    for node_index in Topo::new(&dag).iter(&dag) {
+    for node_index in Topo::new(dag).iter(dag) {
         let node = &dag.graph()[node_index];
 
         match &node.kind {
@@ -202,77 +247,193 @@ fn populate_schemas(
 
                 for edge in dag.graph().edges(node_index) {
                     let port = edge.weight().from;
-                    let port_type = find_output_port_type(&ports, edge);
+                    let port_def = find_output_port(&ports, edge);
                     let port_name = source.get_output_port_name(&port);
                     let schema = source
                         .get_output_schema(&port)
                         .map_err(ExecutionError::Factory)?;
+                    // Stateful ports key their record store by the schema's primary key.
+                    if port_def.typ.is_stateful() && schema.primary_index.is_empty() {
+                        return Err(StorageError::NoPrimaryKey.into());
+                    }
+                    if let OutputPortType::StatefulWithVersionedLookup { max_versions: 0 } =
+                        port_def.typ
+                    {
+                        return Err(ExecutionError::NoVersionsKept {
+                            node: node.handle.clone(),
+                            port,
+                        });
+                    }
                     create_edge(
+                        dag,
                         &mut edges,
                         edge,
                         EdgeKind::FromSource {
-                            port_type,
+                            port_type: port_def.typ,
                             port_name,
+                            compression: port_def.options.compression,
                         },
                         schema,
-                    );
+                        port_def.options.transport.clone(),
+                    )?;
                 }
             }
 
             NodeKind::Processor(processor) => {
                 let input_schemas =
-                    validate_input_schemas(&dag, &edges, node_index, processor.get_input_ports())?;
+                    validate_input_schemas(dag, &edges, node_index, processor.get_input_ports())?;
 
                 for edge in dag.graph().edges(node_index) {
                     let schema = processor
                         .get_output_schema(&edge.weight().from, &input_schemas)
-                        .map_err(ExecutionError::Factory)?;
-                    create_edge(&mut edges, edge, EdgeKind::FromProcessor, schema);
+                        .map_err(|e| output_schema_error(&node.handle, e))?;
+                    create_edge(
+                        dag,
+                        &mut edges,
+                        edge,
+                        EdgeKind::FromProcessor,
+                        schema,
+                        Default::default(),
+                    )?;
                 }
             }
 
             NodeKind::Sink(sink) => {
                 let input_schemas =
-                    validate_input_schemas(&dag, &edges, node_index, sink.get_input_ports())?;
-                sink.prepare(input_schemas)
-                    .map_err(ExecutionError::Factory)?;
+                    validate_input_schemas(dag, &edges, node_index, sink.get_input_ports())?;
+                if prepare_sinks {
+                    sink.prepare(input_schemas)
+                        .map_err(ExecutionError::Factory)?;
+                }
             }
         }
     }
 
-    Ok(dag.map_owned(
-        |_, node| node,
-        |edge, _| edges[edge.index()].take().expect("We traversed every edge"),
-    ))
+    Ok(edges)
+}
+
+/// Describes every node's handle, type, ports and the schemas resolved on those ports as JSON.
+///
+/// Unlike `DagSchemas::new`, this doesn't validate connectivity or prepare sinks.
+pub(crate) fn describe(dag: &Dag) -> Result<Value, ExecutionError> {
+    let graph = dag.graph();
+    let edges = resolve_edges(graph, false)?;
+    let input_schema = |edge: EdgeReference<DagEdgeType>| {
+        edges[edge.id().index()]
+            .as_ref()
+            .map(|edge| &edge.input_schema)
+    };
+    let output_schema = |edge: EdgeReference<DagEdgeType>| {
+        edges[edge.id().index()].as_ref().map(|edge| &edge.schema)
+    };
+
+    let mut nodes = vec![];
+    for (node_index, node) in graph.node_references() {
+        let (typ, input_ports, output_ports) = match &node.kind {
+            NodeKind::Source(source) => (
+                "source",
+                vec![],
+                source
+                    .get_output_ports()
+                    .iter()
+                    .map(|port| port.handle)
+                    .collect(),
+            ),
+            NodeKind::Processor(processor) => (
+                "processor",
+                processor.get_input_ports(),
+                processor.get_output_ports(),
+            ),
+            NodeKind::Sink(sink) => ("sink", sink.get_input_ports(), vec![]),
+        };
+
+        let input_ports = input_ports
+            .into_iter()
+            .map(|port| {
+                let schema = graph
+                    .graph()
+                    .edges_directed(node_index, Direction::Incoming)
+                    .find(|edge| edge.weight().to == port)
+                    .and_then(input_schema);
+                json!({ "port": port, "schema": schema })
+            })
+            .collect::<Vec<_>>();
+        let output_ports = output_ports
+            .into_iter()
+            .map(|port| {
+                let schema = graph
+                    .graph()
+                    .edges(node_index)
+                    .find(|edge| edge.weight().from == port)
+                    .and_then(output_schema);
+                json!({ "port": port, "schema": schema })
+            })
+            .collect::<Vec<_>>();
+
+        nodes.push(json!({
+            "handle": node.handle,
+            "type": typ,
+            "input_ports": input_ports,
+            "output_ports": output_ports,
+        }));
+    }
+
+    Ok(json!({ "nodes": nodes }))
 }
 
-fn find_output_port_type(
-    ports: &[OutputPortDef],
+fn find_output_port<'a>(
+    ports: &'a [OutputPortDef],
     edge: EdgeReference<DagEdgeType>,
-) -> OutputPortType {
+) -> &'a OutputPortDef {
     let handle = edge.weight().from;
     for port in ports {
         if port.handle == handle {
-            return port.typ;
+            return port;
         }
     }
     panic!("BUG: port {handle} not found")
 }
 
 fn create_edge(
+    dag: &daggy::Dag<NodeType, DagEdgeType>,
     edges: &mut [Option<EdgeType>],
     edge: EdgeReference<DagEdgeType>,
     edge_kind: EdgeKind,
     schema: Schema,
-) {
+    transport: EdgeTransport,
+) -> Result<(), ExecutionError> {
     let edge_ref = &mut edges[edge.id().index()];
     debug_assert!(edge_ref.is_none());
-    *edge_ref = Some(EdgeType::new(
-        edge.weight().from,
-        edge.weight().to,
-        schema,
-        edge_kind,
-    ));
+    let mut edge_type = EdgeType::new(edge.weight().from, edge.weight().to, schema, edge_kind);
+    if let Some(projection) = &edge.weight().projection {
+        edge_type.input_schema = projection
+            .project_schema(&edge_type.schema)
+            .ok_or_else(|| ExecutionError::InvalidFieldProjection {
+                node: dag.graph()[edge.target()].handle.clone(),
+                port: edge.weight().to,
+                indexes: projection.indexes().to_vec(),
+                num_fields: edge_type.schema.fields.len(),
+            })?;
+        edge_type.projection = Some(projection.clone());
+    }
+    edge_type.transform = edge.weight().transform.clone();
+    edge_type.transport = transport;
+    *edge_ref = Some(edge_type);
+    Ok(())
+}
+
+/// Reports a field the processor looked up by name in its input schemas, but didn't find, as `MissingField`.
+fn output_schema_error(node: &NodeHandle, e: BoxedError) -> ExecutionError {
+    match e.downcast::<TypeError>() {
+        Ok(e) => match *e {
+            TypeError::InvalidFieldName(field) => ExecutionError::MissingField {
+                node: node.clone(),
+                field,
+            },
+            e => ExecutionError::Factory(Box::new(e)),
+        },
+        Err(e) => ExecutionError::Factory(e),
+    }
 }
 
 fn validate_input_schemas(
@@ -292,7 +453,7 @@ fn validate_input_schemas(
         );
 
         if input_schemas
-            .insert(port_handle, edge.schema.clone())
+            .insert(port_handle, edge.input_schema.clone())
             .is_some()
         {
             return Err(ExecutionError::DuplicateInput {
@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use dozer_storage::errors::StorageError;
+use dozer_storage::{KeyOrder, RocksdbMap, RocksdbMapOptions, WalSync};
+use dozer_types::bincode;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::models::ingestion_types::IngestionMessage;
+use dozer_types::node::{NodeHandle, OpIdentifier};
+use dozer_types::serde::{Deserialize, Serialize};
+use dozer_types::thiserror::{self, Error};
+use dozer_types::types::{Operation, Schema};
+
+use crate::channels::SourceChannelForwarder;
+use crate::errors::ExecutionError;
+use crate::node::{OutputPortDef, OutputPortType, PortHandle, Source, SourceFactory, SourceState};
+use crate::{Dag, Endpoint, DEFAULT_PORT_HANDLE};
+
+/// What the executor does with an operation that a processor or sink fails to process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Log the error, counting it towards `ExecutorOptions::error_threshold`.
+    #[default]
+    Report,
+    /// Persist the operation and its error in the [`DeadLetterStore`] at `path`, to inspect and replay later.
+    ///
+    /// Persisted operations don't count towards the error threshold. Failing to persist one does.
+    DeadLetterStore { path: PathBuf },
+}
+
+#[derive(Debug, Error)]
+pub enum DeadLetterError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+    #[error("Cannot encode dead letter: {0}")]
+    Encode(#[source] bincode::Error),
+    #[error("Corrupted dead letter {0}: {1}")]
+    Corrupted(u64, #[source] bincode::Error),
+    #[error("No dead letters for port {port} of {node}")]
+    NothingToReplay { node: NodeHandle, port: PortHandle },
+}
+
+/// An operation that failed at an input port of a processor or sink.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+pub struct DeadLetter {
+    pub node: NodeHandle,
+    pub port: PortHandle,
+    pub operation: Operation,
+    pub error: String,
+    /// When the operation failed.
+    pub timestamp: SystemTime,
+    /// The schema of the port, so the operation can be replayed without the original DAG.
+    pub schema: Schema,
+}
+
+/// Dead letters kept in RocksDB, in the order they failed.
+///
+/// Every insert is synced to disk before the executor moves on to the next operation.
+#[derive(Debug)]
+pub struct DeadLetterStore {
+    letters: RocksdbMap<u64, Vec<u8>>,
+    next_id: AtomicU64,
+}
+
+impl DeadLetterStore {
+    /// Opens the store at `path`, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<Self, DeadLetterError> {
+        let letters = RocksdbMap::create_with_options(
+            path,
+            Default::default(),
+            RocksdbMapOptions {
+                key_order: KeyOrder::UnsignedInteger,
+                wal_sync: WalSync::EverySync,
+                ..Default::default()
+            },
+        )?;
+        let next_id = match letters.iter().last() {
+            Some(entry) => entry?.0 + 1,
+            None => 0,
+        };
+        Ok(Self {
+            letters,
+            next_id: AtomicU64::new(next_id),
+        })
+    }
+
+    /// Persists `letter`, returning its id. Ids increase in insertion order.
+    pub fn insert(&self, letter: &DeadLetter) -> Result<u64, DeadLetterError> {
+        let data = bincode::serialize(letter).map_err(DeadLetterError::Encode)?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.letters.insert(&id, &data)?;
+        Ok(id)
+    }
+
+    /// Removes the dead letter `id`, like after it's been replayed successfully.
+    pub fn remove(&self, id: u64) -> Result<(), DeadLetterError> {
+        self.letters.remove(&id)?;
+        Ok(())
+    }
+
+    /// Iterates all dead letters with their ids, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(u64, DeadLetter), DeadLetterError>> + '_ {
+        self.letters.iter().map(|entry| {
+            let (id, data) = entry?;
+            let letter =
+                bincode::deserialize(&data).map_err(|e| DeadLetterError::Corrupted(id, e))?;
+            Ok((id, letter))
+        })
+    }
+
+    /// Adds a source to `dag` that replays the operations that failed at `endpoint`, in the order they failed.
+    ///
+    /// The source quits once it has sent them. Their ids are the source's offsets,
+    /// so a run restarted from a checkpoint only replays the rest. Returns the source's handle.
+    pub fn replay_into(
+        &self,
+        dag: &mut Dag,
+        endpoint: Endpoint,
+    ) -> Result<NodeHandle, ExecutionError> {
+        let mut schema = None;
+        let mut operations = vec![];
+        for entry in self.iter() {
+            let (id, letter) = entry?;
+            if letter.node == endpoint.node && letter.port == endpoint.port {
+                schema = Some(letter.schema);
+                operations.push((id, letter.operation));
+            }
+        }
+        let Some(schema) = schema else {
+            return Err(DeadLetterError::NothingToReplay {
+                node: endpoint.node,
+                port: endpoint.port,
+            }
+            .into());
+        };
+
+        let handle = NodeHandle::new(
+            None,
+            format!("dead_letters_{}_{}", endpoint.node, endpoint.port),
+        );
+        dag.add_source(
+            handle.clone(),
+            Box::new(DeadLetterReplaySourceFactory { schema, operations }),
+        );
+        dag.connect(Endpoint::new(handle.clone(), DEFAULT_PORT_HANDLE), endpoint)?;
+        Ok(handle)
+    }
+}
+
+/// Where the executor persists dead letters, with the schema of every input port.
+#[derive(Debug)]
+pub(crate) struct DeadLetterSink {
+    pub store: DeadLetterStore,
+    pub schemas: HashMap<(NodeHandle, PortHandle), Schema>,
+}
+
+#[derive(Debug)]
+struct DeadLetterReplaySourceFactory {
+    schema: Schema,
+    operations: Vec<(u64, Operation)>,
+}
+
+impl SourceFactory for DeadLetterReplaySourceFactory {
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        Ok(self.schema.clone())
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "dead_letters".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            DEFAULT_PORT_HANDLE,
+            OutputPortType::Stateless,
+        )]
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(DeadLetterReplaySource {
+            operations: self.operations.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct DeadLetterReplaySource {
+    operations: Vec<(u64, Operation)>,
+}
+
+impl Source for DeadLetterReplaySource {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        let checkpoint = last_checkpoint.get(&DEFAULT_PORT_HANDLE).copied().flatten();
+        for (id, op) in &self.operations {
+            let id = OpIdentifier::new(*id, 0);
+            if checkpoint.map_or(false, |checkpoint| id <= checkpoint) {
+                continue;
+            }
+            fw.send(
+                IngestionMessage::OperationEvent {
+                    table_index: 0,
+                    op: op.clone(),
+                    id: Some(id),
+                },
+                DEFAULT_PORT_HANDLE,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+    use dozer_types::parking_lot::Mutex;
+    use dozer_types::types::{Field, Record};
+    use tempdir::TempDir;
+
+    use crate::executor::ExecutorOptions;
+    use crate::tests::processors::RecordingProcessorFactory;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, SinkRecording, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    /// Fails inserts of every tenth key.
+    fn reject_every_tenth_key(new: Record) -> Result<Record, BoxedError> {
+        if matches!(&new.values[0], Field::String(key) if key.ends_with('0')) {
+            return Err(format!("rejected {:?}", new.values[0]).into());
+        }
+        Ok(new)
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_dead_letter_store() {
+        let count: u64 = 100;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        let dag = |source_count, rejecting| -> (Dag, Arc<Mutex<SinkRecording>>) {
+            let mut processor = RecordingProcessorFactory::new();
+            if rejecting {
+                processor = processor.map_inserts(reject_every_tenth_key);
+            }
+            let sink = RecordingSinkFactory::new().record_ops();
+            let recording = sink.recording();
+            let dag = DagBuilder::new()
+                .source(
+                    source_handle.clone(),
+                    GeneratorSourceFactory::new(
+                        source_count,
+                        Arc::new(AtomicBool::new(false)),
+                        false,
+                    ),
+                )
+                .processor(proc_handle.clone(), processor)
+                .sink(sink_handle.clone(), sink)
+                .edge(
+                    &source_handle,
+                    GENERATOR_SOURCE_OUTPUT_PORT,
+                    &proc_handle,
+                    DEFAULT_PORT_HANDLE,
+                )
+                .edge(
+                    &proc_handle,
+                    DEFAULT_PORT_HANDLE,
+                    &sink_handle,
+                    RECORDING_SINK_INPUT_PORT,
+                )
+                .build()
+                .unwrap();
+            (dag, recording)
+        };
+        let dead_letter_dir = TempDir::new("test_run_dag_with_dead_letter_store").unwrap();
+        let options = || ExecutorOptions {
+            // Dead letters don't count as errors.
+            error_threshold: Some(0),
+            error_policy: ErrorPolicy::DeadLetterStore {
+                path: dead_letter_dir.path().to_path_buf(),
+            },
+            ..Default::default()
+        };
+
+        let (rejecting_dag, recording) = dag(count, true);
+        run_dag(rejecting_dag, options()).await.unwrap();
+        let mut state = recording.lock().materialize();
+        assert_eq!(state.len(), 90);
+
+        let store = DeadLetterStore::open(dead_letter_dir.path()).unwrap();
+        let letters = store.iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(letters.len(), 10);
+        for (_, letter) in &letters {
+            assert_eq!(letter.node, proc_handle);
+            assert_eq!(letter.port, DEFAULT_PORT_HANDLE);
+            let Operation::Insert { new } = &letter.operation else {
+                panic!("unexpected dead letter {letter:?}");
+            };
+            assert_eq!(letter.error, format!("rejected {:?}", new.values[0]));
+        }
+
+        // Replay them on their own, now that the processor accepts them.
+        let (mut replay_dag, recording) = dag(0, false);
+        store
+            .replay_into(
+                &mut replay_dag,
+                Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+            )
+            .unwrap();
+        drop(store);
+        run_dag(replay_dag, options()).await.unwrap();
+        let replayed = recording.lock().materialize();
+        assert_eq!(replayed.len(), 10);
+        state.extend(replayed);
+        assert_eq!(state.len() as u64, count);
+        for n in (10..=count).step_by(10) {
+            assert!(state.contains_key(&Field::String(format!("key_{n}"))));
+        }
+    }
+}
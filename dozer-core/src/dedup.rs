@@ -0,0 +1,371 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+use dozer_log::storage::Queue;
+use dozer_recordstore::{ProcessorRecord, ProcessorRecordStore, StoreRecord};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::log::debug;
+use dozer_types::node::{OpIdentifier, SourceStates};
+use dozer_types::types::Schema;
+
+use crate::epoch::Epoch;
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::node::{CommitDecision, PortHandle, Sink, SinkFactory, SinkPartitioning};
+use crate::partition::PartitionHasher;
+
+/// Wraps a sink factory, dropping inserts of primary keys its sinks have already applied.
+///
+/// A key is applied by an insert or the new record of an update, and forgotten when it's deleted or updated away,
+/// so it can be inserted again. Up to `capacity` of the most recently applied keys are remembered, or all of them if `None`.
+/// Keys aren't checkpointed, so duplicates are only dropped within a run.
+/// Fails to build if an input port's schema has no primary key.
+#[derive(Debug)]
+pub struct DedupSinkFactory {
+    inner: Box<dyn SinkFactory>,
+    capacity: Option<usize>,
+}
+
+impl DedupSinkFactory {
+    pub fn new(inner: Box<dyn SinkFactory>, capacity: Option<usize>) -> Self {
+        Self { inner, capacity }
+    }
+}
+
+impl SinkFactory for DedupSinkFactory {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.inner.get_input_ports()
+    }
+
+    fn prepare(&self, input_schemas: HashMap<PortHandle, Schema>) -> Result<(), BoxedError> {
+        self.inner.prepare(input_schemas)
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Sink>, BoxedError> {
+        let mut primary_indexes = HashMap::new();
+        for (port, schema) in &input_schemas {
+            if schema.primary_index.is_empty() {
+                return Err(format!(
+                    "Cannot deduplicate by primary key, port {port} has no primary key"
+                )
+                .into());
+            }
+            primary_indexes.insert(*port, schema.primary_index.clone());
+        }
+        Ok(Box::new(DedupSink::new(
+            self.inner.build(input_schemas)?,
+            primary_indexes,
+            self.capacity,
+        )))
+    }
+
+    fn partition_by(&self) -> Option<SinkPartitioning> {
+        self.inner.partition_by()
+    }
+
+    fn partition_hasher(&self) -> PartitionHasher {
+        self.inner.partition_hasher()
+    }
+}
+
+#[derive(Debug)]
+struct DedupSink {
+    inner: Box<dyn Sink>,
+    primary_indexes: HashMap<PortHandle, Vec<usize>>,
+    applied: AppliedKeys,
+}
+
+impl DedupSink {
+    fn new(
+        inner: Box<dyn Sink>,
+        primary_indexes: HashMap<PortHandle, Vec<usize>>,
+        capacity: Option<usize>,
+    ) -> Self {
+        Self {
+            inner,
+            primary_indexes,
+            applied: AppliedKeys::new(capacity),
+        }
+    }
+
+    /// Returns false if `op` is an insert of an applied key, and should be dropped. Otherwise records the keys it applies.
+    fn admit(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: &ProcessorOperation,
+    ) -> Result<bool, BoxedError> {
+        let primary_index = &self.primary_indexes[&from_port];
+        let key = |record: &ProcessorRecord| -> Result<Key, BoxedError> {
+            Ok((
+                from_port,
+                record_store.load_record(record)?.get_key(primary_index),
+            ))
+        };
+        match op {
+            ProcessorOperation::Insert { new } => {
+                let new = key(new)?;
+                if self.applied.touch(&new) {
+                    debug!("Dropping insert of primary key {:?} already applied", new.1);
+                    return Ok(false);
+                }
+                self.applied.insert(new);
+            }
+            ProcessorOperation::Delete { old } => self.applied.remove(&key(old)?),
+            ProcessorOperation::Update { old, new } => {
+                self.applied.remove(&key(old)?);
+                self.applied.insert(key(new)?);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Sink for DedupSink {
+    fn init(&mut self) -> Result<(), BoxedError> {
+        self.inner.init()
+    }
+
+    fn commit(&mut self, epoch_details: &Epoch) -> Result<(), BoxedError> {
+        self.inner.commit(epoch_details)
+    }
+
+    fn commit_with_decision(
+        &mut self,
+        epoch_details: &Epoch,
+    ) -> Result<CommitDecision, BoxedError> {
+        self.inner.commit_with_decision(epoch_details)
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<(), BoxedError> {
+        if !self.admit(from_port, record_store, &op)? {
+            return Ok(());
+        }
+        self.inner.process(from_port, record_store, op)
+    }
+
+    fn process_with_context(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        context: &OperationContext,
+    ) -> Result<(), BoxedError> {
+        if !self.admit(from_port, record_store, &op)? {
+            return Ok(());
+        }
+        self.inner
+            .process_with_context(from_port, record_store, op, context)
+    }
+
+    fn persist(&mut self, queue: &Queue) -> Result<(), BoxedError> {
+        self.inner.persist(queue)
+    }
+
+    fn on_source_snapshotting_done(&mut self, connection_name: String) -> Result<(), BoxedError> {
+        self.inner.on_source_snapshotting_done(connection_name)
+    }
+
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), BoxedError> {
+        self.inner.on_watermark(watermark)
+    }
+
+    fn applied_source_states(&self) -> Option<SourceStates> {
+        self.inner.applied_source_states()
+    }
+
+    fn applied_through(&self) -> Option<OpIdentifier> {
+        self.inner.applied_through()
+    }
+
+    fn on_end_of_stream(&mut self) -> Result<(), BoxedError> {
+        self.inner.on_end_of_stream()
+    }
+}
+
+type Key = (PortHandle, Vec<u8>);
+
+/// Applied keys, forgetting the least recently applied one when full.
+#[derive(Debug)]
+struct AppliedKeys {
+    capacity: Option<usize>,
+    /// The last use of every key.
+    keys: HashMap<Key, u64>,
+    /// Keys by last use, least recent first.
+    uses: BTreeMap<u64, Key>,
+    next_use: u64,
+}
+
+impl AppliedKeys {
+    fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            keys: HashMap::new(),
+            uses: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    /// Returns if `key` is applied, making it the most recently used.
+    fn touch(&mut self, key: &Key) -> bool {
+        let Some(last_use) = self.keys.get_mut(key) else {
+            return false;
+        };
+        let key = self.uses.remove(&*last_use).expect("Every key has a use");
+        *last_use = self.next_use;
+        self.uses.insert(self.next_use, key);
+        self.next_use += 1;
+        true
+    }
+
+    fn insert(&mut self, key: Key) {
+        if self.capacity == Some(0) {
+            return;
+        }
+        self.remove(&key);
+        if Some(self.keys.len()) == self.capacity {
+            let (_, evicted) = self.uses.pop_first().expect("The set is full");
+            self.keys.remove(&evicted);
+        }
+        self.uses.insert(self.next_use, key.clone());
+        self.keys.insert(key, self.next_use);
+        self.next_use += 1;
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(last_use) = self.keys.remove(key) {
+            self.uses.remove(&last_use);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dozer_types::parking_lot::Mutex;
+    use dozer_types::types::{Field, Operation, Record};
+
+    use super::*;
+
+    /// Records the operations it applies.
+    #[derive(Debug)]
+    struct RecordingSink {
+        applied: Arc<Mutex<Vec<Operation>>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn commit(&mut self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn process(
+            &mut self,
+            _from_port: PortHandle,
+            record_store: &ProcessorRecordStore,
+            op: ProcessorOperation,
+        ) -> Result<(), BoxedError> {
+            self.applied.lock().push(op.load(record_store)?);
+            Ok(())
+        }
+
+        fn persist(&mut self, _queue: &Queue) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn on_source_snapshotting_done(
+            &mut self,
+            _connection_name: String,
+        ) -> Result<(), BoxedError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dedup_sink_applies_every_key_once() {
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let record = |key: u64, value: &str| Record::new(vec![Field::UInt(key), value.into()]);
+        let insert = |key, value| Operation::Insert {
+            new: record(key, value),
+        };
+        let run = |capacity, ops: Vec<Operation>| {
+            let applied = Arc::new(Mutex::new(vec![]));
+            let mut sink = DedupSink::new(
+                Box::new(RecordingSink {
+                    applied: applied.clone(),
+                }),
+                HashMap::from([(0, vec![0])]),
+                capacity,
+            );
+            for op in ops {
+                let op = ProcessorOperation::new(&op, &record_store).unwrap();
+                sink.process(0, &record_store, op).unwrap();
+            }
+            let applied = applied.lock().clone();
+            applied
+        };
+
+        let update = Operation::Update {
+            old: record(1, "a"),
+            new: record(1, "b"),
+        };
+        let rekey = Operation::Update {
+            old: record(1, "b"),
+            new: record(3, "b"),
+        };
+        let delete = Operation::Delete {
+            old: record(2, "a"),
+        };
+        let applied = run(
+            None,
+            vec![
+                insert(1, "a"),
+                insert(1, "a"),
+                insert(2, "a"),
+                update.clone(),
+                // Still applied, by the update.
+                insert(1, "c"),
+                delete.clone(),
+                // Deleted, so applied again.
+                insert(2, "c"),
+                rekey.clone(),
+                insert(3, "c"),
+                insert(1, "c"),
+            ],
+        );
+        assert_eq!(
+            applied,
+            vec![
+                insert(1, "a"),
+                insert(2, "a"),
+                update,
+                delete,
+                insert(2, "c"),
+                rekey,
+                insert(1, "c"),
+            ]
+        );
+
+        // Remembering one key, key 1 is forgotten once key 2 is applied.
+        let applied = run(
+            Some(1),
+            vec![
+                insert(1, "a"),
+                insert(1, "a"),
+                insert(2, "a"),
+                insert(1, "b"),
+            ],
+        );
+        assert_eq!(
+            applied,
+            vec![insert(1, "a"), insert(2, "a"), insert(1, "b")]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use dozer_recordstore::ProcessorRecordStore;
+use dozer_types::types::Operation;
+
+use crate::errors::ExecutionError;
+use crate::executor_operation::ProcessorOperation;
+
+type FilterFn = Arc<dyn Fn(&Operation) -> bool + Send + Sync>;
+type MapFn = Arc<dyn Fn(Operation) -> Operation + Send + Sync>;
+
+#[derive(Clone)]
+enum Step {
+    Filter(FilterFn),
+    Map(MapFn),
+}
+
+/// Filters and maps applied to the operations sent through an edge, in the order they were added.
+///
+/// Saves adding a processor for trivial transforms. Maps must keep the records in the schema of the edge's output port.
+/// Transforms are applied before the edge's projection, if any.
+#[derive(Clone, Default)]
+pub struct EdgeTransform {
+    steps: Vec<Step>,
+}
+
+impl EdgeTransform {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only sends the operations for which `filter` returns `true`.
+    pub fn filter(mut self, filter: impl Fn(&Operation) -> bool + Send + Sync + 'static) -> Self {
+        self.steps.push(Step::Filter(Arc::new(filter)));
+        self
+    }
+
+    /// Sends what `map` returns instead of the operation.
+    pub fn map(mut self, map: impl Fn(Operation) -> Operation + Send + Sync + 'static) -> Self {
+        self.steps.push(Step::Map(Arc::new(map)));
+        self
+    }
+
+    /// Returns the transformed operation, or `None` if it's filtered out.
+    pub(crate) fn apply(
+        &self,
+        op: ProcessorOperation,
+        record_store: &ProcessorRecordStore,
+    ) -> Result<Option<ProcessorOperation>, ExecutionError> {
+        if self.steps.is_empty() {
+            return Ok(Some(op));
+        }
+        let mut op = op.load(record_store)?;
+        for step in &self.steps {
+            match step {
+                Step::Filter(filter) => {
+                    if !filter(&op) {
+                        return Ok(None);
+                    }
+                }
+                Step::Map(map) => op = map(op),
+            }
+        }
+        ProcessorOperation::new(&op, record_store).map(Some)
+    }
+}
+
+impl Debug for EdgeTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EdgeTransform")
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+/// Transforms are closures, so two are only equal if they're clones of each other.
+impl PartialEq for EdgeTransform {
+    fn eq(&self, other: &Self) -> bool {
+        self.steps.len() == other.steps.len()
+            && self
+                .steps
+                .iter()
+                .zip(&other.steps)
+                .all(|steps| match steps {
+                    (Step::Filter(a), Step::Filter(b)) => Arc::ptr_eq(a, b),
+                    (Step::Map(a), Step::Map(b)) => Arc::ptr_eq(a, b),
+                    _ => false,
+                })
+    }
+}
+
+impl Eq for EdgeTransform {}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::Field;
+
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{ThreeFieldSourceFactory, THREE_FIELD_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_with_edge_transform() {
+        let count: u64 = 100;
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let mapped_sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let full_sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        let mapped_sink = RecordingSinkFactory::new().record_ops();
+        let mapped_recording = mapped_sink.recording();
+        let full_sink = RecordingSinkFactory::new().record_ops();
+        let full_recording = full_sink.recording();
+        let double = |op: Operation| {
+            let Operation::Insert { mut new } = op else {
+                panic!("Only inserts are sent");
+            };
+            let Field::UInt(n) = new.values[0] else {
+                panic!("Unexpected field");
+            };
+            new.values[0] = Field::UInt(n * 2);
+            Operation::Insert { new }
+        };
+        let dag = DagBuilder::new()
+            .source(source_handle.clone(), ThreeFieldSourceFactory::new(count))
+            .sink(mapped_sink_handle.clone(), mapped_sink)
+            .sink(full_sink_handle.clone(), full_sink)
+            .edge_with_transform(
+                &source_handle,
+                THREE_FIELD_SOURCE_OUTPUT_PORT,
+                &mapped_sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+                EdgeTransform::new().map(double),
+            )
+            .edge(
+                &source_handle,
+                THREE_FIELD_SOURCE_OUTPUT_PORT,
+                &full_sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let mapped_state = mapped_recording.lock().materialize();
+        let full_state = full_recording.lock().materialize();
+        assert_eq!(mapped_state.len(), count as usize);
+        assert_eq!(full_state.len(), count as usize);
+        for n in 1..count + 1 {
+            let record = ThreeFieldSourceFactory::record(n);
+            assert_eq!(full_state[&Field::UInt(n)], record);
+            let mut doubled = record;
+            doubled.values[0] = Field::UInt(n * 2);
+            assert_eq!(mapped_state[&Field::UInt(n * 2)], doubled);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use dozer_types::parking_lot::Mutex;
+
+/// Tracks flush barrier requests, from the epoch closing them to the sinks committing that epoch.
+#[derive(Debug, Default)]
+pub struct FlushBarrier {
+    state: Mutex<FlushBarrierState>,
+}
+
+#[derive(Debug, Default)]
+struct FlushBarrierState {
+    /// The number of flushes requested so far.
+    num_requested: u64,
+    /// The number of requests covered by a closed epoch, and the id of the last such epoch.
+    flushed: Option<(u64, u64)>,
+    sinks: Vec<SinkProgress>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct SinkProgress {
+    last_committed_epoch: Option<u64>,
+    terminated: bool,
+}
+
+impl FlushBarrier {
+    /// Returns the sink's index for reporting progress.
+    pub fn register_sink(&self) -> usize {
+        let mut state = self.state.lock();
+        state.sinks.push(SinkProgress::default());
+        state.sinks.len() - 1
+    }
+
+    /// Requests a flush, returning the request to pass to `is_flushed`.
+    pub fn request(&self) -> u64 {
+        let mut state = self.state.lock();
+        state.num_requested += 1;
+        state.num_requested
+    }
+
+    /// Returns if a flush is requested but no epoch has been closed for it yet.
+    pub fn is_pending(&self) -> bool {
+        let state = self.state.lock();
+        state.num_requested > state.flushed.map_or(0, |(num_flushed, _)| num_flushed)
+    }
+
+    /// Called when an epoch is closed with a commit, which covers all pending requests.
+    pub fn on_epoch_closed(&self, epoch_id: u64) {
+        let mut state = self.state.lock();
+        state.flushed = Some((state.num_requested, epoch_id));
+    }
+
+    pub fn on_sink_commit(&self, sink_index: usize, epoch_id: u64) {
+        self.state.lock().sinks[sink_index].last_committed_epoch = Some(epoch_id);
+    }
+
+    pub fn on_sink_terminated(&self, sink_index: usize) {
+        self.state.lock().sinks[sink_index].terminated = true;
+    }
+
+    pub fn all_sinks_terminated(&self) -> bool {
+        self.state.lock().sinks.iter().all(|sink| sink.terminated)
+    }
+
+    /// Returns if every sink has committed the epoch covering `request`, or has terminated.
+    pub fn is_flushed(&self, request: u64) -> bool {
+        let state = self.state.lock();
+        let flushed_epoch = match state.flushed {
+            Some((num_flushed, epoch_id)) if num_flushed >= request => Some(epoch_id),
+            _ => None,
+        };
+        state.sinks.iter().all(|sink| {
+            sink.terminated
+                || matches!(
+                    (sink.last_committed_epoch, flushed_epoch),
+                    (Some(committed), Some(flushed)) if committed >= flushed
+                )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::errors::ExecutionError;
+    use crate::executor::{DagExecutor, DeliverySemantics, ExecutorOptions};
+    use crate::tests::processors::RecordingProcessorFactory;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{Dag, DagBuilder, Edge, Endpoint, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[test]
+    fn flush_waits_for_all_sinks() {
+        let barrier = FlushBarrier::default();
+        let sink1 = barrier.register_sink();
+        let sink2 = barrier.register_sink();
+        barrier.on_sink_commit(sink1, 3);
+        barrier.on_sink_commit(sink2, 3);
+
+        let request = barrier.request();
+        assert!(barrier.is_pending());
+        assert!(!barrier.is_flushed(request));
+
+        barrier.on_epoch_closed(4);
+        assert!(!barrier.is_pending());
+        barrier.on_sink_commit(sink1, 4);
+        assert!(!barrier.is_flushed(request));
+        barrier.on_sink_terminated(sink2);
+        assert!(barrier.is_flushed(request));
+
+        // A later request isn't covered by the earlier epoch.
+        let request = barrier.request();
+        assert!(!barrier.is_flushed(request));
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_flush_barrier() {
+        let count: u64 = 100;
+        let source_latch = Arc::new(AtomicBool::new(true));
+        let sent = Arc::new(AtomicU64::new(0));
+        let sink = RecordingSinkFactory::new().record_ops();
+        let recording = sink.recording();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, source_latch.clone(), false)
+                    .with_sent_counter(sent.clone()),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        // Never commit on our own, and only apply operations to the sink on commit.
+        let options = ExecutorOptions {
+            commit_sz: 10_000,
+            commit_time_threshold: Duration::from_secs(3600),
+            delivery: DeliverySemantics::ExactlyOnce,
+            ..Default::default()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let running = Arc::new(AtomicBool::new(true));
+        let join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(running.clone(), Default::default())
+            .await
+            .unwrap();
+
+        while sent.load(Ordering::SeqCst) < count {
+            thread::sleep(Duration::from_millis(10));
+        }
+        join_handle.flush_barrier().unwrap();
+        assert_eq!(recording.lock().materialize().len(), count as usize);
+
+        running.store(false, Ordering::SeqCst);
+        source_latch.store(false, Ordering::SeqCst);
+        join_handle.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_drain_timeout_names_stuck_edge() {
+        let count: u64 = 1_000;
+
+        let mut dag = Dag::new();
+        let release = Arc::new(AtomicBool::new(false));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        // The source quits once it has sent its operations.
+        dag.add_source(
+            source_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(
+                count,
+                Arc::new(AtomicBool::new(false)),
+                false,
+            )),
+        );
+        dag.add_processor(
+            proc_handle.clone(),
+            Box::new(RecordingProcessorFactory::new().block_until(release.clone())),
+        );
+        dag.add_sink(sink_handle.clone(), Box::new(RecordingSinkFactory::new()));
+
+        let stuck_edge = Edge::new(
+            Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+        );
+        dag.connect(stuck_edge.from.clone(), stuck_edge.to.clone())
+            .unwrap();
+        dag.connect(
+            Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+            Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        let options = ExecutorOptions {
+            channel_buffer_sz: 4,
+            drain_timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            join_handle.drain_and_stop(),
+            Err(ExecutionError::DrainTimeout { edge }) if edge == stuck_edge
+        ));
+
+        // Once unstuck, the pipeline finishes draining.
+        release.store(true, Ordering::SeqCst);
+        join_handle.join().unwrap();
+    }
+}
@@ -4,13 +4,14 @@ use dozer_types::node::{NodeHandle, SourceStates, TableState};
 use dozer_types::parking_lot::Mutex;
 use std::collections::HashMap;
 use std::ops::DerefMut;
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Barrier};
 use std::thread::sleep;
 use std::time::{Duration, SystemTime};
 
 use crate::checkpoint::{CheckpointFactory, CheckpointWriter};
 
-use super::EpochCommonInfo;
+use super::{EpochCommonInfo, FlushBarrier};
 
 #[derive(Debug)]
 struct EpochManagerState {
@@ -89,6 +90,19 @@ impl EpochManagerStateKind {
     }
 }
 
+/// Progress of a snapshot requested with [`EpochManager::request_snapshot`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum SnapshotState {
+    #[default]
+    Idle,
+    /// The next epoch to close is committed and persisted.
+    Requested,
+    /// Epoch `epoch_id` was closed for the snapshot, and no epoch closes until [`EpochManager::resume`].
+    ///
+    /// `checkpointed` is false if a source wasn't restartable, so no checkpoint was written.
+    Paused { epoch_id: u64, checkpointed: bool },
+}
+
 #[derive(Debug, Clone)]
 pub struct EpochManagerOptions {
     pub max_num_records_before_persist: usize,
@@ -111,7 +125,11 @@ pub struct EpochManager {
     num_sources: usize,
     checkpoint_factory: Arc<CheckpointFactory>,
     options: EpochManagerOptions,
+    /// If epochs are ever persisted, see `ExecutorOptions::checkpointing`.
+    persists: bool,
     state: Mutex<EpochManagerState>,
+    flush_barrier: FlushBarrier,
+    snapshot: Mutex<SnapshotState>,
 }
 
 #[derive(Debug, Clone)]
@@ -136,14 +154,26 @@ impl EpochManager {
             num_sources,
             checkpoint_factory,
             options,
+            persists: true,
             state: Mutex::new(EpochManagerState {
                 kind: EpochManagerStateKind::new_closing(epoch_id, num_sources),
                 next_record_index_to_persist,
                 last_persisted_epoch_decision_instant: SystemTime::now(),
             }),
+            flush_barrier: Default::default(),
+            snapshot: Default::default(),
         }
     }
 
+    /// Never persists an epoch if `persists` is false, so no checkpoint is written and sinks aren't asked to persist.
+    pub fn set_persists(&mut self, persists: bool) {
+        self.persists = persists;
+    }
+
+    pub fn persists(&self) -> bool {
+        self.persists
+    }
+
     pub fn epoch_id(&self) -> u64 {
         self.state.lock().kind.epoch_id()
     }
@@ -152,6 +182,40 @@ impl EpochManager {
         self.checkpoint_factory.record_store()
     }
 
+    pub fn aborted(&self) -> &Arc<AtomicBool> {
+        self.checkpoint_factory.aborted()
+    }
+
+    pub fn flush_barrier(&self) -> &FlushBarrier {
+        &self.flush_barrier
+    }
+
+    pub fn checkpoint_factory(&self) -> &Arc<CheckpointFactory> {
+        &self.checkpoint_factory
+    }
+
+    /// Makes the next epoch to close commit and write a checkpoint, even if app checkpoints are disabled,
+    /// then keeps later epochs from closing until [`resume`](Self::resume).
+    pub fn request_snapshot(&self) {
+        *self.snapshot.lock() = SnapshotState::Requested;
+    }
+
+    /// Returns the epoch closed for the requested snapshot, and whether it's checkpointed, once it's closed.
+    pub fn snapshot_epoch(&self) -> Option<(u64, bool)> {
+        match *self.snapshot.lock() {
+            SnapshotState::Paused {
+                epoch_id,
+                checkpointed,
+            } => Some((epoch_id, checkpointed)),
+            _ => None,
+        }
+    }
+
+    /// Lets epochs close again after a snapshot, or cancels the requested one.
+    pub fn resume(&self) {
+        *self.snapshot.lock() = SnapshotState::Idle;
+    }
+
     /// Waits for the epoch to close until all sources do so.
     ///
     /// Returns whether the participant should terminate, the epoch id if the source should commit, and the instant when the decision was made.
@@ -167,6 +231,11 @@ impl EpochManager {
         request_commit: bool,
     ) -> ClosedEpoch {
         let barrier = loop {
+            if matches!(*self.snapshot.lock(), SnapshotState::Paused { .. }) {
+                // Sources wait here while a snapshot is taken.
+                sleep(Duration::from_millis(1));
+                continue;
+            }
             let mut state = self.state.lock();
             match &mut state.kind {
                 EpochManagerStateKind::Closing {
@@ -206,10 +275,18 @@ impl EpochManager {
         } = &mut state.kind
         {
             let instant = SystemTime::now();
-            let action = if *should_commit {
+            // A pending flush barrier forces a commit, which then covers it.
+            let flushing = self.flush_barrier.is_pending();
+            // A requested snapshot forces a checkpoint.
+            let mut snapshot = self.snapshot.lock();
+            let snapshotting = *snapshot == SnapshotState::Requested;
+            let action = if *should_commit || flushing || snapshotting {
                 let num_records = self.record_store().num_records();
-                if num_records - state.next_record_index_to_persist
-                    >= self.options.max_num_records_before_persist
+                if !self.persists {
+                    Action::Commit
+                } else if snapshotting
+                    || num_records - state.next_record_index_to_persist
+                        >= self.options.max_num_records_before_persist
                     || instant
                         .duration_since(state.last_persisted_epoch_decision_instant)
                         .unwrap_or(Duration::from_secs(0))
@@ -230,6 +307,17 @@ impl EpochManager {
                 Action::Nothing
             };
 
+            if flushing {
+                self.flush_barrier.on_epoch_closed(*epoch_id);
+            }
+            if snapshotting {
+                *snapshot = SnapshotState::Paused {
+                    epoch_id: *epoch_id,
+                    checkpointed: is_restartable(source_states),
+                };
+            }
+            drop(snapshot);
+
             state.kind = EpochManagerStateKind::Closed {
                 terminating: *should_terminate,
                 action,
@@ -250,8 +338,12 @@ impl EpochManager {
                 num_source_confirmations,
             } => {
                 let common_info = action.should_commit().then(|| {
+                    let snapshotting = matches!(
+                        *self.snapshot.lock(),
+                        SnapshotState::Paused { epoch_id: snapshot_epoch_id, .. } if snapshot_epoch_id == *epoch_id
+                    );
                     let checkpoint_writer = (action.should_persist()
-                        && self.options.enable_app_checkpoints
+                        && (self.options.enable_app_checkpoints || snapshotting)
                         && is_restartable(source_states))
                     .then(|| {
                         Arc::new(CheckpointWriter::new(
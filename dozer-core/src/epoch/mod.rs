@@ -44,8 +44,10 @@ impl Epoch {
     }
 }
 
+mod flush_barrier;
 mod manager;
 
+pub use flush_barrier::FlushBarrier;
 pub use manager::{ClosedEpoch, EpochManager, EpochManagerOptions};
 
 use crate::checkpoint::CheckpointWriter;
@@ -1,8 +1,138 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
+use dozer_recordstore::ProcessorRecordStore;
+use dozer_types::log::warn;
+use dozer_types::node::NodeHandle;
+use dozer_types::parking_lot::Mutex;
 use dozer_types::tracing::error_span;
+use dozer_types::types::{Operation, Record, Schema};
 use dozer_types::{errors::internal::BoxedError, log::error};
 
+use crate::dead_letter::{DeadLetter, DeadLetterSink};
+use crate::errors::ExecutionError;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::PortHandle;
+
+/// How [`ErrorManager`] aggregates repeated errors, set with `ExecutorOptions::error_sampling`.
+#[derive(Clone)]
+pub struct ErrorSamplingOptions {
+    /// How long identical errors of a node are counted before a rollup is emitted.
+    ///
+    /// The first error of every period is logged when it happens. Pending rollups are emitted when the executor finishes.
+    pub interval: Duration,
+    /// Called with every rollup, after it's logged.
+    pub on_rollup: Option<ErrorRollupCallback>,
+}
+
+pub type ErrorRollupCallback = Arc<dyn Fn(&ErrorRollup) + Send + Sync>;
+
+impl Debug for ErrorSamplingOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorSamplingOptions")
+            .field("interval", &self.interval)
+            .field("on_rollup", &self.on_rollup.is_some())
+            .finish()
+    }
+}
+
+impl Default for ErrorSamplingOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            on_rollup: None,
+        }
+    }
+}
+
+/// Identical errors reported by a node during one sampling interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorRollup {
+    pub node: NodeHandle,
+    pub error: String,
+    pub count: u64,
+    pub first: SystemTime,
+    pub last: SystemTime,
+}
+
+#[derive(Debug)]
+struct ErrorSampler {
+    options: ErrorSamplingOptions,
+    windows: Mutex<HashMap<(NodeHandle, String), (ErrorRollup, Instant)>>,
+}
+
+impl ErrorSampler {
+    fn report(&self, node: &NodeHandle, error: String) {
+        let now = SystemTime::now();
+        let mut windows = self.windows.lock();
+        let key = (node.clone(), error);
+        let Some((rollup, started)) = windows.get_mut(&key) else {
+            error!("[{node}] {}", key.1);
+            let rollup = ErrorRollup {
+                node: node.clone(),
+                error: key.1.clone(),
+                count: 1,
+                first: now,
+                last: now,
+            };
+            windows.insert(key, (rollup, Instant::now()));
+            return;
+        };
+        rollup.count += 1;
+        rollup.last = now;
+        if started.elapsed() >= self.options.interval {
+            let (rollup, _) = windows.remove(&key).expect("window exists");
+            drop(windows);
+            self.emit(&rollup);
+        }
+    }
+
+    fn flush(&self) {
+        let windows = std::mem::take(&mut *self.windows.lock());
+        for (rollup, _) in windows.into_values() {
+            self.emit(&rollup);
+        }
+    }
+
+    fn emit(&self, rollup: &ErrorRollup) {
+        warn!(
+            "[{}] {} occurred {} times between {:?} and {:?}",
+            rollup.node, rollup.error, rollup.count, rollup.first, rollup.last
+        );
+        if let Some(on_rollup) = &self.options.on_rollup {
+            on_rollup(rollup);
+        }
+    }
+}
+
+/// Describes how `record` doesn't match `schema`, if it doesn't.
+fn schema_violation(schema: &Schema, record: &Record) -> Option<String> {
+    if record.values.len() != schema.fields.len() {
+        return Some(format!(
+            "{} fields instead of {}",
+            record.values.len(),
+            schema.fields.len()
+        ));
+    }
+    schema
+        .fields
+        .iter()
+        .zip(&record.values)
+        .find_map(|(definition, value)| match value.ty() {
+            None if !definition.nullable => {
+                Some(format!("field {} isn't nullable", definition.name))
+            }
+            Some(typ) if typ != definition.typ => Some(format!(
+                "field {} is {typ:?} instead of {:?}",
+                definition.name, definition.typ
+            )),
+            _ => None,
+        })
+}
+
 /// `ErrorManager` records and counts the number of errors happened.
 ///
 /// It panics when an error threshold is set and reached.
@@ -10,6 +140,9 @@ use dozer_types::{errors::internal::BoxedError, log::error};
 pub struct ErrorManager {
     threshold: Option<u32>,
     count: AtomicU32,
+    dead_letters: Option<DeadLetterSink>,
+    sampler: Option<ErrorSampler>,
+    max_record_bytes: Option<usize>,
 }
 
 impl ErrorManager {
@@ -17,6 +150,9 @@ impl ErrorManager {
         Self {
             threshold: Some(threshold),
             count: AtomicU32::new(0),
+            dead_letters: None,
+            sampler: None,
+            max_record_bytes: None,
         }
     }
 
@@ -24,14 +160,63 @@ impl ErrorManager {
         Self {
             threshold: None,
             count: AtomicU32::new(0),
+            dead_letters: None,
+            sampler: None,
+            max_record_bytes: None,
+        }
+    }
+
+    /// Persists failed operations reported with `report_operation` instead of counting them.
+    pub(crate) fn with_dead_letters(mut self, dead_letters: DeadLetterSink) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+
+    /// Aggregates identical errors reported with `report_operation` into periodic rollups instead of logging each.
+    pub(crate) fn with_sampling(mut self, options: ErrorSamplingOptions) -> Self {
+        self.sampler = Some(ErrorSampler {
+            options,
+            windows: Default::default(),
+        });
+        self
+    }
+
+    /// Makes `reject_oversized` reject operations whose encoding is longer than `max_record_bytes`.
+    pub(crate) fn with_max_record_bytes(mut self, max_record_bytes: usize) -> Self {
+        self.max_record_bytes = Some(max_record_bytes);
+        self
+    }
+
+    /// Emits the rollups of errors that haven't been emitted yet.
+    pub fn flush_rollups(&self) {
+        if let Some(sampler) = &self.sampler {
+            sampler.flush();
         }
     }
 
+    /// If operations passed to `report_operation` are persisted, so callers have to keep a copy of them.
+    pub fn collects_dead_letters(&self) -> bool {
+        self.dead_letters.is_some()
+    }
+
     pub fn report(&self, error: BoxedError) {
         let err_span = error_span!("reported error", error = true, e = error);
         let _error_guard = err_span.enter();
         error!("{}", error);
+        self.count_error();
+    }
+
+    /// Like `report`, sampling the error if `with_sampling` was set.
+    fn report_from(&self, error: BoxedError, node: &NodeHandle) {
+        let Some(sampler) = &self.sampler else {
+            self.report(error);
+            return;
+        };
+        sampler.report(node, error.to_string());
+        self.count_error();
+    }
 
+    fn count_error(&self) {
         let count = self.count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         if let Some(threshold) = self.threshold {
             if count >= threshold {
@@ -39,4 +224,253 @@ impl ErrorManager {
             }
         }
     }
+
+    /// Reports `op` like `report_operation` with [`ExecutionError::RecordTooLarge`] if its encoding is too long,
+    /// returning `true` if so, in which case it must not be processed.
+    ///
+    /// Only encodes `op` if `with_max_record_bytes` was set. If it can't be encoded, that's reported instead.
+    pub fn reject_oversized(
+        &self,
+        node: &NodeHandle,
+        port: PortHandle,
+        op: &ProcessorOperation,
+        record_store: &ProcessorRecordStore,
+    ) -> bool {
+        let Some(max) = self.max_record_bytes else {
+            return false;
+        };
+        let size = match op.encoded_size(record_store) {
+            Ok(size) => size,
+            Err(e) => {
+                self.report_from(e.into(), node);
+                return true;
+            }
+        };
+        if size <= max as u64 {
+            return false;
+        }
+        self.report_operation(
+            ExecutionError::RecordTooLarge { size, max }.into(),
+            node,
+            port,
+            Some(op.clone()),
+            record_store,
+        );
+        true
+    }
+
+    /// Reports `op` like `report_operation` with [`ExecutionError::RecordSchemaViolation`] if a record of it doesn't match `schema`,
+    /// returning `true` if so, in which case it must not be sent.
+    pub fn reject_schema_violation(
+        &self,
+        node: &NodeHandle,
+        port: PortHandle,
+        schema: &Schema,
+        op: &ProcessorOperation,
+        record_store: &ProcessorRecordStore,
+    ) -> bool {
+        let reason = match op.load(record_store) {
+            Ok(Operation::Insert { new }) => schema_violation(schema, &new),
+            Ok(Operation::Delete { old }) => schema_violation(schema, &old),
+            Ok(Operation::Update { old, new }) => {
+                schema_violation(schema, &old).or_else(|| schema_violation(schema, &new))
+            }
+            Err(e) => {
+                self.report_from(e.into(), node);
+                return true;
+            }
+        };
+        let Some(reason) = reason else {
+            return false;
+        };
+        self.report_operation(
+            ExecutionError::RecordSchemaViolation { port, reason }.into(),
+            node,
+            port,
+            Some(op.clone()),
+            record_store,
+        );
+        true
+    }
+
+    /// Reports that `op` failed at `port` of `node`, persisting it as a dead letter if there's a store.
+    ///
+    /// Falls back to `report`, or sampling if set, if `op` is `None` or can't be persisted.
+    pub fn report_operation(
+        &self,
+        error: BoxedError,
+        node: &NodeHandle,
+        port: PortHandle,
+        op: Option<ProcessorOperation>,
+        record_store: &ProcessorRecordStore,
+    ) {
+        let (Some(dead_letters), Some(op)) = (&self.dead_letters, op) else {
+            self.report_from(error, node);
+            return;
+        };
+        let operation = match op.load(record_store) {
+            Ok(operation) => operation,
+            Err(e) => {
+                self.report(e.into());
+                self.report(error);
+                return;
+            }
+        };
+        let letter = DeadLetter {
+            node: node.clone(),
+            port,
+            operation,
+            error: error.to_string(),
+            timestamp: SystemTime::now(),
+            schema: dead_letters
+                .schemas
+                .get(&(node.clone(), port))
+                .cloned()
+                .unwrap_or_default(),
+        };
+        match dead_letters.store.insert(&letter) {
+            Ok(id) => warn!("Persisted dead letter {id} from port {port} of {node}: {error}"),
+            Err(e) => {
+                self.report(e.into());
+                self.report(error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use dozer_log::tokio;
+    use dozer_types::types::Field;
+    use tempdir::TempDir;
+
+    use crate::dead_letter::{DeadLetterStore, ErrorPolicy};
+    use crate::executor::ExecutorOptions;
+    use crate::tests::processors::RecordingProcessorFactory;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_diverts_oversized_records() {
+        let count: u64 = 100;
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let oversized_key = Field::String("key_42".to_string());
+        let dead_letter_dir = TempDir::new("test_run_dag_diverts_oversized_records").unwrap();
+        let options = ExecutorOptions {
+            max_record_bytes: Some(1_000),
+            error_policy: ErrorPolicy::DeadLetterStore {
+                path: dead_letter_dir.path().to_path_buf(),
+            },
+            ingress_transform: Some(Arc::new({
+                let oversized_key = oversized_key.clone();
+                move |op: &mut Operation| {
+                    if let Operation::Insert { new } = op {
+                        if new.values[0] == oversized_key {
+                            new.values[1] = Field::String("x".repeat(10_000));
+                        }
+                    }
+                }
+            })),
+            ..Default::default()
+        };
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let latch = Arc::new(AtomicBool::new(true));
+        // The oversized record never reaches the sink.
+        let sink = RecordingSinkFactory::new()
+            .record_ops()
+            .stop_after(count - 1, latch.clone());
+        let recording = sink.recording();
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, options).await.unwrap();
+
+        let state = recording.lock().materialize();
+        assert_eq!(state.len() as u64, count - 1);
+        assert!(!state.contains_key(&oversized_key));
+
+        let store = DeadLetterStore::open(dead_letter_dir.path()).unwrap();
+        let letters = store.iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(letters.len(), 1);
+        let letter = &letters[0].1;
+        assert_eq!(letter.node, sink_handle);
+        assert!(matches!(
+            &letter.operation,
+            Operation::Insert { new } if new.values[0] == oversized_key
+        ));
+        assert!(letter.error.contains("more than the maximum of 1000"));
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_error_sampling() {
+        let count: u64 = 1_000;
+        let source_handle = NodeHandle::new(None, "source".to_string());
+        let proc_handle = NodeHandle::new(None, "proc".to_string());
+        let sink_handle = NodeHandle::new(None, "sink".to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false),
+            )
+            .processor(
+                proc_handle.clone(),
+                RecordingProcessorFactory::new().map_inserts(|_| Err("invalid record".into())),
+            )
+            .sink(sink_handle.clone(), RecordingSinkFactory::new())
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let rollups = Arc::new(Mutex::new(vec![]));
+        let recorded = rollups.clone();
+        let options = ExecutorOptions {
+            error_threshold: None,
+            error_sampling: Some(ErrorSamplingOptions {
+                // Longer than the run, so every error ends up in the rollup emitted at the end.
+                interval: Duration::from_secs(3600),
+                on_rollup: Some(Arc::new(move |rollup: &ErrorRollup| {
+                    recorded.lock().push(rollup.clone())
+                })),
+            }),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let rollups = rollups.lock();
+        assert_eq!(rollups.len(), 1);
+        let rollup = &rollups[0];
+        assert_eq!(rollup.node, proc_handle);
+        assert_eq!(rollup.error, "invalid record");
+        assert_eq!(rollup.count, count);
+        assert!(rollup.first <= rollup.last);
+    }
 }
@@ -1,7 +1,9 @@
 use std::path::PathBuf;
 
 use crate::checkpoint::serialize::{DeserializationError, SerializationError};
+use crate::dead_letter::DeadLetterError;
 use crate::node::PortHandle;
+use crate::Edge;
 use dozer_recordstore::RecordStoreError;
 use dozer_types::errors::internal::BoxedError;
 use dozer_types::node::NodeHandle;
@@ -14,14 +16,37 @@ pub enum ExecutionError {
     WouldCycle,
     #[error("Invalid port handle: {0}")]
     InvalidPortHandle(PortHandle),
+    #[error("Duplicate node handle {0}")]
+    DuplicateNodeHandle(NodeHandle),
+    #[error("Node {0} not found")]
+    NodeNotFound(NodeHandle),
+    #[error("DAG has no source node")]
+    NoSourceNode,
+    #[error("Node {node} is not reachable from a source, or doesn't lead to a sink")]
+    UnreachableNode { node: NodeHandle },
+    #[error("Duplicate edge from {}:{} to {}:{}", .0.from.node, .0.from.port, .0.to.node, .0.to.port)]
+    DuplicateEdge(Edge),
     #[error("Missing input for node {node} on port {port}")]
     MissingInput { node: NodeHandle, port: PortHandle },
     #[error("Duplicate input for node {node} on port {port}")]
     DuplicateInput { node: NodeHandle, port: PortHandle },
+    #[error("Node {node} requires field {field}, which its inputs don't provide")]
+    MissingField { node: NodeHandle, field: String },
+    #[error("Output port {port} of node {node} keeps no versions of records, `max_versions` must be at least 1")]
+    NoVersionsKept { node: NodeHandle, port: PortHandle },
+    #[error("Invalid field projection {indexes:?} into node {node} on port {port}, upstream has {num_fields} fields")]
+    InvalidFieldProjection {
+        node: NodeHandle,
+        port: PortHandle,
+        indexes: Vec<usize>,
+        num_fields: usize,
+    },
     #[error("Cannot send to channel")]
     CannotSendToChannel,
     #[error("Cannot receive from channel")]
     CannotReceiveFromChannel,
+    #[error("Executor was aborted")]
+    Aborted,
     #[error("Cannot spawn worker thread: {0}")]
     CannotSpawnWorkerThread(#[source] std::io::Error),
     #[error("Invalid source name {0}")]
@@ -38,6 +63,8 @@ pub enum ExecutionError {
     Source(#[source] BoxedError),
     #[error("File system error {0:?}: {1}")]
     FileSystemError(PathBuf, #[source] std::io::Error),
+    #[error("Storage error: {0}")]
+    Storage(#[from] dozer_storage::errors::StorageError),
     #[error("Recordstore error: {0}")]
     RecordStore(#[from] RecordStoreError),
     #[error("Object storage error: {0}")]
@@ -48,6 +75,72 @@ pub enum ExecutionError {
     UnrecognizedCheckpoint(String),
     #[error("Cannot deserialize checkpoint: {0}")]
     CorruptedCheckpoint(#[source] bincode::Error),
+    #[error("Cannot serialize operation: {0}")]
+    SerializeOperation(#[source] bincode::Error),
+    #[error("Cannot compress or decompress operation: {0}")]
+    Compression(#[source] std::io::Error),
+    #[error("Checkpoint {0} not found")]
+    CheckpointNotFound(u64),
+    #[error("Cannot snapshot because a source isn't restartable")]
+    SnapshotNotRestartable,
+    #[error("Checkpoint has no state for node {0}")]
+    NodeStateNotFound(NodeHandle),
+    #[error("Cannot export or restore node state: {0}")]
+    NodeStateIo(#[source] std::io::Error),
+    #[error("Cannot snapshot because checkpointing is disabled")]
+    CheckpointingDisabled,
+    #[error("Pipeline stopped before the snapshot was taken")]
+    SnapshotInterrupted,
+    #[error("Edge from {}:{} to {}:{} didn't drain in time", .edge.from.node, .edge.from.port, .edge.to.node, .edge.to.port)]
+    DrainTimeout { edge: Edge },
+    #[error(
+        "Suspected deadlock, no progress with operations queued between nodes {}",
+        .nodes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    DeadlockSuspected { nodes: Vec<NodeHandle> },
+    #[error("Record doesn't match the schema of output port {port}: {reason}")]
+    RecordSchemaViolation { port: PortHandle, reason: String },
+    #[error("Operation is {size} bytes encoded, more than the maximum of {max}")]
+    RecordTooLarge { size: u64, max: usize },
+    #[error(
+        "Sink {sink} received {received} operations, not {count}, before timing out or finishing"
+    )]
+    SinkCountNotReached {
+        sink: NodeHandle,
+        count: u64,
+        received: u64,
+    },
+    #[error("Failed to build node {node}: {error}")]
+    NodeBuild {
+        node: NodeHandle,
+        #[source]
+        error: BoxedError,
+    },
+    #[error("Failed to initialize node {node}: {error}")]
+    NodeInit {
+        node: NodeHandle,
+        #[source]
+        error: BoxedError,
+    },
+    #[error("Failed to flush the state of node {node}: {error}")]
+    NodeFlush {
+        node: NodeHandle,
+        #[source]
+        error: BoxedError,
+    },
+    #[error("Processor {node} has state in `state_dir`, but no snapshot of it at the checkpoint of epoch {checkpoint_epoch} it resumes from. Remove the node's state to start it over")]
+    ProcessorStateNotRestorable {
+        node: NodeHandle,
+        checkpoint_epoch: u64,
+    },
+    #[error("Failed to snapshot the state of processor {node}: {error}")]
+    StateSnapshot {
+        node: NodeHandle,
+        #[source]
+        error: BoxedError,
+    },
+    #[error("Dead letter store error: {0}")]
+    DeadLetter(#[from] DeadLetterError),
     #[error("Table {table_name} of source {source_name} cannot restart. You have to clean data from previous runs by running `dozer clean`")]
     SourceCannotRestart {
         source_name: NodeHandle,
@@ -55,6 +148,12 @@ pub enum ExecutionError {
     },
     #[error("Failed to create checkpoint: {0}")]
     FailedToCreateCheckpoint(BoxedError),
+    #[error("Fetcher {fetcher} emitted sequence number {seq} out of order")]
+    OutOfOrderFetch { fetcher: usize, seq: u64 },
+    #[error("Parallel fetchers finished without sequence number {0}")]
+    MissingSequenceNumber(u64),
+    #[error("Fetcher {0} panicked")]
+    FetcherPanicked(usize),
     #[error("Failed to serialize record writer: {0}")]
     SerializeRecordWriter(#[source] SerializationError),
 }
@@ -0,0 +1,166 @@
+use std::time::Duration;
+
+/// How much a batch can grow or shrink after one batch.
+const MAX_ADJUSTMENT: f64 = 2.0;
+
+/// Adjusts source batch sizes toward a target latency per batch, instead of using fixed commit sizes.
+///
+/// A batch that completes faster than `target_latency` grows the next one, and a slower batch shrinks it.
+/// Batches slow down when downstream nodes can't keep up, because sources block on full channels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveBatchConfig {
+    /// The time a batch should take, from the previous commit to its own. Must not be zero.
+    pub target_latency: Duration,
+    /// The batch size to start with, and the smallest allowed.
+    pub min_batch_size: u32,
+    pub max_batch_size: u32,
+}
+
+impl Default for AdaptiveBatchConfig {
+    fn default() -> Self {
+        Self {
+            target_latency: Duration::from_millis(50),
+            min_batch_size: 100,
+            max_batch_size: 100_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct AdaptiveBatchController {
+    config: AdaptiveBatchConfig,
+    batch_size: u32,
+}
+
+impl AdaptiveBatchController {
+    pub fn new(config: AdaptiveBatchConfig) -> Self {
+        let batch_size = config.min_batch_size.max(1);
+        Self { config, batch_size }
+    }
+
+    pub fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    /// Returns the size of the next batch, given the latency of the last one.
+    pub fn on_batch(&mut self, latency: Duration) -> u32 {
+        let adjustment = (self.config.target_latency.as_secs_f64() / latency.as_secs_f64())
+            .clamp(1.0 / MAX_ADJUSTMENT, MAX_ADJUSTMENT);
+        let batch_size = (self.batch_size as f64 * adjustment).round() as u32;
+        self.batch_size = batch_size.clamp(
+            self.config.min_batch_size.max(1),
+            self.config.max_batch_size.max(1),
+        );
+        self.batch_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::executor::ExecutorOptions;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{BackfillSourceFactory, BACKFILL_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    fn controller() -> AdaptiveBatchController {
+        AdaptiveBatchController::new(AdaptiveBatchConfig {
+            target_latency: Duration::from_millis(10),
+            min_batch_size: 10,
+            max_batch_size: 1000,
+        })
+    }
+
+    #[test]
+    fn batches_grow_when_fast_and_shrink_when_slow() {
+        let mut controller = controller();
+        assert_eq!(controller.batch_size(), 10);
+        assert_eq!(controller.on_batch(Duration::from_millis(5)), 20);
+        assert_eq!(controller.on_batch(Duration::ZERO), 40);
+        assert_eq!(controller.on_batch(Duration::from_millis(10)), 40);
+        assert_eq!(controller.on_batch(Duration::from_millis(16)), 25);
+        assert_eq!(controller.on_batch(Duration::from_secs(1)), 13);
+    }
+
+    #[test]
+    fn batches_stay_within_bounds() {
+        let mut controller = controller();
+        for _ in 0..20 {
+            controller.on_batch(Duration::ZERO);
+        }
+        assert_eq!(controller.batch_size(), 1000);
+        for _ in 0..20 {
+            controller.on_batch(Duration::from_secs(1));
+        }
+        assert_eq!(controller.batch_size(), 10);
+    }
+
+    /// Runs `count` operations into a sink sleeping `op_delay` per operation with adaptive batching,
+    /// returning the size of every epoch.
+    async fn run_dag_with_adaptive_batching(count: u64, op_delay: Option<Duration>) -> Vec<usize> {
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let mut sink = RecordingSinkFactory::new();
+        if let Some(op_delay) = op_delay {
+            sink = sink.with_op_delay(op_delay);
+        }
+        let recording = sink.recording();
+        let dag = DagBuilder::new()
+            .source(source_handle.clone(), BackfillSourceFactory::new(0, count))
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                BACKFILL_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        // Only commit on batch size, and keep channels small so the sink throttles the source.
+        let options = ExecutorOptions {
+            channel_buffer_sz: 4,
+            commit_time_threshold: Duration::from_secs(3600),
+            backfill_commit_time_threshold: Duration::from_secs(3600),
+            adaptive_batching: Some(AdaptiveBatchConfig {
+                target_latency: Duration::from_millis(20),
+                min_batch_size: 1,
+                max_batch_size: 1_000,
+            }),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let epoch_sizes = recording.lock().epoch_sizes.clone();
+        assert_eq!(epoch_sizes.iter().sum::<usize>(), count as usize);
+        epoch_sizes
+    }
+
+    /// Returns the median epoch size of the second half of the run, after the batch size has settled.
+    fn settled_epoch_size(epoch_sizes: &[usize]) -> usize {
+        let mut tail = epoch_sizes[epoch_sizes.len() / 2..].to_vec();
+        tail.sort();
+        tail[tail.len() / 2]
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_adaptive_batching() {
+        let steady = run_dag_with_adaptive_batching(50_000, None).await;
+        let throttled = run_dag_with_adaptive_batching(300, Some(Duration::from_millis(1))).await;
+
+        // Batches start at the minimum size and grow while the sink keeps up.
+        assert_eq!(steady[0], 1);
+        assert!(steady.iter().max().unwrap() > &100);
+        // A sink taking 1ms per operation can only keep up with about 20 operations per batch.
+        let steady = settled_epoch_size(&steady);
+        let throttled = settled_epoch_size(&throttled);
+        assert!(throttled <= 100, "throttled batch size {throttled}");
+        assert!(steady > throttled, "steady {steady}, throttled {throttled}");
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use dozer_log::storage::{Object, Queue};
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::models::ingestion_types::IngestionMessage;
+use dozer_types::node::{NodeHandle, OpIdentifier};
+use dozer_types::parking_lot::Mutex;
+use dozer_types::types::{
+    Field, FieldDefinition, FieldType, Operation, Record, Schema, SourceDefinition,
+};
+use tempdir::TempDir;
+
+use crate::channels::{ProcessorChannelForwarder, SourceChannelForwarder};
+use crate::checkpoint::OptionCheckpoint;
+use crate::epoch::Epoch;
+use crate::errors::ExecutionError;
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::node::{
+    OutputPortDef, OutputPortType, PortHandle, Processor, ProcessorFactory, Sink, SinkFactory,
+    Source, SourceFactory, SourceState, StateBackend,
+};
+use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+use super::{DagExecutor, ExecutorOptions};
+
+/// What [`DagExecutor::benchmark`] measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkReport {
+    /// Operations that reached the sink.
+    pub processed: u64,
+    /// Wall time from starting the executor until it finished.
+    pub elapsed: Duration,
+    pub records_per_sec: f64,
+    /// 99th percentile latency from the source listener to the sink, rounded up to a power of two microseconds.
+    pub p99_latency: Duration,
+    /// The most operations that were sent by the source but hadn't reached the sink yet.
+    pub peak_queue_depth: u64,
+}
+
+impl DagExecutor {
+    /// Runs `count` inserts through a generator source, a no-op processor and a counting sink as fast as possible.
+    ///
+    /// Checkpoints and processor state are written to a temporary directory, removed when this returns.
+    pub async fn benchmark(
+        count: u64,
+        options: ExecutorOptions,
+    ) -> Result<BenchmarkReport, ExecutionError> {
+        let stats = Arc::new(BenchmarkStats::default());
+        let source = NodeHandle::new(None, "benchmark_source".to_string());
+        let processor = NodeHandle::new(None, "benchmark_processor".to_string());
+        let sink = NodeHandle::new(None, "benchmark_sink".to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source.clone(),
+                BenchmarkSourceFactory {
+                    count,
+                    stats: stats.clone(),
+                },
+            )
+            .processor(processor.clone(), BenchmarkProcessorFactory)
+            .sink(sink.clone(), BenchmarkSinkFactory(stats.clone()))
+            .edge(
+                &source,
+                DEFAULT_PORT_HANDLE,
+                &processor,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(&processor, DEFAULT_PORT_HANDLE, &sink, DEFAULT_PORT_HANDLE)
+            .build()?;
+
+        let temp_dir = TempDir::new("dozer_benchmark")
+            .map_err(|e| ExecutionError::FileSystemError("dozer_benchmark".into(), e))?;
+        let checkpoint_dir = temp_dir.path().to_string_lossy().into_owned();
+        let checkpoint = OptionCheckpoint::new(checkpoint_dir, Default::default()).await?;
+
+        let start = Instant::now();
+        DagExecutor::new(dag, checkpoint, options)
+            .await?
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await?
+            .join()?;
+        let elapsed = start.elapsed();
+
+        let processed = stats.received.load(Ordering::SeqCst);
+        Ok(BenchmarkReport {
+            processed,
+            elapsed,
+            records_per_sec: processed as f64 / elapsed.as_secs_f64(),
+            p99_latency: stats.latencies.lock().percentile(0.99),
+            peak_queue_depth: stats.peak_queue_depth.load(Ordering::SeqCst),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct BenchmarkStats {
+    sent: AtomicU64,
+    received: AtomicU64,
+    peak_queue_depth: AtomicU64,
+    latencies: Mutex<LatencyHistogram>,
+}
+
+/// Counts latencies in buckets of powers of two microseconds.
+#[derive(Debug)]
+struct LatencyHistogram {
+    buckets: [u64; 64],
+    count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; 64],
+            count: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()).min(63) as usize;
+        self.buckets[bucket] += 1;
+        self.count += 1;
+    }
+
+    /// Returns the upper bound of the bucket containing the `fraction` percentile.
+    fn percentile(&self, fraction: f64) -> Duration {
+        let target = (self.count as f64 * fraction).ceil() as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target.max(1) {
+                return Duration::from_micros(1 << bucket);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+#[derive(Debug)]
+struct BenchmarkSourceFactory {
+    count: u64,
+    stats: Arc<BenchmarkStats>,
+}
+
+impl SourceFactory for BenchmarkSourceFactory {
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        Ok(Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "id".to_string(),
+                    FieldType::UInt,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                true,
+            )
+            .clone())
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "benchmark".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            DEFAULT_PORT_HANDLE,
+            OutputPortType::Stateless,
+        )]
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(BenchmarkSource {
+            count: self.count,
+            stats: self.stats.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct BenchmarkSource {
+    count: u64,
+    stats: Arc<BenchmarkStats>,
+}
+
+impl Source for BenchmarkSource {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        _last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        for n in 0..self.count {
+            let message = IngestionMessage::OperationEvent {
+                table_index: 0,
+                op: Operation::Insert {
+                    new: Record::new(vec![Field::UInt(n)]),
+                },
+                id: Some(OpIdentifier::new(n, 0)),
+            };
+            self.stats.sent.fetch_add(1, Ordering::SeqCst);
+            fw.send(message, DEFAULT_PORT_HANDLE)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct BenchmarkProcessorFactory;
+
+impl ProcessorFactory for BenchmarkProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        Ok(input_schemas[&DEFAULT_PORT_HANDLE].clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        Ok(Box::new(BenchmarkProcessor))
+    }
+
+    fn type_name(&self) -> String {
+        "Benchmark".to_string()
+    }
+
+    fn id(&self) -> String {
+        "Benchmark".to_string()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::Memory
+    }
+}
+
+#[derive(Debug)]
+struct BenchmarkProcessor;
+
+impl Processor for BenchmarkProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        _record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        fw.send(op, DEFAULT_PORT_HANDLE);
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct BenchmarkSinkFactory(Arc<BenchmarkStats>);
+
+impl SinkFactory for BenchmarkSinkFactory {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn prepare(&self, _input_schemas: HashMap<PortHandle, Schema>) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Sink>, BoxedError> {
+        Ok(Box::new(BenchmarkSink(self.0.clone())))
+    }
+}
+
+#[derive(Debug)]
+struct BenchmarkSink(Arc<BenchmarkStats>);
+
+impl Sink for BenchmarkSink {
+    fn commit(&mut self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<(), BoxedError> {
+        self.process_with_context(from_port, record_store, op, &Default::default())
+    }
+
+    fn process_with_context(
+        &mut self,
+        _from_port: PortHandle,
+        _record_store: &ProcessorRecordStore,
+        _op: ProcessorOperation,
+        context: &OperationContext,
+    ) -> Result<(), BoxedError> {
+        let stats = &self.0;
+        let received = stats.received.fetch_add(1, Ordering::SeqCst) + 1;
+        let depth = stats.sent.load(Ordering::SeqCst).saturating_sub(received);
+        stats.peak_queue_depth.fetch_max(depth, Ordering::SeqCst);
+        if let Some(processing_time) = context.timestamps.processing_time {
+            let latency = SystemTime::now()
+                .duration_since(processing_time)
+                .unwrap_or_default();
+            stats.latencies.lock().record(latency);
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self, _queue: &Queue) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn on_source_snapshotting_done(&mut self, _connection_name: String) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn benchmark_reports_throughput() {
+        let count = 10_000;
+        let report = DagExecutor::benchmark(count, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(report.processed, count);
+        assert!(report.records_per_sec > 0.0);
+        assert!(report.p99_latency > Duration::ZERO);
+    }
+
+    #[test]
+    fn latency_histogram_percentile() {
+        let mut histogram = LatencyHistogram::default();
+        for _ in 0..99 {
+            histogram.record(Duration::from_micros(3));
+        }
+        histogram.record(Duration::from_millis(10));
+        assert_eq!(histogram.percentile(0.99), Duration::from_micros(4));
+        assert_eq!(histogram.percentile(1.0), Duration::from_micros(16384));
+    }
+}
@@ -0,0 +1,137 @@
+use daggy::petgraph::visit::IntoNodeReferences;
+use dozer_types::node::NodeHandle;
+use dozer_types::types::Schema;
+
+use crate::builder_dag::{BuilderDag, NodeKind};
+use crate::Endpoint;
+
+/// A snapshot of the executor's topology, for correlating metrics and status with nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DagInfo {
+    pub nodes: Vec<NodeInfo>,
+    pub edges: Vec<EdgeInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub handle: NodeHandle,
+    pub typ: DagNodeType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DagNodeType {
+    Source,
+    Processor,
+    Sink,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeInfo {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    pub schema: Schema,
+}
+
+impl DagInfo {
+    pub(super) fn new(builder_dag: &BuilderDag) -> Self {
+        let graph = builder_dag.graph();
+
+        let nodes = graph
+            .node_references()
+            .map(|(_, node)| NodeInfo {
+                handle: node.handle.clone(),
+                typ: match node.kind {
+                    NodeKind::Source { .. } => DagNodeType::Source,
+                    NodeKind::Processor { .. } => DagNodeType::Processor,
+                    NodeKind::Sink(_) => DagNodeType::Sink,
+                },
+            })
+            .collect();
+
+        let edges = graph
+            .raw_edges()
+            .iter()
+            .map(|edge| EdgeInfo {
+                from: Endpoint::new(graph[edge.source()].handle.clone(), edge.weight.output_port),
+                to: Endpoint::new(graph[edge.target()].handle.clone(), edge.weight.input_port),
+                schema: edge.weight.schema.clone(),
+            })
+            .collect();
+
+        Self { nodes, edges }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::executor::DagExecutor;
+    use crate::tests::dag_base_run::NoopProcessorFactory;
+    use crate::tests::sinks::{CountingSinkFactory, COUNTING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{Dag, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_executor_exposes_dag() {
+        let mut dag = Dag::new();
+        let latch = Arc::new(AtomicBool::new(true));
+
+        let source_handle = NodeHandle::new(Some(1), 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        dag.add_source(
+            source_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(1, latch.clone(), false)),
+        );
+        dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+        dag.add_sink(
+            sink_handle.clone(),
+            Box::new(CountingSinkFactory::new(1, latch)),
+        );
+
+        dag.connect(
+            Endpoint::new(source_handle.clone(), GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+        )
+        .unwrap();
+
+        dag.connect(
+            Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+            Endpoint::new(sink_handle.clone(), COUNTING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        assert_eq!(dag.nodes_by_type(DagNodeType::Source), vec![&source_handle]);
+        assert_eq!(
+            dag.nodes_by_type(DagNodeType::Processor),
+            vec![&proc_handle]
+        );
+        assert_eq!(dag.nodes_by_type(DagNodeType::Sink), vec![&sink_handle]);
+
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let executor = DagExecutor::new(dag, checkpoint, Default::default())
+            .await
+            .unwrap();
+
+        let dag = executor.dag();
+        assert_eq!(dag.nodes.len(), 3);
+        assert_eq!(dag.edges.len(), 2);
+        assert_eq!(dag.nodes[0].handle, source_handle);
+        assert_eq!(dag.nodes[0].typ, DagNodeType::Source);
+        assert_eq!(
+            dag.edges
+                .iter()
+                .filter(|edge| edge.from.node == source_handle)
+                .count(),
+            1
+        );
+    }
+}
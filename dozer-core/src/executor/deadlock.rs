@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dozer_types::node::NodeHandle;
+use dozer_types::parking_lot::Mutex;
+
+use crate::epoch::EpochManager;
+use crate::forwarder::QueueLen;
+use crate::Edge;
+
+use super::node_metrics::ExecutorMetrics;
+
+/// Longest time between two looks at the pipeline.
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Watches a running pipeline for a suspected deadlock, see `ExecutorOptions::deadlock_timeout`.
+///
+/// The pipeline is stuck if operations are queued on some edge, yet no node has received an operation
+/// and no queue has changed for `timeout`.
+#[derive(Debug)]
+pub struct DeadlockDetector {
+    timeout: Duration,
+    queues: Vec<(Edge, QueueLen)>,
+    metrics: Arc<ExecutorMetrics>,
+    epoch_manager: Arc<EpochManager>,
+    aborted: Arc<AtomicBool>,
+    /// Where the nodes suspected of being deadlocked are reported.
+    suspected: Arc<Mutex<Option<Vec<NodeHandle>>>>,
+}
+
+impl DeadlockDetector {
+    pub fn new(
+        timeout: Duration,
+        queues: Vec<(Edge, QueueLen)>,
+        metrics: Arc<ExecutorMetrics>,
+        epoch_manager: Arc<EpochManager>,
+        aborted: Arc<AtomicBool>,
+        suspected: Arc<Mutex<Option<Vec<NodeHandle>>>>,
+    ) -> Self {
+        Self {
+            timeout,
+            queues,
+            metrics,
+            epoch_manager,
+            aborted,
+            suspected,
+        }
+    }
+
+    /// Watches until all sinks have terminated, the executor is aborted, or a deadlock is suspected.
+    pub fn run(self) {
+        let poll_interval = (self.timeout / 10).min(MAX_POLL_INTERVAL);
+        let mut last = self.progress();
+        let mut since = Instant::now();
+        while !self.aborted.load(Ordering::SeqCst)
+            && !self.epoch_manager.flush_barrier().all_sinks_terminated()
+        {
+            thread::sleep(poll_interval);
+            let progress = self.progress();
+            if progress != last || progress.1.iter().all(|len| *len == 0) {
+                last = progress;
+                since = Instant::now();
+            } else if since.elapsed() >= self.timeout {
+                *self.suspected.lock() = Some(self.stuck_nodes());
+                return;
+            }
+        }
+    }
+
+    /// Operations received by all nodes, and the length of every edge's queue.
+    fn progress(&self) -> (u64, Vec<usize>) {
+        let lens = self
+            .queues
+            .iter()
+            .map(|(_, queue_len)| queue_len.get())
+            .collect();
+        (self.metrics.total_operations(), lens)
+    }
+
+    /// The nodes at both ends of every edge with operations queued, in edge order.
+    fn stuck_nodes(&self) -> Vec<NodeHandle> {
+        let mut nodes = vec![];
+        for (edge, queue_len) in &self.queues {
+            if queue_len.get() == 0 {
+                continue;
+            }
+            for node in [&edge.from.node, &edge.to.node] {
+                if !nodes.contains(node) {
+                    nodes.push(node.clone());
+                }
+            }
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::errors::ExecutionError;
+    use crate::executor::{DagExecutor, ExecutorOptions};
+    use crate::tests::processors::{
+        RecordingProcessorFactory, RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+        RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+    };
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        DualPortGeneratorSourceFactory, DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_1,
+        DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_2,
+    };
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_detects_deadlock() {
+        let count: u64 = 1_000;
+        let release = Arc::new(AtomicBool::new(false));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        // The source alternates between the ports. Once the processor receives an operation from the left port before
+        // its counterpart from the right port, it waits for the right port, while the source is blocked on the full channel of the left port.
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                DualPortGeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false),
+            )
+            .processor(
+                proc_handle.clone(),
+                RecordingProcessorFactory::new().wait_for_right_port(release.clone()),
+            )
+            .sink(sink_handle.clone(), RecordingSinkFactory::new())
+            .edge(
+                &source_handle,
+                DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_1,
+                &proc_handle,
+                RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+            )
+            .edge(
+                &source_handle,
+                DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_2,
+                &proc_handle,
+                RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            channel_buffer_sz: 4,
+            deadlock_timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let mut join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+
+        let result = join_handle.join_timeout(Duration::from_secs(30));
+        assert!(
+            matches!(
+                &result,
+                Err(ExecutionError::DeadlockSuspected { nodes })
+                    if nodes == &[source_handle.clone(), proc_handle.clone()]
+            ),
+            "{result:?}"
+        );
+
+        // Once unstuck, the pipeline finishes.
+        release.store(true, Ordering::SeqCst);
+        join_handle.join().unwrap();
+    }
+}
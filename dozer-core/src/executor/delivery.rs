@@ -0,0 +1,602 @@
+use std::mem::take;
+use std::time::SystemTime;
+
+use dozer_types::node::{OpIdentifier, SourceStates, TableState};
+
+use crate::{
+    epoch::Epoch,
+    executor_operation::{OperationContext, ProcessorOperation, SourceOffset},
+};
+
+/// When source offsets advance relative to sink commits.
+///
+/// Source offsets are persisted in checkpoints, which are written once every node has released the epoch.
+/// This decides what a sink sees of an epoch if the pipeline stops between the sink applying it and the checkpoint being written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeliverySemantics {
+    /// The sink applies an epoch only after releasing its checkpoint, so offsets advance before the sink commits.
+    ///
+    /// Operations may be lost on restart, but are never delivered twice.
+    AtMostOnce,
+    /// The sink applies operations as they arrive and releases the checkpoint after committing.
+    ///
+    /// Operations are never lost on restart, but may be delivered twice.
+    #[default]
+    AtLeastOnce,
+    /// Like `AtLeastOnce`, but operations are applied per epoch on commit,
+    /// and operations already covered by `Sink::applied_source_states` or `Sink::applied_through` are skipped.
+    ExactlyOnce,
+}
+
+pub type EpochOp = (
+    usize,
+    ProcessorOperation,
+    OperationContext,
+    Option<SourceOffset>,
+);
+pub type EpochOps = Vec<EpochOp>;
+
+/// Decides when a sink node hands operations and commits to its sink.
+#[derive(Debug)]
+pub struct DeliveryBuffer {
+    semantics: DeliverySemantics,
+    /// Operations received since last commit, if they're not applied immediately.
+    pending: EpochOps,
+    /// The latest watermark received since last commit, if operations aren't applied immediately.
+    pending_watermark: Option<SystemTime>,
+    /// `AtMostOnce` only: an epoch that has been released but not applied, with its watermark.
+    deferred: Option<(Epoch, EpochOps, Option<SystemTime>)>,
+    /// The watermark of the epochs returned last, to pass to the sink after their operations.
+    released_watermark: Option<SystemTime>,
+    /// `ExactlyOnce` only: the source states the sink has applied.
+    applied: Option<SourceStates>,
+    /// `ExactlyOnce` only: the offset the sink has applied operations of every source table through.
+    applied_through: Option<OpIdentifier>,
+}
+
+impl DeliveryBuffer {
+    pub fn new(semantics: DeliverySemantics, applied: Option<SourceStates>) -> Self {
+        Self {
+            semantics,
+            pending: vec![],
+            pending_watermark: None,
+            deferred: None,
+            released_watermark: None,
+            applied,
+            applied_through: None,
+        }
+    }
+
+    /// Also skips operations whose source offset is at or below `applied_through`.
+    pub fn with_applied_through(mut self, applied_through: Option<OpIdentifier>) -> Self {
+        self.applied_through = applied_through;
+        self
+    }
+
+    /// Returns the operation if it should be applied right away.
+    pub fn on_op(
+        &mut self,
+        index: usize,
+        op: ProcessorOperation,
+        context: OperationContext,
+        source_offset: Option<SourceOffset>,
+    ) -> Option<EpochOp> {
+        let op = (index, op, context, source_offset);
+        if self.semantics == DeliverySemantics::AtLeastOnce {
+            Some(op)
+        } else {
+            self.pending.push(op);
+            None
+        }
+    }
+
+    /// Returns the watermark if it should be passed to the sink right away,
+    /// otherwise holds it back until the operations received before it are applied.
+    pub fn on_watermark(&mut self, watermark: SystemTime) -> Option<SystemTime> {
+        if self.semantics == DeliverySemantics::AtLeastOnce {
+            Some(watermark)
+        } else {
+            self.pending_watermark = Some(watermark);
+            None
+        }
+    }
+
+    /// Returns the watermark held back with the epochs `on_commit` or `on_terminate` returned last, if any.
+    pub fn take_released_watermark(&mut self) -> Option<SystemTime> {
+        self.released_watermark.take()
+    }
+
+    /// Returns the epochs that should be applied now, with their operations.
+    pub fn on_commit(&mut self, epoch: &Epoch) -> Vec<(Epoch, EpochOps)> {
+        match self.semantics {
+            DeliverySemantics::AtLeastOnce => vec![(epoch.clone(), vec![])],
+            DeliverySemantics::AtMostOnce => {
+                // Drop our handle on the checkpoint, so it's written before this epoch is applied.
+                let mut released = epoch.clone();
+                released.common_info.checkpoint_writer = None;
+                let ready = self.deferred.take();
+                self.deferred = Some((
+                    released,
+                    take(&mut self.pending),
+                    self.pending_watermark.take(),
+                ));
+                self.release(ready).into_iter().collect()
+            }
+            DeliverySemantics::ExactlyOnce => {
+                let mut ops = take(&mut self.pending);
+                // Skipped epochs still let time pass.
+                self.released_watermark = self.pending_watermark.take();
+                let source_states = &epoch.common_info.source_states;
+                let covered = self
+                    .applied
+                    .as_ref()
+                    .map_or(false, |applied| is_covered(source_states, applied))
+                    || self.applied_through.map_or(false, |applied_through| {
+                        is_covered_through(source_states, applied_through)
+                    });
+                if covered {
+                    return vec![];
+                }
+                // The sink may have applied part of the epoch, or some sources further than others.
+                // Operations sent by processors carry no offset, so they're only skipped with their whole epoch.
+                let (applied, applied_through) = (&self.applied, self.applied_through);
+                ops.retain(|(_, _, _, source_offset)| {
+                    !source_offset.as_ref().map_or(false, |offset| {
+                        applied
+                            .as_ref()
+                            .map_or(false, |applied| is_applied(offset, applied))
+                            || applied_through
+                                .map_or(false, |applied_through| offset.id <= applied_through)
+                    })
+                });
+                match &mut self.applied {
+                    Some(applied) => advance(applied, source_states),
+                    None => self.applied = Some(source_states.as_ref().clone()),
+                }
+                vec![(epoch.clone(), ops)]
+            }
+        }
+    }
+
+    /// Returns the released epoch that hasn't been applied yet, if any.
+    pub fn on_terminate(&mut self) -> Option<(Epoch, EpochOps)> {
+        let deferred = self.deferred.take();
+        self.release(deferred)
+    }
+
+    fn release(
+        &mut self,
+        deferred: Option<(Epoch, EpochOps, Option<SystemTime>)>,
+    ) -> Option<(Epoch, EpochOps)> {
+        let (epoch, ops, watermark) = deferred?;
+        self.released_watermark = watermark;
+        Some((epoch, ops))
+    }
+}
+
+fn is_covered(source_states: &SourceStates, applied: &SourceStates) -> bool {
+    source_states.iter().all(|(node_handle, tables)| {
+        tables.iter().all(|(table_name, state)| {
+            let applied_state = applied
+                .get(node_handle)
+                .and_then(|tables| tables.get(table_name));
+            match (state, applied_state) {
+                (TableState::NotStarted, _) => true,
+                (TableState::Restartable(id), Some(TableState::Restartable(applied_id))) => {
+                    id <= applied_id
+                }
+                _ => false,
+            }
+        })
+    })
+}
+
+fn is_covered_through(source_states: &SourceStates, applied_through: OpIdentifier) -> bool {
+    source_states.values().all(|tables| {
+        tables.values().all(|state| match state {
+            TableState::NotStarted => true,
+            TableState::Restartable(id) => *id <= applied_through,
+            TableState::NonRestartable => false,
+        })
+    })
+}
+
+fn is_applied(offset: &SourceOffset, applied: &SourceStates) -> bool {
+    let (node_handle, table_name) = offset.table.as_ref();
+    matches!(
+        applied.get(node_handle).and_then(|tables| tables.get(table_name)),
+        Some(TableState::Restartable(applied_id)) if offset.id <= *applied_id
+    )
+}
+
+/// Moves `applied` to `source_states`, except for tables `applied` is already past.
+fn advance(applied: &mut SourceStates, source_states: &SourceStates) {
+    for (node_handle, tables) in source_states {
+        let applied_tables = applied.entry(node_handle.clone()).or_default();
+        for (table_name, state) in tables {
+            let applied_state = applied_tables
+                .entry(table_name.clone())
+                .or_insert(TableState::NotStarted);
+            let advances = match (state, &*applied_state) {
+                (TableState::NotStarted, _) => false,
+                (TableState::Restartable(id), TableState::Restartable(applied_id)) => {
+                    id > applied_id
+                }
+                _ => true,
+            };
+            if advances {
+                *applied_state = *state;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, SystemTime},
+    };
+
+    use dozer_log::tokio;
+    use dozer_recordstore::{ProcessorRecordStore, StoreRecord};
+    use dozer_types::{
+        node::{NodeHandle, OpIdentifier},
+        types::{Field, Record},
+    };
+
+    use crate::checkpoint::{create_checkpoint_for_test, CheckpointFactory, CheckpointWriter};
+    use crate::executor::ExecutorOptions;
+    use crate::node::StateBackend;
+    use crate::tests::processors::StateCountingProcessorFactory;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{
+        RecordingSinkFactory, RECORDING_SINK_INPUT_PORT, RECORDING_SINK_INPUT_PORT_2,
+    };
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    const RECORDS_PER_EPOCH: u64 = 10;
+    const NUM_EPOCHS: u64 = 5;
+
+    fn source_states(last_txid: u64) -> SourceStates {
+        let mut tables = HashMap::new();
+        tables.insert(
+            "table".to_string(),
+            TableState::Restartable(OpIdentifier::new(last_txid, 0)),
+        );
+        let mut source_states = SourceStates::new();
+        source_states.insert(NodeHandle::new(None, "source".to_string()), tables);
+        source_states
+    }
+
+    fn source_offset(txid: u64) -> SourceOffset {
+        SourceOffset {
+            table: Arc::new((
+                NodeHandle::new(None, "source".to_string()),
+                "table".to_string(),
+            )),
+            id: OpIdentifier::new(txid, 0),
+        }
+    }
+
+    /// Runs one pipeline lifetime starting at `from_epoch`, checkpointing every epoch to `factory`, if any, once the sink releases it.
+    ///
+    /// If `crash_at` is set, stops while the sink handles the commit of epoch `crash_at`, before it applies what the commit
+    /// released, and without writing any more checkpoints.
+    /// Returns the record ids applied to the sink and the source states of the last epoch the sink committed.
+    fn run(
+        buffer: &mut DeliveryBuffer,
+        record_store: &ProcessorRecordStore,
+        factory: Option<&Arc<CheckpointFactory>>,
+        from_epoch: u64,
+        crash_at: Option<u64>,
+    ) -> (Vec<u64>, Option<SourceStates>) {
+        let mut applied = vec![];
+        let mut committed = None;
+        let mut apply = |ops: EpochOps| {
+            for (_, op, _, _) in ops {
+                let ProcessorOperation::Insert { new } = op else {
+                    panic!("Only inserts are generated");
+                };
+                let record = record_store.load_record(&new).unwrap();
+                let Field::UInt(id) = record.values[0] else {
+                    panic!("Unexpected field");
+                };
+                applied.push(id);
+            }
+        };
+
+        for epoch_id in from_epoch..NUM_EPOCHS {
+            for id in epoch_id * RECORDS_PER_EPOCH..(epoch_id + 1) * RECORDS_PER_EPOCH {
+                let new = record_store
+                    .create_record(&Record::new(vec![Field::UInt(id)]))
+                    .unwrap();
+                if let Some(op) = buffer.on_op(
+                    0,
+                    ProcessorOperation::Insert { new },
+                    Default::default(),
+                    Some(source_offset(id)),
+                ) {
+                    apply(vec![op]);
+                }
+            }
+
+            let source_states = Arc::new(source_states((epoch_id + 1) * RECORDS_PER_EPOCH - 1));
+            let checkpoint_writer = factory.map(|factory| {
+                Arc::new(CheckpointWriter::new(
+                    factory.clone(),
+                    epoch_id,
+                    source_states.clone(),
+                ))
+            });
+            let epoch = Epoch::new(
+                epoch_id,
+                source_states,
+                checkpoint_writer,
+                None,
+                SystemTime::now(),
+            );
+            let released = buffer.on_commit(&epoch);
+            if crash_at == Some(epoch_id) {
+                // Checkpoint writers still held aren't written on abort, like on a crash.
+                if let Some(factory) = factory {
+                    factory.aborted().store(true, Ordering::SeqCst);
+                }
+                return (applied, committed);
+            }
+            for (epoch, ops) in released {
+                apply(ops);
+                committed = Some(epoch.common_info.source_states.as_ref().clone());
+            }
+            // The sink node releases its handle on the epoch once it's handled the commit.
+            drop(epoch);
+        }
+
+        if let Some((_, ops)) = buffer.on_terminate() {
+            apply(ops);
+        }
+        (applied, committed)
+    }
+
+    /// Crashes at epoch 2, then restarts after the last checkpoint written,
+    /// reporting the source states the sink committed as applied.
+    async fn run_with_restart(semantics: DeliverySemantics) -> Vec<u64> {
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let (factory, _worker) = CheckpointFactory::new(checkpoint, Default::default())
+            .await
+            .unwrap();
+        let factory = Arc::new(factory);
+        // Checkpoint writers must be dropped outside the tokio runtime.
+        tokio::task::spawn_blocking(move || {
+            let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+
+            let mut buffer = DeliveryBuffer::new(semantics, None);
+            let (mut applied, committed) =
+                run(&mut buffer, &record_store, Some(&factory), 0, Some(2));
+            drop(buffer);
+            let restart_epoch = factory
+                .last_written_epoch()
+                .map_or(0, |epoch_id| epoch_id + 1);
+            assert!(restart_epoch < 3);
+
+            factory.aborted().store(false, Ordering::SeqCst);
+            let mut buffer = DeliveryBuffer::new(semantics, committed);
+            let (restarted, _) = run(
+                &mut buffer,
+                &record_store,
+                Some(&factory),
+                restart_epoch,
+                None,
+            );
+            applied.extend(restarted);
+            applied
+        })
+        .await
+        .unwrap()
+    }
+
+    fn all_ids() -> Vec<u64> {
+        (0..NUM_EPOCHS * RECORDS_PER_EPOCH).collect()
+    }
+
+    #[tokio::test]
+    async fn at_most_once_never_duplicates() {
+        let mut applied = run_with_restart(DeliverySemantics::AtMostOnce).await;
+        let len = applied.len();
+        applied.sort();
+        applied.dedup();
+        assert_eq!(applied.len(), len);
+        assert!(len < all_ids().len());
+    }
+
+    #[tokio::test]
+    async fn at_least_once_never_loses() {
+        let mut applied = run_with_restart(DeliverySemantics::AtLeastOnce).await;
+        assert!(applied.len() > all_ids().len());
+        applied.sort();
+        applied.dedup();
+        assert_eq!(applied, all_ids());
+    }
+
+    #[tokio::test]
+    async fn exactly_once_skips_applied_epochs() {
+        let applied = run_with_restart(DeliverySemantics::ExactlyOnce).await;
+        assert_eq!(applied, all_ids());
+    }
+
+    #[test]
+    fn exactly_once_skips_ops_applied_mid_epoch() {
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        // The sink applied through the middle of epoch 2, but the pipeline restarts from epoch 0.
+        let applied_through = 2 * RECORDS_PER_EPOCH + 4;
+        let mut buffer = DeliveryBuffer::new(
+            DeliverySemantics::ExactlyOnce,
+            Some(source_states(applied_through)),
+        );
+        let (applied, _) = run(&mut buffer, &record_store, None, 0, None);
+        assert_eq!(
+            applied,
+            (applied_through + 1..NUM_EPOCHS * RECORDS_PER_EPOCH).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn exactly_once_skips_ops_applied_through_offset() {
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        // The sink applied through the middle of epoch 2, but the pipeline restarts from epoch 0.
+        let applied_through = 2 * RECORDS_PER_EPOCH + 4;
+        let mut buffer = DeliveryBuffer::new(DeliverySemantics::ExactlyOnce, None)
+            .with_applied_through(Some(OpIdentifier::new(applied_through, 0)));
+        let (applied, _) = run(&mut buffer, &record_store, None, 0, None);
+        assert_eq!(
+            applied,
+            (applied_through + 1..NUM_EPOCHS * RECORDS_PER_EPOCH).collect::<Vec<_>>()
+        );
+    }
+
+    fn generated_keys(from: u64, count: u64) -> Vec<Field> {
+        (from..count + 1)
+            .map(|n| Field::String(format!("key_{n}")))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_exactly_once_skips_ops_applied_per_source() {
+        let count: u64 = 100;
+        let source_handle_1 = NodeHandle::new(None, 1.to_string());
+        let source_handle_2 = NodeHandle::new(None, 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        // The sink applied the sources through different operations, both in the middle of an epoch.
+        let applied_through = |txid| {
+            HashMap::from([(
+                "generator".to_string(),
+                TableState::Restartable(OpIdentifier::new(txid, 0)),
+            )])
+        };
+        let applied = SourceStates::from([
+            (source_handle_1.clone(), applied_through(40)),
+            (source_handle_2.clone(), applied_through(70)),
+        ]);
+        let sink = RecordingSinkFactory::new()
+            .with_second_port()
+            .with_applied_source_states(applied)
+            .record_ops();
+        let recording = sink.recording();
+
+        let latch = Arc::new(AtomicBool::new(false));
+        let dag = DagBuilder::new()
+            .source(
+                source_handle_1.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .source(
+                source_handle_2.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle_1,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .edge(
+                &source_handle_2,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT_2,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            commit_sz: 25,
+            delivery: DeliverySemantics::ExactlyOnce,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let recording = recording.lock();
+        assert_eq!(
+            recording.keys(RECORDING_SINK_INPUT_PORT),
+            generated_keys(41, count)
+        );
+        assert_eq!(
+            recording.keys(RECORDING_SINK_INPUT_PORT_2),
+            generated_keys(71, count)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_exactly_once_skips_ops_applied_through_offset() {
+        let count: u64 = 100;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        // The sink applied through the middle of the second epoch.
+        let sink = RecordingSinkFactory::new()
+            .with_second_port()
+            .with_applied_through(OpIdentifier::new(40, 0))
+            .record_ops();
+        let recording = sink.recording();
+
+        // The source feeds the sink directly, and through a stateful processor.
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false),
+            )
+            .processor(
+                proc_handle.clone(),
+                StateCountingProcessorFactory::new(StateBackend::Memory),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT_2,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            commit_sz: 25,
+            commit_time_threshold: Duration::from_secs(60),
+            delivery: DeliverySemantics::ExactlyOnce,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let recording = recording.lock();
+        // Operations straight from the source are skipped one by one.
+        assert_eq!(
+            recording.keys(RECORDING_SINK_INPUT_PORT),
+            generated_keys(41, count)
+        );
+        // Operations the processor derived carry no offset, so only the first epoch, wholly applied, is skipped.
+        assert_eq!(
+            recording.keys(RECORDING_SINK_INPUT_PORT_2),
+            generated_keys(26, count)
+        );
+    }
+}
@@ -1,31 +1,35 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
     fmt::Debug,
-    sync::Arc,
+    sync::{atomic::AtomicBool, Arc},
 };
 
 use crate::{
     builder_dag::{BuilderDag, NodeKind, NodeType},
-    checkpoint::{CheckpointFactory, CheckpointFactoryOptions, OptionCheckpoint},
+    checkpoint::{CheckpointFactory, OptionCheckpoint},
     dag_schemas::EdgeKind,
-    epoch::{EpochManager, EpochManagerOptions},
+    dead_letter::{DeadLetterSink, DeadLetterStore, ErrorPolicy},
+    edge_transform::EdgeTransform,
+    epoch::EpochManager,
     error_manager::ErrorManager,
     errors::ExecutionError,
-    executor_operation::ExecutorOperation,
+    forwarder::{EdgeReceiver, EdgeSender, QueueLen},
     hash_map_to_vec::insert_vec_element,
-    node::{OutputPortType, PortHandle},
-    record_store::{create_record_writer, RecordWriter},
+    node::PortHandle,
+    projection::FieldProjection,
+    record_store::{create_record_writer, InputRecordReader, SharedRecordWriter},
+    transport::{TransportReceiver, TransportSender},
 };
-use crossbeam::channel::{bounded, Receiver, Sender};
+
+use super::{inflight::InflightLog, ExecutorOptions};
 use daggy::petgraph::{
     visit::{EdgeRef, IntoEdges, IntoEdgesDirected, IntoNodeIdentifiers},
     Direction,
 };
-use dozer_log::tokio::sync::Mutex;
 use dozer_recordstore::ProcessorRecordStore;
 use dozer_tracing::LabelsAndProgress;
-
-pub type SharedRecordWriter = Arc<Mutex<Option<Box<dyn RecordWriter>>>>;
+use dozer_types::parking_lot::RwLock;
+use dozer_types::types::Schema;
 
 #[derive(Debug, Clone)]
 pub struct EdgeType {
@@ -34,13 +38,27 @@ pub struct EdgeType {
     /// Edge kind.
     pub edge_kind: EdgeKind,
     /// The sender for data flowing downstream.
-    pub sender: Sender<ExecutorOperation>,
+    pub sender: Arc<dyn TransportSender>,
+    /// The sender for high priority operations, see `OperationPriority`.
+    pub priority_sender: Arc<dyn TransportSender>,
+    /// Applied to records sent through this edge, if any.
+    pub projection: Option<FieldProjection>,
+    /// Applied to operations sent through this edge, before `projection`, if any.
+    pub transform: Option<EdgeTransform>,
     /// The record writer for persisting data for downstream queries, if persistency is needed. Different edges with the same output port share the same record writer.
-    pub record_writer: SharedRecordWriter,
+    pub record_writer: Option<SharedRecordWriter>,
     /// Input port handle.
     pub input_port: PortHandle,
     /// The receiver from receiving data from upstream.
-    pub receiver: Receiver<ExecutorOperation>,
+    pub receiver: Arc<dyn TransportReceiver>,
+    /// Counts the messages queued between `sender` and `receiver`.
+    pub queue_len: QueueLen,
+    /// The receiver for high priority operations from upstream.
+    pub priority_receiver: Arc<dyn TransportReceiver>,
+    /// Records what's sent on `sender`, if `ExecutorOptions::track_inflight` is set.
+    pub inflight: Option<Arc<InflightLog>>,
+    /// The schema of the output port, if `ExecutorOptions::validate_schema` is set.
+    pub output_schema: Option<Schema>,
 }
 
 #[derive(Debug)]
@@ -57,11 +75,10 @@ impl ExecutionDag {
         builder_dag: BuilderDag,
         checkpoint: OptionCheckpoint,
         labels: LabelsAndProgress,
-        channel_buffer_sz: usize,
-        error_threshold: Option<u32>,
-        checkpoint_factory_options: CheckpointFactoryOptions,
-        epoch_manager_options: EpochManagerOptions,
+        options: &ExecutorOptions,
     ) -> Result<Self, ExecutionError> {
+        // Unbounded with the single threaded scheduler, as a node can't wait for a downstream node on the same thread.
+        let channel_buffer_sz = (!options.single_threaded).then_some(options.channel_buffer_sz);
         // Count number of sources.
         let num_sources = builder_dag
             .graph()
@@ -76,7 +93,7 @@ impl ExecutionDag {
 
         // We only create record stored once for every output port. Every `HashMap` in this `Vec` tracks if a node's output ports already have the record store created.
         let mut all_record_writers = vec![
-            HashMap::<PortHandle, SharedRecordWriter>::new();
+            HashMap::<PortHandle, Option<SharedRecordWriter>>::new();
             builder_dag.graph().node_count()
         ];
 
@@ -89,62 +106,115 @@ impl ExecutionDag {
             let edge_kind = edge.edge_kind.clone();
 
             // Create or get record store.
-            let record_writer =
-                match all_record_writers[source_node_index.index()].entry(output_port) {
-                    Entry::Vacant(entry) => {
-                        let record_writer = match &edge_kind {
-                            EdgeKind::FromSource {
-                                port_type: OutputPortType::StatefulWithPrimaryKeyLookup,
-                                port_name,
-                            } => {
-                                let record_writer_data = checkpoint
-                                    .load_record_writer_data(
-                                        &builder_dag.graph()[source_node_index].handle,
-                                        port_name,
-                                    )
-                                    .await?;
-                                Some(
-                                    create_record_writer(
-                                        edge.schema.clone(),
-                                        checkpoint.record_store(),
-                                        record_writer_data,
-                                    )
-                                    .map_err(ExecutionError::RestoreRecordWriter)?,
+            let record_writer = match all_record_writers[source_node_index.index()]
+                .entry(output_port)
+            {
+                Entry::Vacant(entry) => {
+                    let record_writer = match &edge_kind {
+                        EdgeKind::FromSource {
+                            port_type,
+                            port_name,
+                            ..
+                        } if port_type.is_stateful() => {
+                            let record_writer_data = checkpoint
+                                .load_record_writer_data(
+                                    &builder_dag.graph()[source_node_index].handle,
+                                    port_name,
                                 )
-                            }
-                            _ => None,
-                        };
-                        let record_writer = Arc::new(Mutex::new(record_writer));
-                        entry.insert(record_writer).clone()
-                    }
-                    Entry::Occupied(entry) => entry.get().clone(),
-                };
-
-            // Create channel.
-            let (sender, receiver) = bounded(channel_buffer_sz);
+                                .await?;
+                            Some(
+                                create_record_writer(
+                                    edge.schema.clone(),
+                                    *port_type,
+                                    checkpoint.record_store(),
+                                    record_writer_data,
+                                )
+                                .map_err(ExecutionError::RestoreRecordWriter)?,
+                            )
+                        }
+                        _ => None,
+                    };
+                    let record_writer = record_writer.map(|writer| Arc::new(RwLock::new(writer)));
+                    entry.insert(record_writer).clone()
+                }
+                Entry::Occupied(entry) => entry.get().clone(),
+            };
+
+            // Create channels. Priority operations are rare, so their channel doesn't apply backpressure.
+            let (sender, receiver) = edge.transport.channel(channel_buffer_sz);
+            let (priority_sender, priority_receiver) = edge.transport.channel(None);
+            let inflight = options.track_inflight.then(|| {
+                Arc::new(InflightLog::new(
+                    edge.input_schema.primary_index.clone(),
+                    channel_buffer_sz,
+                ))
+            });
 
             // Create edge.
             let edge = EdgeType {
                 output_port,
                 edge_kind,
                 sender,
+                priority_sender,
+                projection: edge.projection.clone(),
+                transform: edge.transform.clone(),
                 record_writer,
                 input_port: edge.input_port,
                 receiver,
+                priority_receiver,
+                queue_len: QueueLen::default(),
+                inflight,
+                output_schema: options.validate_schema.then(|| edge.schema.clone()),
             };
             edges.push(Some(edge));
         }
 
+        let mut error_manager = if let Some(threshold) = options.error_threshold {
+            ErrorManager::new_threshold(threshold)
+        } else {
+            ErrorManager::new_unlimited()
+        };
+        if let ErrorPolicy::DeadLetterStore { path } = &options.error_policy {
+            let schemas = builder_dag
+                .graph()
+                .raw_edges()
+                .iter()
+                .map(|edge| {
+                    let node = builder_dag.graph()[edge.target()].handle.clone();
+                    (
+                        (node, edge.weight.input_port),
+                        edge.weight.input_schema.clone(),
+                    )
+                })
+                .collect();
+            error_manager = error_manager.with_dead_letters(DeadLetterSink {
+                store: DeadLetterStore::open(path)?,
+                schemas,
+            });
+        }
+        if let Some(error_sampling) = options.error_sampling.clone() {
+            error_manager = error_manager.with_sampling(error_sampling);
+        }
+        if let Some(max_record_bytes) = options.max_record_bytes {
+            error_manager = error_manager.with_max_record_bytes(max_record_bytes);
+        }
+
         // Create new graph.
         let initial_epoch_id = checkpoint.next_epoch_id();
-        let (checkpoint_factory, _) =
-            CheckpointFactory::new(checkpoint, checkpoint_factory_options).await?;
-        let epoch_manager = Arc::new(EpochManager::new(
+        let (mut checkpoint_factory, _) =
+            CheckpointFactory::new(checkpoint, options.checkpoint_factory_options.clone()).await?;
+        if let Some(on_checkpoint) = options.on_checkpoint.clone() {
+            checkpoint_factory.set_on_checkpoint(on_checkpoint);
+        }
+        checkpoint_factory.set_retention(options.checkpoint_retention);
+        let mut epoch_manager = EpochManager::new(
             num_sources,
             initial_epoch_id,
             Arc::new(checkpoint_factory),
-            epoch_manager_options,
-        ));
+            options.epoch_manager_options.clone(),
+        );
+        epoch_manager.set_persists(options.checkpointing);
+        let epoch_manager = Arc::new(epoch_manager);
         let graph = builder_dag.into_graph().map_owned(
             |_, node| Some(node),
             |edge_index, _| {
@@ -156,11 +226,7 @@ impl ExecutionDag {
         Ok(ExecutionDag {
             graph,
             epoch_manager,
-            error_manager: Arc::new(if let Some(threshold) = error_threshold {
-                ErrorManager::new_threshold(threshold)
-            } else {
-                ErrorManager::new_unlimited()
-            }),
+            error_manager: Arc::new(error_manager),
             labels,
         })
     }
@@ -181,6 +247,10 @@ impl ExecutionDag {
         &self.epoch_manager
     }
 
+    pub fn aborted(&self) -> &Arc<AtomicBool> {
+        self.epoch_manager.aborted()
+    }
+
     pub fn error_manager(&self) -> &Arc<ErrorManager> {
         &self.error_manager
     }
@@ -189,13 +259,12 @@ impl ExecutionDag {
         &self.labels
     }
 
-    #[allow(clippy::type_complexity)]
-    pub async fn collect_senders_and_record_writers(
+    pub fn collect_senders_and_record_writers(
         &mut self,
         node_index: daggy::NodeIndex,
     ) -> (
-        HashMap<PortHandle, Vec<Sender<ExecutorOperation>>>,
-        HashMap<PortHandle, Box<dyn RecordWriter>>,
+        HashMap<PortHandle, Vec<EdgeSender>>,
+        HashMap<PortHandle, SharedRecordWriter>,
     ) {
         let edge_indexes = self
             .graph
@@ -210,22 +279,76 @@ impl ExecutionDag {
                 .graph
                 .edge_weight_mut(edge_index)
                 .expect("We don't modify graph structure, only modify the edge weight");
-            insert_vec_element(&mut senders, edge.output_port, edge.sender.clone());
-            if let Entry::Vacant(entry) = record_writers.entry(edge.output_port) {
-                // This interior mutability is to word around `Arc`. Other parts of this function is correctly marked `mut`.
-                if let Some(record_writer) = edge.record_writer.lock().await.take() {
-                    entry.insert(record_writer);
-                }
+            insert_vec_element(
+                &mut senders,
+                edge.output_port,
+                EdgeSender {
+                    sender: edge.sender.clone(),
+                    queue_len: edge.queue_len.clone(),
+                    priority_sender: edge.priority_sender.clone(),
+                    projection: edge.projection.clone(),
+                    transform: edge.transform.clone(),
+                    compression: match &edge.edge_kind {
+                        EdgeKind::FromSource { compression, .. } => *compression,
+                        EdgeKind::FromProcessor => None,
+                    },
+                    inflight: edge.inflight.clone(),
+                },
+            );
+            if let Some(record_writer) = &edge.record_writer {
+                record_writers
+                    .entry(edge.output_port)
+                    .or_insert_with(|| record_writer.clone());
             }
         }
 
         (senders, record_writers)
     }
 
+    /// Returns the schemas to validate the records sent from every output port of the node against.
+    ///
+    /// Empty unless `ExecutorOptions::validate_schema` is set.
+    pub fn collect_output_schemas(
+        &self,
+        node_index: daggy::NodeIndex,
+    ) -> HashMap<PortHandle, Schema> {
+        self.graph
+            .edges(node_index)
+            .filter_map(|edge| {
+                let edge = edge.weight();
+                Some((edge.output_port, edge.output_schema.clone()?))
+            })
+            .collect()
+    }
+
+    /// Returns a reader for every input port of the node that's connected to a stateful output port.
+    pub fn collect_record_readers(
+        &self,
+        node_index: daggy::NodeIndex,
+    ) -> HashMap<PortHandle, InputRecordReader> {
+        self.graph
+            .edges_directed(node_index, Direction::Incoming)
+            .filter_map(|edge| {
+                let edge = edge.weight();
+                let record_writer = edge.record_writer.as_ref()?;
+                Some((
+                    edge.input_port,
+                    InputRecordReader::new(record_writer.clone()),
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the input ports of the node, and the normal and priority receivers of each.
+    #[allow(clippy::type_complexity)]
     pub fn collect_receivers(
         &mut self,
         node_index: daggy::NodeIndex,
-    ) -> (Vec<PortHandle>, Vec<Receiver<ExecutorOperation>>) {
+    ) -> (
+        Vec<PortHandle>,
+        Vec<EdgeReceiver>,
+        Vec<Arc<dyn TransportReceiver>>,
+    ) {
         let edge_indexes = self
             .graph
             .edges_directed(node_index, Direction::Incoming)
@@ -234,14 +357,19 @@ impl ExecutionDag {
 
         let mut input_ports = Vec::new();
         let mut receivers = Vec::new();
+        let mut priority_receivers = Vec::new();
         for edge_index in edge_indexes {
             let edge = self
                 .graph
                 .edge_weight_mut(edge_index)
                 .expect("We don't modify graph structure, only modify the edge weight");
             input_ports.push(edge.input_port);
-            receivers.push(edge.receiver.clone());
+            receivers.push(EdgeReceiver::new(
+                edge.receiver.clone(),
+                edge.queue_len.clone(),
+            ));
+            priority_receivers.push(edge.priority_receiver.clone());
         }
-        (input_ports, receivers)
+        (input_ports, receivers, priority_receivers)
     }
 }
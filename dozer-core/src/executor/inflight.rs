@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+
+use dozer_recordstore::ProcessorRecordStore;
+use dozer_types::parking_lot::Mutex;
+use dozer_types::types::{Field, Operation, Record};
+
+use crate::errors::ExecutionError;
+use crate::executor_operation::{ExecutorOperation, ProcessorOperation};
+
+/// Summaries kept for an unbounded edge, whose queue can grow past any capacity.
+const UNBOUNDED_CAPACITY: usize = 10_000;
+
+/// What kind of message is queued on an edge, see [`OperationSummary`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    Insert,
+    Delete,
+    Update,
+    Commit,
+    Terminate,
+    SnapshottingDone,
+    PortClosed,
+    Watermark,
+}
+
+/// An operation queued on an edge, returned from [`DagExecutorJoinHandle::dump_inflight`](super::DagExecutorJoinHandle::dump_inflight).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationSummary {
+    pub kind: OperationKind,
+    /// The primary key of the operation's record, or all its fields if the edge's schema has no primary key.
+    /// The new record's for updates. `None` for control messages like commits.
+    pub key: Option<Vec<Field>>,
+}
+
+/// Summaries of the messages last sent on an edge, so the ones still queued can be dumped.
+///
+/// A channel is first in, first out, so the queued messages are the newest as many summaries as the channel's length.
+#[derive(Debug)]
+pub(crate) struct InflightLog {
+    primary_index: Vec<usize>,
+    capacity: usize,
+    summaries: Mutex<VecDeque<OperationSummary>>,
+}
+
+impl InflightLog {
+    /// `capacity` is the edge's channel capacity, `None` if it's unbounded.
+    pub fn new(primary_index: Vec<usize>, capacity: Option<usize>) -> Self {
+        Self {
+            primary_index,
+            capacity: capacity.unwrap_or(UNBOUNDED_CAPACITY),
+            summaries: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Summarizes `op` before it's sent, so the summary can be recorded with [`record`](Self::record) once it is.
+    pub fn summarize_op(
+        &self,
+        op: &ProcessorOperation,
+        record_store: &ProcessorRecordStore,
+    ) -> Result<OperationSummary, ExecutionError> {
+        let (kind, record) = match op.load(record_store)? {
+            Operation::Insert { new } => (OperationKind::Insert, new),
+            Operation::Delete { old } => (OperationKind::Delete, old),
+            Operation::Update { new, .. } => (OperationKind::Update, new),
+        };
+        Ok(OperationSummary {
+            kind,
+            key: Some(self.key(record)),
+        })
+    }
+
+    /// Summarizes a control message. Operations must be summarized with [`summarize_op`](Self::summarize_op).
+    pub fn summarize_control(op: &ExecutorOperation) -> OperationSummary {
+        let kind = match op {
+            ExecutorOperation::Commit { .. } => OperationKind::Commit,
+            ExecutorOperation::Terminate => OperationKind::Terminate,
+            ExecutorOperation::SnapshottingDone { .. } => OperationKind::SnapshottingDone,
+            ExecutorOperation::PortClosed => OperationKind::PortClosed,
+            ExecutorOperation::Watermark { .. } => OperationKind::Watermark,
+            ExecutorOperation::Op { .. } | ExecutorOperation::CompressedOp { .. } => {
+                unreachable!("Operations are summarized with their records")
+            }
+        };
+        OperationSummary { kind, key: None }
+    }
+
+    pub fn record(&self, summary: OperationSummary) {
+        let mut summaries = self.summaries.lock();
+        if summaries.len() == self.capacity {
+            summaries.pop_front();
+        }
+        summaries.push_back(summary);
+    }
+
+    /// The summaries of the `queue_len` messages sent last, oldest first.
+    pub fn queued(&self, queue_len: usize) -> Vec<OperationSummary> {
+        let summaries = self.summaries.lock();
+        let skip = summaries.len().saturating_sub(queue_len);
+        summaries.iter().skip(skip).cloned().collect()
+    }
+
+    fn key(&self, record: Record) -> Vec<Field> {
+        if self.primary_index.is_empty() {
+            return record.values;
+        }
+        self.primary_index
+            .iter()
+            .map(|index| record.values[*index].clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::executor::{DagExecutor, ExecutorOptions};
+    use crate::tests::processors::RecordingProcessorFactory;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{DagBuilder, Edge, Endpoint, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_dump_inflight_of_stalled_edge() {
+        let count: u64 = 1_000;
+        let channel_buffer_sz = 4;
+        let release = Arc::new(AtomicBool::new(false));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false),
+            )
+            .processor(
+                proc_handle.clone(),
+                RecordingProcessorFactory::new().block_until(release.clone()),
+            )
+            .sink(sink_handle.clone(), RecordingSinkFactory::new())
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        let stalled_edge = Edge::new(
+            Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+        );
+
+        let options = ExecutorOptions {
+            channel_buffer_sz,
+            commit_sz: 1_000_000,
+            commit_time_threshold: Duration::from_secs(3600),
+            track_inflight: true,
+            ..Default::default()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+
+        // The processor is stuck on the first operation, so the channel into it fills up.
+        let start = Instant::now();
+        let dump = loop {
+            let dump = join_handle.dump_inflight();
+            if dump[&stalled_edge].len() == channel_buffer_sz {
+                break dump;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        };
+        let summaries = &dump[&stalled_edge];
+        assert!(summaries
+            .iter()
+            .all(|summary| summary.kind == OperationKind::Insert));
+        let keys = summaries
+            .iter()
+            .map(|summary| summary.key.clone().unwrap())
+            .collect::<Vec<_>>();
+        let first = match &keys[0][..] {
+            [Field::String(key)] => key["key_".len()..].parse::<u64>().unwrap(),
+            key => panic!("Unexpected key {key:?}"),
+        };
+        assert!(first > 1);
+        assert_eq!(
+            keys,
+            (first..first + channel_buffer_sz as u64)
+                .map(|n| vec![Field::String(format!("key_{n}"))])
+                .collect::<Vec<_>>()
+        );
+        let into_sink = Edge::new(
+            Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+            Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+        );
+        assert!(dump[&into_sink].is_empty());
+
+        release.store(true, Ordering::SeqCst);
+        join_handle.join().unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+/// A rough estimate of the memory an operation buffered in a channel holds on to, including the records it references.
+const ESTIMATED_OPERATION_SIZE: usize = 256;
+
+/// Returns the capacity of each of the `num_channels` channels so their buffers fit in `budget`,
+/// never above `max_capacity` and never below 1.
+pub(crate) fn channel_capacity(budget: usize, num_channels: usize, max_capacity: usize) -> usize {
+    let per_channel = budget / 2 / num_channels.max(1) / ESTIMATED_OPERATION_SIZE;
+    per_channel.clamp(1, max_capacity.max(1))
+}
+
+/// Returns the memtable size above which the record store should be flushed to disk.
+pub(crate) fn memtable_budget(budget: usize) -> usize {
+    budget / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use dozer_log::tokio;
+    use dozer_types::models::app_config::RecordStore;
+    use dozer_types::node::NodeHandle;
+    use tempdir::TempDir;
+
+    use crate::checkpoint::{CheckpointOptions, OptionCheckpoint};
+    use crate::executor::{DagExecutor, ExecutorOptions};
+    use crate::tests::dir_entries;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{Dag, DagBuilder, Endpoint};
+
+    use super::*;
+
+    #[test]
+    fn channel_capacity_fits_budget() {
+        let budget = 1024 * 1024;
+        let capacity = channel_capacity(budget, 4, 20_000);
+        assert_eq!(capacity, 512);
+        assert!(capacity * 4 * ESTIMATED_OPERATION_SIZE + memtable_budget(budget) <= budget);
+    }
+
+    #[test]
+    fn channel_capacity_is_bounded() {
+        assert_eq!(channel_capacity(usize::MAX, 4, 20_000), 20_000);
+        assert_eq!(channel_capacity(0, 4, 20_000), 1);
+        assert_eq!(channel_capacity(1024, 0, 20_000), 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_memory_budget() {
+        let count: u64 = 100_000;
+        let memory_budget = 64 * 1024;
+
+        let mut dag = Dag::new();
+        let latch = Arc::new(AtomicBool::new(true));
+        let sent = Arc::new(AtomicU64::new(0));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let sink = RecordingSinkFactory::new()
+            .stop_after(count, latch.clone())
+            .with_queue_depth(sent.clone());
+        let recording = sink.recording();
+        dag.add_source(
+            source_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(count, latch, false).with_sent_counter(sent)),
+        );
+        dag.add_sink(sink_handle.clone(), Box::new(sink));
+
+        dag.connect(
+            Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        let options = ExecutorOptions {
+            memory_budget: Some(memory_budget),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        // The source listener channel and the edge channel get a quarter of the budget each,
+        // and the listener and the sink may hold one operation each.
+        let channel_capacity = memory_budget as u64 / 4 / ESTIMATED_OPERATION_SIZE as u64;
+        assert!(recording.lock().max_queue_depth <= 2 * channel_capacity + 2);
+    }
+
+    /// Runs `count` records through a RocksDB record store, returning the number of table files it flushed to disk.
+    async fn record_store_tables(count: u64, memory_budget: Option<usize>) -> usize {
+        let running = Arc::new(AtomicBool::new(true));
+        let checkpoint_dir = TempDir::new("test_run_dag_flushes_memtables_checkpoint").unwrap();
+        let spill_dir = TempDir::new("test_run_dag_flushes_memtables_spill").unwrap();
+        let sink = RecordingSinkFactory::new();
+        let received = sink.received();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, running.clone(), false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let checkpoint = OptionCheckpoint::new(
+            checkpoint_dir.path().to_str().unwrap().to_string(),
+            CheckpointOptions {
+                record_store: RecordStore::Rocksdb(Default::default()),
+                spill_path: Some(spill_dir.path().to_path_buf()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let options = ExecutorOptions {
+            commit_sz: 100,
+            memory_budget,
+            ..Default::default()
+        };
+        let join_handle = DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        while received.load(Ordering::SeqCst) < count {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        // The record store only exists while the executor runs, so it's checked before stopping the source.
+        let store_dir = dir_entries(spill_dir.path())
+            .into_iter()
+            .find(|name| name.starts_with("rocksdb_processor_record_store"))
+            .unwrap();
+        let tables = dir_entries(&spill_dir.path().join(store_dir))
+            .into_iter()
+            .filter(|name| name.ends_with(".sst"))
+            .count();
+
+        running.store(false, Ordering::SeqCst);
+        join_handle.join().unwrap();
+        tables
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_flushes_memtables_over_budget() {
+        // The records take well over the 32 KiB of the budget that bounds memtables.
+        let count: u64 = 10_000;
+        assert!(record_store_tables(count, Some(64 * 1024)).await > 0);
+
+        // RocksDB's own memtable limit is far above the records without a budget, so they're never flushed.
+        assert_eq!(record_store_tables(count, None).await, 0);
+    }
+}
@@ -1,30 +1,183 @@
-use crate::builder_dag::{BuilderDag, NodeKind};
-use crate::checkpoint::{CheckpointFactoryOptions, OptionCheckpoint};
+use crate::builder_dag::{check_state_restorable, BuilderDag, NodeKind};
+use crate::checkpoint::{
+    CheckpointCallback, CheckpointFactoryOptions, CheckpointOptions, OptionCheckpoint,
+};
 use crate::dag_schemas::DagSchemas;
-use crate::epoch::EpochManagerOptions;
+use crate::dead_letter::ErrorPolicy;
+use crate::epoch::{EpochManager, EpochManagerOptions};
+use crate::error_manager::{ErrorManager, ErrorSamplingOptions};
 use crate::errors::ExecutionError;
-use crate::Dag;
+use crate::executor_operation::ExecutorOperation;
+use crate::forwarder::QueueLen;
+use crate::node::{Progress, Source};
+use crate::{Dag, Edge};
 
 use daggy::petgraph::visit::IntoNodeIdentifiers;
 
+use dozer_log::tokio;
 use dozer_tracing::LabelsAndProgress;
+use dozer_types::log::info;
+use dozer_types::node::NodeHandle;
+use dozer_types::parking_lot::Mutex;
 use dozer_types::serde::{self, Deserialize, Serialize};
+use dozer_types::types::Operation;
+use std::any::Any;
+use std::collections::HashMap;
 use std::fmt::Debug;
-use std::panic::panic_any;
-use std::sync::atomic::AtomicBool;
+use std::io::{Read, Write};
+use std::panic::resume_unwind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::thread::JoinHandle;
 use std::thread::{self, Builder};
-use std::time::Duration;
+use std::thread::{JoinHandle, ThreadId};
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct ExecutorOptions {
     pub commit_sz: u32,
     pub channel_buffer_sz: usize,
     pub commit_time_threshold: Duration,
+    /// Replace `commit_sz` and `commit_time_threshold` for sources in [`SourceMode::Backfill`](crate::node::SourceMode::Backfill),
+    /// until they're done snapshotting.
+    pub backfill_commit_sz: u32,
+    pub backfill_commit_time_threshold: Duration,
     pub error_threshold: Option<u32>,
     pub epoch_manager_options: EpochManagerOptions,
     pub checkpoint_factory_options: CheckpointFactoryOptions,
+    pub delivery: DeliverySemantics,
+    /// Caps the memory of buffered operations and record store memtables, in bytes.
+    ///
+    /// Half of it bounds the channel buffers, which throttles sources when downstream falls behind.
+    /// The other half bounds record store memtables, which are flushed to disk on commit when over it.
+    pub memory_budget: Option<usize>,
+    /// Where processor state storage is provisioned, see [`StateBackend`](crate::node::StateBackend).
+    ///
+    /// State is kept across runs if set, along with a snapshot of it at every checkpoint, which is restored when the executor
+    /// resumes from the checkpoint. Otherwise a temporary directory is used and removed with the executor.
+    pub state_dir: Option<PathBuf>,
+    /// Where temporary storage is created instead of the system temp dir, like processor state if `state_dir` is not set.
+    ///
+    /// The record store is created with the checkpoint, so pass the same path in `CheckpointOptions::spill_path` to move it too.
+    pub spill_path: Option<PathBuf>,
+    /// Adapts source batch sizes to a target latency if set, replacing `commit_sz` and `backfill_commit_sz`.
+    pub adaptive_batching: Option<AdaptiveBatchConfig>,
+    /// Called after every checkpoint becomes durable, with the state it has of every source.
+    ///
+    /// Only called if `epoch_manager_options.enable_app_checkpoints` is set. It runs on the tokio runtime, so it shouldn't block.
+    pub on_checkpoint: Option<CheckpointCallback>,
+    /// Number of most recent checkpoints whose processor states are kept, 0 to keep all.
+    ///
+    /// Older ones are deleted after every new checkpoint becomes durable. Snapshots of processor state storage are kept for one more checkpoint,
+    /// which may not be durable yet.
+    pub checkpoint_retention: usize,
+    /// Stops the pipeline like [`DagExecutorJoinHandle::drain_and_stop`] once sinks have processed this many operations in total.
+    ///
+    /// Operations already sent by sources are still delivered, so sinks may process a few more.
+    pub max_operations: Option<u64>,
+    /// What happens to operations that processors and sinks fail to process.
+    pub error_policy: ErrorPolicy,
+    /// Aggregates identical errors of a node into periodic rollups if set, instead of logging every one.
+    ///
+    /// Applies to operations that fail without being persisted as dead letters. They still count towards `error_threshold`.
+    pub error_sampling: Option<ErrorSamplingOptions>,
+    /// What happens when a processor panics while processing an operation.
+    pub supervision: SupervisionPolicy,
+    /// Applied to every operation as it enters the DAG from a source, before it's sent to any processor or sink.
+    pub ingress_transform: Option<IngressTransform>,
+    /// Runs all processors and sinks on one thread, taking turns in a fixed order, so runs are reproducible.
+    ///
+    /// Each node reads its inputs in strict rotation, so given the same operations and epochs from every source,
+    /// every node processes the same operations in the same order on every run. Sources still run on their own threads.
+    /// Channels between nodes are unbounded in this mode, as a node can't wait for a downstream node on the same thread.
+    pub single_threaded: bool,
+    /// Makes [`DagExecutorJoinHandle::drain_and_stop`] wait for the pipeline to drain, giving up on an edge
+    /// whose queued operations haven't gone down for this long.
+    ///
+    /// If not set, `drain_and_stop` returns right away and only [`join`](DagExecutorJoinHandle::join) waits.
+    pub drain_timeout: Option<Duration>,
+    /// Operations longer than this many bytes when encoded are rejected where they enter a processor or sink,
+    /// and handled by `error_policy` with [`ExecutionError::RecordTooLarge`] instead of being processed.
+    ///
+    /// Every operation is encoded to be measured, so this costs some throughput.
+    pub max_record_bytes: Option<usize>,
+    /// Checks every record a source or processor sends against the schema of the port it's sent from.
+    ///
+    /// Records with the wrong number of fields, or a field of the wrong type or null where it isn't nullable,
+    /// are handled by `error_policy` with [`ExecutionError::RecordSchemaViolation`] instead of being sent.
+    pub validate_schema: bool,
+    /// Holds sources back until every processor and sink has returned from `init`.
+    ///
+    /// Without it, nodes initialize concurrently with sources starting, and operations queue on the channels
+    /// until their nodes are ready.
+    pub strict_startup_ordering: bool,
+    /// Flushes processor state and the record store to disk when the pipeline stops gracefully, see [`Processor::flush`](crate::node::Processor::flush).
+    ///
+    /// Stopping is slower, but the state doesn't depend on RocksDB replaying its write-ahead log when it's reopened.
+    /// Nothing is flushed if the executor is aborted.
+    pub flush_on_stop: bool,
+    /// Records a summary of every message sent on an edge, so [`DagExecutorJoinHandle::dump_inflight`] can tell what's queued.
+    ///
+    /// It's for debugging a stalled pipeline, as loading every record to summarize it costs throughput.
+    pub track_inflight: bool,
+    /// Persists checkpoints and sink state if set, so the pipeline can resume where it stopped. On by default.
+    ///
+    /// Turn it off for pipelines that don't need to survive a restart: nothing is written to the checkpoint storage,
+    /// `state_dir` is ignored for a temporary directory, and [`DagExecutorJoinHandle::snapshot`] fails.
+    /// The executor still resumes from the checkpoint it's given.
+    pub checkpointing: bool,
+    /// Watches the pipeline in the background, and makes [`DagExecutorJoinHandle::join`] fail with
+    /// [`ExecutionError::DeadlockSuspected`] once it's made no progress for this long with operations queued on some edge.
+    ///
+    /// Bounded channels can deadlock a valid DAG, like a processor waiting for an operation on one input port
+    /// while its upstream is blocked sending on the other. A processor that takes longer than this on one operation
+    /// is suspected too. The nodes are left blocked, so abort the executor after the error.
+    pub deadlock_timeout: Option<Duration>,
+}
+
+pub type IngressTransform = Arc<dyn Fn(&mut Operation) + Send + Sync>;
+
+impl Debug for ExecutorOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecutorOptions")
+            .field("commit_sz", &self.commit_sz)
+            .field("channel_buffer_sz", &self.channel_buffer_sz)
+            .field("commit_time_threshold", &self.commit_time_threshold)
+            .field("backfill_commit_sz", &self.backfill_commit_sz)
+            .field(
+                "backfill_commit_time_threshold",
+                &self.backfill_commit_time_threshold,
+            )
+            .field("error_threshold", &self.error_threshold)
+            .field("epoch_manager_options", &self.epoch_manager_options)
+            .field(
+                "checkpoint_factory_options",
+                &self.checkpoint_factory_options,
+            )
+            .field("delivery", &self.delivery)
+            .field("memory_budget", &self.memory_budget)
+            .field("state_dir", &self.state_dir)
+            .field("spill_path", &self.spill_path)
+            .field("adaptive_batching", &self.adaptive_batching)
+            .field("on_checkpoint", &self.on_checkpoint.is_some())
+            .field("checkpoint_retention", &self.checkpoint_retention)
+            .field("max_operations", &self.max_operations)
+            .field("error_policy", &self.error_policy)
+            .field("error_sampling", &self.error_sampling)
+            .field("supervision", &self.supervision)
+            .field("ingress_transform", &self.ingress_transform.is_some())
+            .field("single_threaded", &self.single_threaded)
+            .field("drain_timeout", &self.drain_timeout)
+            .field("max_record_bytes", &self.max_record_bytes)
+            .field("validate_schema", &self.validate_schema)
+            .field("strict_startup_ordering", &self.strict_startup_ordering)
+            .field("flush_on_stop", &self.flush_on_stop)
+            .field("track_inflight", &self.track_inflight)
+            .field("checkpointing", &self.checkpointing)
+            .field("deadlock_timeout", &self.deadlock_timeout)
+            .finish()
+    }
 }
 
 impl Default for ExecutorOptions {
@@ -33,9 +186,32 @@ impl Default for ExecutorOptions {
             commit_sz: 10_000,
             channel_buffer_sz: 20_000,
             commit_time_threshold: Duration::from_millis(50),
+            backfill_commit_sz: 100_000,
+            backfill_commit_time_threshold: Duration::from_secs(1),
             error_threshold: Some(0),
             epoch_manager_options: Default::default(),
             checkpoint_factory_options: Default::default(),
+            delivery: Default::default(),
+            memory_budget: None,
+            state_dir: None,
+            spill_path: None,
+            adaptive_batching: None,
+            on_checkpoint: None,
+            checkpoint_retention: 0,
+            max_operations: None,
+            error_policy: Default::default(),
+            error_sampling: None,
+            supervision: Default::default(),
+            ingress_transform: None,
+            single_threaded: false,
+            drain_timeout: None,
+            max_record_bytes: None,
+            validate_schema: false,
+            strict_startup_ordering: false,
+            flush_on_stop: false,
+            track_inflight: false,
+            checkpointing: true,
+            deadlock_timeout: None,
         }
     }
 }
@@ -47,29 +223,96 @@ pub(crate) enum InputPortState {
     Terminated,
 }
 
+mod adaptive_batching;
+mod benchmark;
+mod dag_info;
+mod deadlock;
+mod delivery;
 mod execution_dag;
+mod inflight;
+mod memory_budget;
 mod name;
 mod node;
+mod node_metrics;
 mod processor_node;
 mod receiver_loop;
+mod single_threaded;
 mod sink_node;
 mod source_node;
+mod startup;
+mod total_order;
+mod watermark;
 
+pub use adaptive_batching::AdaptiveBatchConfig;
+pub(crate) use adaptive_batching::AdaptiveBatchController;
+pub use benchmark::BenchmarkReport;
+pub use dag_info::{DagInfo, DagNodeType, EdgeInfo, NodeInfo};
+pub use delivery::DeliverySemantics;
+pub(crate) use inflight::InflightLog;
+pub use inflight::{OperationKind, OperationSummary};
+pub(crate) use memory_budget::memtable_budget;
 use node::Node;
+use node_metrics::ExecutorMetrics;
 use processor_node::ProcessorNode;
-use sink_node::SinkNode;
+pub use processor_node::SupervisionPolicy;
+use receiver_loop::ReceiverLoop;
+use single_threaded::SingleThreadedScheduler;
+use sink_node::{OperationLimit, SinkNode};
+use startup::StartupGate;
 
+use self::deadlock::DeadlockDetector;
 use self::execution_dag::ExecutionDag;
 use self::source_node::{create_source_nodes, SourceListenerNode, SourceSenderNode};
 
 pub struct DagExecutor {
     builder_dag: BuilderDag,
+    dag_info: DagInfo,
     checkpoint: OptionCheckpoint,
     options: ExecutorOptions,
+    metrics: Arc<ExecutorMetrics>,
+    /// Holds processor state if `options.state_dir` is not set.
+    state_temp_dir: Option<TempDir>,
 }
 
 pub struct DagExecutorJoinHandle {
     join_handles: Vec<JoinHandle<()>>,
+    /// The threads running sources, which aren't joined on abort as they may be blocked inside the source.
+    source_senders: Vec<ThreadId>,
+    aborted: Arc<AtomicBool>,
+    epoch_manager: Arc<EpochManager>,
+    error_manager: Arc<ErrorManager>,
+    running: Arc<AtomicBool>,
+    sources: Vec<(NodeHandle, Arc<dyn Source>)>,
+    drain_timeout: Option<Duration>,
+    /// The queue of every edge, to watch while draining. Only kept if `drain_timeout` is set.
+    drain_queues: Vec<(Edge, QueueLen)>,
+    /// The queue and log of every edge. Only kept if `track_inflight` is set.
+    inflight: Vec<(Edge, QueueLen, Arc<InflightLog>)>,
+    /// Number of operations every sink has received.
+    sink_counts: HashMap<NodeHandle, Arc<AtomicU64>>,
+    metrics: Arc<ExecutorMetrics>,
+    /// Flush the record store once all nodes have quit.
+    flush_on_stop: bool,
+    /// The epoch of the checkpoint the executor resumed from.
+    resumed_epoch: Option<u64>,
+    /// The nodes suspected of being deadlocked, set by the deadlock detector if `deadlock_timeout` is set.
+    suspected_deadlock: Arc<Mutex<Option<Vec<NodeHandle>>>>,
+    /// The first panic of a node that only failed because a neighbour quit, with the time it is surfaced at
+    /// if the neighbour's own panic hasn't been joined by then.
+    disconnected_panic: Option<(Box<dyn Any + Send>, Instant)>,
+    _state_temp_dir: Option<TempDir>,
+}
+
+/// Aborts a running executor, see [`DagExecutorJoinHandle::abort`].
+#[derive(Debug, Clone)]
+pub struct AbortHandle {
+    aborted: Arc<AtomicBool>,
+}
+
+impl AbortHandle {
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
 }
 
 impl DagExecutor {
@@ -80,15 +323,168 @@ impl DagExecutor {
     ) -> Result<Self, ExecutionError> {
         let dag_schemas = DagSchemas::new(dag)?;
 
-        let builder_dag = BuilderDag::new(&checkpoint, dag_schemas).await?;
+        let state_dir = options.state_dir.as_ref().filter(|_| options.checkpointing);
+        let (state_dir, state_temp_dir) = match state_dir {
+            Some(state_dir) => (state_dir.clone(), None),
+            None => {
+                let parent = options
+                    .spill_path
+                    .clone()
+                    .unwrap_or_else(std::env::temp_dir);
+                let temp_dir = TempDir::new_in(&parent, "dozer_processor_state")
+                    .map_err(|e| ExecutionError::FileSystemError(parent, e))?;
+                (temp_dir.path().to_path_buf(), Some(temp_dir))
+            }
+        };
+        let builder_dag = BuilderDag::new(&checkpoint, dag_schemas, &state_dir).await?;
+        let dag_info = DagInfo::new(&builder_dag);
+        let metrics = Arc::new(ExecutorMetrics::new(&dag_info));
 
         Ok(Self {
             builder_dag,
+            dag_info,
             checkpoint,
             options,
+            metrics,
+            state_temp_dir,
         })
     }
 
+    /// Creates an executor that resumes from checkpoint `checkpoint_id` in `checkpoint_dir` instead of the latest one,
+    /// opened with `checkpoint_options` like [`OptionCheckpoint::new`].
+    ///
+    /// Newer checkpoints are deleted, so sources replay from the offsets recorded in `checkpoint_id`.
+    /// Processor state storage in `ExecutorOptions::state_dir` is restored from its snapshot at `checkpoint_id`, and newer snapshots are deleted.
+    ///
+    /// Fails with [`ExecutionError::ProcessorStateNotRestorable`] before deleting anything if a processor has state storage
+    /// but no snapshot of it at `checkpoint_id`, like one that doesn't implement [`Processor::snapshot_state`](crate::node::Processor::snapshot_state).
+    /// Remove that state to start the processor over.
+    pub async fn restart_from_checkpoint(
+        dag: Dag,
+        checkpoint_dir: String,
+        checkpoint_id: u64,
+        checkpoint_options: CheckpointOptions,
+        options: ExecutorOptions,
+    ) -> Result<Self, ExecutionError> {
+        if let Some(state_dir) = options.state_dir.as_ref().filter(|_| options.checkpointing) {
+            for (handle, factory) in dag.processors() {
+                check_state_restorable(factory.state_backend(), state_dir, handle, checkpoint_id)?;
+            }
+        }
+        let checkpoint =
+            OptionCheckpoint::rollback(checkpoint_dir, checkpoint_options, checkpoint_id).await?;
+        Self::new(dag, checkpoint, options).await
+    }
+
+    /// Builds every node of `dag` and initializes its processors and sinks, then tears them down,
+    /// without starting sources or processing any data.
+    ///
+    /// Processors are built from the checkpoint in `checkpoint_dir`, like a real run would.
+    /// Returns the first failure, which names the node it happened in.
+    pub async fn validate_build(
+        dag: Dag,
+        checkpoint_dir: String,
+        options: ExecutorOptions,
+    ) -> Result<(), ExecutionError> {
+        let checkpoint_options = CheckpointOptions {
+            spill_path: options.spill_path.clone(),
+            ..Default::default()
+        };
+        let checkpoint = OptionCheckpoint::new(checkpoint_dir, checkpoint_options).await?;
+        let executor = Self::new(dag, checkpoint, options).await?;
+        let mut graph = executor.builder_dag.into_graph();
+        let node_indexes = graph.node_identifiers().collect::<Vec<_>>();
+        for node_index in node_indexes {
+            let node = &mut graph[node_index];
+            let result = match &mut node.kind {
+                NodeKind::Source { .. } => continue,
+                NodeKind::Processor { processor, .. } => processor.init(),
+                NodeKind::Sink(sink) => sink.init(),
+            };
+            result.map_err(|error| ExecutionError::NodeInit {
+                node: node.handle.clone(),
+                error,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The epoch of the checkpoint this executor resumes from, or `None` if there's none.
+    ///
+    /// Every checkpoint is identified by the epoch it was written at. Epochs increase across restarts,
+    /// so a later checkpoint always has a greater epoch, unless it was discarded by `restart_from_checkpoint`.
+    pub fn current_epoch(&self) -> Option<u64> {
+        self.checkpoint.epoch_id()
+    }
+
+    /// The topology this executor was built from.
+    pub fn dag(&self) -> &DagInfo {
+        &self.dag_info
+    }
+
+    /// Writes the state processor `handle` serialized in the checkpoint this executor resumes from to `writer`,
+    /// to be loaded with [`restore_node_state`](Self::restore_node_state).
+    ///
+    /// Records the state refers to are kept in the checkpoint's record store, not in the exported state,
+    /// so restore it into an executor resuming from the same checkpoint. Neither is state kept by the processor's state backend exported.
+    /// Fails if `handle` isn't a processor, or the checkpoint has no state for it.
+    pub async fn export_node_state(
+        &self,
+        handle: &NodeHandle,
+        writer: &mut impl Write,
+    ) -> Result<(), ExecutionError> {
+        let is_processor =
+            self.builder_dag.graph().node_weights().any(|node| {
+                &node.handle == handle && matches!(node.kind, NodeKind::Processor { .. })
+            });
+        if !is_processor {
+            return Err(ExecutionError::NodeNotFound(handle.clone()));
+        }
+        let data = self
+            .checkpoint
+            .load_processor_data(handle)
+            .await?
+            .ok_or_else(|| ExecutionError::NodeStateNotFound(handle.clone()))?;
+        writer.write_all(&data).map_err(ExecutionError::NodeStateIo)
+    }
+
+    /// Builds processor `handle` again from the state in `reader`, written by [`export_node_state`](Self::export_node_state),
+    /// instead of the state in the checkpoint this executor resumes from. The rest of the pipeline is untouched.
+    ///
+    /// Fails if `handle` isn't a processor, or it can't be built from the state.
+    pub fn restore_node_state(
+        mut self,
+        handle: &NodeHandle,
+        reader: &mut impl Read,
+    ) -> Result<Self, ExecutionError> {
+        let mut data = vec![];
+        reader
+            .read_to_end(&mut data)
+            .map_err(ExecutionError::NodeStateIo)?;
+        self.builder_dag
+            .restore_processor(handle, self.checkpoint.record_store(), data)?;
+        info!("Restored the state of processor {handle}");
+        Ok(self)
+    }
+
+    /// The progress of every source that reports it, see [`Source::progress`].
+    pub fn source_progress(&self) -> HashMap<NodeHandle, Progress> {
+        collect_source_progress(self.builder_dag.graph().node_weights().filter_map(|node| {
+            match &node.kind {
+                NodeKind::Source { source, .. } => Some((&node.handle, source)),
+                _ => None,
+            }
+        }))
+    }
+
+    /// Renders the throughput, queue depth and latency of every node in the Prometheus text exposition format.
+    ///
+    /// Every sample is labelled with the node's handle. All values are zero until the executor starts,
+    /// see [`DagExecutorJoinHandle::metrics_prometheus`] for rendering them while it runs.
+    pub fn metrics_prometheus(&self) -> String {
+        self.metrics.render_prometheus()
+    }
+
     pub fn validate<T: Clone + Debug>(dag: Dag) -> Result<(), ExecutionError> {
         DagSchemas::new(dag)?;
         Ok(())
@@ -99,98 +495,540 @@ impl DagExecutor {
         running: Arc<AtomicBool>,
         labels: LabelsAndProgress,
     ) -> Result<DagExecutorJoinHandle, ExecutionError> {
+        let mut options = self.options;
+        let resumed_epoch = self.current_epoch();
+        let sources = self
+            .builder_dag
+            .graph()
+            .node_weights()
+            .filter_map(|node| match &node.kind {
+                NodeKind::Source { source, .. } => Some((node.handle.clone(), source.clone())),
+                _ => None,
+            })
+            .collect();
+        if let Some(budget) = options.memory_budget {
+            // Every edge has a channel, and every source has one more between its sender and listener.
+            let num_channels = self.dag_info.edges.len()
+                + self
+                    .dag_info
+                    .nodes
+                    .iter()
+                    .filter(|node| node.typ == DagNodeType::Source)
+                    .count();
+            options.channel_buffer_sz =
+                memory_budget::channel_capacity(budget, num_channels, options.channel_buffer_sz);
+        }
+
         // Construct execution dag.
-        let mut execution_dag = ExecutionDag::new(
-            self.builder_dag,
-            self.checkpoint,
-            labels,
-            self.options.channel_buffer_sz,
-            self.options.error_threshold,
-            self.options.checkpoint_factory_options.clone(),
-            self.options.epoch_manager_options.clone(),
-        )
-        .await?;
+        let mut execution_dag =
+            ExecutionDag::new(self.builder_dag, self.checkpoint, labels, &options).await?;
         let node_indexes = execution_dag.graph().node_identifiers().collect::<Vec<_>>();
+        let edge_queues = || {
+            // The execution DAG keeps the builder DAG's edge indexes.
+            self.dag_info
+                .edges
+                .iter()
+                .zip(execution_dag.graph().raw_edges())
+                .map(|(info, edge)| {
+                    (
+                        Edge::new(info.from.clone(), info.to.clone()),
+                        edge.weight.queue_len.clone(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let drain_queues = if options.drain_timeout.is_some() {
+            edge_queues()
+        } else {
+            vec![]
+        };
+        let deadlock_queues = options.deadlock_timeout.map(|_| edge_queues());
+        let inflight = self
+            .dag_info
+            .edges
+            .iter()
+            .zip(execution_dag.graph().raw_edges())
+            .filter_map(|(info, edge)| {
+                Some((
+                    Edge::new(info.from.clone(), info.to.clone()),
+                    edge.weight.queue_len.clone(),
+                    edge.weight.inflight.clone()?,
+                ))
+            })
+            .collect();
+        let aborted = execution_dag.aborted().clone();
+        let epoch_manager = execution_dag.epoch_manager().clone();
+        let error_manager = execution_dag.error_manager().clone();
+        let operation_limit = options
+            .max_operations
+            .map(|max| Arc::new(OperationLimit::new(max, running.clone())));
+
+        let startup = options.strict_startup_ordering.then(|| {
+            let num_threads = if options.single_threaded {
+                1
+            } else {
+                self.dag_info
+                    .nodes
+                    .iter()
+                    .filter(|node| node.typ != DagNodeType::Source)
+                    .count()
+            };
+            Arc::new(StartupGate::new(num_threads))
+        });
 
         // Start the threads.
         let mut join_handles = Vec::new();
+        let mut source_senders = Vec::new();
+        let mut sink_counts = HashMap::new();
+        let mut scheduled_nodes: Vec<Box<dyn ReceiverLoop + Send>> = Vec::new();
         for node_index in node_indexes {
             let node = execution_dag.graph()[node_index]
                 .as_ref()
                 .expect("We created all nodes");
+            let metrics = self.metrics.node(&node.handle);
             match &node.kind {
                 NodeKind::Source { .. } => {
                     let (source_sender_node, source_listener_node) = create_source_nodes(
                         &mut execution_dag,
                         node_index,
-                        &self.options,
+                        &options,
                         running.clone(),
+                        metrics,
                     )
                     .await;
-                    let (sender, receiver) =
-                        start_source(source_sender_node, source_listener_node)?;
+                    let (sender, receiver) = start_source(
+                        source_sender_node,
+                        source_listener_node,
+                        aborted.clone(),
+                        startup.clone(),
+                    )?;
+                    source_senders.push(sender.thread().id());
                     join_handles.extend([sender, receiver]);
                 }
-                NodeKind::Processor(_) => {
-                    let processor_node = ProcessorNode::new(&mut execution_dag, node_index).await;
-                    join_handles.push(start_processor(processor_node)?);
+                NodeKind::Processor { .. } => {
+                    let processor_node = ProcessorNode::new(
+                        &mut execution_dag,
+                        node_index,
+                        options.supervision,
+                        options.flush_on_stop,
+                        options.checkpoint_retention,
+                        metrics,
+                    )
+                    .await;
+                    if options.single_threaded {
+                        scheduled_nodes.push(Box::new(processor_node));
+                    } else {
+                        join_handles.push(start_processor(
+                            processor_node,
+                            aborted.clone(),
+                            startup.clone(),
+                        )?);
+                    }
                 }
                 NodeKind::Sink(_) => {
-                    let sink_node = SinkNode::new(&mut execution_dag, node_index);
-                    join_handles.push(start_sink(sink_node)?);
+                    let sink_node = SinkNode::new(
+                        &mut execution_dag,
+                        node_index,
+                        options.delivery,
+                        operation_limit.clone(),
+                        metrics,
+                    );
+                    sink_counts.insert(sink_node.handle().clone(), sink_node.received().clone());
+                    if options.single_threaded {
+                        scheduled_nodes.push(Box::new(sink_node));
+                    } else {
+                        join_handles.push(start_sink(sink_node, aborted.clone(), startup.clone())?);
+                    }
                 }
             }
         }
+        if options.single_threaded {
+            join_handles.push(start_single_threaded(
+                SingleThreadedScheduler::new(scheduled_nodes),
+                aborted.clone(),
+                startup,
+            )?);
+        }
+        let suspected_deadlock = Arc::new(Mutex::new(None));
+        if let (Some(timeout), Some(queues)) = (options.deadlock_timeout, deadlock_queues) {
+            join_handles.push(start_deadlock_detector(DeadlockDetector::new(
+                timeout,
+                queues,
+                self.metrics.clone(),
+                epoch_manager.clone(),
+                aborted.clone(),
+                suspected_deadlock.clone(),
+            ))?);
+        }
 
-        Ok(DagExecutorJoinHandle { join_handles })
+        Ok(DagExecutorJoinHandle {
+            join_handles,
+            source_senders,
+            aborted,
+            epoch_manager,
+            error_manager,
+            running,
+            sources,
+            drain_timeout: options.drain_timeout,
+            drain_queues,
+            inflight,
+            sink_counts,
+            metrics: self.metrics,
+            flush_on_stop: options.flush_on_stop,
+            resumed_epoch,
+            suspected_deadlock,
+            disconnected_panic: None,
+            _state_temp_dir: self.state_temp_dir,
+        })
     }
 }
 
 impl DagExecutorJoinHandle {
+    /// Stops all nodes as soon as possible, without committing or terminating gracefully.
+    ///
+    /// No checkpoint is written after this call, and sinks may have applied part of an epoch.
+    /// [`join`](Self::join) returns [`ExecutionError::Aborted`] once every node thread has quit, which they do at their next
+    /// operation or within the source listener's timeout. Threads running sources aren't waited for, they keep running
+    /// in the background until the source returns. A processor or sink stuck inside a call never quits, which blocks `join`.
+    pub fn abort(&self) {
+        self.abort_handle().abort();
+    }
+
+    /// Returns a handle that can abort the executor while another thread is joining it.
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            aborted: self.aborted.clone(),
+        }
+    }
+
+    /// Stops the pipeline gracefully, like setting the `running` flag passed to [`DagExecutor::start`] to `false`.
+    ///
+    /// Sources stop sending, and every operation they've sent is processed and committed before the nodes terminate,
+    /// so the last checkpoint is consistent. [`join`](Self::join) returns once all nodes have quit.
+    ///
+    /// If `ExecutorOptions::drain_timeout` is set, waits until all sinks have terminated, and fails with
+    /// [`ExecutionError::DrainTimeout`] naming the first edge whose queue hasn't gone down within the timeout,
+    /// like one into a wedged processor. The pipeline keeps draining in the background after that.
+    pub fn drain_and_stop(&self) -> Result<(), ExecutionError> {
+        self.running.store(false, Ordering::SeqCst);
+        let Some(timeout) = self.drain_timeout else {
+            return Ok(());
+        };
+
+        // The shortest queue seen on every edge, and when it was seen.
+        let now = Instant::now();
+        let mut shortest = vec![(usize::MAX, now); self.drain_queues.len()];
+        while !self.epoch_manager.flush_barrier().all_sinks_terminated() {
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(ExecutionError::Aborted);
+            }
+            for ((edge, queue_len), (len, since)) in self.drain_queues.iter().zip(&mut shortest) {
+                let current = queue_len.get();
+                if current < *len || current == 0 {
+                    *len = current;
+                    *since = Instant::now();
+                } else if since.elapsed() >= timeout {
+                    return Err(ExecutionError::DrainTimeout { edge: edge.clone() });
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    /// Summarizes the operations and control messages queued on every edge, oldest first, to see where a stalled pipeline is stuck.
+    ///
+    /// Empty unless `ExecutorOptions::track_inflight` is set. High priority operations aren't included.
+    /// While the pipeline is moving, the summaries may be off by the messages sent or received during the call.
+    pub fn dump_inflight(&self) -> HashMap<Edge, Vec<OperationSummary>> {
+        self.inflight
+            .iter()
+            .map(|(edge, queue_len, log)| (edge.clone(), log.queued(queue_len.get())))
+            .collect()
+    }
+
+    /// Makes every source commit, and waits until every sink has committed that epoch, without stopping the pipeline.
+    ///
+    /// Commits flow through processors, so all operations sent before this call have been processed and committed
+    /// by every node on their way to sinks when it returns.
+    /// Idle sources join the commit within 100ms.
+    /// Under `DeliverySemantics::AtMostOnce`, sinks apply the epoch on the next commit.
+    ///
+    /// Returns early if all sinks have terminated, and [`ExecutionError::Aborted`] if the executor is aborted.
+    pub fn flush_barrier(&self) -> Result<(), ExecutionError> {
+        let flush_barrier = self.epoch_manager.flush_barrier();
+        let request = flush_barrier.request();
+        while !flush_barrier.is_flushed(request) {
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(ExecutionError::Aborted);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+
+    /// Pauses the pipeline at a checkpoint, copies the checkpoint to the local directory `dest`, and resumes.
+    ///
+    /// The checkpoint is written even if app checkpoints are disabled. Sources are paused at their next commit after it,
+    /// so no newer checkpoint is written while copying. Operations already sent keep flowing to sinks meanwhile.
+    /// Restart from the snapshot by opening `dest` with [`OptionCheckpoint::new`](crate::checkpoint::OptionCheckpoint::new).
+    /// Processor state that isn't checkpointed, like RocksDB state in `ExecutorOptions::state_dir`, isn't copied.
+    ///
+    /// Fails with [`ExecutionError::SnapshotNotRestartable`] if a source isn't restartable,
+    /// and [`ExecutionError::CheckpointingDisabled`] if `ExecutorOptions::checkpointing` isn't set.
+    pub async fn snapshot(&self, dest: &Path) -> Result<(), ExecutionError> {
+        if !self.epoch_manager.persists() {
+            return Err(ExecutionError::CheckpointingDisabled);
+        }
+        self.epoch_manager.request_snapshot();
+        let result = self.copy_snapshot(dest).await;
+        self.epoch_manager.resume();
+        result
+    }
+
+    async fn copy_snapshot(&self, dest: &Path) -> Result<(), ExecutionError> {
+        let (epoch_id, checkpointed) = loop {
+            if let Some(snapshot_epoch) = self.epoch_manager.snapshot_epoch() {
+                break snapshot_epoch;
+            }
+            self.check_snapshot_running()?;
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        if !checkpointed {
+            return Err(ExecutionError::SnapshotNotRestartable);
+        }
+
+        // The checkpoint is written once every node has committed the epoch.
+        let checkpoint_factory = self.epoch_manager.checkpoint_factory();
+        while checkpoint_factory.last_written_epoch() < Some(epoch_id) {
+            self.check_snapshot_running()?;
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // Copied after the checkpoint is uploaded.
+        checkpoint_factory
+            .queue()
+            .copy_prefix(
+                checkpoint_factory.prefix().to_string(),
+                dest.to_string_lossy().into_owned(),
+            )
+            .await
+            .map_err(|_| ExecutionError::CheckpointWriterThreadPanicked)?
+            .await
+            .map_err(|_| ExecutionError::CheckpointWriterThreadPanicked)?;
+        info!("Snapshot of epoch {epoch_id} copied to {dest:?}");
+        Ok(())
+    }
+
+    fn check_snapshot_running(&self) -> Result<(), ExecutionError> {
+        if self.aborted.load(Ordering::SeqCst) {
+            Err(ExecutionError::Aborted)
+        } else if self.join_handles.iter().all(JoinHandle::is_finished) {
+            Err(ExecutionError::SnapshotInterrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The progress of every source that reports it, see [`Source::progress`].
+    pub fn source_progress(&self) -> HashMap<NodeHandle, Progress> {
+        collect_source_progress(
+            self.sources
+                .iter()
+                .map(|(node_handle, source)| (node_handle, source)),
+        )
+    }
+
+    /// The epoch of the newest checkpoint written so far, or [`DagExecutor::current_epoch`] if none has been written yet.
+    ///
+    /// A checkpoint counts as written once it's queued for upload, so it may not be durable yet.
+    pub fn current_epoch(&self) -> Option<u64> {
+        self.epoch_manager
+            .checkpoint_factory()
+            .last_written_epoch()
+            .or(self.resumed_epoch)
+    }
+
+    /// Like [`DagExecutor::metrics_prometheus`], with the values the nodes have reached so far.
+    pub fn metrics_prometheus(&self) -> String {
+        self.metrics.render_prometheus()
+    }
+
+    /// Blocks until sink `handle` has received at least `count` operations, including the ones it failed to process.
+    ///
+    /// Returns [`ExecutionError::SinkCountNotReached`] if it hasn't within `timeout`, or if the pipeline finishes first.
+    pub fn wait_for_sink_count(
+        &self,
+        handle: &NodeHandle,
+        count: u64,
+        timeout: Duration,
+    ) -> Result<(), ExecutionError> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+        let received = self
+            .sink_counts
+            .get(handle)
+            .ok_or_else(|| ExecutionError::NodeNotFound(handle.clone()))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if received.load(Ordering::SeqCst) >= count {
+                return Ok(());
+            }
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(ExecutionError::Aborted);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.join_handles.iter().all(JoinHandle::is_finished) {
+                // Operations may have arrived just before time was up or the pipeline finished.
+                let received = received.load(Ordering::SeqCst);
+                if received >= count {
+                    return Ok(());
+                }
+                return Err(ExecutionError::SinkCountNotReached {
+                    sink: handle.clone(),
+                    count,
+                    received,
+                });
+            }
+            thread::sleep(remaining.min(POLL_INTERVAL));
+        }
+    }
+
     pub fn join(mut self) -> Result<(), ExecutionError> {
+        self.join_until(None).map(|_| ())
+    }
+
+    /// Like `join`, but returns `Ok(None)` if the pipeline hasn't finished within `timeout`, leaving it running.
+    ///
+    /// Can be called again to keep waiting.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Result<Option<()>, ExecutionError> {
+        self.join_until(Some(Instant::now() + timeout))
+    }
+
+    fn join_until(&mut self, deadline: Option<Instant>) -> Result<Option<()>, ExecutionError> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(250);
+        /// How long a panic caused by a disconnected channel waits for the panic that disconnected it.
+        const ROOT_PANIC_GRACE: Duration = Duration::from_secs(1);
         loop {
+            if self.aborted.load(Ordering::SeqCst) {
+                self.join_aborted();
+                return Err(ExecutionError::Aborted);
+            }
+            if let Some((_, surface_at)) = &self.disconnected_panic {
+                if self.join_handles.is_empty() || Instant::now() >= *surface_at {
+                    let (panic, _) = self.disconnected_panic.take().expect("checked above");
+                    resume_unwind(panic);
+                }
+            }
+            if let Some(nodes) = self.suspected_deadlock.lock().take() {
+                return Err(ExecutionError::DeadlockSuspected { nodes });
+            }
             let Some(finished) = self
                 .join_handles
                 .iter()
                 .enumerate()
                 .find_map(|(i, handle)| handle.is_finished().then_some(i))
             else {
-                thread::sleep(Duration::from_millis(250));
+                let sleep = match deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        if remaining.is_zero() {
+                            return Ok(None);
+                        }
+                        remaining.min(POLL_INTERVAL)
+                    }
+                    None => POLL_INTERVAL,
+                };
+                thread::sleep(sleep);
 
                 continue;
             };
             let handle = self.join_handles.swap_remove(finished);
-            if let Err(e) = handle.join() {
-                panic_any(e)
+            if let Err(panic) = handle.join() {
+                // Nodes next to a panicked node panic too once their channels disconnect, and may be joined first.
+                // Hold those back for a while, so the panic surfaced is the one that brought the pipeline down.
+                if !is_disconnected_panic(panic.as_ref()) {
+                    resume_unwind(panic);
+                }
+                if self.disconnected_panic.is_none() {
+                    self.disconnected_panic = Some((panic, Instant::now() + ROOT_PANIC_GRACE));
+                }
+                continue;
             }
 
             if self.join_handles.is_empty() {
-                return Ok(());
+                if let Some((panic, _)) = self.disconnected_panic.take() {
+                    resume_unwind(panic);
+                }
+                self.error_manager.flush_rollups();
+                if self.flush_on_stop {
+                    self.epoch_manager.record_store().flush()?;
+                }
+                return Ok(Some(()));
+            }
+        }
+    }
+
+    /// Joins every thread but the ones running sources. Errors and panics are expected as nodes quit, so they're ignored.
+    fn join_aborted(&mut self) {
+        for handle in self.join_handles.drain(..) {
+            if !self.source_senders.contains(&handle.thread().id()) {
+                let _ = handle.join();
             }
         }
     }
 }
 
+fn collect_source_progress<'a>(
+    sources: impl Iterator<Item = (&'a NodeHandle, &'a Arc<dyn Source>)>,
+) -> HashMap<NodeHandle, Progress> {
+    sources
+        .filter_map(|(node_handle, source)| Some((node_handle.clone(), source.progress()?)))
+        .collect()
+}
+
+/// Whether a node panicked only because a channel to a neighbour disconnected.
+fn is_disconnected_panic(panic: &(dyn Any + Send)) -> bool {
+    if let Some(error) = panic.downcast_ref::<ExecutionError>() {
+        return match error {
+            ExecutionError::CannotSendToChannel | ExecutionError::CannotReceiveFromChannel => true,
+            ExecutionError::Source(error) => error
+                .downcast_ref::<ExecutionError>()
+                .map_or(false, |error| {
+                    matches!(error, ExecutionError::CannotSendToChannel)
+                }),
+            _ => false,
+        };
+    }
+    panic.downcast_ref::<String>().map_or(false, |message| {
+        message.starts_with("Failed to send operation")
+    })
+}
+
+/// Panics with `error`, unless the executor was aborted, in which case errors are expected as nodes quit.
+fn panic_unless_aborted(error: ExecutionError, aborted: &AtomicBool) {
+    if !aborted.load(Ordering::SeqCst) {
+        std::panic::panic_any(error);
+    }
+}
+
 fn start_source(
     source_sender: SourceSenderNode,
     source_listener: SourceListenerNode,
+    aborted: Arc<AtomicBool>,
+    startup: Option<Arc<StartupGate>>,
 ) -> Result<(JoinHandle<()>, JoinHandle<()>), ExecutionError> {
     let handle = source_sender.handle().clone();
 
+    let sender_aborted = aborted.clone();
     let sender_handle = Builder::new()
         .name(format!("{handle}-sender"))
-        .spawn(move || match source_sender.run() {
-            Ok(_) => {}
-            // Channel disconnection means the source listener has quit.
-            // Maybe it quit gracefully so we don't need to panic.
-            Err(e) => {
-                if let ExecutionError::Source(e) = &e {
-                    if let Some(ExecutionError::CannotSendToChannel) = e.downcast_ref() {
-                        return;
-                    }
+        .spawn(move || {
+            if let Some(startup) = startup {
+                if startup.wait(&sender_aborted).is_err() {
+                    return;
                 }
-                std::panic::panic_any(e);
             }
+            run_source_sender(source_sender, &sender_aborted)
         })
         .map_err(ExecutionError::CannotSpawnWorkerThread)?;
 
@@ -198,7 +1036,7 @@ fn start_source(
         .name(format!("{handle}-listener"))
         .spawn(move || {
             if let Err(e) = source_listener.run() {
-                std::panic::panic_any(e);
+                panic_unless_aborted(e, &aborted);
             }
         })
         .map_err(ExecutionError::CannotSpawnWorkerThread)?;
@@ -206,23 +1044,86 @@ fn start_source(
     Ok((sender_handle, listener_handle))
 }
 
-fn start_processor(processor: ProcessorNode) -> Result<JoinHandle<()>, ExecutionError> {
+fn run_source_sender(source_sender: SourceSenderNode, aborted: &AtomicBool) {
+    match source_sender.run() {
+        Ok(_) => {}
+        // Channel disconnection means the source listener has quit.
+        // Maybe it quit gracefully so we don't need to panic.
+        Err(e) => {
+            if let ExecutionError::Source(e) = &e {
+                if let Some(ExecutionError::CannotSendToChannel) = e.downcast_ref() {
+                    return;
+                }
+            }
+            panic_unless_aborted(e, aborted);
+        }
+    }
+}
+
+/// Initializes `node` and runs it, letting `startup` know once it's initialized.
+fn init_and_run<T: ReceiverLoop + Debug>(
+    mut node: T,
+    startup: Option<Arc<StartupGate>>,
+) -> Result<(), ExecutionError> {
+    let result = node.init();
+    if let Some(startup) = startup {
+        startup.arrive();
+    }
+    result?;
+    node.run()
+}
+
+fn start_processor(
+    processor: ProcessorNode,
+    aborted: Arc<AtomicBool>,
+    startup: Option<Arc<StartupGate>>,
+) -> Result<JoinHandle<()>, ExecutionError> {
     Builder::new()
         .name(processor.handle().to_string())
         .spawn(move || {
-            if let Err(e) = processor.run() {
-                std::panic::panic_any(e);
+            if let Err(e) = init_and_run(processor, startup) {
+                panic_unless_aborted(e, &aborted);
             }
         })
         .map_err(ExecutionError::CannotSpawnWorkerThread)
 }
 
-fn start_sink(sink: SinkNode) -> Result<JoinHandle<()>, ExecutionError> {
+fn start_sink(
+    sink: SinkNode,
+    aborted: Arc<AtomicBool>,
+    startup: Option<Arc<StartupGate>>,
+) -> Result<JoinHandle<()>, ExecutionError> {
     Builder::new()
         .name(sink.handle().to_string())
-        .spawn(|| {
-            if let Err(e) = sink.run() {
-                std::panic::panic_any(e);
+        .spawn(move || {
+            if let Err(e) = init_and_run(sink, startup) {
+                panic_unless_aborted(e, &aborted);
+            }
+        })
+        .map_err(ExecutionError::CannotSpawnWorkerThread)
+}
+
+fn start_deadlock_detector(detector: DeadlockDetector) -> Result<JoinHandle<()>, ExecutionError> {
+    Builder::new()
+        .name("deadlock_detector".to_string())
+        .spawn(move || detector.run())
+        .map_err(ExecutionError::CannotSpawnWorkerThread)
+}
+
+fn start_single_threaded(
+    mut scheduler: SingleThreadedScheduler,
+    aborted: Arc<AtomicBool>,
+    startup: Option<Arc<StartupGate>>,
+) -> Result<JoinHandle<()>, ExecutionError> {
+    Builder::new()
+        .name("single_threaded".to_string())
+        .spawn(move || {
+            let result = scheduler.init();
+            if let Some(startup) = startup {
+                startup.arrive();
+            }
+            if let Err(e) = result.and_then(|()| scheduler.run()) {
+                panic_unless_aborted(e, &aborted);
             }
         })
         .map_err(ExecutionError::CannotSpawnWorkerThread)
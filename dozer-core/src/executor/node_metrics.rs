@@ -0,0 +1,219 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use dozer_types::node::NodeHandle;
+
+use crate::executor_operation::OperationTimestamps;
+
+use super::dag_info::DagInfo;
+
+/// Throughput, queue depth and latency of one node, updated by the node as it runs.
+#[derive(Debug, Default)]
+pub struct NodeMetrics {
+    /// Operations received by a processor or sink, or sent by a source.
+    operations: AtomicU64,
+    /// Operations waiting in the node's input channels when it last received one.
+    queue_depth: AtomicU64,
+    /// Microseconds from the source listener to the node, summed over `latency_count` operations.
+    latency_micros: AtomicU64,
+    latency_count: AtomicU64,
+}
+
+impl NodeMetrics {
+    pub fn on_op(&self, timestamps: &OperationTimestamps) {
+        self.operations.fetch_add(1, Ordering::Relaxed);
+        if let Some(latency) = timestamps
+            .processing_time
+            .and_then(|processing_time| SystemTime::now().duration_since(processing_time).ok())
+        {
+            let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+            self.latency_micros.fetch_add(micros, Ordering::Relaxed);
+            self.latency_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts an operation sent by a source, which has no latency.
+    pub fn on_sent(&self) {
+        self.operations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_queue_depth(&self, depth: usize) {
+        self.queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+}
+
+/// The metrics of every node in the executor, see [`DagExecutor::metrics_prometheus`](super::DagExecutor::metrics_prometheus).
+#[derive(Debug)]
+pub struct ExecutorMetrics {
+    nodes: Vec<(NodeHandle, Arc<NodeMetrics>)>,
+}
+
+impl ExecutorMetrics {
+    pub fn new(dag_info: &DagInfo) -> Self {
+        Self {
+            nodes: dag_info
+                .nodes
+                .iter()
+                .map(|node| (node.handle.clone(), Default::default()))
+                .collect(),
+        }
+    }
+
+    pub fn node(&self, handle: &NodeHandle) -> Arc<NodeMetrics> {
+        self.nodes
+            .iter()
+            .find(|(node_handle, _)| node_handle == handle)
+            .map(|(_, metrics)| metrics.clone())
+            .expect("Every node has metrics")
+    }
+
+    /// Operations received by all processors and sinks, and sent by all sources.
+    pub fn total_operations(&self) -> u64 {
+        self.nodes
+            .iter()
+            .map(|(_, metrics)| metrics.operations.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format, labelled with the node handles.
+    pub fn render_prometheus(&self) -> String {
+        let mut output = String::new();
+        self.render_metric(
+            &mut output,
+            "dozer_node_operations_total",
+            "Operations received by the node, or sent by a source.",
+            "counter",
+            |name, node, metrics| {
+                let operations = metrics.operations.load(Ordering::Relaxed);
+                format!("{name}{{node=\"{node}\"}} {operations}")
+            },
+        );
+        self.render_metric(
+            &mut output,
+            "dozer_node_queue_depth",
+            "Operations waiting in the node's input channels when it last received one.",
+            "gauge",
+            |name, node, metrics| {
+                let depth = metrics.queue_depth.load(Ordering::Relaxed);
+                format!("{name}{{node=\"{node}\"}} {depth}")
+            },
+        );
+        self.render_metric(
+            &mut output,
+            "dozer_node_latency_seconds",
+            "Time from the source listener to the node receiving an operation.",
+            "summary",
+            |name, node, metrics| {
+                let sum = metrics.latency_micros.load(Ordering::Relaxed) as f64 / 1e6;
+                let count = metrics.latency_count.load(Ordering::Relaxed);
+                format!(
+                    "{name}_sum{{node=\"{node}\"}} {sum}\n{name}_count{{node=\"{node}\"}} {count}"
+                )
+            },
+        );
+        output
+    }
+
+    fn render_metric(
+        &self,
+        output: &mut String,
+        name: &str,
+        help: &str,
+        typ: &str,
+        sample: impl Fn(&str, &str, &NodeMetrics) -> String,
+    ) {
+        writeln!(output, "# HELP {name} {help}").unwrap();
+        writeln!(output, "# TYPE {name} {typ}").unwrap();
+        for (handle, metrics) in &self.nodes {
+            let node = escape_label_value(&handle.to_string());
+            writeln!(output, "{}", sample(name, &node, metrics)).unwrap();
+        }
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    use dozer_log::tokio;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::executor::DagExecutor;
+    use crate::tests::dag_base_run::NoopProcessorFactory;
+    use crate::tests::sinks::{CountingSinkFactory, COUNTING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_metrics_prometheus() {
+        let count: u64 = 1_000;
+        let latch = Arc::new(AtomicBool::new(true));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .processor(proc_handle.clone(), NoopProcessorFactory {})
+            .sink(sink_handle.clone(), CountingSinkFactory::new(count, latch))
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                COUNTING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let executor = DagExecutor::new(dag, checkpoint, Default::default())
+            .await
+            .unwrap();
+        assert!(executor.metrics_prometheus().contains(&format!(
+            "dozer_node_operations_total{{node=\"{sink_handle}\"}} 0\n"
+        )));
+
+        let mut join_handle = executor
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            join_handle.join_timeout(Duration::from_secs(60)).unwrap(),
+            Some(())
+        );
+
+        let metrics = join_handle.metrics_prometheus();
+        assert!(metrics.contains("# TYPE dozer_node_operations_total counter\n"));
+        for handle in [&source_handle, &proc_handle, &sink_handle] {
+            assert!(metrics.contains(&format!(
+                "dozer_node_operations_total{{node=\"{handle}\"}} {count}\n"
+            )));
+            assert!(metrics.contains(&format!("dozer_node_queue_depth{{node=\"{handle}\"}} ")));
+        }
+        assert!(metrics.contains(&format!(
+            "dozer_node_latency_seconds_count{{node=\"{sink_handle}\"}} {count}\n"
+        )));
+    }
+}
@@ -1,22 +1,82 @@
+use std::collections::HashMap;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
 use std::{borrow::Cow, mem::swap};
 
-use crossbeam::channel::Receiver;
 use daggy::NodeIndex;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::log::warn;
 use dozer_types::node::NodeHandle;
+use dozer_types::parking_lot::Mutex;
 
 use crate::epoch::Epoch;
 use crate::error_manager::ErrorManager;
-use crate::executor_operation::{ExecutorOperation, ProcessorOperation};
+use crate::executor_operation::{OperationContext, ProcessorOperation, SourceOffset};
+use crate::record_store::InputRecordReader;
 use crate::{
-    builder_dag::NodeKind,
+    builder_dag::{NodeKind, ProcessorRebuild, ReplacedProcessor},
+    channels::ProcessorChannelForwarder,
     errors::ExecutionError,
-    forwarder::ChannelManager,
+    forwarder::{ChannelManager, EdgeReceiver},
     node::{PortHandle, Processor},
+    transport::TransportReceiver,
 };
-use dozer_recordstore::ProcessorRecordStore;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
 
-use super::{execution_dag::ExecutionDag, name::Name, receiver_loop::ReceiverLoop};
+/// What the executor does when a processor panics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SupervisionPolicy {
+    /// The panic tears down the pipeline.
+    #[default]
+    Escalate,
+    /// The processor is built again from the last checkpoint, its data and its state storage, and the calls it handled since are
+    /// replayed on it without forwarding what it sends. Then the call that panicked is retried.
+    ///
+    /// The calls since the last checkpoint, and the records that checkpoint refers to, are kept in memory until the next one,
+    /// so checkpoints should be frequent. Without checkpoints, the processor is built anew and every call is replayed.
+    /// Only the in-memory record store is supported, processors escalate with the RocksDB one.
+    /// Operations the processor sent before panicking aren't taken back, so downstream nodes may receive them twice.
+    /// The panic tears down the pipeline once the processor has been restarted `max_restarts` times.
+    RestartFromCheckpoint { max_restarts: u32 },
+}
+
+use super::{
+    execution_dag::ExecutionDag, name::Name, node_metrics::NodeMetrics,
+    receiver_loop::ReceiverLoop, total_order::TotalOrderBuffer,
+};
+
+/// What a panicked processor is rebuilt and caught up from.
+#[derive(Debug)]
+struct Recovery {
+    /// The epoch of the last checkpoint, `None` if there was none.
+    checkpoint_epoch: Option<u64>,
+    /// The processor's data in the last checkpoint.
+    checkpoint_data: Option<Vec<u8>>,
+    /// The records live at the last checkpoint, which its data may refer to.
+    records: ProcessorRecordStoreDeserializer,
+    /// The calls the processor handled since the last checkpoint.
+    calls: Vec<Call>,
+}
+
+/// A call to the processor, to be replayed after it's rebuilt.
+#[derive(Debug)]
+enum Call {
+    Process(PortHandle, ProcessorOperation),
+    PortClosed(PortHandle),
+    Watermark(SystemTime),
+    BeforeCommit(Epoch),
+    Commit(Epoch),
+}
+
+/// Drops what a processor sends while its calls are replayed, because downstream nodes already received it.
+#[derive(Debug)]
+struct DiscardingForwarder;
+
+impl ProcessorChannelForwarder for DiscardingForwarder {
+    fn send(&mut self, _op: ProcessorOperation, _port: PortHandle) {}
+}
 
 /// A processor in the execution DAG.
 #[derive(Debug)]
@@ -28,7 +88,11 @@ pub struct ProcessorNode {
     /// Input port handles.
     port_handles: Vec<PortHandle>,
     /// Input data channels.
-    receivers: Vec<Receiver<ExecutorOperation>>,
+    receivers: Vec<EdgeReceiver>,
+    /// Input channels for high priority operations, one for each data channel.
+    priority_receivers: Vec<Arc<dyn TransportReceiver>>,
+    /// Number of input channels whose upstream sources have finished.
+    num_closed_ports: usize,
     /// The processor.
     processor: Box<dyn Processor>,
     /// This node's output channel manager, for forwarding data, writing metadata and writing port state.
@@ -37,21 +101,79 @@ pub struct ProcessorNode {
     record_store: Arc<ProcessorRecordStore>,
     /// The error manager, for reporting non-fatal errors.
     error_manager: Arc<ErrorManager>,
+    /// If the executor was aborted.
+    aborted: Arc<AtomicBool>,
+    /// What to do when the processor panics.
+    supervision: SupervisionPolicy,
+    /// Builds the processor again after it panics.
+    rebuild: ProcessorRebuild,
+    /// Passed to the processor again after it's rebuilt.
+    record_readers: HashMap<PortHandle, InputRecordReader>,
+    /// Number of times the processor has been rebuilt.
+    restarts: u32,
+    /// Only kept under `SupervisionPolicy::RestartFromCheckpoint`.
+    recovery: Option<Recovery>,
+    /// Set if the processor requires its inputs in total order.
+    total_order: Option<TotalOrderBuffer>,
+    /// Flush the processor's state on terminate.
+    flush_on_stop: bool,
+    /// Number of checkpoints whose state snapshots are kept, see `ExecutorOptions::checkpoint_retention`.
+    checkpoint_retention: usize,
+    metrics: Arc<NodeMetrics>,
 }
 
 impl ProcessorNode {
-    pub async fn new(dag: &mut ExecutionDag, node_index: NodeIndex) -> Self {
+    pub async fn new(
+        dag: &mut ExecutionDag,
+        node_index: NodeIndex,
+        supervision: SupervisionPolicy,
+        flush_on_stop: bool,
+        checkpoint_retention: usize,
+        metrics: Arc<NodeMetrics>,
+    ) -> Self {
         let Some(node) = dag.node_weight_mut(node_index).take() else {
             panic!("Must pass in a node")
         };
         let node_handle = node.handle;
-        let NodeKind::Processor(processor) = node.kind else {
+        let NodeKind::Processor {
+            mut processor,
+            mut rebuild,
+        } = node.kind
+        else {
             panic!("Must pass in a processor node");
         };
 
-        let (port_handles, receivers) = dag.collect_receivers(node_index);
+        let (checkpoint_epoch, checkpoint_data) = rebuild.take_checkpoint();
+        let mut recovery = None;
+        let mut supervision = supervision;
+        if let SupervisionPolicy::RestartFromCheckpoint { .. } = supervision {
+            match dag.record_store().pin() {
+                Some(records) => {
+                    recovery = Some(Recovery {
+                        checkpoint_epoch,
+                        checkpoint_data,
+                        records,
+                        calls: vec![],
+                    })
+                }
+                None => {
+                    warn!(
+                        "[{node_handle}] Processors can't be restarted with the RocksDB record store, panics will escalate"
+                    );
+                    supervision = SupervisionPolicy::Escalate;
+                }
+            }
+        }
+
+        let (port_handles, receivers, priority_receivers) = dag.collect_receivers(node_index);
+        let total_order = rebuild
+            .factory()
+            .requires_total_order()
+            .then(|| TotalOrderBuffer::new(port_handles.len()));
+        let record_readers = dag.collect_record_readers(node_index);
+        processor.set_record_readers(record_readers.clone());
 
-        let (senders, record_writers) = dag.collect_senders_and_record_writers(node_index).await;
+        let (senders, record_writers) = dag.collect_senders_and_record_writers(node_index);
 
         let channel_manager = ChannelManager::new(
             node_handle.clone(),
@@ -59,6 +181,7 @@ impl ProcessorNode {
             senders,
             dag.record_store().clone(),
             dag.error_manager().clone(),
+            dag.collect_output_schemas(node_index),
         );
 
         Self {
@@ -66,16 +189,217 @@ impl ProcessorNode {
             initial_epoch_id: dag.epoch_manager().epoch_id(),
             port_handles,
             receivers,
+            priority_receivers,
+            num_closed_ports: 0,
             processor,
             channel_manager,
             record_store: dag.record_store().clone(),
             error_manager: dag.error_manager().clone(),
+            aborted: dag.aborted().clone(),
+            supervision,
+            rebuild,
+            record_readers,
+            restarts: 0,
+            recovery,
+            total_order,
+            flush_on_stop,
+            checkpoint_retention,
+            metrics,
         }
     }
 
     pub fn handle(&self) -> &NodeHandle {
         &self.node_handle
     }
+
+    /// Runs `call` on the processor, rebuilding it and retrying if it panics, as `supervision` says.
+    fn supervised<R>(
+        &mut self,
+        mut call: impl FnMut(&mut dyn Processor, &ProcessorRecordStore, &mut ChannelManager) -> R,
+    ) -> Result<R, ExecutionError> {
+        let SupervisionPolicy::RestartFromCheckpoint { max_restarts } = self.supervision else {
+            return Ok(call(
+                self.processor.as_mut(),
+                &self.record_store,
+                &mut self.channel_manager,
+            ));
+        };
+        loop {
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                call(
+                    self.processor.as_mut(),
+                    &self.record_store,
+                    &mut self.channel_manager,
+                )
+            }));
+            match result {
+                Ok(result) => return Ok(result),
+                Err(panic) if self.restarts >= max_restarts => resume_unwind(panic),
+                Err(_) => {
+                    self.restarts += 1;
+                    warn!(
+                        "[{}] Processor panicked, restarting it ({}/{max_restarts})",
+                        self.node_handle, self.restarts
+                    );
+                    self.restart()?;
+                }
+            }
+        }
+    }
+
+    /// Builds the processor again from the last checkpoint and replays the calls since.
+    fn restart(&mut self) -> Result<(), ExecutionError> {
+        // The crashed processor may still hold its state storage open.
+        drop(std::mem::replace(
+            &mut self.processor,
+            Box::new(ReplacedProcessor),
+        ));
+        let recovery = self
+            .recovery
+            .take()
+            .expect("supervised processors keep their recovery");
+        let mut processor = self.rebuild.build_from_checkpoint(
+            &recovery.records,
+            recovery.checkpoint_epoch,
+            recovery.checkpoint_data.clone(),
+        )?;
+        processor.set_record_readers(self.record_readers.clone());
+        self.processor = processor;
+        self.init()?;
+
+        // Errors were reported when the calls were first handled.
+        let mut forwarder = DiscardingForwarder;
+        for call in &recovery.calls {
+            let _ = match call {
+                Call::Process(port, op) => {
+                    self.processor
+                        .process(*port, &self.record_store, op.clone(), &mut forwarder)
+                }
+                Call::PortClosed(port) => self.processor.on_port_closed(*port, &mut forwarder),
+                Call::Watermark(watermark) => {
+                    self.processor.on_watermark(*watermark, &mut forwarder)
+                }
+                Call::BeforeCommit(epoch) => {
+                    self.processor
+                        .before_commit(epoch, &self.record_store, &mut forwarder)
+                }
+                Call::Commit(epoch) => self.processor.commit(epoch),
+            };
+        }
+        self.recovery = Some(recovery);
+        Ok(())
+    }
+
+    /// Keeps `call` for replay, if the processor is supervised.
+    fn record_call(&mut self, call: impl FnOnce() -> Call) {
+        if let Some(recovery) = &mut self.recovery {
+            recovery.calls.push(call());
+        }
+    }
+
+    fn process(
+        &mut self,
+        port: PortHandle,
+        op: ProcessorOperation,
+    ) -> Result<Result<(), BoxedError>, ExecutionError> {
+        if self.recovery.is_none() {
+            return Ok(self.processor.process(
+                port,
+                &self.record_store,
+                op,
+                &mut self.channel_manager,
+            ));
+        }
+        let result = self.supervised(|processor, record_store, forwarder| {
+            processor.process(port, record_store, op.clone(), forwarder)
+        })?;
+        self.record_call(|| Call::Process(port, op));
+        Ok(result)
+    }
+
+    fn apply_op(
+        &mut self,
+        index: usize,
+        op: ProcessorOperation,
+        context: OperationContext,
+    ) -> Result<(), ExecutionError> {
+        self.channel_manager.set_context(context);
+        let port = self.port_handles[index];
+        if self
+            .error_manager
+            .reject_oversized(&self.node_handle, port, &op, &self.record_store)
+        {
+            return Ok(());
+        }
+        let dead_letter = self
+            .error_manager
+            .collects_dead_letters()
+            .then(|| op.clone());
+        if let Err(e) = self.process(port, op)? {
+            self.error_manager.report_operation(
+                e,
+                &self.node_handle,
+                port,
+                dead_letter,
+                &self.record_store,
+            );
+        }
+        Ok(())
+    }
+
+    /// Processes the operations held back for total order, then closes the ports that closed meanwhile and passes on the watermark.
+    fn release_total_order(&mut self) -> Result<(), ExecutionError> {
+        while let Some((index, op, context, _)) = self
+            .total_order
+            .as_mut()
+            .and_then(TotalOrderBuffer::next_op)
+        {
+            self.apply_op(index, op, context)?;
+        }
+        let closed = self
+            .total_order
+            .as_mut()
+            .map(TotalOrderBuffer::take_closed)
+            .unwrap_or_default();
+        for index in closed {
+            self.close_port(index)?;
+        }
+        if let Some(watermark) = self
+            .total_order
+            .as_mut()
+            .and_then(TotalOrderBuffer::take_watermark)
+        {
+            self.apply_watermark(watermark)?;
+        }
+        Ok(())
+    }
+
+    fn apply_watermark(&mut self, watermark: SystemTime) -> Result<(), ExecutionError> {
+        let result = self
+            .supervised(|processor, _, forwarder| processor.on_watermark(watermark, forwarder))?;
+        self.record_call(|| Call::Watermark(watermark));
+        if let Err(e) = result {
+            self.error_manager.report(e);
+        }
+        self.channel_manager.send_watermark(watermark)
+    }
+
+    fn close_port(&mut self, index: usize) -> Result<(), ExecutionError> {
+        let port = self.port_handles[index];
+        let result =
+            self.supervised(|processor, _, forwarder| processor.on_port_closed(port, forwarder))?;
+        self.record_call(|| Call::PortClosed(port));
+        if let Err(e) = result {
+            self.error_manager.report(e);
+        }
+
+        // Once all inputs are closed, this processor won't produce more data either.
+        self.num_closed_ports += 1;
+        if self.num_closed_ports == self.port_handles.len() {
+            self.channel_manager.send_port_closed()?;
+        }
+        Ok(())
+    }
 }
 
 impl Name for ProcessorNode {
@@ -89,48 +413,314 @@ impl ReceiverLoop for ProcessorNode {
         self.initial_epoch_id
     }
 
-    fn receivers(&mut self) -> Vec<Receiver<ExecutorOperation>> {
+    fn init(&mut self) -> Result<(), ExecutionError> {
+        self.processor
+            .init()
+            .map_err(|error| ExecutionError::NodeInit {
+                node: self.node_handle.clone(),
+                error,
+            })
+    }
+
+    fn receivers(&mut self) -> Vec<EdgeReceiver> {
         let mut result = vec![];
         swap(&mut self.receivers, &mut result);
         result
     }
 
+    fn priority_receivers(&mut self) -> Vec<Arc<dyn TransportReceiver>> {
+        let mut result = vec![];
+        swap(&mut self.priority_receivers, &mut result);
+        result
+    }
+
+    fn record_store(&self) -> &ProcessorRecordStore {
+        &self.record_store
+    }
+
     fn receiver_name(&self, index: usize) -> Cow<str> {
         Cow::Owned(self.port_handles[index].to_string())
     }
 
-    fn on_op(&mut self, index: usize, op: ProcessorOperation) -> Result<(), ExecutionError> {
-        if let Err(e) = self.processor.process(
-            self.port_handles[index],
-            &self.record_store,
-            op,
-            &mut self.channel_manager,
-        ) {
-            self.error_manager.report(e);
+    fn metrics(&self) -> Option<&NodeMetrics> {
+        Some(&self.metrics)
+    }
+
+    fn on_op(
+        &mut self,
+        index: usize,
+        op: ProcessorOperation,
+        context: OperationContext,
+        _source_offset: Option<SourceOffset>,
+    ) -> Result<(), ExecutionError> {
+        // What a processor sends may derive from state built from earlier operations, so it doesn't inherit the offset.
+        // Sinks under `DeliverySemantics::ExactlyOnce` skip it only with its whole epoch.
+        if let Some(total_order) = &mut self.total_order {
+            total_order.on_op(index, op, context, None);
+            return Ok(());
         }
-        Ok(())
+        self.apply_op(index, op, context)
     }
 
     fn on_commit(&mut self, epoch: &Epoch) -> Result<(), ExecutionError> {
-        if let Err(e) = self.processor.commit(epoch) {
+        self.release_total_order()?;
+        let result = self.supervised(|processor, record_store, forwarder| {
+            processor.before_commit(epoch, record_store, forwarder)
+        })?;
+        self.record_call(|| Call::BeforeCommit(epoch.clone()));
+        if let Err(e) = result {
+            self.error_manager.report(e);
+        }
+        let result = self.supervised(|processor, _, _| processor.commit(epoch))?;
+        self.record_call(|| Call::Commit(epoch.clone()));
+        if let Err(e) = result {
             self.error_manager.report(e);
         }
 
         if let Some(checkpoint_writer) = &epoch.common_info.checkpoint_writer {
-            let object = checkpoint_writer.create_processor_object(&self.node_handle)?;
+            let mut object = checkpoint_writer.create_processor_object(&self.node_handle)?;
+            let copy = self.recovery.is_some().then(Arc::<Mutex<Vec<u8>>>::default);
+            if let Some(copy) = &copy {
+                object = object.with_copy(copy.clone());
+            }
             self.processor
                 .serialize(&self.record_store, object)
                 .map_err(ExecutionError::FailedToCreateCheckpoint)?;
+            self.rebuild.snapshot_state(
+                self.processor.as_ref(),
+                epoch.common_info.id,
+                self.checkpoint_retention,
+            )?;
+            if let Some(copy) = copy {
+                // The calls so far are in the checkpoint now.
+                self.recovery = Some(Recovery {
+                    checkpoint_epoch: Some(epoch.common_info.id),
+                    checkpoint_data: Some(std::mem::take(&mut *copy.lock())),
+                    records: self
+                        .record_store
+                        .pin()
+                        .expect("only the in-memory record store is supervised"),
+                    calls: vec![],
+                });
+            }
         }
 
         self.channel_manager.send_commit(epoch)
     }
 
     fn on_terminate(&mut self) -> Result<(), ExecutionError> {
+        self.release_total_order()?;
+        if self.flush_on_stop {
+            self.processor
+                .flush()
+                .map_err(|error| ExecutionError::NodeFlush {
+                    node: self.node_handle.clone(),
+                    error,
+                })?;
+        }
         self.channel_manager.send_terminate()
     }
 
     fn on_snapshotting_done(&mut self, connection_name: String) -> Result<(), ExecutionError> {
         self.channel_manager.send_snapshotting_done(connection_name)
     }
+
+    fn on_port_closed(&mut self, index: usize) -> Result<(), ExecutionError> {
+        if let Some(total_order) = &mut self.total_order {
+            total_order.on_port_closed(index);
+            return Ok(());
+        }
+        self.close_port(index)
+    }
+
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), ExecutionError> {
+        if let Some(total_order) = &mut self.total_order {
+            total_order.on_watermark(watermark);
+            return Ok(());
+        }
+        self.apply_watermark(watermark)
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+    use dozer_storage::RocksdbMap;
+    use tempdir::TempDir;
+
+    use crate::executor::ExecutorOptions;
+    use crate::node::StateBackend;
+    use crate::tests::processors::StateCountingProcessorFactory;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::tests::{checkpoint_every_commit, dir_entries, run_dag};
+    use crate::{Dag, DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_flush_on_stop() {
+        let count: u64 = 1_000;
+        let state_dir = TempDir::new("test_run_dag_flush_on_stop").unwrap();
+        let latch = Arc::new(AtomicBool::new(true));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .processor(
+                proc_handle.clone(),
+                StateCountingProcessorFactory::new(StateBackend::RocksDb),
+            )
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().stop_after(count, latch),
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            state_dir: Some(state_dir.path().to_path_buf()),
+            flush_on_stop: true,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        // Without the write-ahead log, only what was flushed to table files is recovered.
+        let map_dir = state_dir.path().join(proc_handle.to_string());
+        for name in dir_entries(&map_dir) {
+            if name.ends_with(".log") {
+                std::fs::remove_file(map_dir.join(name)).unwrap();
+            }
+        }
+        let map = RocksdbMap::<u64, u64>::create(&map_dir, Default::default()).unwrap();
+        assert_eq!(map.get(&0).unwrap(), Some(count));
+    }
+
+    /// A generator source feeding `processor`, then a sink. The source quits once it has sent `count` operations.
+    fn generator_to_panicking_dag(count: u64, processor: StateCountingProcessorFactory) -> Dag {
+        let source_handle = NodeHandle::new(None, "source".to_string());
+        let proc_handle = NodeHandle::new(None, "proc".to_string());
+        let sink_handle = NodeHandle::new(None, "sink".to_string());
+        DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false),
+            )
+            .processor(proc_handle.clone(), processor)
+            .sink(sink_handle.clone(), RecordingSinkFactory::new())
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap()
+    }
+
+    async fn run_with_supervision(dag: Dag, max_restarts: u32, options: ExecutorOptions) {
+        let options = ExecutorOptions {
+            supervision: SupervisionPolicy::RestartFromCheckpoint { max_restarts },
+            ..options
+        };
+        run_dag(dag, options).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_restarts_panicked_processor() {
+        let count: u64 = 1_000;
+        let processor =
+            StateCountingProcessorFactory::new(StateBackend::RocksDb).panic_once_at(count / 2);
+        let (builds, state_count) = (processor.builds(), processor.count());
+        run_with_supervision(
+            generator_to_panicking_dag(count, processor),
+            1,
+            Default::default(),
+        )
+        .await;
+
+        // Without checkpoints, the rebuilt processor started on empty state, caught up on every operation and retried the one it panicked on.
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+        assert_eq!(state_count.load(Ordering::SeqCst), count);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_restarts_panicked_memory_processor() {
+        let count: u64 = 1_000;
+        let processor =
+            StateCountingProcessorFactory::new(StateBackend::Memory).panic_once_at(count / 2);
+        let (builds, state_count) = (processor.builds(), processor.count());
+        run_with_supervision(
+            generator_to_panicking_dag(count, processor),
+            1,
+            Default::default(),
+        )
+        .await;
+
+        // The count it only kept in memory was rebuilt from the operations replayed.
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+        assert_eq!(state_count.load(Ordering::SeqCst), count);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_restarts_panicked_processor_from_checkpoint() {
+        let count: u64 = 1_000;
+        let state_dir =
+            TempDir::new("test_run_dag_restarts_panicked_processor_from_checkpoint").unwrap();
+        let processor =
+            StateCountingProcessorFactory::new(StateBackend::RocksDb).panic_once_at(count / 2);
+        let (builds, state_count) = (processor.builds(), processor.count());
+        run_with_supervision(
+            generator_to_panicking_dag(count, processor),
+            1,
+            checkpoint_every_commit(&state_dir),
+        )
+        .await;
+
+        // The state was restored to the last checkpoint and only the operations since were replayed, so none counts twice.
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+        assert_eq!(state_count.load(Ordering::SeqCst), count);
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "processor crashed")]
+    async fn test_run_dag_escalates_processor_panic_past_max_restarts() {
+        let count: u64 = 1_000;
+        let processor =
+            StateCountingProcessorFactory::new(StateBackend::RocksDb).panic_once_at(count / 2);
+        run_with_supervision(
+            generator_to_panicking_dag(count, processor),
+            0,
+            Default::default(),
+        )
+        .await;
+    }
 }
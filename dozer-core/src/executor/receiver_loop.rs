@@ -1,15 +1,53 @@
 use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use crossbeam::channel::{Receiver, Select};
+use crossbeam::channel::{RecvError, Select, TryRecvError};
+use dozer_recordstore::ProcessorRecordStore;
 use dozer_types::log::debug;
 
 use crate::{
     epoch::Epoch,
     errors::ExecutionError,
-    executor_operation::{ExecutorOperation, ProcessorOperation},
+    executor_operation::{ExecutorOperation, OperationContext, ProcessorOperation, SourceOffset},
+    forwarder::EdgeReceiver,
+    transport::{TransportReceiver, POLL_INTERVAL},
 };
 
-use super::{name::Name, InputPortState};
+use super::{name::Name, node_metrics::NodeMetrics, watermark::InputWatermarks, InputPortState};
+
+/// What a node is waiting for from its inputs, shared by [`ReceiverLoop::receiver_loop`] and the single threaded scheduler.
+#[derive(Debug)]
+pub struct LoopState {
+    port_states: Vec<InputPortState>,
+    /// Inputs that haven't committed the current epoch, nor terminated.
+    pub selected: Vec<bool>,
+    commits_received: usize,
+    epoch_id: u64,
+    watermarks: InputWatermarks,
+}
+
+impl LoopState {
+    pub fn new(num_inputs: usize, initial_epoch_id: u64) -> Self {
+        Self {
+            port_states: vec![InputPortState::Open; num_inputs],
+            selected: vec![true; num_inputs],
+            commits_received: 0,
+            epoch_id: initial_epoch_id,
+            watermarks: InputWatermarks::new(num_inputs),
+        }
+    }
+}
+
+/// What [`ReceiverLoop::handle_operation`] changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handled {
+    Continue,
+    /// `LoopState::selected` changed.
+    Reselect,
+    /// All inputs terminated and the node quit.
+    Quit,
+}
 
 /// Common code for processor and sink nodes.
 ///
@@ -17,18 +55,127 @@ use super::{name::Name, InputPortState};
 pub trait ReceiverLoop: Name {
     /// Returns the epoch id that this node was constructed for.
     fn initial_epoch_id(&self) -> u64;
+    /// Initializes the node's processor or sink. Called before [`receiver_loop`], on the thread that runs it.
+    fn init(&mut self) -> Result<(), ExecutionError>;
     /// Returns input channels to this node. Will be called exactly once in [`receiver_loop`].
-    fn receivers(&mut self) -> Vec<Receiver<ExecutorOperation>>;
+    fn receivers(&mut self) -> Vec<EdgeReceiver>;
+    /// Returns the high priority channel of each input channel, or none if the node has no priority channels.
+    /// Will be called exactly once in [`receiver_loop`].
+    fn priority_receivers(&mut self) -> Vec<Arc<dyn TransportReceiver>> {
+        vec![]
+    }
+    /// Returns the record store that compressed operations are decompressed into.
+    fn record_store(&self) -> &ProcessorRecordStore;
     /// Returns the name of the receiver at `index`. Used for logging.
     fn receiver_name(&self, index: usize) -> Cow<str>;
+    /// Returns the metrics that [`handle_operation`] counts operations in, if the node has any.
+    fn metrics(&self) -> Option<&NodeMetrics> {
+        None
+    }
     /// Responds to `op` from the receiver at `index`.
-    fn on_op(&mut self, index: usize, op: ProcessorOperation) -> Result<(), ExecutionError>;
+    fn on_op(
+        &mut self,
+        index: usize,
+        op: ProcessorOperation,
+        context: OperationContext,
+        source_offset: Option<SourceOffset>,
+    ) -> Result<(), ExecutionError>;
     /// Responds to `commit` of `epoch`.
     fn on_commit(&mut self, epoch: &Epoch) -> Result<(), ExecutionError>;
     /// Responds to `terminate`.
     fn on_terminate(&mut self) -> Result<(), ExecutionError>;
     /// Responds to `SnapshottingDone`.
     fn on_snapshotting_done(&mut self, connection_name: String) -> Result<(), ExecutionError>;
+    /// Responds to `PortClosed` from the receiver at `index`.
+    fn on_port_closed(&mut self, index: usize) -> Result<(), ExecutionError>;
+    /// Responds to the node's watermark advancing to `watermark`, the earliest of its open inputs' watermarks.
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), ExecutionError>;
+    /// Returns if the executor was aborted, in which case the loop quits without handling further messages.
+    fn is_aborted(&self) -> bool;
+
+    /// Calls [`on_op`], [`on_commit`] or [`on_terminate`] as appropriate for `op` from the receiver at `index`.
+    fn handle_operation(
+        &mut self,
+        state: &mut LoopState,
+        index: usize,
+        op: ExecutorOperation,
+    ) -> Result<Handled, ExecutionError> {
+        match op {
+            ExecutorOperation::Op {
+                op,
+                context,
+                source_offset,
+            } => {
+                if let Some(metrics) = self.metrics() {
+                    metrics.on_op(&context.timestamps);
+                }
+                self.on_op(index, op, context, source_offset)?;
+            }
+            ExecutorOperation::CompressedOp {
+                op,
+                context,
+                source_offset,
+            } => {
+                if let Some(metrics) = self.metrics() {
+                    metrics.on_op(&context.timestamps);
+                }
+                let op = op.decompress(self.record_store())?;
+                self.on_op(index, op, context, source_offset)?;
+            }
+            ExecutorOperation::Commit { epoch } => {
+                assert_eq!(epoch.common_info.id, state.epoch_id);
+                state.commits_received += 1;
+                state.selected[index] = false;
+
+                if state.commits_received == state.selected.len() {
+                    self.on_commit(&epoch)?;
+                    state.epoch_id += 1;
+                    state.commits_received = 0;
+                    state.selected.fill(true);
+                }
+                return Ok(Handled::Reselect);
+            }
+            ExecutorOperation::Terminate => {
+                state.port_states[index] = InputPortState::Terminated;
+                state.selected[index] = false;
+                debug!(
+                    "[{}] Received Terminate request on port {}",
+                    self.name(),
+                    self.receiver_name(index)
+                );
+                if state
+                    .port_states
+                    .iter()
+                    .all(|v| v == &InputPortState::Terminated)
+                {
+                    self.on_terminate()?;
+                    debug!("[{}] Quit", self.name());
+                    return Ok(Handled::Quit);
+                }
+                return Ok(Handled::Reselect);
+            }
+            ExecutorOperation::SnapshottingDone { connection_name } => {
+                self.on_snapshotting_done(connection_name)?;
+            }
+            ExecutorOperation::PortClosed => {
+                debug!(
+                    "[{}] Port {} closed",
+                    self.name(),
+                    self.receiver_name(index)
+                );
+                self.on_port_closed(index)?;
+                if let Some(watermark) = state.watermarks.close(index) {
+                    self.on_watermark(watermark)?;
+                }
+            }
+            ExecutorOperation::Watermark { watermark } => {
+                if let Some(watermark) = state.watermarks.advance(index, watermark) {
+                    self.on_watermark(watermark)?;
+                }
+            }
+        }
+        Ok(Handled::Continue)
+    }
 
     /// The loop implementation, calls [`on_op`], [`on_commit`] and [`on_terminate`] at appropriate times.
     fn receiver_loop(&mut self, initial_epoch_id: u64) -> Result<(), ExecutionError> {
@@ -37,69 +184,171 @@ pub trait ReceiverLoop: Name {
             !receivers.is_empty(),
             "Processor or sink must have at least 1 incoming edge"
         );
-        let mut port_states = vec![InputPortState::Open; receivers.len()];
-
-        let mut commits_received: usize = 0;
-        let mut epoch_id = initial_epoch_id;
+        let priority_receivers = self.priority_receivers();
+        debug_assert!(priority_receivers.is_empty() || priority_receivers.len() == receivers.len());
 
-        let mut sel = init_select(&receivers);
+        // Priority channels stay selected while waiting for the other inputs to commit, so their operations jump epochs too.
+        let mut state = LoopState::new(receivers.len(), initial_epoch_id);
+        let mut priority_connected = vec![true; priority_receivers.len()];
+        let (mut sel, mut inputs) = init_select(
+            &receivers,
+            &state.selected,
+            &priority_receivers,
+            &priority_connected,
+        );
+        let polled = needs_polling(&receivers, &priority_receivers);
         loop {
-            let index = sel.ready();
-            let op = receivers[index]
-                .recv()
-                .map_err(|_| ExecutionError::CannotReceiveFromChannel)?;
-
-            match op {
-                ExecutorOperation::Op { op } => {
-                    self.on_op(index, op)?;
-                }
-                ExecutorOperation::Commit { epoch } => {
-                    assert_eq!(epoch.common_info.id, epoch_id);
-                    commits_received += 1;
-                    sel.remove(index);
-
-                    if commits_received == receivers.len() {
-                        self.on_commit(&epoch)?;
-                        epoch_id += 1;
-                        commits_received = 0;
-                        sel = init_select(&receivers);
-                    }
-                }
-                ExecutorOperation::Terminate => {
-                    port_states[index] = InputPortState::Terminated;
-                    sel.remove(index);
-                    debug!(
-                        "[{}] Received Terminate request on port {}",
-                        self.name(),
-                        self.receiver_name(index)
+            let ready = wait_ready(&mut sel, polled);
+            let (index, op) = if let Some((index, op)) =
+                try_recv_priority(&priority_receivers, &mut priority_connected)
+            {
+                (index, Ok(op))
+            } else if let Some(ready) = ready {
+                if let SelectedInput::Normal(index) = inputs[ready] {
+                    (index, receivers[index].recv())
+                } else {
+                    // The priority channel disconnected, which only happens when upstream quits.
+                    (sel, inputs) = init_select(
+                        &receivers,
+                        &state.selected,
+                        &priority_receivers,
+                        &priority_connected,
                     );
-                    if port_states.iter().all(|v| v == &InputPortState::Terminated) {
-                        self.on_terminate()?;
-                        debug!("[{}] Quit", self.name());
-                        return Ok(());
-                    }
+                    continue;
                 }
-                ExecutorOperation::SnapshottingDone { connection_name } => {
-                    self.on_snapshotting_done(connection_name)?;
+            } else if let Some((index, op)) = try_recv_polled(&receivers, &state.selected) {
+                (index, op)
+            } else {
+                continue;
+            };
+            // Upstream nodes quit when aborted, so check this before treating disconnection as an error.
+            if self.is_aborted() {
+                debug!("[{}] Aborted", self.name());
+                return Err(ExecutionError::Aborted);
+            }
+            let op = op.map_err(|_| ExecutionError::CannotReceiveFromChannel)?;
+            if let Some(metrics) = self.metrics() {
+                metrics.set_queue_depth(receivers.iter().map(|r| r.receiver.len()).sum());
+            }
+
+            match self.handle_operation(&mut state, index, op)? {
+                Handled::Continue => {}
+                Handled::Reselect => {
+                    (sel, inputs) = init_select(
+                        &receivers,
+                        &state.selected,
+                        &priority_receivers,
+                        &priority_connected,
+                    );
                 }
+                Handled::Quit => return Ok(()),
             }
         }
     }
 }
 
-fn init_select(receivers: &Vec<Receiver<ExecutorOperation>>) -> Select {
+/// An input channel registered in a [`Select`].
+#[derive(Debug, Clone, Copy)]
+enum SelectedInput {
+    Normal(usize),
+    Priority,
+}
+
+/// Selects the `selected` input channels and the connected priority channels, the ones that aren't polled.
+///
+/// Returns the input channel of each operation index of the `Select`.
+fn init_select<'a>(
+    receivers: &'a [EdgeReceiver],
+    selected: &[bool],
+    priority_receivers: &'a [Arc<dyn TransportReceiver>],
+    priority_connected: &[bool],
+) -> (Select<'a>, Vec<SelectedInput>) {
     let mut sel = Select::new();
-    for r in receivers {
-        sel.recv(r);
+    let mut inputs = vec![];
+    for (index, r) in receivers.iter().enumerate() {
+        if let (true, Some(receiver)) = (selected[index], r.receiver.as_crossbeam()) {
+            sel.recv(receiver);
+            inputs.push(SelectedInput::Normal(index));
+        }
+    }
+    for (index, r) in priority_receivers.iter().enumerate() {
+        if let (true, Some(receiver)) = (priority_connected[index], r.as_crossbeam()) {
+            sel.recv(receiver);
+            inputs.push(SelectedInput::Priority);
+        }
+    }
+    (sel, inputs)
+}
+
+/// Whether any of the channels has to be polled, see [`TransportReceiver::as_crossbeam`].
+fn needs_polling(
+    receivers: &[EdgeReceiver],
+    priority_receivers: &[Arc<dyn TransportReceiver>],
+) -> bool {
+    receivers
+        .iter()
+        .any(|r| r.receiver.as_crossbeam().is_none())
+        || priority_receivers
+            .iter()
+            .any(|r| r.as_crossbeam().is_none())
+}
+
+/// Waits until a channel registered in `sel` is ready, and returns its operation index.
+///
+/// If channels are `polled`, waits at most [`POLL_INTERVAL`] so they can be polled, and returns `None` on timeout.
+pub(super) fn wait_ready(sel: &mut Select, polled: bool) -> Option<usize> {
+    if polled {
+        sel.ready_timeout(POLL_INTERVAL).ok()
+    } else {
+        Some(sel.ready())
     }
-    sel
+}
+
+/// Returns the first queued operation of the `selected` inputs that are polled, and the index of its input.
+///
+/// A disconnected input is returned with an error.
+fn try_recv_polled(
+    receivers: &[EdgeReceiver],
+    selected: &[bool],
+) -> Option<(usize, Result<ExecutorOperation, RecvError>)> {
+    for (index, r) in receivers.iter().enumerate() {
+        if !selected[index] || r.receiver.as_crossbeam().is_some() {
+            continue;
+        }
+        match r.try_recv() {
+            Ok(op) => return Some((index, Ok(op))),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Some((index, Err(RecvError))),
+        }
+    }
+    None
+}
+
+/// Returns the first queued priority operation and the index of its input, marking disconnected channels.
+pub(super) fn try_recv_priority(
+    priority_receivers: &[Arc<dyn TransportReceiver>],
+    priority_connected: &mut [bool],
+) -> Option<(usize, ExecutorOperation)> {
+    for (index, r) in priority_receivers.iter().enumerate() {
+        if !priority_connected[index] {
+            continue;
+        }
+        match r.try_recv() {
+            Ok(op) => return Some((index, op)),
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => priority_connected[index] = false,
+        }
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{mem::swap, sync::Arc, time::SystemTime};
+    use std::{
+        mem::swap,
+        time::{Duration, UNIX_EPOCH},
+    };
 
-    use crossbeam::channel::{unbounded, Sender};
     use dozer_types::{
         node::{NodeHandle, SourceStates},
         types::{Field, Record},
@@ -107,14 +356,33 @@ mod tests {
 
     use dozer_recordstore::{ProcessorRecord, ProcessorRecordStore, StoreRecord};
 
+    use dozer_log::tokio;
+    use std::sync::atomic::AtomicBool;
+
+    use crate::executor_operation::OperationTimestamps;
+    use crate::tests::processors::{
+        RecordingProcessorFactory, RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+        RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+    };
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::transport::{ChannelTransport, InProcessTransport, TransportSender};
+    use crate::{Dag, Endpoint, DEFAULT_PORT_HANDLE};
+
     use super::*;
 
     struct TestReceiverLoop {
-        receivers: Vec<Receiver<ExecutorOperation>>,
-        ops: Vec<(usize, ProcessorOperation)>,
+        record_store: ProcessorRecordStore,
+        receivers: Vec<EdgeReceiver>,
+        priority_receivers: Vec<Arc<dyn TransportReceiver>>,
+        ops: Vec<(usize, ProcessorOperation, OperationContext)>,
         commits: Vec<Epoch>,
         snapshotting_done: Vec<String>,
+        closed_ports: Vec<usize>,
+        watermarks: Vec<SystemTime>,
         num_terminations: usize,
+        aborted: bool,
     }
 
     impl Name for TestReceiverLoop {
@@ -128,18 +396,38 @@ mod tests {
             0
         }
 
-        fn receivers(&mut self) -> Vec<Receiver<ExecutorOperation>> {
+        fn init(&mut self) -> Result<(), ExecutionError> {
+            Ok(())
+        }
+
+        fn receivers(&mut self) -> Vec<EdgeReceiver> {
             let mut result = vec![];
             swap(&mut self.receivers, &mut result);
             result
         }
 
+        fn priority_receivers(&mut self) -> Vec<Arc<dyn TransportReceiver>> {
+            let mut result = vec![];
+            swap(&mut self.priority_receivers, &mut result);
+            result
+        }
+
+        fn record_store(&self) -> &ProcessorRecordStore {
+            &self.record_store
+        }
+
         fn receiver_name(&self, index: usize) -> Cow<str> {
             Cow::Owned(format!("receiver_{index}"))
         }
 
-        fn on_op(&mut self, index: usize, op: ProcessorOperation) -> Result<(), ExecutionError> {
-            self.ops.push((index, op));
+        fn on_op(
+            &mut self,
+            index: usize,
+            op: ProcessorOperation,
+            context: OperationContext,
+            _source_offset: Option<SourceOffset>,
+        ) -> Result<(), ExecutionError> {
+            self.ops.push((index, op, context));
             Ok(())
         }
 
@@ -157,22 +445,61 @@ mod tests {
             self.snapshotting_done.push(connection_name);
             Ok(())
         }
+
+        fn on_port_closed(&mut self, index: usize) -> Result<(), ExecutionError> {
+            self.closed_ports.push(index);
+            Ok(())
+        }
+
+        fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), ExecutionError> {
+            self.watermarks.push(watermark);
+            Ok(())
+        }
+
+        fn is_aborted(&self) -> bool {
+            self.aborted
+        }
     }
 
     impl TestReceiverLoop {
-        fn new(num_receivers: usize) -> (TestReceiverLoop, Vec<Sender<ExecutorOperation>>) {
-            let (senders, receivers) = (0..num_receivers).map(|_| unbounded()).unzip();
+        fn new(num_receivers: usize) -> (TestReceiverLoop, Vec<Arc<dyn TransportSender>>) {
+            let (senders, receivers) = (0..num_receivers)
+                .map(|_| {
+                    let (sender, receiver) = InProcessTransport.channel(None);
+                    (sender, EdgeReceiver::new(receiver, Default::default()))
+                })
+                .unzip();
             (
                 TestReceiverLoop {
+                    record_store: ProcessorRecordStore::new(Default::default()).unwrap(),
                     receivers,
+                    priority_receivers: vec![],
                     ops: vec![],
                     commits: vec![],
                     snapshotting_done: vec![],
+                    closed_ports: vec![],
+                    watermarks: vec![],
                     num_terminations: 0,
+                    aborted: false,
                 },
                 senders,
             )
         }
+
+        fn with_priority(
+            num_receivers: usize,
+        ) -> (
+            TestReceiverLoop,
+            Vec<Arc<dyn TransportSender>>,
+            Vec<Arc<dyn TransportSender>>,
+        ) {
+            let (mut test_loop, senders) = TestReceiverLoop::new(num_receivers);
+            let (priority_senders, priority_receivers) = (0..num_receivers)
+                .map(|_| InProcessTransport.channel(None))
+                .unzip();
+            test_loop.priority_receivers = priority_receivers;
+            (test_loop, senders, priority_senders)
+        }
     }
 
     #[test]
@@ -199,6 +526,49 @@ mod tests {
         assert_eq!(test_loop.snapshotting_done, vec![connection_name])
     }
 
+    #[test]
+    fn receiver_loop_forwards_port_closed() {
+        let (mut test_loop, senders) = TestReceiverLoop::new(2);
+        senders[1].send(ExecutorOperation::PortClosed).unwrap();
+        senders[0].send(ExecutorOperation::Terminate).unwrap();
+        senders[1].send(ExecutorOperation::Terminate).unwrap();
+        test_loop.receiver_loop(0).unwrap();
+        assert_eq!(test_loop.closed_ports, vec![1]);
+    }
+
+    #[test]
+    fn receiver_loop_forwards_earliest_watermark() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let watermark = |secs| ExecutorOperation::Watermark {
+            watermark: at(secs),
+        };
+        let (mut test_loop, senders) = TestReceiverLoop::new(2);
+        senders[0].send(watermark(2)).unwrap();
+        senders[1].send(watermark(1)).unwrap();
+        senders[1].send(watermark(3)).unwrap();
+        senders[1].send(ExecutorOperation::PortClosed).unwrap();
+        senders[0].send(watermark(4)).unwrap();
+        senders[0].send(ExecutorOperation::Terminate).unwrap();
+        senders[1].send(ExecutorOperation::Terminate).unwrap();
+        test_loop.receiver_loop(0).unwrap();
+        // The inputs interleave in any order, but the watermark only goes forward and ends at the open input's.
+        assert!(test_loop.watermarks.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(test_loop.watermarks.last(), Some(&at(4)));
+    }
+
+    #[test]
+    fn receiver_loop_quits_when_aborted() {
+        let (mut test_loop, senders) = TestReceiverLoop::new(2);
+        test_loop.aborted = true;
+        senders[0].send(ExecutorOperation::Terminate).unwrap();
+        senders[1].send(ExecutorOperation::Terminate).unwrap();
+        assert!(matches!(
+            test_loop.receiver_loop(0),
+            Err(ExecutionError::Aborted)
+        ));
+        assert_eq!(test_loop.num_terminations, 0);
+    }
+
     #[test]
     fn receiver_loop_forwards_op() {
         let (mut test_loop, senders) = TestReceiverLoop::new(2);
@@ -206,11 +576,20 @@ mod tests {
         let record: ProcessorRecord = record_store
             .create_record(&Record::new(vec![Field::Int(1)]))
             .unwrap();
+        let context = OperationContext {
+            timestamps: OperationTimestamps {
+                event_time: Some(UNIX_EPOCH + Duration::from_secs(1)),
+                processing_time: Some(SystemTime::now()),
+            },
+            headers: Default::default(),
+        };
         senders[0]
             .send(ExecutorOperation::Op {
                 op: ProcessorOperation::Insert {
                     new: record.clone(),
                 },
+                context: context.clone(),
+                source_offset: None,
             })
             .unwrap();
         senders[0].send(ExecutorOperation::Terminate).unwrap();
@@ -218,10 +597,47 @@ mod tests {
         test_loop.receiver_loop(0).unwrap();
         assert_eq!(
             test_loop.ops,
-            vec![(0, ProcessorOperation::Insert { new: record })]
+            vec![(0, ProcessorOperation::Insert { new: record }, context)]
         );
     }
 
+    #[test]
+    fn receiver_loop_receives_priority_op_first() {
+        let (mut test_loop, senders, priority_senders) = TestReceiverLoop::with_priority(2);
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let insert = |value| ExecutorOperation::Op {
+            op: ProcessorOperation::Insert {
+                new: record_store
+                    .create_record(&Record::new(vec![Field::Int(value)]))
+                    .unwrap(),
+            },
+            context: Default::default(),
+            source_offset: None,
+        };
+        for value in 0..100 {
+            senders[0].send(insert(value)).unwrap();
+        }
+        priority_senders[0].send(insert(-1)).unwrap();
+        senders[0].send(ExecutorOperation::Terminate).unwrap();
+        senders[1].send(ExecutorOperation::Terminate).unwrap();
+        test_loop.receiver_loop(0).unwrap();
+
+        let values = test_loop
+            .ops
+            .iter()
+            .map(|(index, op, _)| {
+                assert_eq!(*index, 0);
+                let ProcessorOperation::Insert { new } = op else {
+                    panic!("Only inserts are sent");
+                };
+                record_store.load_record(new).unwrap().values[0].clone()
+            })
+            .collect::<Vec<_>>();
+        let mut expected = vec![Field::Int(-1)];
+        expected.extend((0..100).map(Field::Int));
+        assert_eq!(values, expected);
+    }
+
     #[test]
     fn receiver_loop_increases_epoch_id() {
         let (mut test_loop, senders) = TestReceiverLoop::new(2);
@@ -285,4 +701,62 @@ mod tests {
         senders[1].send(ExecutorOperation::Terminate).unwrap();
         test_loop.receiver_loop(0).unwrap();
     }
+
+    #[tokio::test]
+    async fn test_run_dag_2_sources_port_closed() {
+        let mut dag = Dag::new();
+        // The short source quits as soon as it has sent its operations.
+        let short_latch = Arc::new(AtomicBool::new(false));
+        // The long source keeps running until the processor sees the short source's port closed.
+        let long_latch = Arc::new(AtomicBool::new(true));
+
+        let short_source_handle = NodeHandle::new(None, 1.to_string());
+        let long_source_handle = NodeHandle::new(None, 2.to_string());
+
+        let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let processor = RecordingProcessorFactory::new()
+            .join()
+            .stop_on_port_closed(long_latch.clone());
+        let recording = processor.recording();
+        dag.add_source(
+            short_source_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(10, short_latch, false)),
+        );
+        dag.add_source(
+            long_source_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(10_000, long_latch, false)),
+        );
+        dag.add_processor(proc_handle.clone(), Box::new(processor));
+        dag.add_sink(sink_handle.clone(), Box::new(RecordingSinkFactory::new()));
+
+        dag.connect(
+            Endpoint::new(short_source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), RECORDING_PROCESSOR_LEFT_INPUT_PORT),
+        )
+        .unwrap();
+
+        dag.connect(
+            Endpoint::new(long_source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), RECORDING_PROCESSOR_RIGHT_INPUT_PORT),
+        )
+        .unwrap();
+
+        dag.connect(
+            Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+            Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        run_dag(dag, Default::default()).await.unwrap();
+
+        assert_eq!(
+            recording.lock().closed_ports,
+            vec![
+                RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+                RECORDING_PROCESSOR_RIGHT_INPUT_PORT
+            ]
+        );
+    }
 }
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use crossbeam::channel::{Select, TryRecvError};
+use dozer_types::log::debug;
+
+use crate::{errors::ExecutionError, forwarder::EdgeReceiver, transport::TransportReceiver};
+
+use super::receiver_loop::{try_recv_priority, wait_ready, Handled, LoopState, ReceiverLoop};
+
+/// Runs processors and sinks in turns on the calling thread, see `ExecutorOptions::single_threaded`.
+///
+/// Every turn, each node handles at most one operation. A node reads its inputs in strict rotation,
+/// waiting for the next one in turn even if others have operations queued, so what a node receives
+/// only depends on what its upstream nodes sent, not on thread timing. Priority operations are handled
+/// as soon as they're seen, so they're the exception.
+pub struct SingleThreadedScheduler {
+    nodes: Vec<ScheduledNode>,
+}
+
+impl SingleThreadedScheduler {
+    /// `nodes` take their turns in this order.
+    pub fn new(nodes: Vec<Box<dyn ReceiverLoop + Send>>) -> Self {
+        Self {
+            nodes: nodes.into_iter().map(ScheduledNode::new).collect(),
+        }
+    }
+
+    /// Initializes every node, in turn order.
+    pub fn init(&mut self) -> Result<(), ExecutionError> {
+        for node in &mut self.nodes {
+            node.node.init()?;
+        }
+        Ok(())
+    }
+
+    /// Returns once all nodes have quit, or when one fails. Call [`init`](Self::init) first.
+    pub fn run(mut self) -> Result<(), ExecutionError> {
+        while !self.nodes.is_empty() {
+            let mut progressed = false;
+            let mut index = 0;
+            while index < self.nodes.len() {
+                match self.nodes[index].turn()? {
+                    Turn::Handled => {
+                        progressed = true;
+                        index += 1;
+                    }
+                    Turn::Blocked => index += 1,
+                    Turn::Quit => {
+                        self.nodes.remove(index);
+                        progressed = true;
+                    }
+                }
+            }
+            if !progressed {
+                self.wait();
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks until any node can take its turn, or until polled channels are due.
+    fn wait(&self) {
+        let mut sel = Select::new();
+        let mut polled = false;
+        for node in &self.nodes {
+            let current = node
+                .current_input()
+                .map(|index| &*node.receivers[index].receiver);
+            let priority = node
+                .priority_receivers
+                .iter()
+                .zip(&node.priority_connected)
+                .filter(|(_, connected)| **connected)
+                .map(|(receiver, _)| &**receiver);
+            for receiver in current.into_iter().chain(priority) {
+                match receiver.as_crossbeam() {
+                    Some(receiver) => {
+                        sel.recv(receiver);
+                    }
+                    None => polled = true,
+                }
+            }
+        }
+        wait_ready(&mut sel, polled);
+    }
+}
+
+enum Turn {
+    Handled,
+    /// The node's next input is empty.
+    Blocked,
+    Quit,
+}
+
+struct ScheduledNode {
+    node: Box<dyn ReceiverLoop + Send>,
+    receivers: Vec<EdgeReceiver>,
+    priority_receivers: Vec<Arc<dyn TransportReceiver>>,
+    priority_connected: Vec<bool>,
+    state: LoopState,
+    /// Where the rotation over the inputs is.
+    next_input: usize,
+}
+
+impl ScheduledNode {
+    fn new(mut node: Box<dyn ReceiverLoop + Send>) -> Self {
+        let receivers = node.receivers();
+        debug_assert!(
+            !receivers.is_empty(),
+            "Processor or sink must have at least 1 incoming edge"
+        );
+        let priority_receivers = node.priority_receivers();
+        let state = LoopState::new(receivers.len(), node.initial_epoch_id());
+        Self {
+            node,
+            priority_connected: vec![true; priority_receivers.len()],
+            receivers,
+            priority_receivers,
+            state,
+            next_input: 0,
+        }
+    }
+
+    /// Returns the input this node reads next, skipping the ones that aren't selected.
+    fn current_input(&self) -> Option<usize> {
+        let num_inputs = self.receivers.len();
+        (0..num_inputs)
+            .map(|offset| (self.next_input + offset) % num_inputs)
+            .find(|index| self.state.selected[*index])
+    }
+
+    fn turn(&mut self) -> Result<Turn, ExecutionError> {
+        let (index, op) = if let Some((index, op)) =
+            try_recv_priority(&self.priority_receivers, &mut self.priority_connected)
+        {
+            (index, Ok(op))
+        } else {
+            let Some(index) = self.current_input() else {
+                return Ok(Turn::Blocked);
+            };
+            let op = match self.receivers[index].try_recv() {
+                Ok(op) => Ok(op),
+                Err(TryRecvError::Empty) => return Ok(Turn::Blocked),
+                Err(TryRecvError::Disconnected) => Err(ExecutionError::CannotReceiveFromChannel),
+            };
+            self.next_input = (index + 1) % self.receivers.len();
+            (index, op)
+        };
+        // Upstream nodes quit when aborted, so check this before treating disconnection as an error.
+        if self.node.is_aborted() {
+            debug!("[{}] Aborted", self.node.name());
+            return Err(ExecutionError::Aborted);
+        }
+
+        if let Some(metrics) = self.node.metrics() {
+            metrics.set_queue_depth(self.receivers.iter().map(|r| r.receiver.len()).sum());
+        }
+        match self.node.handle_operation(&mut self.state, index, op?)? {
+            Handled::Continue | Handled::Reselect => Ok(Turn::Handled),
+            Handled::Quit => Ok(Turn::Quit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    use dozer_log::tokio;
+    use dozer_types::bincode;
+    use dozer_types::node::NodeHandle;
+
+    use crate::executor::ExecutorOptions;
+    use crate::tests::dag_base_run::{
+        NoopJoinProcessorFactory, NOOP_JOIN_LEFT_INPUT_PORT, NOOP_JOIN_RIGHT_INPUT_PORT,
+    };
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{Dag, Endpoint, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    async fn run_2_sources_stateful_single_threaded(count: u64) -> Vec<u8> {
+        let mut dag = Dag::new();
+        let latch = Arc::new(AtomicBool::new(true));
+
+        let source1_handle = NodeHandle::new(None, 1.to_string());
+        let source2_handle = NodeHandle::new(None, 2.to_string());
+
+        let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let sink = RecordingSinkFactory::new()
+            .record_ops()
+            .stop_after(count * 2, latch.clone());
+        let recording = sink.recording();
+        dag.add_source(
+            source1_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(count, latch.clone(), true)),
+        );
+        dag.add_source(
+            source2_handle.clone(),
+            Box::new(GeneratorSourceFactory::new(count, latch, true)),
+        );
+        dag.add_processor(proc_handle.clone(), Box::new(NoopJoinProcessorFactory {}));
+        dag.add_sink(sink_handle.clone(), Box::new(sink));
+
+        dag.connect(
+            Endpoint::new(source1_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), NOOP_JOIN_LEFT_INPUT_PORT),
+        )
+        .unwrap();
+        dag.connect(
+            Endpoint::new(source2_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+            Endpoint::new(proc_handle.clone(), NOOP_JOIN_RIGHT_INPUT_PORT),
+        )
+        .unwrap();
+        dag.connect(
+            Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+            Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        // Only the final epoch is closed, so its boundary doesn't depend on timing.
+        let options = ExecutorOptions {
+            commit_sz: u32::MAX,
+            commit_time_threshold: Duration::from_secs(3600),
+            single_threaded: true,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let recording = recording.lock();
+        assert_eq!(recording.ops.len() as u64, count * 2);
+        let ops = recording
+            .ops
+            .iter()
+            .map(|recorded| &recorded.op)
+            .collect::<Vec<_>>();
+        bincode::serialize(&ops).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_2_sources_stateful_single_threaded_is_deterministic() {
+        let count: u64 = 5_000;
+        let first = run_2_sources_stateful_single_threaded(count).await;
+        let second = run_2_sources_stateful_single_threaded(count).await;
+        assert!(first == second, "Runs delivered different operations");
+    }
+}
@@ -1,8 +1,18 @@
-use std::{borrow::Cow, mem::swap, sync::Arc};
+use std::{
+    borrow::Cow,
+    mem::swap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
 
-use crossbeam::channel::Receiver;
 use daggy::NodeIndex;
+use dozer_recordstore::ProcessorRecordStore;
 use dozer_tracing::LabelsAndProgress;
+use dozer_types::log::{debug, info};
 use dozer_types::node::NodeHandle;
 use metrics::{describe_counter, describe_histogram, histogram, increment_counter};
 
@@ -11,12 +21,15 @@ use crate::{
     epoch::{Epoch, EpochManager},
     error_manager::ErrorManager,
     errors::ExecutionError,
-    executor_operation::{ExecutorOperation, ProcessorOperation},
-    node::{PortHandle, Sink},
+    executor_operation::{OperationContext, ProcessorOperation, SourceOffset},
+    forwarder::EdgeReceiver,
+    node::{CommitDecision, PortHandle, Sink},
+    transport::TransportReceiver,
 };
 
+use super::delivery::{DeliveryBuffer, DeliverySemantics, EpochOps};
 use super::execution_dag::ExecutionDag;
-use super::{name::Name, receiver_loop::ReceiverLoop};
+use super::{name::Name, node_metrics::NodeMetrics, receiver_loop::ReceiverLoop};
 
 /// A sink in the execution DAG.
 #[derive(Debug)]
@@ -28,22 +41,70 @@ pub struct SinkNode {
     /// Input port handles.
     port_handles: Vec<PortHandle>,
     /// Input data channels.
-    receivers: Vec<Receiver<ExecutorOperation>>,
+    receivers: Vec<EdgeReceiver>,
+    /// Input channels for high priority operations, one for each data channel.
+    priority_receivers: Vec<Arc<dyn TransportReceiver>>,
     /// The sink.
     sink: Box<dyn Sink>,
+    /// Decides when operations and commits reach the sink.
+    delivery: DeliveryBuffer,
     /// Where all the records from ingested data are stored.
     epoch_manager: Arc<EpochManager>,
     /// The error manager, for reporting non-fatal errors.
     error_manager: Arc<ErrorManager>,
     /// The metrics labels.
     labels: LabelsAndProgress,
+    /// This sink's index in the epoch manager's flush barrier.
+    flush_barrier_index: usize,
+    /// Shared by all sinks if `ExecutorOptions::max_operations` is set.
+    operation_limit: Option<Arc<OperationLimit>>,
+    /// Number of input ports whose upstream sources have finished.
+    num_closed_ports: usize,
+    /// Number of operations passed to the sink.
+    received: Arc<AtomicU64>,
+    metrics: Arc<NodeMetrics>,
+}
+
+/// Stops the pipeline once all sinks together have processed `max` operations.
+#[derive(Debug)]
+pub struct OperationLimit {
+    max: u64,
+    processed: AtomicU64,
+    running: Arc<AtomicBool>,
+}
+
+impl OperationLimit {
+    pub fn new(max: u64, running: Arc<AtomicBool>) -> Self {
+        Self {
+            max,
+            processed: AtomicU64::new(0),
+            running,
+        }
+    }
+
+    fn on_op(&self) {
+        if self.processed.fetch_add(1, Ordering::SeqCst) + 1 == self.max {
+            info!("Sinks processed {} operations, stopping", self.max);
+            self.running.store(false, Ordering::SeqCst);
+        }
+    }
 }
 
+/// How long a sink waits before retrying a commit it pushed back on the first time, doubling on every retry.
+const MIN_BACKPRESSURE_BACKOFF: Duration = Duration::from_millis(1);
+const MAX_BACKPRESSURE_BACKOFF: Duration = Duration::from_millis(100);
+
 const SINK_OPERATION_COUNTER_NAME: &str = "sink_operation";
 const PIPELINE_LATENCY_HISTOGRAM_NAME: &str = "pipeline_latency";
 
 impl SinkNode {
-    pub fn new(dag: &mut ExecutionDag, node_index: NodeIndex) -> Self {
+    pub fn new(
+        dag: &mut ExecutionDag,
+        node_index: NodeIndex,
+        delivery: DeliverySemantics,
+        operation_limit: Option<Arc<OperationLimit>>,
+        metrics: Arc<NodeMetrics>,
+    ) -> Self {
         let Some(node) = dag.node_weight_mut(node_index).take() else {
             panic!("Must pass in a node")
         };
@@ -52,7 +113,12 @@ impl SinkNode {
             panic!("Must pass in a sink node");
         };
 
-        let (port_handles, receivers) = dag.collect_receivers(node_index);
+        let (port_handles, receivers, priority_receivers) = dag.collect_receivers(node_index);
+        let (applied, applied_through) = if delivery == DeliverySemantics::ExactlyOnce {
+            (sink.applied_source_states(), sink.applied_through())
+        } else {
+            (None, None)
+        };
 
         describe_counter!(
             SINK_OPERATION_COUNTER_NAME,
@@ -68,40 +134,40 @@ impl SinkNode {
             initial_epoch_id: dag.epoch_manager().epoch_id(),
             port_handles,
             receivers,
+            priority_receivers,
             sink,
+            delivery: DeliveryBuffer::new(delivery, applied).with_applied_through(applied_through),
             epoch_manager: dag.epoch_manager().clone(),
             error_manager: dag.error_manager().clone(),
             labels: dag.labels().clone(),
+            flush_barrier_index: dag.epoch_manager().flush_barrier().register_sink(),
+            operation_limit,
+            num_closed_ports: 0,
+            received: Default::default(),
+            metrics,
         }
     }
 
     pub fn handle(&self) -> &NodeHandle {
         &self.node_handle
     }
-}
-
-impl Name for SinkNode {
-    fn name(&self) -> Cow<str> {
-        Cow::Owned(self.node_handle.to_string())
-    }
-}
-
-impl ReceiverLoop for SinkNode {
-    fn initial_epoch_id(&self) -> u64 {
-        self.initial_epoch_id
-    }
 
-    fn receivers(&mut self) -> Vec<Receiver<ExecutorOperation>> {
-        let mut result = vec![];
-        swap(&mut self.receivers, &mut result);
-        result
+    /// Number of operations passed to the sink, whether it processed them successfully or not.
+    pub fn received(&self) -> &Arc<AtomicU64> {
+        &self.received
     }
 
-    fn receiver_name(&self, index: usize) -> Cow<str> {
-        Cow::Owned(self.port_handles[index].to_string())
-    }
+    fn process(&mut self, index: usize, op: ProcessorOperation, context: OperationContext) {
+        let port = self.port_handles[index];
+        if self.error_manager.reject_oversized(
+            &self.node_handle,
+            port,
+            &op,
+            self.epoch_manager.record_store(),
+        ) {
+            return;
+        }
 
-    fn on_op(&mut self, index: usize, op: ProcessorOperation) -> Result<(), ExecutionError> {
         let mut labels = self.labels.labels().clone();
         labels.push("table", self.node_handle.id.clone());
         const OPERATION_TYPE_LABEL: &str = "operation_type";
@@ -117,23 +183,52 @@ impl ReceiverLoop for SinkNode {
             }
         }
 
-        if let Err(e) = self.sink.process(
-            self.port_handles[index],
-            self.epoch_manager.record_store(),
-            op,
-        ) {
-            self.error_manager.report(e);
+        let dead_letter = self
+            .error_manager
+            .collects_dead_letters()
+            .then(|| op.clone());
+        if let Err(e) =
+            self.sink
+                .process_with_context(port, self.epoch_manager.record_store(), op, &context)
+        {
+            self.error_manager.report_operation(
+                e,
+                &self.node_handle,
+                port,
+                dead_letter,
+                self.epoch_manager.record_store(),
+            );
         }
 
         increment_counter!(SINK_OPERATION_COUNTER_NAME, labels);
-
-        Ok(())
+        self.received.fetch_add(1, Ordering::SeqCst);
+        if let Some(operation_limit) = &self.operation_limit {
+            operation_limit.on_op();
+        }
     }
 
-    fn on_commit(&mut self, epoch: &Epoch) -> Result<(), ExecutionError> {
+    fn commit(&mut self, epoch: &Epoch) {
         // debug!("[{}] Checkpointing - {}", self.node_handle, epoch);
-        if let Err(e) = self.sink.commit(epoch) {
-            self.error_manager.report(e);
+        let mut backoff = MIN_BACKPRESSURE_BACKOFF;
+        loop {
+            match self.sink.commit_with_decision(epoch) {
+                Ok(CommitDecision::Committed | CommitDecision::Defer) => break,
+                Ok(CommitDecision::Backpressure) => {
+                    if self.is_aborted() {
+                        break;
+                    }
+                    debug!(
+                        "[{}] Sink pushed back on epoch {}, retrying in {:?}",
+                        self.node_handle, epoch.common_info.id, backoff
+                    );
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKPRESSURE_BACKOFF);
+                }
+                Err(e) => {
+                    self.error_manager.report(e);
+                    break;
+                }
+            }
         }
 
         if let Ok(duration) = epoch.decision_instant.elapsed() {
@@ -147,11 +242,108 @@ impl ReceiverLoop for SinkNode {
                 self.error_manager.report(e);
             }
         }
+    }
+
+    fn apply(&mut self, epoch: &Epoch, ops: EpochOps) {
+        for (index, op, context, _) in ops {
+            self.process(index, op, context);
+        }
+        self.commit(epoch);
+    }
+
+    fn apply_watermark(&mut self, watermark: SystemTime) {
+        if let Err(e) = self.sink.on_watermark(watermark) {
+            self.error_manager.report(e);
+        }
+    }
+}
+
+impl Name for SinkNode {
+    fn name(&self) -> Cow<str> {
+        Cow::Owned(self.node_handle.to_string())
+    }
+}
+
+impl ReceiverLoop for SinkNode {
+    fn initial_epoch_id(&self) -> u64 {
+        self.initial_epoch_id
+    }
 
+    fn init(&mut self) -> Result<(), ExecutionError> {
+        self.sink.init().map_err(|error| ExecutionError::NodeInit {
+            node: self.node_handle.clone(),
+            error,
+        })
+    }
+
+    fn receivers(&mut self) -> Vec<EdgeReceiver> {
+        let mut result = vec![];
+        swap(&mut self.receivers, &mut result);
+        result
+    }
+
+    fn priority_receivers(&mut self) -> Vec<Arc<dyn TransportReceiver>> {
+        let mut result = vec![];
+        swap(&mut self.priority_receivers, &mut result);
+        result
+    }
+
+    fn record_store(&self) -> &ProcessorRecordStore {
+        self.epoch_manager.record_store()
+    }
+
+    fn receiver_name(&self, index: usize) -> Cow<str> {
+        Cow::Owned(self.port_handles[index].to_string())
+    }
+
+    fn metrics(&self) -> Option<&NodeMetrics> {
+        Some(&self.metrics)
+    }
+
+    fn on_op(
+        &mut self,
+        index: usize,
+        op: ProcessorOperation,
+        context: OperationContext,
+        source_offset: Option<SourceOffset>,
+    ) -> Result<(), ExecutionError> {
+        if let Some((index, op, context, _)) =
+            self.delivery.on_op(index, op, context, source_offset)
+        {
+            self.process(index, op, context);
+        }
+        Ok(())
+    }
+
+    fn on_commit(&mut self, epoch: &Epoch) -> Result<(), ExecutionError> {
+        for (epoch, ops) in self.delivery.on_commit(epoch) {
+            self.apply(&epoch, ops);
+        }
+        if let Some(watermark) = self.delivery.take_released_watermark() {
+            self.apply_watermark(watermark);
+        }
+        self.epoch_manager
+            .flush_barrier()
+            .on_sink_commit(self.flush_barrier_index, epoch.common_info.id);
         Ok(())
     }
 
     fn on_terminate(&mut self) -> Result<(), ExecutionError> {
+        if let Some((epoch, ops)) = self.delivery.on_terminate() {
+            self.apply(&epoch, ops);
+        }
+        if let Some(watermark) = self.delivery.take_released_watermark() {
+            self.apply_watermark(watermark);
+        }
+        // Terminating after all upstream sources finished, rather than being stopped, is the end of the stream.
+        if self.num_closed_ports == self.port_handles.len() {
+            if let Err(e) = self.sink.on_end_of_stream() {
+                self.error_manager.report(e);
+            }
+        }
+        self.epoch_manager
+            .flush_barrier()
+            .on_sink_terminated(self.flush_barrier_index);
         Ok(())
     }
 
@@ -161,4 +353,170 @@ impl ReceiverLoop for SinkNode {
         }
         Ok(())
     }
+
+    fn on_port_closed(&mut self, _index: usize) -> Result<(), ExecutionError> {
+        self.num_closed_ports += 1;
+        Ok(())
+    }
+
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), ExecutionError> {
+        if let Some(watermark) = self.delivery.on_watermark(watermark) {
+            self.apply_watermark(watermark);
+        }
+        Ok(())
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.epoch_manager.aborted().load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64};
+
+    use dozer_log::tokio;
+    use dozer_types::node::OpIdentifier;
+
+    use crate::checkpoint::{create_checkpoint_for_test, OptionCheckpoint};
+    use crate::epoch::EpochManagerOptions;
+    use crate::executor::{DagExecutor, ExecutorOptions};
+    use crate::tests::dag_base_run::NoopProcessorFactory;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::tests::{generator_to_materializing_dag, run_dag};
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_sink_backpressure_bounds_queue() {
+        let count: u64 = 100_000;
+        let channel_buffer_sz = 1_000;
+
+        let latch = Arc::new(AtomicBool::new(true));
+        let sent = Arc::new(AtomicU64::new(0));
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let sink = RecordingSinkFactory::new()
+            .stop_after(count, latch.clone())
+            .with_queue_depth(sent.clone())
+            .with_deferred_commits(5_000, 5);
+        let recording = sink.recording();
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false).with_sent_counter(sent),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            channel_buffer_sz,
+            commit_sz: 500,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let recording = recording.lock();
+        assert!(recording.backpressured > 0);
+        // While the sink pushes back, the source blocks once the source listener channel and the edge channel are full,
+        // and the listener and the sink may hold one operation each.
+        assert!(recording.max_queue_depth <= 2 * channel_buffer_sz as u64 + 2);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_ends_stream_after_sources_finish() {
+        let count: u64 = 1_000;
+        let sink = RecordingSinkFactory::new();
+        let recording = sink.recording();
+
+        let source_handle = NodeHandle::new(None, "source".to_string());
+        let proc_handle = NodeHandle::new(None, "proc".to_string());
+        let sink_handle = NodeHandle::new(None, "sink".to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                // The source quits right after sending, closing its ports.
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false),
+            )
+            .processor(proc_handle.clone(), NoopProcessorFactory {})
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        // Called once, after every record reached the sink.
+        assert_eq!(recording.lock().ends, vec![count]);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_max_operations() {
+        let count: u64 = 1_000_000;
+        let max_operations: u64 = 10_000;
+        let (dag, recording) = generator_to_materializing_dag(count);
+        let options = ExecutorOptions {
+            commit_sz: 1_000,
+            channel_buffer_sz: 100,
+            epoch_manager_options: EpochManagerOptions {
+                max_num_records_before_persist: 1,
+                enable_app_checkpoints: true,
+                ..Default::default()
+            },
+            max_operations: Some(max_operations),
+            ..Default::default()
+        };
+        let (temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap()
+            .join()
+            .unwrap();
+
+        // Operations in flight when the limit was hit are still delivered.
+        let received = recording.lock().materialize().len() as u64;
+        assert!(received >= max_operations);
+        assert!(received < max_operations + 1_000, "received {received}");
+
+        // The last checkpoint covers exactly what the sink received.
+        let checkpoint = OptionCheckpoint::new(
+            temp_dir.path().to_str().unwrap().to_string(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        assert!(checkpoint.verify_integrity().await.unwrap().is_consistent());
+        let source_state = checkpoint
+            .get_source_state(&NodeHandle::new(None, 1.to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            source_state.into_values().next().flatten(),
+            Some(OpIdentifier::new(received, 0))
+        );
+    }
 }
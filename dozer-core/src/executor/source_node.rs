@@ -3,7 +3,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender};
@@ -16,25 +16,57 @@ use crate::{
     channels::SourceChannelForwarder,
     dag_schemas::EdgeKind,
     errors::ExecutionError,
+    executor_operation::OperationContext,
     forwarder::SourceChannelManager,
     node::{PortHandle, Source, SourceState},
 };
 
-use super::{execution_dag::ExecutionDag, node::Node, ExecutorOptions};
+use super::{execution_dag::ExecutionDag, node::Node, node_metrics::NodeMetrics, ExecutorOptions};
 
 impl SourceChannelForwarder for InternalChannelSourceForwarder {
     fn send(&mut self, message: IngestionMessage, port: PortHandle) -> Result<(), ExecutionError> {
-        Ok(self.sender.send((port, message))?)
+        Ok(self
+            .sender
+            .send(SourceMessage::Data(port, message, Default::default()))?)
+    }
+
+    fn send_with_context(
+        &mut self,
+        message: IngestionMessage,
+        port: PortHandle,
+        context: OperationContext,
+    ) -> Result<(), ExecutionError> {
+        Ok(self
+            .sender
+            .send(SourceMessage::Data(port, message, context))?)
+    }
+
+    fn send_watermark(
+        &mut self,
+        watermark: SystemTime,
+        port: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        Ok(self
+            .sender
+            .send(SourceMessage::Watermark(port, watermark))?)
     }
 }
 
+/// A message from a source sender to its listener.
+#[derive(Debug, Clone, PartialEq)]
+enum SourceMessage {
+    /// Data, with the context the source sent it with.
+    Data(PortHandle, IngestionMessage, OperationContext),
+    Watermark(PortHandle, SystemTime),
+}
+
 /// The sender half of a source in the execution DAG.
 #[derive(Debug)]
 pub struct SourceSenderNode {
     /// Node handle in description DAG.
     node_handle: NodeHandle,
     /// The source.
-    source: Box<dyn Source>,
+    source: Arc<dyn Source>,
     /// Last checkpointed output data sequence numbers.
     last_checkpoint: SourceState,
     /// The forwarder that will be passed to the source for outputting data.
@@ -55,24 +87,32 @@ impl Node for SourceSenderNode {
     }
 }
 
+/// The longest a source listener waits for data before checking if it should commit.
+const MAX_RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
 /// The listener part of a source in the execution DAG.
 #[derive(Debug)]
 pub struct SourceListenerNode {
     /// Node handle in description DAG.
     node_handle: NodeHandle,
     /// Output from corresponding source sender.
-    receiver: Receiver<(PortHandle, IngestionMessage)>,
+    receiver: Receiver<SourceMessage>,
     /// Receiving timeout.
     timeout: Duration,
     /// If the execution DAG should be running. Used for determining if a `terminate` message should be sent.
     running: Arc<AtomicBool>,
     /// This node's output channel manager, for communicating to other sources to coordinate terminate and commit, forwarding data, writing metadata and writing port state.
     channel_manager: SourceChannelManager,
+    /// If downstream nodes have been told that the source quit.
+    ports_closed: bool,
+    /// If the executor was aborted. The listener quits without committing or terminating.
+    aborted: Arc<AtomicBool>,
+    metrics: Arc<NodeMetrics>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 enum DataKind {
-    Data((PortHandle, IngestionMessage)),
+    Data(SourceMessage),
     NoDataBecauseOfTimeout,
     NoDataBecauseOfChannelDisconnection,
 }
@@ -83,14 +123,32 @@ impl SourceListenerNode {
         &mut self,
         data: DataKind,
     ) -> Result<bool, ExecutionError> {
+        if data == DataKind::NoDataBecauseOfChannelDisconnection && !self.ports_closed {
+            self.channel_manager.close_ports()?;
+            self.ports_closed = true;
+        }
         // If termination was requested the or source quit, we try to terminate.
         let terminating = data == DataKind::NoDataBecauseOfChannelDisconnection
             || !self.running.load(Ordering::SeqCst);
         // If this commit was not requested with termination at the start, we shouldn't terminate either.
         let terminating = match data {
-            DataKind::Data((port, message)) => self
-                .channel_manager
-                .send_and_trigger_commit_if_needed(message, port, terminating)?,
+            DataKind::Data(SourceMessage::Data(port, message, context)) => {
+                if let IngestionMessage::OperationEvent { .. } = message {
+                    self.metrics.on_sent();
+                }
+                self.metrics.set_queue_depth(self.receiver.len());
+                self.channel_manager.send_and_trigger_commit_if_needed(
+                    message,
+                    port,
+                    context,
+                    terminating,
+                )?
+            }
+            DataKind::Data(SourceMessage::Watermark(port, watermark)) => {
+                self.metrics.set_queue_depth(self.receiver.len());
+                self.channel_manager
+                    .send_watermark_and_trigger_commit_if_needed(watermark, port, terminating)?
+            }
             DataKind::NoDataBecauseOfTimeout | DataKind::NoDataBecauseOfChannelDisconnection => {
                 self.channel_manager.trigger_commit_if_needed(terminating)?
             }
@@ -106,7 +164,12 @@ impl SourceListenerNode {
 impl Node for SourceListenerNode {
     fn run(mut self) -> Result<(), ExecutionError> {
         loop {
-            let terminating = match self.receiver.recv_timeout(self.timeout) {
+            let data = self.receiver.recv_timeout(self.timeout);
+            if self.aborted.load(Ordering::SeqCst) {
+                debug!("[{}-listener] Aborted", &self.node_handle);
+                return Err(ExecutionError::Aborted);
+            }
+            let terminating = match data {
                 Ok(data) => self.send_and_trigger_commit_if_needed(DataKind::Data(data))?,
                 Err(RecvTimeoutError::Timeout) => {
                     self.send_and_trigger_commit_if_needed(DataKind::NoDataBecauseOfTimeout)?
@@ -124,11 +187,11 @@ impl Node for SourceListenerNode {
 
 #[derive(Debug)]
 struct InternalChannelSourceForwarder {
-    sender: Sender<(PortHandle, IngestionMessage)>,
+    sender: Sender<SourceMessage>,
 }
 
 impl InternalChannelSourceForwarder {
-    pub fn new(sender: Sender<(PortHandle, IngestionMessage)>) -> Self {
+    pub fn new(sender: Sender<SourceMessage>) -> Self {
         Self { sender }
     }
 }
@@ -138,6 +201,7 @@ pub async fn create_source_nodes(
     node_index: daggy::NodeIndex,
     options: &ExecutorOptions,
     running: Arc<AtomicBool>,
+    metrics: Arc<NodeMetrics>,
 ) -> (SourceSenderNode, SourceListenerNode) {
     // Get the source node.
     let Some(node) = dag.node_weight_mut(node_index).take() else {
@@ -166,6 +230,8 @@ pub async fn create_source_nodes(
     let (source_sender, source_receiver) = bounded(options.channel_buffer_sz);
     // let (source_sender, source_receiver) = bounded(1);
 
+    let mode = source.mode();
+
     // Create source listener.
     let forwarder = InternalChannelSourceForwarder::new(source_sender);
     let source_sender_node = SourceSenderNode {
@@ -176,24 +242,143 @@ pub async fn create_source_nodes(
     };
 
     // Create source sender node.
-    let (senders, record_writers) = dag.collect_senders_and_record_writers(node_index).await;
+    let (senders, record_writers) = dag.collect_senders_and_record_writers(node_index);
     let channel_manager = SourceChannelManager::new(
         node_handle.clone(),
         port_names,
         record_writers,
         senders,
-        options.commit_sz,
-        options.commit_time_threshold,
+        mode,
+        options,
         dag.epoch_manager().clone(),
         dag.error_manager().clone(),
+        dag.collect_output_schemas(node_index),
     );
     let source_listener_node = SourceListenerNode {
         node_handle,
         receiver: source_receiver,
-        timeout: options.commit_time_threshold,
+        // Wake up regularly even with a long commit threshold, to join flush barriers.
+        timeout: options.commit_time_threshold.min(MAX_RECEIVE_TIMEOUT),
         running,
         channel_manager,
+        ports_closed: false,
+        aborted: dag.aborted().clone(),
+        metrics,
     };
 
     (source_sender_node, source_listener_node)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Instant;
+
+    use dozer_log::tokio;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::executor::DagExecutor;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        BackfillSourceFactory, GeneratorSourceFactory, BACKFILL_SOURCE_OUTPUT_PORT,
+        GENERATOR_SOURCE_OUTPUT_PORT,
+    };
+    use crate::{Dag, DagBuilder, Endpoint};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_backfill_then_streaming() {
+        let mut dag = Dag::new();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let sink = RecordingSinkFactory::new();
+        let recording = sink.recording();
+        dag.add_source(
+            source_handle.clone(),
+            Box::new(BackfillSourceFactory::new(100, 100)),
+        );
+        dag.add_sink(sink_handle.clone(), Box::new(sink));
+
+        dag.connect(
+            Endpoint::new(source_handle, BACKFILL_SOURCE_OUTPUT_PORT),
+            Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        // Only commit on batch size.
+        let options = ExecutorOptions {
+            commit_sz: 10,
+            commit_time_threshold: Duration::from_secs(3600),
+            backfill_commit_sz: 50,
+            backfill_commit_time_threshold: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        // Two backfill batches, the snapshotting done commit, then ten streaming batches.
+        let mut expected = vec![50, 50, 0];
+        expected.extend([10; 10]);
+        assert_eq!(recording.lock().epoch_sizes, expected);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_reports_source_progress() {
+        let count: u64 = 200;
+        let running = Arc::new(AtomicBool::new(true));
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, running.clone(), false),
+            )
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().with_op_delay(Duration::from_millis(1)),
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        // A small buffer makes the slow sink throttle the source.
+        let options = ExecutorOptions {
+            channel_buffer_sz: 10,
+            ..Default::default()
+        };
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        let executor = DagExecutor::new(dag, checkpoint, options).await.unwrap();
+        assert_eq!(executor.source_progress()[&source_handle].processed, 0);
+        let join_handle = executor
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap();
+
+        let start = Instant::now();
+        let mut fractions = vec![];
+        loop {
+            let progress = join_handle.source_progress()[&source_handle];
+            assert_eq!(progress.total, Some(count));
+            let fraction = progress.fraction().unwrap();
+            fractions.push(fraction);
+            if fraction == 1.0 {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        running.store(false, Ordering::SeqCst);
+        join_handle.join().unwrap();
+
+        assert!(fractions.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(fractions[0] < 1.0, "{fractions:?}");
+    }
+}
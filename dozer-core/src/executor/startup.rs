@@ -0,0 +1,107 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use dozer_types::parking_lot::{Condvar, Mutex};
+
+use crate::errors::ExecutionError;
+
+/// How often sources waiting at the gate check if the executor was aborted.
+const ABORT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Holds sources back until every processor and sink has initialized, see `ExecutorOptions::strict_startup_ordering`.
+#[derive(Debug)]
+pub struct StartupGate {
+    /// Number of processor and sink threads that haven't initialized their nodes.
+    remaining: Mutex<usize>,
+    condvar: Condvar,
+}
+
+impl StartupGate {
+    pub fn new(num_threads: usize) -> Self {
+        Self {
+            remaining: Mutex::new(num_threads),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Called by every processor and sink thread once it's initialized its nodes, even if that failed.
+    pub fn arrive(&self) {
+        let mut remaining = self.remaining.lock();
+        *remaining = remaining.saturating_sub(1);
+        if *remaining == 0 {
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Blocks until all threads have arrived, or returns `Aborted` if the executor is aborted first.
+    pub fn wait(&self, aborted: &AtomicBool) -> Result<(), ExecutionError> {
+        let mut remaining = self.remaining.lock();
+        while *remaining > 0 {
+            if aborted.load(Ordering::SeqCst) {
+                return Err(ExecutionError::Aborted);
+            }
+            self.condvar.wait_for(&mut remaining, ABORT_CHECK_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::executor::ExecutorOptions;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    #[tokio::test]
+    async fn test_run_dag_strict_startup_ordering() {
+        let count: u64 = 10_000;
+        let latch = Arc::new(AtomicBool::new(true));
+        let sink = RecordingSinkFactory::new()
+            .with_init_delay(Duration::from_millis(500))
+            .record_ops()
+            .stop_after(count, latch.clone());
+        let recording = sink.recording();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            strict_startup_ordering: true,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        // Every operation was ingested after the sink was ready.
+        let recording = recording.lock();
+        let initialized_at = recording.initialized_at.unwrap();
+        assert_eq!(recording.ops.len() as u64, count);
+        assert!(recording.ops.iter().all(|recorded| recorded
+            .context
+            .timestamps
+            .processing_time
+            .map_or(false, |ingested| ingested >= initialized_at)));
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use crate::executor_operation::{OperationContext, ProcessorOperation, SourceOffset};
+
+use super::delivery::EpochOp;
+
+/// Holds back the operations a processor receives in an epoch, to hand them over in one order across all inputs,
+/// see `ProcessorFactory::requires_total_order`.
+///
+/// Operations are handed over when the epoch commits, merging the inputs by processing time.
+/// An operation is taken from the input whose next operation has the earliest processing time, the input listed first on ties,
+/// so every input's operations stay in the order they arrived in.
+#[derive(Debug)]
+pub struct TotalOrderBuffer {
+    /// Operations received on every input since the last commit.
+    inputs: Vec<VecDeque<EpochOp>>,
+    /// Inputs that closed since the last commit, in the order they closed.
+    closed: Vec<usize>,
+    /// The latest watermark received since the last commit.
+    watermark: Option<SystemTime>,
+}
+
+impl TotalOrderBuffer {
+    pub fn new(num_inputs: usize) -> Self {
+        Self {
+            inputs: vec![VecDeque::new(); num_inputs],
+            closed: vec![],
+            watermark: None,
+        }
+    }
+
+    pub fn on_op(
+        &mut self,
+        index: usize,
+        op: ProcessorOperation,
+        context: OperationContext,
+        source_offset: Option<SourceOffset>,
+    ) {
+        self.inputs[index].push_back((index, op, context, source_offset));
+    }
+
+    /// Records that input `index` closed, after the operations it sent.
+    pub fn on_port_closed(&mut self, index: usize) {
+        self.closed.push(index);
+    }
+
+    /// Records that the processor's watermark advanced, after the operations it received.
+    pub fn on_watermark(&mut self, watermark: SystemTime) {
+        self.watermark = Some(watermark);
+    }
+
+    /// Returns the next operation in total order, or `None` once all held back operations are taken.
+    pub fn next_op(&mut self) -> Option<EpochOp> {
+        let index = self
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, ops)| Some((ops.front()?.2.timestamps.processing_time, index)))
+            .min()?
+            .1;
+        self.inputs[index].pop_front()
+    }
+
+    /// Returns the inputs that closed, once their operations are taken.
+    pub fn take_closed(&mut self) -> Vec<usize> {
+        debug_assert!(self.inputs.iter().all(VecDeque::is_empty));
+        std::mem::take(&mut self.closed)
+    }
+
+    /// Returns the latest watermark, once the operations before it are taken.
+    pub fn take_watermark(&mut self) -> Option<SystemTime> {
+        debug_assert!(self.inputs.iter().all(VecDeque::is_empty));
+        self.watermark.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use dozer_log::tokio;
+    use dozer_recordstore::{ProcessorRecordStore, StoreRecord};
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, Operation, Record};
+
+    use crate::executor::ExecutorOptions;
+    use crate::executor_operation::OperationTimestamps;
+    use crate::tests::processors::{
+        ProcessedOp, RecordingProcessorFactory, RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+        RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+    };
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[test]
+    fn test_total_order_merges_by_processing_time() {
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let new = record_store
+            .create_record(&Record::new(vec![Field::Null]))
+            .unwrap();
+        let context = |secs| OperationContext {
+            timestamps: OperationTimestamps {
+                event_time: None,
+                processing_time: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            },
+            headers: Default::default(),
+        };
+
+        let mut buffer = TotalOrderBuffer::new(2);
+        for (index, secs) in [(1, 1), (0, 2), (1, 2), (1, 5), (0, 3), (0, 4)] {
+            let op = ProcessorOperation::Insert { new: new.clone() };
+            buffer.on_op(index, op, context(secs), None);
+        }
+        buffer.on_port_closed(1);
+        buffer.on_watermark(UNIX_EPOCH + Duration::from_secs(3));
+
+        let mut order = vec![];
+        while let Some((index, _, context, _)) = buffer.next_op() {
+            let secs = context
+                .timestamps
+                .processing_time
+                .unwrap()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            order.push((index, secs));
+        }
+        // Ties go to the input listed first.
+        assert_eq!(order, vec![(1, 1), (0, 2), (1, 2), (0, 3), (0, 4), (1, 5)]);
+        assert_eq!(buffer.take_closed(), vec![1]);
+        assert_eq!(
+            buffer.take_watermark(),
+            Some(UNIX_EPOCH + Duration::from_secs(3))
+        );
+    }
+
+    /// Runs two generator sources into a recording join, returning the operations it processed.
+    async fn run_order_recording(count: u64, total_order: bool) -> Vec<ProcessedOp> {
+        let latch = Arc::new(AtomicBool::new(true));
+        let mut processor = RecordingProcessorFactory::new().join().record_ops();
+        if total_order {
+            processor = processor.with_total_order();
+        }
+        let recording = processor.recording();
+
+        let source1_handle = NodeHandle::new(None, 1.to_string());
+        let source2_handle = NodeHandle::new(None, 2.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+        let dag = DagBuilder::new()
+            .source(
+                source1_handle.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .source(
+                source2_handle.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .processor(proc_handle.clone(), processor)
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().stop_after(count * 2, latch),
+            )
+            .edge(
+                &source1_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+            )
+            .edge(
+                &source2_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            commit_sz: 1_000,
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let processed = recording.lock().ops.clone();
+        assert_eq!(processed.len() as u64, count * 2);
+        processed
+    }
+
+    /// The `n` of the generated key of `processed`.
+    fn key_n(processed: &ProcessedOp) -> u64 {
+        let Operation::Insert { new } = &processed.op else {
+            panic!("Only inserts are expected, got {:?}", processed.op);
+        };
+        let Field::String(key) = &new.values[0] else {
+            panic!("Generated keys are strings, got {new:?}");
+        };
+        key.trim_start_matches("key_").parse().unwrap()
+    }
+
+    fn assert_per_port_fifo(processed: &[ProcessedOp]) {
+        for port in [
+            RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+            RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+        ] {
+            let keys = processed
+                .iter()
+                .filter(|processed| processed.port == port)
+                .map(key_n)
+                .collect::<Vec<_>>();
+            assert_eq!(keys, (1..keys.len() as u64 + 1).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_keeps_per_port_order() {
+        let processed = run_order_recording(10_000, false).await;
+        assert_per_port_fifo(&processed);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_total_order() {
+        let processed = run_order_recording(10_000, true).await;
+        assert_per_port_fifo(&processed);
+        // Within every epoch, operations from both ports are processed in processing time order.
+        for pair in processed.windows(2) {
+            let (first, second) = (&pair[0], &pair[1]);
+            if first.epoch == second.epoch {
+                let time1 = first.context.timestamps.processing_time;
+                let time2 = second.context.timestamps.processing_time;
+                assert!(time1 <= time2);
+                if time1 == time2 {
+                    assert!(first.port <= second.port);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::SystemTime;
+
+/// The watermark of an input of a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputWatermark {
+    /// No watermark received yet.
+    Pending,
+    At(SystemTime),
+    /// The input's upstream sources finished, so it no longer holds back the node's watermark.
+    Closed,
+}
+
+/// Combines the watermarks a node receives on its inputs into its own, the earliest of them.
+///
+/// The node's watermark only advances once every open input has sent one, and never goes back.
+#[derive(Debug)]
+pub struct InputWatermarks {
+    inputs: Vec<InputWatermark>,
+    watermark: Option<SystemTime>,
+}
+
+impl InputWatermarks {
+    pub fn new(num_inputs: usize) -> Self {
+        Self {
+            inputs: vec![InputWatermark::Pending; num_inputs],
+            watermark: None,
+        }
+    }
+
+    /// Records `watermark` from input `index`. Returns the node's watermark if it advanced.
+    pub fn advance(&mut self, index: usize, watermark: SystemTime) -> Option<SystemTime> {
+        match &mut self.inputs[index] {
+            InputWatermark::Closed => return None,
+            InputWatermark::At(current) if *current >= watermark => return None,
+            input => *input = InputWatermark::At(watermark),
+        }
+        self.combine()
+    }
+
+    /// Records that input `index` closed. Returns the node's watermark if it advanced.
+    pub fn close(&mut self, index: usize) -> Option<SystemTime> {
+        self.inputs[index] = InputWatermark::Closed;
+        self.combine()
+    }
+
+    fn combine(&mut self) -> Option<SystemTime> {
+        let mut earliest = None;
+        for input in &self.inputs {
+            match input {
+                InputWatermark::Pending => return None,
+                InputWatermark::At(watermark) => {
+                    earliest = Some(
+                        earliest
+                            .map_or(*watermark, |earliest: SystemTime| earliest.min(*watermark)),
+                    )
+                }
+                InputWatermark::Closed => {}
+            }
+        }
+        let earliest = earliest?;
+        if self
+            .watermark
+            .map_or(false, |watermark| watermark >= earliest)
+        {
+            return None;
+        }
+        self.watermark = Some(earliest);
+        self.watermark
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::tests::processors::RecordingProcessorFactory;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        generator_event_time, GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT,
+    };
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[test]
+    fn test_input_watermarks_advance_with_earliest_open_input() {
+        let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let mut watermarks = InputWatermarks::new(3);
+        // Input 2 hasn't sent a watermark yet.
+        assert_eq!(watermarks.advance(0, at(5)), None);
+        assert_eq!(watermarks.advance(1, at(3)), None);
+        assert_eq!(watermarks.advance(2, at(4)), Some(at(3)));
+        // Watermarks going back are ignored.
+        assert_eq!(watermarks.advance(1, at(2)), None);
+        assert_eq!(watermarks.advance(1, at(6)), Some(at(4)));
+        // The closed input no longer holds back the others.
+        assert_eq!(watermarks.close(2), Some(at(5)));
+        assert_eq!(watermarks.advance(2, at(10)), None);
+        assert_eq!(watermarks.close(0), Some(at(6)));
+        assert_eq!(watermarks.close(1), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_propagates_watermarks() {
+        let count: u64 = 1_000;
+        let interval: u64 = 10;
+        let processor = RecordingProcessorFactory::new().hold_until_watermark();
+        let processor_recording = processor.recording();
+        let sink = RecordingSinkFactory::new();
+        let received = sink.received();
+        let sink_recording = sink.recording();
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        // The source quits once it has sent everything, so every watermark is delivered before the pipeline terminates.
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false)
+                    .with_watermarks(interval),
+            )
+            .processor(proc_handle.clone(), processor)
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let expected = (1..=count / interval)
+            .map(|n| generator_event_time(n * interval))
+            .collect::<Vec<_>>();
+        let sink_recording = sink_recording.lock();
+        let sink_watermarks = &sink_recording.watermarks;
+        assert!(sink_watermarks.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(*sink_watermarks, expected);
+        assert_eq!(processor_recording.lock().watermarks, expected);
+        // The processor released its window on every watermark, the last one after the last operation.
+        assert_eq!(received.load(Ordering::SeqCst), count);
+    }
+}
@@ -1,7 +1,13 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use dozer_recordstore::{ProcessorRecord, StoreRecord};
+use dozer_types::bincode;
+use dozer_types::node::{NodeHandle, OpIdentifier};
 use dozer_types::types::Operation;
 
-use crate::{epoch::Epoch, errors::ExecutionError};
+use crate::{epoch::Epoch, errors::ExecutionError, node::Compression};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 /// A CDC event.
@@ -48,12 +54,363 @@ impl ProcessorOperation {
             },
         })
     }
+
+    /// The length of the operation's bincode encoding, which is how it's stored in checkpoints and dead letters.
+    pub fn encoded_size(&self, record_store: &impl StoreRecord) -> Result<u64, ExecutionError> {
+        bincode::serialized_size(&self.load(record_store)?)
+            .map_err(ExecutionError::SerializeOperation)
+    }
+}
+
+/// An operation with its records serialized and compressed, so it doesn't hold references into the record store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedOperation {
+    compression: Compression,
+    data: Vec<u8>,
+}
+
+impl CompressedOperation {
+    pub fn new(
+        op: &ProcessorOperation,
+        compression: Compression,
+        record_store: &impl StoreRecord,
+    ) -> Result<Self, ExecutionError> {
+        let data = bincode::serialize(&op.load(record_store)?)
+            .map_err(ExecutionError::SerializeOperation)?;
+        let data = match compression {
+            Compression::Lz4 => lz4::block::compress(&data, None, true),
+            Compression::Zstd => zstd::encode_all(data.as_slice(), 0),
+        }
+        .map_err(ExecutionError::Compression)?;
+        Ok(Self { compression, data })
+    }
+
+    /// Decompresses the operation, adding its records to `record_store`.
+    pub fn decompress(
+        &self,
+        record_store: &impl StoreRecord,
+    ) -> Result<ProcessorOperation, ExecutionError> {
+        let data = match self.compression {
+            Compression::Lz4 => lz4::block::decompress(&self.data, None),
+            Compression::Zstd => zstd::decode_all(self.data.as_slice()),
+        }
+        .map_err(ExecutionError::Compression)?;
+        let op: Operation =
+            bincode::deserialize(&data).map_err(ExecutionError::SerializeOperation)?;
+        ProcessorOperation::new(&op, record_store)
+    }
+}
+
+/// When an operation happened at its source, and when the executor ingested it, part of its [`OperationContext`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OperationTimestamps {
+    /// Set if the source provided it, see `SourceChannelForwarder::send_with_context`.
+    pub event_time: Option<SystemTime>,
+    /// Stamped by the source listener. Never decreases for operations from the same source.
+    pub processing_time: Option<SystemTime>,
+}
+
+/// Which timestamp time based processors, like windows, key on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeDomain {
+    EventTime,
+    ProcessingTime,
+}
+
+impl OperationTimestamps {
+    pub fn get(&self, domain: TimeDomain) -> Option<SystemTime> {
+        match domain {
+            TimeDomain::EventTime => self.event_time,
+            TimeDomain::ProcessingTime => self.processing_time,
+        }
+    }
+}
+
+/// Metadata a source attached to an operation, like a tenant or trace id, see `SourceChannelForwarder::send_with_context`.
+///
+/// Part of the operation's [`OperationContext`]. Headers are shared, so cloning them is cheap.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationHeaders(Option<Arc<HashMap<String, Vec<u8>>>>);
+
+impl OperationHeaders {
+    pub fn new(headers: HashMap<String, Vec<u8>>) -> Self {
+        Self((!headers.is_empty()).then(|| Arc::new(headers)))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.0.as_ref()?.get(key).map(Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.0
+            .iter()
+            .flat_map(|headers| headers.iter())
+            .map(|(key, value)| (key.as_str(), value.as_slice()))
+    }
+}
+
+/// What travels with an operation from its source to processors and sinks, besides its records.
+///
+/// Operations sent by a processor inherit the context of the operation it's processing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OperationContext {
+    pub timestamps: OperationTimestamps,
+    pub headers: OperationHeaders,
+}
+
+/// Where in its source table an operation was read, for sources that report operation identifiers.
+///
+/// Only operations sent by sources carry one. Operations sent by processors may derive from state built from earlier operations,
+/// so they don't inherit the offset of the operation being processed.
+/// The table is shared, so cloning an offset is cheap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceOffset {
+    /// The source node and the table's name, as keyed in `SourceStates`.
+    pub table: Arc<(NodeHandle, String)>,
+    pub id: OpIdentifier,
+}
+
+/// Which channel of an edge an operation is sent through.
+///
+/// `High` operations, like control markers, are received before any `Normal` operation still queued on the same node,
+/// so they may reach it in an earlier epoch than the one they were sent in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OperationPriority {
+    #[default]
+    Normal,
+    High,
 }
 
 #[derive(Clone, Debug)]
 pub enum ExecutorOperation {
-    Op { op: ProcessorOperation },
-    Commit { epoch: Epoch },
+    Op {
+        op: ProcessorOperation,
+        context: OperationContext,
+        source_offset: Option<SourceOffset>,
+    },
+    /// An `Op` sent through an edge with compression.
+    CompressedOp {
+        op: CompressedOperation,
+        context: OperationContext,
+        source_offset: Option<SourceOffset>,
+    },
+    Commit {
+        epoch: Epoch,
+    },
     Terminate,
-    SnapshottingDone { connection_name: String },
+    SnapshottingDone {
+        connection_name: String,
+    },
+    PortClosed,
+    /// The upstream node won't send operations with event times before `watermark` anymore.
+    Watermark {
+        watermark: SystemTime,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+    use dozer_recordstore::ProcessorRecordStore;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, Record};
+
+    use crate::node::{OutputPortDefOptions, SinkOptions};
+    use crate::tests::dag_base_run::NoopProcessorFactory;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        generated_value, generator_event_time, generator_tenant, GeneratorSourceFactory,
+        GENERATOR_SOURCE_OUTPUT_PORT, GENERATOR_TENANT_HEADER,
+    };
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    #[test]
+    fn compressed_operation_round_trips() {
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let old = Record::new(vec![Field::UInt(1), Field::String("a".repeat(1_000))]);
+        let new = Record::new(vec![Field::UInt(1), Field::String("b".repeat(1_000))]);
+        let op = ProcessorOperation::new(
+            &Operation::Update {
+                old: old.clone(),
+                new: new.clone(),
+            },
+            &record_store,
+        )
+        .unwrap();
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let compressed = CompressedOperation::new(&op, compression, &record_store).unwrap();
+            assert!(compressed.data.len() < 1_000, "{compression:?}");
+            let decompressed = compressed.decompress(&record_store).unwrap();
+            assert_eq!(
+                decompressed.load(&record_store).unwrap(),
+                Operation::Update {
+                    old: old.clone(),
+                    new: new.clone()
+                }
+            );
+        }
+    }
+
+    /// Runs generated operations with event times and headers through a noop processor into a recording sink,
+    /// split into `num_partitions` and run with `options` if given, and returns the contexts it received.
+    async fn run_context_recording_dag(
+        count: u64,
+        num_partitions: Option<usize>,
+        options: Option<SinkOptions>,
+    ) -> Vec<(Field, OperationContext)> {
+        let latch = Arc::new(AtomicBool::new(true));
+        let mut sink = RecordingSinkFactory::new()
+            .record_ops()
+            .stop_after(count, latch.clone());
+        if let Some(num_partitions) = num_partitions {
+            sink = sink.with_partitions(num_partitions);
+        }
+        let recording = sink.recording();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        let builder = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false)
+                    .with_event_times()
+                    .with_headers(),
+            )
+            .processor(proc_handle.clone(), NoopProcessorFactory {});
+        let builder = match options {
+            Some(options) => builder.sink_with_options(sink_handle.clone(), sink, options),
+            None => builder.sink(sink_handle.clone(), sink),
+        };
+        let dag = builder
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let recording = recording.lock();
+        recording
+            .ops
+            .iter()
+            .map(|recorded| (recorded.key().clone(), recorded.context.clone()))
+            .collect()
+    }
+
+    fn assert_generator_context(n: u64, context: &OperationContext) {
+        assert_eq!(context.timestamps.event_time, Some(generator_event_time(n)));
+        assert!(context.timestamps.processing_time.is_some());
+        assert_eq!(
+            context.headers.get(GENERATOR_TENANT_HEADER),
+            Some(generator_tenant(n).as_slice())
+        );
+        assert_eq!(context.headers.iter().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_preserves_context() {
+        let count: u64 = 1_000;
+        let contexts = run_context_recording_dag(count, None, None).await;
+
+        assert_eq!(contexts.len(), count as usize);
+        let mut last_processing_time = None;
+        for (n, (key, context)) in (1..count + 1).zip(contexts.iter()) {
+            assert_eq!(key, &Field::String(format!("key_{n}")));
+            assert_generator_context(n, context);
+            let processing_time = context.timestamps.processing_time;
+            assert!(last_processing_time <= processing_time);
+            last_processing_time = processing_time;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_wrapped_sinks_pass_context_on() {
+        let count: u64 = 1_000;
+        let options = SinkOptions {
+            circuit_breaker: Some(Default::default()),
+            dedup_by_primary_key: true,
+            dedup_capacity: None,
+        };
+        let contexts = run_context_recording_dag(count, Some(3), Some(options)).await;
+
+        // Partitions record concurrently, so only every key's context is checked.
+        assert_eq!(contexts.len(), count as usize);
+        let contexts = contexts.into_iter().collect::<HashMap<_, _>>();
+        for n in 1..count + 1 {
+            assert_generator_context(n, &contexts[&Field::String(format!("key_{n}"))]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_compression() {
+        let count: u64 = 1_000;
+        let value_len = 10_000;
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let sink = RecordingSinkFactory::new().record_ops();
+            let recording = sink.recording();
+            let source_handle = NodeHandle::new(None, 1.to_string());
+            let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+            let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+            let dag = DagBuilder::new()
+                .source(
+                    source_handle.clone(),
+                    GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false)
+                        .with_value_len(value_len)
+                        .with_port_options(OutputPortDefOptions {
+                            compression: Some(compression),
+                            ..Default::default()
+                        }),
+                )
+                .processor(proc_handle.clone(), NoopProcessorFactory {})
+                .sink(sink_handle.clone(), sink)
+                .edge(
+                    &source_handle,
+                    GENERATOR_SOURCE_OUTPUT_PORT,
+                    &proc_handle,
+                    DEFAULT_PORT_HANDLE,
+                )
+                .edge(
+                    &proc_handle,
+                    DEFAULT_PORT_HANDLE,
+                    &sink_handle,
+                    RECORDING_SINK_INPUT_PORT,
+                )
+                .build()
+                .unwrap();
+            run_dag(dag, Default::default()).await.unwrap();
+
+            let state = recording.lock().materialize();
+            assert_eq!(state.len() as u64, count);
+            for n in 1..=count {
+                let key = Field::String(format!("key_{n}"));
+                let expected = Record::new(vec![
+                    key.clone(),
+                    Field::String(generated_value(n, value_len)),
+                ]);
+                assert_eq!(state[&key], expected, "{compression:?}");
+            }
+        }
+    }
 }
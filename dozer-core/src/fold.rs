@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use dozer_log::storage::Queue;
+use dozer_recordstore::ProcessorRecordStore;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::parking_lot::Mutex;
+use dozer_types::types::{Operation, Schema};
+
+use crate::epoch::Epoch;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::{PortHandle, Sink, SinkFactory};
+use crate::DEFAULT_PORT_HANDLE;
+
+pub type FoldFn<A> = Arc<dyn Fn(A, &Operation) -> A + Send + Sync>;
+
+/// Folds every operation it receives into an accumulator, like `Iterator::fold` over the stream.
+///
+/// Read the result from the [`FoldAccumulator`] returned by [`FoldSinkFactory::accumulator`], typically after the executor is joined.
+/// The accumulator isn't checkpointed, so a restarted executor folds from the initial value again.
+pub struct FoldSinkFactory<A> {
+    accumulator: FoldAccumulator<A>,
+    fold: FoldFn<A>,
+}
+
+impl<A> FoldSinkFactory<A> {
+    pub fn new(initial: A, fold: impl Fn(A, &Operation) -> A + Send + Sync + 'static) -> Self {
+        Self {
+            accumulator: FoldAccumulator(Arc::new(Mutex::new(Some(initial)))),
+            fold: Arc::new(fold),
+        }
+    }
+
+    /// A handle to the accumulator, which stays valid after the factory is added to a DAG.
+    pub fn accumulator(&self) -> FoldAccumulator<A> {
+        self.accumulator.clone()
+    }
+}
+
+impl<A: Debug> Debug for FoldSinkFactory<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FoldSinkFactory")
+            .field("accumulator", &self.accumulator)
+            .finish()
+    }
+}
+
+impl<A: Debug + Send + 'static> SinkFactory for FoldSinkFactory<A> {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn prepare(&self, _input_schemas: HashMap<PortHandle, Schema>) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Sink>, BoxedError> {
+        Ok(Box::new(FoldSink {
+            accumulator: self.accumulator.clone(),
+            fold: self.fold.clone(),
+        }))
+    }
+}
+
+/// The accumulator of a [`FoldSinkFactory`], shared with its sink.
+#[derive(Debug)]
+pub struct FoldAccumulator<A>(Arc<Mutex<Option<A>>>);
+
+impl<A> Clone for FoldAccumulator<A> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<A: Clone> FoldAccumulator<A> {
+    /// The operations folded so far. Panics if the fold function panicked.
+    pub fn get(&self) -> A {
+        self.0.lock().clone().expect("Fold function panicked")
+    }
+}
+
+struct FoldSink<A> {
+    accumulator: FoldAccumulator<A>,
+    fold: FoldFn<A>,
+}
+
+impl<A: Debug> Debug for FoldSink<A> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FoldSink")
+            .field("accumulator", &self.accumulator)
+            .finish()
+    }
+}
+
+impl<A: Debug + Send + 'static> Sink for FoldSink<A> {
+    fn commit(&mut self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<(), BoxedError> {
+        let op = op.load(record_store)?;
+        let mut accumulator = self.accumulator.0.lock();
+        let value = accumulator.take().expect("Fold function panicked");
+        *accumulator = Some((self.fold)(value, &op));
+        Ok(())
+    }
+
+    fn persist(&mut self, _queue: &Queue) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn on_source_snapshotting_done(&mut self, _connection_name: String) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::tests::run_dag;
+    use crate::tests::sources::{ThreeFieldSourceFactory, THREE_FIELD_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_with_fold_sink() {
+        let count: u64 = 1_000;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink = FoldSinkFactory::new(0u64, |sum, op| match op {
+            Operation::Insert { new } => sum + new.values[0].as_uint().unwrap(),
+            _ => sum,
+        });
+        let sum = sink.accumulator();
+        let dag = DagBuilder::new()
+            .source(source_handle.clone(), ThreeFieldSourceFactory::new(count))
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                THREE_FIELD_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        assert_eq!(sum.get(), count * (count + 1) / 2);
+    }
+}
@@ -1,29 +1,207 @@
 use crate::channels::ProcessorChannelForwarder;
+use crate::edge_transform::EdgeTransform;
 use crate::epoch::{Epoch, EpochManager};
 use crate::error_manager::ErrorManager;
 use crate::errors::ExecutionError;
 use crate::errors::ExecutionError::InvalidPortHandle;
-use crate::executor_operation::{ExecutorOperation, ProcessorOperation};
-use crate::node::PortHandle;
-use crate::record_store::RecordWriter;
+use crate::executor::{
+    memtable_budget, AdaptiveBatchController, ExecutorOptions, InflightLog, IngressTransform,
+    OperationSummary,
+};
+use crate::executor_operation::{
+    CompressedOperation, ExecutorOperation, OperationContext, OperationPriority,
+    OperationTimestamps, ProcessorOperation, SourceOffset,
+};
+use crate::node::{Compression, PortHandle, SourceMode};
+use crate::projection::FieldProjection;
+use crate::record_store::SharedRecordWriter;
+use crate::transport::{TransportReceiver, TransportSender};
 
-use crossbeam::channel::Sender;
+use crossbeam::channel::{RecvError, TryRecvError};
 use dozer_recordstore::ProcessorRecordStore;
 use dozer_types::log::debug;
 use dozer_types::models::ingestion_types::IngestionMessage;
 use dozer_types::node::{NodeHandle, TableState};
+use dozer_types::types::Schema;
 use std::collections::HashMap;
 use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of messages queued on the normal channel of an edge.
+///
+/// Counted apart from the channel, so the executor can watch queues without holding a receiver,
+/// which would keep the channel connected after the receiving node quit, and block its senders forever.
+#[derive(Debug, Clone, Default)]
+pub struct QueueLen(Arc<AtomicUsize>);
+
+impl QueueLen {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Counted before sending, so the count can't drop below zero if the receiver takes the message right away.
+    fn on_send(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_recv(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The receiving end of an edge's normal channel.
+#[derive(Debug)]
+pub struct EdgeReceiver {
+    pub receiver: Arc<dyn TransportReceiver>,
+    pub queue_len: QueueLen,
+}
+
+impl EdgeReceiver {
+    pub fn new(receiver: Arc<dyn TransportReceiver>, queue_len: QueueLen) -> Self {
+        Self {
+            receiver,
+            queue_len,
+        }
+    }
+
+    pub fn recv(&self) -> Result<ExecutorOperation, RecvError> {
+        let op = self.receiver.recv()?;
+        self.queue_len.on_recv();
+        Ok(op)
+    }
+
+    pub fn try_recv(&self) -> Result<ExecutorOperation, TryRecvError> {
+        let op = self.receiver.try_recv()?;
+        self.queue_len.on_recv();
+        Ok(op)
+    }
+}
+
+/// The sending end of an edge.
+#[derive(Debug, Clone)]
+pub struct EdgeSender {
+    pub sender: Arc<dyn TransportSender>,
+    /// Counts the messages queued in `sender`.
+    pub queue_len: QueueLen,
+    /// Carries `OperationPriority::High` operations, which the receiver takes before those queued in `sender`.
+    pub priority_sender: Arc<dyn TransportSender>,
+    /// Applied to records before they're sent, if any.
+    pub projection: Option<FieldProjection>,
+    /// Applied to operations before `projection`, if any.
+    pub transform: Option<EdgeTransform>,
+    /// Operations are compressed before they're sent, if set.
+    pub compression: Option<Compression>,
+    /// Records what's sent on `sender`, if `ExecutorOptions::track_inflight` is set.
+    pub inflight: Option<Arc<InflightLog>>,
+}
+
+impl EdgeSender {
+    fn send_op(
+        &self,
+        op: ProcessorOperation,
+        context: &OperationContext,
+        source_offset: &Option<SourceOffset>,
+        priority: OperationPriority,
+        record_store: &ProcessorRecordStore,
+    ) -> Result<(), ExecutionError> {
+        let op = match &self.transform {
+            Some(transform) => match transform.apply(op, record_store)? {
+                Some(op) => op,
+                None => return Ok(()),
+            },
+            None => op,
+        };
+        let op = match &self.projection {
+            Some(projection) => projection.project_operation(&op, record_store)?,
+            None => op,
+        };
+        let summary = match (&self.inflight, priority) {
+            (Some(inflight), OperationPriority::Normal) => {
+                Some(inflight.summarize_op(&op, record_store)?)
+            }
+            _ => None,
+        };
+        let op = match self.compression {
+            Some(compression) => ExecutorOperation::CompressedOp {
+                op: CompressedOperation::new(&op, compression, record_store)?,
+                context: context.clone(),
+                source_offset: source_offset.clone(),
+            },
+            None => ExecutorOperation::Op {
+                op,
+                context: context.clone(),
+                source_offset: source_offset.clone(),
+            },
+        };
+        match priority {
+            OperationPriority::Normal => self.send_queued(op)?,
+            OperationPriority::High => self.priority_sender.send(op)?,
+        }
+        self.record_inflight(summary);
+        Ok(())
+    }
+
+    /// Sends `op` through the normal channel, counting it in `queue_len`.
+    fn send_queued(&self, op: ExecutorOperation) -> Result<(), ExecutionError> {
+        self.queue_len.on_send();
+        if let Err(e) = self.sender.send(op) {
+            self.queue_len.on_recv();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Sends a message that isn't an operation, like a commit.
+    fn send_control(&self, op: ExecutorOperation) -> Result<(), ExecutionError> {
+        let summary = self
+            .inflight
+            .as_ref()
+            .map(|_| InflightLog::summarize_control(&op));
+        self.send_queued(op)?;
+        self.record_inflight(summary);
+        Ok(())
+    }
+
+    /// Recorded once sent, so the log never has more than the channel holds.
+    fn record_inflight(&self, summary: Option<OperationSummary>) {
+        if let (Some(inflight), Some(summary)) = (&self.inflight, summary) {
+            inflight.record(summary);
+        }
+    }
+}
+
+fn send_to_edges(
+    senders: &[EdgeSender],
+    op: ProcessorOperation,
+    context: &OperationContext,
+    source_offset: &Option<SourceOffset>,
+    priority: OperationPriority,
+    record_store: &ProcessorRecordStore,
+) -> Result<(), ExecutionError> {
+    if let Some((last_sender, senders)) = senders.split_last() {
+        for sender in senders {
+            sender.send_op(op.clone(), context, source_offset, priority, record_store)?;
+        }
+        last_sender.send_op(op, context, source_offset, priority, record_store)?;
+    }
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct ChannelManager {
     owner: NodeHandle,
-    record_writers: HashMap<PortHandle, Box<dyn RecordWriter>>,
-    senders: HashMap<PortHandle, Vec<Sender<ExecutorOperation>>>,
+    record_writers: HashMap<PortHandle, SharedRecordWriter>,
+    senders: HashMap<PortHandle, Vec<EdgeSender>>,
     record_store: Arc<ProcessorRecordStore>,
     error_manager: Arc<ErrorManager>,
+    /// Records sent from these ports are checked against their schema, see `ExecutorOptions::validate_schema`.
+    output_schemas: HashMap<PortHandle, Schema>,
+    /// Context of the operation being processed, which sent operations inherit.
+    context: OperationContext,
+    /// Source offset of the operation being processed, which sent operations inherit.
+    source_offset: Option<SourceOffset>,
 }
 
 impl ChannelManager {
@@ -32,9 +210,13 @@ impl ChannelManager {
         &mut self,
         mut op: ProcessorOperation,
         port_id: PortHandle,
+        priority: OperationPriority,
     ) -> Result<(), ExecutionError> {
-        if let Some(writer) = self.record_writers.get_mut(&port_id) {
-            match writer.write(&self.record_store, op) {
+        if self.rejects(&op, port_id) {
+            return Ok(());
+        }
+        if let Some(writer) = self.record_writers.get(&port_id) {
+            match writer.write().write(&self.record_store, op) {
                 Ok(new_op) => op = new_op,
                 Err(e) => {
                     self.error_manager.report(e.into());
@@ -47,23 +229,78 @@ impl ChannelManager {
             .senders
             .get(&port_id)
             .ok_or(InvalidPortHandle(port_id))?;
+        send_to_edges(
+            senders,
+            op,
+            &self.context,
+            &self.source_offset,
+            priority,
+            &self.record_store,
+        )
+    }
 
-        let exec_op = ExecutorOperation::Op { op };
-
-        if let Some((last_sender, senders)) = senders.split_last() {
-            for sender in senders {
-                sender.send(exec_op.clone())?;
+    /// Like `send_op` for several operations, looking up the port's record writer and edges once.
+    fn send_ops(
+        &mut self,
+        ops: Vec<ProcessorOperation>,
+        port_id: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        let senders = self
+            .senders
+            .get(&port_id)
+            .ok_or(InvalidPortHandle(port_id))?;
+        let writer = self.record_writers.get(&port_id);
+        for mut op in ops {
+            if self.rejects(&op, port_id) {
+                continue;
             }
-            last_sender.send(exec_op)?;
+            if let Some(writer) = writer {
+                match writer.write().write(&self.record_store, op) {
+                    Ok(new_op) => op = new_op,
+                    Err(e) => {
+                        self.error_manager.report(e.into());
+                        continue;
+                    }
+                }
+            }
+            send_to_edges(
+                senders,
+                op,
+                &self.context,
+                &self.source_offset,
+                OperationPriority::Normal,
+                &self.record_store,
+            )?;
         }
-
         Ok(())
     }
 
+    /// Reports `op` if it doesn't match the schema of `port`, in which case it must not be sent.
+    fn rejects(&self, op: &ProcessorOperation, port: PortHandle) -> bool {
+        let Some(schema) = self.output_schemas.get(&port) else {
+            return false;
+        };
+        self.error_manager.reject_schema_violation(
+            &self.owner,
+            port,
+            schema,
+            op,
+            &self.record_store,
+        )
+    }
+
+    pub fn set_context(&mut self, context: OperationContext) {
+        self.context = context;
+    }
+
+    pub fn set_source_offset(&mut self, source_offset: Option<SourceOffset>) {
+        self.source_offset = source_offset;
+    }
+
     pub fn send_terminate(&self) -> Result<(), ExecutionError> {
         for senders in self.senders.values() {
             for sender in senders {
-                sender.send(ExecutorOperation::Terminate)?;
+                sender.send_control(ExecutorOperation::Terminate)?;
             }
         }
 
@@ -73,7 +310,7 @@ impl ChannelManager {
     pub fn send_snapshotting_done(&self, connection_name: String) -> Result<(), ExecutionError> {
         for senders in self.senders.values() {
             for sender in senders {
-                sender.send(ExecutorOperation::SnapshottingDone {
+                sender.send_control(ExecutorOperation::SnapshottingDone {
                     connection_name: connection_name.clone(),
                 })?;
             }
@@ -82,6 +319,39 @@ impl ChannelManager {
         Ok(())
     }
 
+    /// Tells downstream nodes that all upstream sources of this node have finished producing data.
+    pub fn send_port_closed(&self) -> Result<(), ExecutionError> {
+        for senders in self.senders.values() {
+            for sender in senders {
+                sender.send_control(ExecutorOperation::PortClosed)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tells downstream nodes of all ports that operations with event times before `watermark` won't be sent anymore.
+    pub fn send_watermark(&self, watermark: SystemTime) -> Result<(), ExecutionError> {
+        for port in self.senders.keys() {
+            self.send_watermark_on(watermark, *port)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_watermark_on(
+        &self,
+        watermark: SystemTime,
+        port: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        let senders = self.senders.get(&port).ok_or(InvalidPortHandle(port))?;
+        for sender in senders {
+            sender.send_control(ExecutorOperation::Watermark { watermark })?;
+        }
+
+        Ok(())
+    }
+
     pub fn send_commit(&mut self, epoch: &Epoch) -> Result<(), ExecutionError> {
         debug!(
             "[{}] Checkpointing - {}: {:?}",
@@ -92,7 +362,7 @@ impl ChannelManager {
 
         for senders in &self.senders {
             for sender in senders.1 {
-                sender.send(ExecutorOperation::Commit {
+                sender.send_control(ExecutorOperation::Commit {
                     epoch: epoch.clone(),
                 })?;
             }
@@ -103,10 +373,11 @@ impl ChannelManager {
 
     pub fn new(
         owner: NodeHandle,
-        record_writers: HashMap<PortHandle, Box<dyn RecordWriter>>,
-        senders: HashMap<PortHandle, Vec<Sender<ExecutorOperation>>>,
+        record_writers: HashMap<PortHandle, SharedRecordWriter>,
+        senders: HashMap<PortHandle, Vec<EdgeSender>>,
         record_store: Arc<ProcessorRecordStore>,
         error_manager: Arc<ErrorManager>,
+        output_schemas: HashMap<PortHandle, Schema>,
     ) -> Self {
         Self {
             owner,
@@ -114,6 +385,9 @@ impl ChannelManager {
             senders,
             record_store,
             error_manager,
+            output_schemas,
+            context: Default::default(),
+            source_offset: None,
         }
     }
 }
@@ -121,13 +395,26 @@ impl ChannelManager {
 #[derive(Debug)]
 pub(crate) struct SourceChannelManager {
     port_names: HashMap<PortHandle, String>,
+    /// The table of every port, shared by the source offsets of the operations sent there.
+    port_tables: HashMap<PortHandle, Arc<(NodeHandle, String)>>,
     manager: ChannelManager,
     current_op_ids: HashMap<String, TableState>,
     commit_sz: u32,
     num_uncommitted_ops: u32,
     max_duration_between_commits: Duration,
+    /// The commit settings to switch to when a backfilling source is done snapshotting.
+    streaming_commit_settings: Option<(u32, Duration)>,
+    /// Sets `commit_sz` after every commit if adaptive batching is enabled.
+    batch_controller: Option<AdaptiveBatchController>,
+    /// The record store is flushed on commit if its memtables grow above this.
+    memtable_budget: Option<usize>,
     last_commit_instant: SystemTime,
+    /// The last processing time stamped, so it never decreases if the system clock goes back.
+    last_processing_time: SystemTime,
+    /// The last watermark sent on every port, so watermarks never go back.
+    last_watermarks: HashMap<PortHandle, SystemTime>,
     epoch_manager: Arc<EpochManager>,
+    ingress_transform: Option<IngressTransform>,
 }
 
 impl SourceChannelManager {
@@ -135,18 +422,42 @@ impl SourceChannelManager {
     pub fn new(
         owner: NodeHandle,
         port_names: HashMap<PortHandle, String>,
-        record_writers: HashMap<PortHandle, Box<dyn RecordWriter>>,
-        senders: HashMap<PortHandle, Vec<Sender<ExecutorOperation>>>,
-        commit_sz: u32,
-        max_duration_between_commits: Duration,
+        record_writers: HashMap<PortHandle, SharedRecordWriter>,
+        senders: HashMap<PortHandle, Vec<EdgeSender>>,
+        mode: SourceMode,
+        options: &ExecutorOptions,
         epoch_manager: Arc<EpochManager>,
         error_manager: Arc<ErrorManager>,
+        output_schemas: HashMap<PortHandle, Schema>,
     ) -> Self {
         // FIXME: Read current_op_id from persisted state.
         let current_op_ids = port_names
             .values()
             .map(|n| (n.clone(), TableState::NotStarted))
             .collect();
+        let port_tables = port_names
+            .iter()
+            .map(|(port, name)| (*port, Arc::new((owner.clone(), name.clone()))))
+            .collect();
+
+        let streaming_commit_settings = (options.commit_sz, options.commit_time_threshold);
+        let ((commit_sz, max_duration_between_commits), streaming_commit_settings) = match mode {
+            SourceMode::Backfill => (
+                (
+                    options.backfill_commit_sz,
+                    options.backfill_commit_time_threshold,
+                ),
+                Some(streaming_commit_settings),
+            ),
+            SourceMode::Streaming => (streaming_commit_settings, None),
+        };
+        let batch_controller = options
+            .adaptive_batching
+            .clone()
+            .map(AdaptiveBatchController::new);
+        let commit_sz = batch_controller
+            .as_ref()
+            .map_or(commit_sz, AdaptiveBatchController::batch_size);
 
         Self {
             manager: ChannelManager::new(
@@ -155,19 +466,28 @@ impl SourceChannelManager {
                 senders,
                 epoch_manager.record_store().clone(),
                 error_manager,
+                output_schemas,
             ),
             port_names,
+            port_tables,
             current_op_ids,
             commit_sz,
             num_uncommitted_ops: 0,
             max_duration_between_commits,
+            streaming_commit_settings,
+            batch_controller,
+            memtable_budget: options.memory_budget.map(memtable_budget),
             last_commit_instant: SystemTime::now(),
+            last_processing_time: UNIX_EPOCH,
+            last_watermarks: HashMap::new(),
             epoch_manager,
+            ingress_transform: options.ingress_transform.clone(),
         }
     }
 
     fn should_participate_in_commit(&self) -> bool {
         self.num_uncommitted_ops >= self.commit_sz
+            || self.epoch_manager.flush_barrier().is_pending()
             || self
                 .last_commit_instant
                 .elapsed()
@@ -189,6 +509,7 @@ impl SourceChannelManager {
                         let object = checkpoint_writer
                             .create_record_writer_object(&self.manager.owner, port_name)?;
                         record_writer
+                            .read()
                             .serialize(self.epoch_manager.record_store(), object)
                             .map_err(ExecutionError::SerializeRecordWriter)?;
                     }
@@ -199,6 +520,23 @@ impl SourceChannelManager {
                 .send_commit(&Epoch::from(common_info, epoch.decision_instant))?;
         }
 
+        if let Some(memtable_budget) = self.memtable_budget {
+            let record_store = self.epoch_manager.record_store();
+            if record_store.memtable_size()? > memtable_budget {
+                record_store.flush()?;
+            }
+        }
+
+        if let Some(batch_controller) = &mut self.batch_controller {
+            if !request_termination {
+                let latency = epoch
+                    .decision_instant
+                    .duration_since(self.last_commit_instant)
+                    .unwrap_or_default();
+                self.commit_sz = batch_controller.on_batch(latency);
+            }
+        }
+
         self.num_uncommitted_ops = 0;
         self.last_commit_instant = epoch.decision_instant;
         Ok(epoch.should_terminate)
@@ -219,10 +557,14 @@ impl SourceChannelManager {
         &mut self,
         message: IngestionMessage,
         port: PortHandle,
+        context: OperationContext,
         request_termination: bool,
     ) -> Result<bool, ExecutionError> {
         match message {
-            IngestionMessage::OperationEvent { op, id, .. } => {
+            IngestionMessage::OperationEvent { mut op, id, .. } => {
+                if let Some(transform) = &self.ingress_transform {
+                    transform(&mut op);
+                }
                 let port_name = self.port_names[&port].clone();
                 self.current_op_ids.insert(
                     port_name,
@@ -233,14 +575,35 @@ impl SourceChannelManager {
                     },
                 );
 
+                self.last_processing_time = SystemTime::now().max(self.last_processing_time);
+                self.manager.set_context(OperationContext {
+                    timestamps: OperationTimestamps {
+                        event_time: context.timestamps.event_time,
+                        processing_time: Some(self.last_processing_time),
+                    },
+                    headers: context.headers,
+                });
+                self.manager.set_source_offset(id.map(|id| SourceOffset {
+                    table: self.port_tables[&port].clone(),
+                    id,
+                }));
                 self.manager.send_op(
                     ProcessorOperation::new(&op, self.epoch_manager.record_store().deref())?,
                     port,
+                    OperationPriority::Normal,
                 )?;
                 self.num_uncommitted_ops += 1;
                 self.trigger_commit_if_needed(request_termination)
             }
             IngestionMessage::SnapshottingDone => {
+                if let Some((commit_sz, max_duration_between_commits)) =
+                    self.streaming_commit_settings.take()
+                {
+                    if self.batch_controller.is_none() {
+                        self.commit_sz = commit_sz;
+                    }
+                    self.max_duration_between_commits = max_duration_between_commits;
+                }
                 self.num_uncommitted_ops += 1;
                 self.manager
                     .send_snapshotting_done(self.manager.owner.id.clone())?;
@@ -253,14 +616,244 @@ impl SourceChannelManager {
         }
     }
 
+    /// Sends `watermark` on `port`, unless it's not later than the last watermark sent there.
+    pub fn send_watermark_and_trigger_commit_if_needed(
+        &mut self,
+        watermark: SystemTime,
+        port: PortHandle,
+        request_termination: bool,
+    ) -> Result<bool, ExecutionError> {
+        if self
+            .last_watermarks
+            .get(&port)
+            .map_or(false, |last| *last >= watermark)
+        {
+            debug!(
+                "[{}] Dropping watermark {watermark:?} on port {port}, not later than the last one",
+                self.manager.owner
+            );
+        } else {
+            self.manager.send_watermark_on(watermark, port)?;
+            self.last_watermarks.insert(port, watermark);
+        }
+        self.trigger_commit_if_needed(request_termination)
+    }
+
     pub fn terminate(&mut self) -> Result<(), ExecutionError> {
         self.manager.send_terminate()
     }
+
+    /// Tells downstream nodes that the source won't send more data.
+    pub fn close_ports(&mut self) -> Result<(), ExecutionError> {
+        self.manager.send_port_closed()
+    }
 }
 
 impl ProcessorChannelForwarder for ChannelManager {
     fn send(&mut self, op: ProcessorOperation, port: PortHandle) {
-        self.send_op(op, port)
+        self.send_with_priority(op, port, OperationPriority::Normal)
+    }
+
+    fn send_with_priority(
+        &mut self,
+        op: ProcessorOperation,
+        port: PortHandle,
+        priority: OperationPriority,
+    ) {
+        self.send_op(op, port, priority)
             .unwrap_or_else(|e| panic!("Failed to send operation: {e}"))
     }
+
+    fn send_all(&mut self, ops: Vec<ProcessorOperation>, port: PortHandle) {
+        self.send_ops(ops, port)
+            .unwrap_or_else(|e| panic!("Failed to send operations: {e}"))
+    }
+
+    fn send_with_context(
+        &mut self,
+        op: ProcessorOperation,
+        port: PortHandle,
+        context: OperationContext,
+    ) {
+        let inherited = std::mem::replace(&mut self.context, context);
+        self.send(op, port);
+        self.context = inherited;
+    }
+
+    fn context(&self) -> OperationContext {
+        self.context.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+    use dozer_types::errors::internal::BoxedError;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, Operation, Record};
+    use tempdir::TempDir;
+
+    use crate::dead_letter::{DeadLetterStore, ErrorPolicy};
+    use crate::executor::ExecutorOptions;
+    use crate::tests::processors::RecordingProcessorFactory;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::tests::{run_dag, run_materializing_with_options};
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    #[tokio::test]
+    async fn test_run_dag_send_all() {
+        let count: u64 = 1_000;
+        let latch = Arc::new(AtomicBool::new(true));
+        let sink = RecordingSinkFactory::new().stop_after(count * 3, latch.clone());
+        let received = sink.received();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .processor(
+                proc_handle.clone(),
+                RecordingProcessorFactory::new().with_fanout(3),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        assert_eq!(received.load(Ordering::SeqCst), count * 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_ingress_transform() {
+        let count: u64 = 1_000;
+        let redact = |record: &mut Record| record.values[1] = Field::String("redacted".to_string());
+        let options = ExecutorOptions {
+            ingress_transform: Some(Arc::new(move |op: &mut Operation| match op {
+                Operation::Insert { new } => redact(new),
+                Operation::Delete { old } => redact(old),
+                Operation::Update { old, new } => {
+                    redact(old);
+                    redact(new);
+                }
+            })),
+            ..Default::default()
+        };
+
+        let latch = Arc::new(AtomicBool::new(true));
+        let records = run_materializing_with_options(
+            GeneratorSourceFactory::new(count, latch.clone(), false),
+            count,
+            latch,
+            options,
+        )
+        .await;
+
+        assert_eq!(records.len() as u64, count);
+        for record in records {
+            assert_eq!(record.values[1], Field::String("redacted".to_string()));
+        }
+    }
+
+    /// Drops the value of `key_7` and replaces the one of `key_9` with a number.
+    fn malform(mut new: Record) -> Result<Record, BoxedError> {
+        match &new.values[0] {
+            Field::String(key) if key == "key_7" => {
+                new.values.pop();
+            }
+            Field::String(key) if key == "key_9" => new.values[1] = Field::UInt(9),
+            _ => {}
+        }
+        Ok(new)
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_diverts_records_violating_schema() {
+        let count: u64 = 20;
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        let latch = Arc::new(AtomicBool::new(true));
+        // The malformed records never reach the sink.
+        let sink = RecordingSinkFactory::new()
+            .record_ops()
+            .stop_after(count - 2, latch.clone());
+        let recording = sink.recording();
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .processor(
+                proc_handle.clone(),
+                RecordingProcessorFactory::new().map_inserts(malform),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        let dead_letter_dir =
+            TempDir::new("test_run_dag_diverts_records_violating_schema").unwrap();
+        let options = ExecutorOptions {
+            validate_schema: true,
+            error_policy: ErrorPolicy::DeadLetterStore {
+                path: dead_letter_dir.path().to_path_buf(),
+            },
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        let state = recording.lock().materialize();
+        assert_eq!(state.len() as u64, count - 2);
+        assert!(!state.contains_key(&Field::String("key_7".to_string())));
+        assert!(!state.contains_key(&Field::String("key_9".to_string())));
+
+        let store = DeadLetterStore::open(dead_letter_dir.path()).unwrap();
+        let mut letters = store
+            .iter()
+            .map(|letter| letter.unwrap().1)
+            .collect::<Vec<_>>();
+        letters.sort_by_key(|letter| letter.error.clone());
+        assert_eq!(letters.len(), 2);
+        assert!(letters.iter().all(|letter| letter.node == proc_handle));
+        assert_eq!(
+            letters[0].error,
+            format!(
+                "Record doesn't match the schema of output port {DEFAULT_PORT_HANDLE}: 1 fields instead of 2"
+            )
+        );
+        assert!(letters[1]
+            .error
+            .contains("field value is UInt instead of String"));
+    }
 }
@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer, StoreRecord};
+use dozer_storage::RocksdbMap;
+use dozer_types::bincode;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::thiserror::{self, Error};
+use dozer_types::types::{Field, Record, Schema};
+use tempdir::TempDir;
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::epoch::Epoch;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::{PortHandle, Processor, ProcessorFactory, StateBackend, StateEnvironment};
+use crate::DEFAULT_PORT_HANDLE;
+
+pub const JOIN_LEFT_INPUT_PORT: PortHandle = 1;
+pub const JOIN_RIGHT_INPUT_PORT: PortHandle = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinType {
+    /// Only matched pairs are emitted.
+    Inner,
+    /// Left records without a match are also emitted, with nulls for the right fields.
+    Left,
+    /// Records of either side without a match are also emitted, with nulls for the other side's fields.
+    Full,
+}
+
+#[derive(Debug, Error)]
+pub enum JoinError {
+    #[error("Deleted record not found on port {0}")]
+    RecordNotFound(PortHandle),
+}
+
+/// Joins records arriving on `JOIN_LEFT_INPUT_PORT` and `JOIN_RIGHT_INPUT_PORT` on key fields.
+///
+/// Joined records have the left fields followed by the right fields. Records with a null key field don't match anything.
+/// Both sides are kept in RocksDB in the directory provisioned by the executor.
+/// The state isn't serialized into checkpoints but snapshotted in `ExecutorOptions::state_dir`, so the processor starts empty unless that is kept across runs.
+#[derive(Debug)]
+pub struct JoinProcessorFactory {
+    join_type: JoinType,
+    left_key: Vec<usize>,
+    right_key: Vec<usize>,
+}
+
+impl JoinProcessorFactory {
+    /// `left_key` and `right_key` are the indexes of the key fields in either side's schema, in the same order.
+    pub fn new(join_type: JoinType, left_key: Vec<usize>, right_key: Vec<usize>) -> Self {
+        debug_assert_eq!(left_key.len(), right_key.len());
+        Self {
+            join_type,
+            left_key,
+            right_key,
+        }
+    }
+
+    fn create_processor(
+        &self,
+        input_schemas: &HashMap<PortHandle, Schema>,
+        state_dir: &Path,
+        temp_dir: Option<TempDir>,
+    ) -> Result<JoinProcessor, BoxedError> {
+        let side = |port, key: &Vec<usize>, name| {
+            Ok::<_, BoxedError>(JoinSide {
+                key: key.clone(),
+                num_fields: input_schemas[&port].fields.len(),
+                records: RocksdbMap::create(&state_dir.join(name), Default::default())?,
+            })
+        };
+        Ok(JoinProcessor {
+            join_type: self.join_type,
+            left: side(JOIN_LEFT_INPUT_PORT, &self.left_key, "left")?,
+            right: side(JOIN_RIGHT_INPUT_PORT, &self.right_key, "right")?,
+            _temp_dir: temp_dir,
+        })
+    }
+}
+
+impl ProcessorFactory for JoinProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        let mut schema = Schema::default();
+        for (port, nullable) in [
+            (JOIN_LEFT_INPUT_PORT, self.join_type == JoinType::Full),
+            (JOIN_RIGHT_INPUT_PORT, self.join_type != JoinType::Inner),
+        ] {
+            for field in &input_schemas[&port].fields {
+                let mut field = field.clone();
+                field.nullable |= nullable;
+                schema.field(field, false);
+            }
+        }
+        Ok(schema)
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![JOIN_LEFT_INPUT_PORT, JOIN_RIGHT_INPUT_PORT]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        let temp_dir = TempDir::new("join_processor")?;
+        let state_dir = temp_dir.path().to_path_buf();
+        Ok(Box::new(self.create_processor(
+            &input_schemas,
+            &state_dir,
+            Some(temp_dir),
+        )?))
+    }
+
+    fn type_name(&self) -> String {
+        "Join".to_owned()
+    }
+
+    fn id(&self) -> String {
+        "Join".to_owned()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::RocksDb
+    }
+
+    fn build_with_state(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        output_schemas: HashMap<PortHandle, Schema>,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+        state: StateEnvironment,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        match state {
+            StateEnvironment::RocksDb(state_dir) => Ok(Box::new(self.create_processor(
+                &input_schemas,
+                &state_dir,
+                None,
+            )?)),
+            _ => self.build(input_schemas, output_schemas, record_store, checkpoint_data),
+        }
+    }
+}
+
+/// The records of one side of the join.
+#[derive(Debug)]
+struct JoinSide {
+    key: Vec<usize>,
+    num_fields: usize,
+    /// Maps the encoded join key followed by the encoded record to the number of copies of that record.
+    records: RocksdbMap<Vec<u8>, u64>,
+}
+
+impl JoinSide {
+    /// Returns the encoded join key of `record`, or `None` if a key field is null.
+    fn join_key(&self, record: &Record) -> Result<Option<Vec<u8>>, BoxedError> {
+        let key = self
+            .key
+            .iter()
+            .map(|index| &record.values[*index])
+            .collect::<Vec<_>>();
+        if key.iter().any(|field| **field == Field::Null) {
+            return Ok(None);
+        }
+        Ok(Some(bincode::serialize(&key)?))
+    }
+
+    fn entry_key(join_key: &[u8], record: &Record) -> Result<Vec<u8>, BoxedError> {
+        let mut entry_key = join_key.to_vec();
+        bincode::serialize_into(&mut entry_key, &record.values)?;
+        Ok(entry_key)
+    }
+
+    /// Returns the records with `join_key`, and their number of copies.
+    fn matches(&self, join_key: &[u8]) -> Result<Vec<(Record, u64)>, BoxedError> {
+        let mut matches = vec![];
+        let entries: Box<dyn Iterator<Item = _>> = match prefix_successor(join_key) {
+            Some(end) => Box::new(self.records.range(join_key, &end)?),
+            None => Box::new(self.records.iter().filter(|entry| {
+                entry
+                    .as_ref()
+                    .map_or(true, |(entry_key, _)| entry_key.starts_with(join_key))
+            })),
+        };
+        for entry in entries {
+            let (entry_key, count) = entry?;
+            let values: Vec<Field> = bincode::deserialize(&entry_key[join_key.len()..])?;
+            matches.push((Record::new(values), count));
+        }
+        Ok(matches)
+    }
+
+    fn insert(&self, join_key: &[u8], record: &Record) -> Result<(), BoxedError> {
+        let entry_key = Self::entry_key(join_key, record)?;
+        let count = self.records.get(&entry_key)?.unwrap_or(0);
+        self.records.insert(&entry_key, &(count + 1))?;
+        Ok(())
+    }
+
+    /// Returns `false` if the record doesn't exist.
+    fn remove(&self, join_key: &[u8], record: &Record) -> Result<bool, BoxedError> {
+        let entry_key = Self::entry_key(join_key, record)?;
+        match self.records.get(&entry_key)? {
+            None => Ok(false),
+            Some(1) => {
+                self.records.remove(&entry_key)?;
+                Ok(true)
+            }
+            Some(count) => {
+                self.records.insert(&entry_key, &(count - 1))?;
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Returns the smallest key greater than all keys starting with `prefix`, `None` if there is none.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last < u8::MAX {
+            successor.push(last + 1);
+            return Some(successor);
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct JoinProcessor {
+    join_type: JoinType,
+    left: JoinSide,
+    right: JoinSide,
+    /// Holds the state if the executor didn't provision a directory.
+    _temp_dir: Option<TempDir>,
+}
+
+/// Whether an operation inserts or deletes a record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Insert,
+    Delete,
+}
+
+impl JoinProcessor {
+    /// Returns if unmatched records of the side at `port` are emitted.
+    fn is_preserved(&self, port: PortHandle) -> bool {
+        match self.join_type {
+            JoinType::Inner => false,
+            JoinType::Left => port == JOIN_LEFT_INPUT_PORT,
+            JoinType::Full => true,
+        }
+    }
+
+    fn sides(&self, port: PortHandle) -> (&JoinSide, &JoinSide) {
+        if port == JOIN_LEFT_INPUT_PORT {
+            (&self.left, &self.right)
+        } else {
+            (&self.right, &self.left)
+        }
+    }
+
+    /// Returns the joined record of `record` from `port` and `other`, or nulls if `other` is `None`.
+    fn join(&self, port: PortHandle, record: &Record, other: Option<&Record>) -> Record {
+        let (this, other_side) = self.sides(port);
+        let other_values = other.map_or_else(
+            || vec![Field::Null; other_side.num_fields],
+            |other| other.values.clone(),
+        );
+        debug_assert_eq!(record.values.len(), this.num_fields);
+        let values = if port == JOIN_LEFT_INPUT_PORT {
+            [record.values.clone(), other_values].concat()
+        } else {
+            [other_values, record.values.clone()].concat()
+        };
+        Record::new(values)
+    }
+
+    /// Applies `change` of `record` from `port`, returning the changes to the joined records.
+    fn apply(
+        &self,
+        port: PortHandle,
+        change: Change,
+        record: &Record,
+    ) -> Result<Vec<(Change, Record)>, BoxedError> {
+        let (this, other) = self.sides(port);
+        let Some(join_key) = this.join_key(record)? else {
+            return Ok(if self.is_preserved(port) {
+                vec![(change, self.join(port, record, None))]
+            } else {
+                vec![]
+            });
+        };
+
+        let had_matches = !this.matches(&join_key)?.is_empty();
+        match change {
+            Change::Insert => this.insert(&join_key, record)?,
+            Change::Delete => {
+                if !this.remove(&join_key, record)? {
+                    return Err(JoinError::RecordNotFound(port).into());
+                }
+            }
+        }
+        let has_matches = !this.matches(&join_key)?.is_empty();
+
+        let matches = other.matches(&join_key)?;
+        if matches.is_empty() {
+            return Ok(if self.is_preserved(port) {
+                vec![(change, self.join(port, record, None))]
+            } else {
+                vec![]
+            });
+        }
+
+        let mut changes = vec![];
+        // The other side's records go from unmatched to matched or back, so their null-padded records change.
+        let other_port = if port == JOIN_LEFT_INPUT_PORT {
+            JOIN_RIGHT_INPUT_PORT
+        } else {
+            JOIN_LEFT_INPUT_PORT
+        };
+        if self.is_preserved(other_port) && had_matches != has_matches {
+            let padded_change = if has_matches {
+                Change::Delete
+            } else {
+                Change::Insert
+            };
+            for (other_record, count) in &matches {
+                for _ in 0..*count {
+                    changes.push((padded_change, self.join(other_port, other_record, None)));
+                }
+            }
+        }
+        for (other_record, count) in &matches {
+            for _ in 0..*count {
+                changes.push((change, self.join(port, record, Some(other_record))));
+            }
+        }
+        Ok(changes)
+    }
+
+    fn forward(
+        &self,
+        record_store: &ProcessorRecordStore,
+        changes: Vec<(Change, Record)>,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        for (change, record) in changes {
+            let record = record_store.create_record(&record)?;
+            let op = match change {
+                Change::Insert => ProcessorOperation::Insert { new: record },
+                Change::Delete => ProcessorOperation::Delete { old: record },
+            };
+            fw.send(op, DEFAULT_PORT_HANDLE);
+        }
+        Ok(())
+    }
+}
+
+impl Processor for JoinProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BoxedError> {
+        self.left.records.flush()?;
+        self.right.records.flush()?;
+        Ok(())
+    }
+
+    fn snapshot_state(&self, dir: &Path) -> Result<(), BoxedError> {
+        std::fs::create_dir_all(dir)?;
+        self.left.records.checkpoint(&dir.join("left"))?;
+        self.right.records.checkpoint(&dir.join("right"))?;
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        // Updates are applied as a delete and an insert, so retractions go out before the new joined records.
+        let changes = match op {
+            ProcessorOperation::Insert { new } => {
+                vec![(Change::Insert, record_store.load_record(&new)?)]
+            }
+            ProcessorOperation::Delete { old } => {
+                vec![(Change::Delete, record_store.load_record(&old)?)]
+            }
+            ProcessorOperation::Update { old, new } => vec![
+                (Change::Delete, record_store.load_record(&old)?),
+                (Change::Insert, record_store.load_record(&new)?),
+            ],
+        };
+        for (change, record) in changes {
+            let joined = self.apply(from_port, change, &record)?;
+            self.forward(record_store, joined, fw)?;
+        }
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_types::types::{FieldDefinition, FieldType, SourceDefinition};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct TestForwarder {
+        ops: Vec<ProcessorOperation>,
+    }
+
+    impl ProcessorChannelForwarder for TestForwarder {
+        fn send(&mut self, op: ProcessorOperation, _port: PortHandle) {
+            self.ops.push(op);
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "id".to_string(),
+                    FieldType::UInt,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            )
+            .field(
+                FieldDefinition::new(
+                    "name".to_string(),
+                    FieldType::String,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            )
+            .clone()
+    }
+
+    fn record(id: u64, name: &str) -> Record {
+        Record::new(vec![Field::UInt(id), Field::String(name.to_string())])
+    }
+
+    struct Harness {
+        processor: Box<dyn Processor>,
+        record_store: ProcessorRecordStore,
+        fw: TestForwarder,
+    }
+
+    impl Harness {
+        fn new(join_type: JoinType) -> Self {
+            let factory = JoinProcessorFactory::new(join_type, vec![0], vec![0]);
+            let input_schemas = [
+                (JOIN_LEFT_INPUT_PORT, schema()),
+                (JOIN_RIGHT_INPUT_PORT, schema()),
+            ]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+            let record_store = ProcessorRecordStoreDeserializer::new(Default::default()).unwrap();
+            let processor = factory
+                .build(input_schemas, HashMap::new(), &record_store, None)
+                .unwrap();
+            Self {
+                processor,
+                record_store: record_store.into_record_store(),
+                fw: TestForwarder::default(),
+            }
+        }
+
+        fn insert(&mut self, port: PortHandle, record: Record) {
+            let new = self.record_store.create_record(&record).unwrap();
+            self.process(port, ProcessorOperation::Insert { new });
+        }
+
+        fn delete(&mut self, port: PortHandle, record: Record) {
+            let old = self.record_store.create_record(&record).unwrap();
+            self.process(port, ProcessorOperation::Delete { old });
+        }
+
+        fn process(&mut self, port: PortHandle, op: ProcessorOperation) {
+            self.processor
+                .process(port, &self.record_store, op, &mut self.fw)
+                .unwrap();
+        }
+
+        /// Returns the joined changes since last call, as `(is_insert, values)`.
+        fn take_changes(&mut self) -> Vec<(bool, Vec<Field>)> {
+            std::mem::take(&mut self.fw.ops)
+                .into_iter()
+                .map(|op| match op {
+                    ProcessorOperation::Insert { new } => {
+                        (true, self.record_store.load_record(&new).unwrap().values)
+                    }
+                    ProcessorOperation::Delete { old } => {
+                        (false, self.record_store.load_record(&old).unwrap().values)
+                    }
+                    ProcessorOperation::Update { .. } => panic!("Joins don't emit updates"),
+                })
+                .collect()
+        }
+    }
+
+    fn joined(left: &Record, right: &Record) -> Vec<Field> {
+        [left.values.clone(), right.values.clone()].concat()
+    }
+
+    #[test]
+    fn inner_join_emits_matches_and_retracts_on_delete() {
+        let mut harness = Harness::new(JoinType::Inner);
+
+        harness.insert(JOIN_LEFT_INPUT_PORT, record(1, "left 1"));
+        harness.insert(JOIN_LEFT_INPUT_PORT, record(2, "left 2"));
+        harness.insert(JOIN_RIGHT_INPUT_PORT, record(3, "right 3"));
+        assert!(harness.take_changes().is_empty());
+
+        harness.insert(JOIN_RIGHT_INPUT_PORT, record(1, "right 1"));
+        assert_eq!(
+            harness.take_changes(),
+            vec![(true, joined(&record(1, "left 1"), &record(1, "right 1")))]
+        );
+        harness.insert(JOIN_LEFT_INPUT_PORT, record(1, "left 1 again"));
+        assert_eq!(
+            harness.take_changes(),
+            vec![(
+                true,
+                joined(&record(1, "left 1 again"), &record(1, "right 1"))
+            )]
+        );
+
+        harness.delete(JOIN_RIGHT_INPUT_PORT, record(1, "right 1"));
+        let mut changes = harness.take_changes();
+        changes.sort();
+        let mut expected = vec![
+            (false, joined(&record(1, "left 1"), &record(1, "right 1"))),
+            (
+                false,
+                joined(&record(1, "left 1 again"), &record(1, "right 1")),
+            ),
+        ];
+        expected.sort();
+        assert_eq!(changes, expected);
+
+        harness.delete(JOIN_RIGHT_INPUT_PORT, record(3, "right 3"));
+        assert!(harness.take_changes().is_empty());
+    }
+
+    #[test]
+    fn left_join_pads_unmatched_records() {
+        let mut harness = Harness::new(JoinType::Left);
+        let nulls = vec![Field::Null, Field::Null];
+
+        harness.insert(JOIN_LEFT_INPUT_PORT, record(1, "left"));
+        assert_eq!(
+            harness.take_changes(),
+            vec![(true, [record(1, "left").values, nulls.clone()].concat())]
+        );
+
+        harness.insert(JOIN_RIGHT_INPUT_PORT, record(1, "right"));
+        assert_eq!(
+            harness.take_changes(),
+            vec![
+                (false, [record(1, "left").values, nulls.clone()].concat()),
+                (true, joined(&record(1, "left"), &record(1, "right"))),
+            ]
+        );
+
+        harness.delete(JOIN_RIGHT_INPUT_PORT, record(1, "right"));
+        assert_eq!(
+            harness.take_changes(),
+            vec![
+                (true, [record(1, "left").values, nulls].concat()),
+                (false, joined(&record(1, "left"), &record(1, "right"))),
+            ]
+        );
+
+        // Unmatched right records aren't emitted.
+        harness.insert(JOIN_RIGHT_INPUT_PORT, record(2, "right"));
+        assert!(harness.take_changes().is_empty());
+    }
+}
@@ -1,9 +1,18 @@
+pub mod aggregate;
 pub mod app;
 pub mod appsource;
 mod builder_dag;
 pub mod channels;
+pub mod circuit_breaker;
+pub mod coalesce;
+mod dag_builder;
 mod dag_impl;
+pub mod dead_letter;
+pub mod dedup;
+pub mod edge_transform;
+pub use dag_builder::DagBuilder;
 pub use dag_impl::*;
+pub use error_manager::{ErrorRollup, ErrorRollupCallback, ErrorSamplingOptions};
 pub mod checkpoint;
 pub mod dag_schemas;
 pub mod epoch;
@@ -11,10 +20,23 @@ mod error_manager;
 pub mod errors;
 pub mod executor;
 pub mod executor_operation;
+pub mod fold;
 pub mod forwarder;
 mod hash_map_to_vec;
+pub mod join;
+pub mod merge_sort;
 pub mod node;
+pub mod parallel_source;
+pub mod partition;
+mod partitioned_processor;
+mod partitioned_sink;
+pub mod projection;
 pub mod record_store;
+pub mod recording;
+pub mod rocksdb_map_source;
+pub mod transform_registry;
+mod transforming_sink;
+pub mod transport;
 
 #[cfg(test)]
 pub mod tests;
@@ -0,0 +1,551 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{
+    ProcessorRecordStore, ProcessorRecordStoreDeserializer, RecordStoreError, StoreRecord,
+};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::types::{Field, Schema};
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::checkpoint::serialize::{
+    deserialize_bincode, deserialize_record, deserialize_u64, serialize_bincode, serialize_record,
+    serialize_u64, Cursor, DeserializationError, SerializationError,
+};
+use crate::epoch::Epoch;
+use crate::executor_operation::{OperationContext, OperationTimestamps, ProcessorOperation};
+use crate::node::{PortHandle, Processor, ProcessorFactory, StateBackend};
+use crate::DEFAULT_PORT_HANDLE;
+
+/// Merges operations arriving on several input ports into one stream on `DEFAULT_PORT_HANDLE`, ordered by event time.
+///
+/// Operations on every input must have non-decreasing event times, like those of a single source.
+/// An operation is held back until no input can send one that goes before it: every other input has either
+/// sent an operation that goes after it, or closed. Operations with the same event time are ordered by the [`TieBreaker`].
+/// Operations without an event time are sent right away.
+/// All inputs must have the same schema. Held back operations are checkpointed.
+#[derive(Debug)]
+pub struct MergeSortProcessorFactory {
+    input_ports: Vec<PortHandle>,
+    tie_breaker: TieBreaker,
+}
+
+/// How [`MergeSortProcessorFactory`] orders operations with the same event time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreaker {
+    /// The input listed first goes first.
+    #[default]
+    InputOrder,
+    /// The lower value of the record field at this index goes first, like a sequence number. Then the input listed first.
+    ///
+    /// Every input must send operations with the same event time in the order of this field.
+    /// Updates are ordered by their new record.
+    Field(usize),
+}
+
+impl MergeSortProcessorFactory {
+    pub fn new(input_ports: Vec<PortHandle>) -> Self {
+        debug_assert!(!input_ports.is_empty());
+        Self {
+            input_ports,
+            tie_breaker: TieBreaker::default(),
+        }
+    }
+
+    pub fn with_tie_breaker(mut self, tie_breaker: TieBreaker) -> Self {
+        self.tie_breaker = tie_breaker;
+        self
+    }
+}
+
+impl TieBreaker {
+    fn key(
+        &self,
+        op: &ProcessorOperation,
+        record_store: &impl StoreRecord,
+    ) -> Result<Option<Field>, RecordStoreError> {
+        let TieBreaker::Field(index) = self else {
+            return Ok(None);
+        };
+        let record = match op {
+            ProcessorOperation::Insert { new } | ProcessorOperation::Update { new, .. } => new,
+            ProcessorOperation::Delete { old } => old,
+        };
+        let mut record = record_store.load_record(record)?;
+        Ok(Some(record.values.swap_remove(*index)))
+    }
+}
+
+impl ProcessorFactory for MergeSortProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        Ok(input_schemas[&self.input_ports[0]].clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.input_ports.clone()
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        let mut inputs = self
+            .input_ports
+            .iter()
+            .map(|port| MergeInput::new(*port))
+            .collect::<Vec<_>>();
+        if let Some(checkpoint_data) = checkpoint_data {
+            let mut cursor = Cursor::new(&checkpoint_data);
+            for input in &mut inputs {
+                input.deserialize(&mut cursor, record_store, self.tie_breaker)?;
+            }
+        }
+        Ok(Box::new(MergeSortProcessor {
+            inputs,
+            tie_breaker: self.tie_breaker,
+        }))
+    }
+
+    fn type_name(&self) -> String {
+        "MergeSort".to_owned()
+    }
+
+    fn id(&self) -> String {
+        "MergeSort".to_owned()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::Memory
+    }
+}
+
+#[derive(Debug)]
+struct Buffered {
+    op: ProcessorOperation,
+    context: OperationContext,
+    tie_key: Option<Field>,
+}
+
+#[derive(Debug)]
+struct MergeInput {
+    port: PortHandle,
+    buffer: VecDeque<Buffered>,
+    /// The event time and tie key of the latest operation received on this input. No operation that goes before it will arrive.
+    watermark: Option<(SystemTime, Option<Field>)>,
+    closed: bool,
+}
+
+impl MergeInput {
+    fn new(port: PortHandle) -> Self {
+        Self {
+            port,
+            buffer: VecDeque::new(),
+            watermark: None,
+            closed: false,
+        }
+    }
+
+    /// If no operation that goes before the one from input `index` with `event_time` and `tie_key` will be sent from this input, which is input `self_index`.
+    fn is_past(
+        &self,
+        self_index: usize,
+        event_time: SystemTime,
+        tie_key: Option<&Field>,
+        index: usize,
+    ) -> bool {
+        self.closed
+            || !self.buffer.is_empty()
+            || self.watermark.as_ref().map_or(false, |(time, key)| {
+                (*time, key.as_ref(), self_index) > (event_time, tie_key, index)
+            })
+    }
+
+    fn serialize(
+        &self,
+        record_store: &ProcessorRecordStore,
+        object: &mut Object,
+    ) -> Result<(), SerializationError> {
+        serialize_u64(self.buffer.len() as u64, object)?;
+        for Buffered { op, context, .. } in &self.buffer {
+            let records = match op {
+                ProcessorOperation::Insert { new } => vec![new],
+                ProcessorOperation::Delete { old } => vec![old],
+                ProcessorOperation::Update { old, new } => vec![old, new],
+            };
+            let kind = match op {
+                ProcessorOperation::Insert { .. } => 0,
+                ProcessorOperation::Delete { .. } => 1,
+                ProcessorOperation::Update { .. } => 2,
+            };
+            serialize_u64(kind, object)?;
+            for record in records {
+                serialize_record(record, record_store, object)?;
+            }
+            // Headers aren't checkpointed, so restored operations have none.
+            let timestamps = context.timestamps;
+            serialize_bincode((timestamps.event_time, timestamps.processing_time), object)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize(
+        &mut self,
+        cursor: &mut Cursor,
+        record_store: &ProcessorRecordStoreDeserializer,
+        tie_breaker: TieBreaker,
+    ) -> Result<(), DeserializationError> {
+        let len = deserialize_u64(cursor)?;
+        for _ in 0..len {
+            let op = match deserialize_u64(cursor)? {
+                0 => ProcessorOperation::Insert {
+                    new: deserialize_record(cursor, record_store)?,
+                },
+                1 => ProcessorOperation::Delete {
+                    old: deserialize_record(cursor, record_store)?,
+                },
+                _ => ProcessorOperation::Update {
+                    old: deserialize_record(cursor, record_store)?,
+                    new: deserialize_record(cursor, record_store)?,
+                },
+            };
+            let (event_time, processing_time) = deserialize_bincode(cursor)?;
+            let tie_key = tie_breaker.key(&op, record_store)?;
+            if let Some(event_time) = event_time {
+                self.watermark = self
+                    .watermark
+                    .take()
+                    .max(Some((event_time, tie_key.clone())));
+            }
+            self.buffer.push_back(Buffered {
+                op,
+                context: OperationContext {
+                    timestamps: OperationTimestamps {
+                        event_time,
+                        processing_time,
+                    },
+                    headers: Default::default(),
+                },
+                tie_key,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct MergeSortProcessor {
+    inputs: Vec<MergeInput>,
+    tie_breaker: TieBreaker,
+}
+
+impl MergeSortProcessor {
+    /// Sends buffered operations in event time order, as long as no input can still send one that goes before.
+    fn send_ready(&mut self, fw: &mut dyn ProcessorChannelForwarder) {
+        loop {
+            // `min_by_key` returns the first minimum, so ties between equal keys go to the input listed first.
+            let Some((index, event_time, tie_key)) = self
+                .inputs
+                .iter()
+                .enumerate()
+                .filter_map(|(index, input)| {
+                    let head = input.buffer.front()?;
+                    Some((
+                        index,
+                        head.context.timestamps.event_time?,
+                        head.tie_key.as_ref(),
+                    ))
+                })
+                .min_by_key(|(_, event_time, tie_key)| (*event_time, *tie_key))
+            else {
+                return;
+            };
+            if !self
+                .inputs
+                .iter()
+                .enumerate()
+                .all(|(i, input)| input.is_past(i, event_time, tie_key, index))
+            {
+                return;
+            }
+            let head = self.inputs[index]
+                .buffer
+                .pop_front()
+                .expect("input has a head");
+            fw.send_with_context(head.op, DEFAULT_PORT_HANDLE, head.context);
+        }
+    }
+}
+
+impl Processor for MergeSortProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        let context = fw.context();
+        let Some(event_time) = context.timestamps.event_time else {
+            fw.send(op, DEFAULT_PORT_HANDLE);
+            return Ok(());
+        };
+        let tie_key = self.tie_breaker.key(&op, record_store)?;
+        let input = self
+            .inputs
+            .iter_mut()
+            .find(|input| input.port == from_port)
+            .expect("operation from an input port");
+        input.watermark = input
+            .watermark
+            .take()
+            .max(Some((event_time, tie_key.clone())));
+        input.buffer.push_back(Buffered {
+            op,
+            context,
+            tie_key,
+        });
+        self.send_ready(fw);
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        record_store: &ProcessorRecordStore,
+        mut object: Object,
+    ) -> Result<(), BoxedError> {
+        for input in &self.inputs {
+            input.serialize(record_store, &mut object)?;
+        }
+        Ok(())
+    }
+
+    fn on_port_closed(
+        &mut self,
+        port: PortHandle,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        if let Some(input) = self.inputs.iter_mut().find(|input| input.port == port) {
+            input.closed = true;
+        }
+        self.send_ready(fw);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, Record};
+
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct TestForwarder {
+        context: OperationContext,
+        sent: Vec<OperationContext>,
+        ops: Vec<ProcessorOperation>,
+    }
+
+    impl ProcessorChannelForwarder for TestForwarder {
+        fn send(&mut self, op: ProcessorOperation, _port: PortHandle) {
+            self.sent.push(self.context.clone());
+            self.ops.push(op);
+        }
+
+        fn send_with_context(
+            &mut self,
+            op: ProcessorOperation,
+            _port: PortHandle,
+            context: OperationContext,
+        ) {
+            self.sent.push(context);
+            self.ops.push(op);
+        }
+
+        fn context(&self) -> OperationContext {
+            self.context.clone()
+        }
+    }
+
+    fn event_time(secs: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn merge_sort_waits_for_every_input() {
+        let factory = MergeSortProcessorFactory::new(vec![1, 2]);
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let mut processor = factory
+            .build(
+                HashMap::new(),
+                HashMap::new(),
+                &ProcessorRecordStoreDeserializer::new(Default::default()).unwrap(),
+                None,
+            )
+            .unwrap();
+        let new = record_store
+            .create_record(&Record::new(vec![Field::UInt(0)]))
+            .unwrap();
+        let mut fw = TestForwarder::default();
+        let mut process = |port, secs, fw: &mut TestForwarder| {
+            fw.context.timestamps.event_time = event_time(secs);
+            let op = ProcessorOperation::Insert { new: new.clone() };
+            processor.process(port, &record_store, op, fw).unwrap();
+        };
+
+        process(1, 1, &mut fw);
+        process(1, 4, &mut fw);
+        // Port 2 hasn't sent anything yet.
+        assert!(fw.sent.is_empty());
+        process(2, 2, &mut fw);
+        process(2, 3, &mut fw);
+        process(2, 5, &mut fw);
+        let sent = |fw: &TestForwarder| {
+            fw.sent
+                .iter()
+                .map(|context| context.timestamps.event_time)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sent(&fw),
+            vec![event_time(1), event_time(2), event_time(3), event_time(4)]
+        );
+
+        processor.on_port_closed(1, &mut fw).unwrap();
+        assert_eq!(sent(&fw).last(), Some(&event_time(5)));
+    }
+
+    /// Sends two operations with the same event time, one from each input in `arrival` order,
+    /// and returns the sequence numbers in the order they're merged.
+    fn merge_equal_event_times(arrival: [(PortHandle, u64); 2]) -> Vec<u64> {
+        let factory =
+            MergeSortProcessorFactory::new(vec![1, 2]).with_tie_breaker(TieBreaker::Field(0));
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let mut processor = factory
+            .build(
+                HashMap::new(),
+                HashMap::new(),
+                &ProcessorRecordStoreDeserializer::new(Default::default()).unwrap(),
+                None,
+            )
+            .unwrap();
+        let mut fw = TestForwarder::default();
+        fw.context.timestamps.event_time = event_time(1);
+        for (port, sequence) in arrival {
+            let new = record_store
+                .create_record(&Record::new(vec![Field::UInt(sequence)]))
+                .unwrap();
+            let op = ProcessorOperation::Insert { new };
+            processor.process(port, &record_store, op, &mut fw).unwrap();
+        }
+        processor.on_port_closed(1, &mut fw).unwrap();
+        processor.on_port_closed(2, &mut fw).unwrap();
+
+        fw.ops
+            .iter()
+            .map(|op| {
+                let ProcessorOperation::Insert { new } = op else {
+                    panic!("Only inserts are sent");
+                };
+                let Field::UInt(sequence) = record_store.load_record(new).unwrap().values[0] else {
+                    panic!("Unexpected field");
+                };
+                sequence
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_sort_breaks_ties_by_field() {
+        // The lower sequence number goes first, whichever input it's from and whenever it arrives.
+        assert_eq!(merge_equal_event_times([(1, 2), (2, 1)]), vec![1, 2]);
+        assert_eq!(merge_equal_event_times([(2, 1), (1, 2)]), vec![1, 2]);
+        assert_eq!(merge_equal_event_times([(2, 2), (1, 1)]), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_merge_sorts_by_event_time() {
+        let count: u64 = 1_000;
+        let sink = RecordingSinkFactory::new().record_ops();
+        let recording = sink.recording();
+
+        let even_source_handle = NodeHandle::new(None, 1.to_string());
+        let odd_source_handle = NodeHandle::new(None, 2.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 3.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 4.to_string());
+
+        // Both sources quit once they've sent their operations, one at every second and one half a second later.
+        let dag = DagBuilder::new()
+            .source(
+                even_source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false)
+                    .with_event_times(),
+            )
+            .source(
+                odd_source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false)
+                    .with_event_time_offset(Duration::from_millis(500)),
+            )
+            .processor(
+                proc_handle.clone(),
+                MergeSortProcessorFactory::new(vec![1, 2]),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &even_source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                1,
+            )
+            .edge(
+                &odd_source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                2,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let event_times = recording
+            .lock()
+            .ops
+            .iter()
+            .map(|recorded| recorded.context.timestamps.event_time.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(event_times.len() as u64, 2 * count);
+        assert!(event_times.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
@@ -1,15 +1,22 @@
 use crate::channels::{ProcessorChannelForwarder, SourceChannelForwarder};
+use crate::circuit_breaker::CircuitBreakerOptions;
 use crate::epoch::Epoch;
-use crate::executor_operation::ProcessorOperation;
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::partition::{stable_hash, PartitionHasher};
+use crate::record_store::InputRecordReader;
+use crate::transport::EdgeTransport;
 use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
 
 use dozer_log::storage::{Object, Queue};
+use dozer_storage::RwLmdbEnvironment;
 use dozer_types::errors::internal::BoxedError;
-use dozer_types::node::OpIdentifier;
+use dozer_types::node::{OpIdentifier, SourceStates};
 use dozer_types::serde::{Deserialize, Serialize};
 use dozer_types::types::Schema;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 pub type PortHandle = u16;
 
@@ -18,6 +25,18 @@ pub type PortHandle = u16;
 pub enum OutputPortType {
     Stateless,
     StatefulWithPrimaryKeyLookup,
+    /// Like `StatefulWithPrimaryKeyLookup`, also keeping the last `max_versions` versions of every record,
+    /// see [`InputRecordReader::get_version`].
+    StatefulWithVersionedLookup {
+        max_versions: usize,
+    },
+}
+
+impl OutputPortType {
+    /// If records sent from the port are kept for downstream processors to look up by primary key.
+    pub fn is_stateful(&self) -> bool {
+        !matches!(self, OutputPortType::Stateless)
+    }
 }
 
 impl Display for OutputPortType {
@@ -27,23 +46,58 @@ impl Display for OutputPortType {
             OutputPortType::StatefulWithPrimaryKeyLookup { .. } => {
                 f.write_str("StatefulWithPrimaryKeyLookup")
             }
+            OutputPortType::StatefulWithVersionedLookup { .. } => {
+                f.write_str("StatefulWithVersionedLookup")
+            }
         }
     }
 }
 
+/// How operations are compressed while they're queued on a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+pub enum Compression {
+    Lz4,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputPortDefOptions {
+    /// Compresses operations sent through every edge from this port, and decompresses them before they're processed.
+    ///
+    /// Costs CPU to save channel memory, so it's meant for ports with large records.
+    pub compression: Option<Compression>,
+    /// Creates the channels of every edge from this port, see [`ChannelTransport`](crate::transport::ChannelTransport).
+    pub transport: EdgeTransport,
+}
+
 #[derive(Debug, Clone)]
 pub struct OutputPortDef {
     pub handle: PortHandle,
     pub typ: OutputPortType,
+    pub options: OutputPortDefOptions,
 }
 
 impl OutputPortDef {
     pub fn new(handle: PortHandle, typ: OutputPortType) -> Self {
-        Self { handle, typ }
+        Self {
+            handle,
+            typ,
+            options: Default::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: OutputPortDefOptions) -> Self {
+        self.options = options;
+        self
     }
 }
 
 pub trait SourceFactory: Send + Sync + Debug {
+    /// The schema of output `port`.
+    ///
+    /// Schemas are resolved from this, like `ProcessorFactory::get_output_schema`, before and without calling `build`,
+    /// so describing a DAG never starts a source. It mustn't depend on the source running.
     fn get_output_schema(&self, port: &PortHandle) -> Result<Schema, BoxedError>;
     fn get_output_port_name(&self, port: &PortHandle) -> String;
     fn get_output_ports(&self) -> Vec<OutputPortDef>;
@@ -55,12 +109,91 @@ pub trait SourceFactory: Send + Sync + Debug {
 
 pub type SourceState = HashMap<PortHandle, Option<OpIdentifier>>;
 
+/// How a source is ingesting, which decides the commit settings the executor applies to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SourceMode {
+    /// Loading a bounded historical snapshot, committed with the backfill settings in `ExecutorOptions`.
+    ///
+    /// Only the commit cadence changes, the executor still runs the source on one thread.
+    /// A source reads its snapshot in parallel itself, like with [`ParallelFetchSource`](crate::parallel_source::ParallelFetchSource).
+    Backfill,
+    /// Ingesting live changes.
+    #[default]
+    Streaming,
+}
+
+/// How much of a bounded dataset a source has sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub processed: u64,
+    /// The size of the dataset, if the source knows it.
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// Returns `processed / total`, if `total` is known.
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| {
+            if total == 0 {
+                1.0
+            } else {
+                self.processed as f64 / total as f64
+            }
+        })
+    }
+
+    /// Estimates the time left at the rate achieved in `elapsed`, if `total` is known.
+    pub fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        let total = self.total?;
+        if self.processed == 0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(self.processed);
+        Some(elapsed.mul_f64(remaining as f64 / self.processed as f64))
+    }
+}
+
 pub trait Source: Send + Sync + Debug {
     fn start(
         &self,
         fw: &mut dyn SourceChannelForwarder,
         last_checkpoint: SourceState,
     ) -> Result<(), BoxedError>;
+
+    /// The mode this source starts in.
+    ///
+    /// A source starting in `Backfill` switches to `Streaming` by sending `IngestionMessage::SnapshottingDone`.
+    fn mode(&self) -> SourceMode {
+        SourceMode::Streaming
+    }
+
+    /// Reports how much of its dataset the source has sent. Called from other threads while `start` runs.
+    fn progress(&self) -> Option<Progress> {
+        None
+    }
+}
+
+/// Where a processor keeps its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StateBackend {
+    #[default]
+    RocksDb,
+    Lmdb,
+    /// No storage is provisioned.
+    Memory,
+}
+
+/// The storage the executor provisions for a processor's [`StateBackend`].
+///
+/// Storage on disk lives under `ExecutorOptions::state_dir`, so it's kept across runs if that is set.
+/// The executor restores it from the snapshot the processor took with [`Processor::snapshot_state`] at the checkpoint it resumes from,
+/// and clears it if there's no checkpoint to resume from. It fails to start if the storage isn't empty but there's no such snapshot.
+#[derive(Debug)]
+pub enum StateEnvironment {
+    /// A directory for the processor to open its RocksDB databases in.
+    RocksDb(PathBuf),
+    Lmdb(RwLmdbEnvironment),
+    Memory,
 }
 
 pub trait ProcessorFactory: Send + Sync + Debug {
@@ -80,9 +213,76 @@ pub trait ProcessorFactory: Send + Sync + Debug {
     ) -> Result<Box<dyn Processor>, BoxedError>;
     fn type_name(&self) -> String;
     fn id(&self) -> String;
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::RocksDb
+    }
+
+    /// Like `build`, also passing the storage provisioned for `state_backend`.
+    ///
+    /// The executor calls this instead of `build`. Override it if the processor uses the storage.
+    fn build_with_state(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        output_schemas: HashMap<PortHandle, Schema>,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+        _state: StateEnvironment,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        self.build(input_schemas, output_schemas, record_store, checkpoint_data)
+    }
+
+    /// If the processor needs the operations of all its input ports in one order, on top of each port's.
+    ///
+    /// The operations of every input port are always processed in the order the upstream node sent them,
+    /// but the inputs interleave as operations happen to arrive. If this returns `true`, the executor holds back every epoch's
+    /// operations until it commits, then processes them in processing time order across all ports, the port listed first on ties.
+    /// That delays processing by an epoch and keeps its operations in memory.
+    fn requires_total_order(&self) -> bool {
+        false
+    }
+
+    /// Return `Some` to split the processor's input between several processors built by this factory,
+    /// each owning the records whose key hashes to it.
+    ///
+    /// Operations on the same key keep their order. Read by `Dag::add_processor`.
+    fn partition_by(&self) -> Option<ProcessorPartitioning> {
+        None
+    }
+
+    /// The hasher used to route records to the partitions of `partition_by`.
+    ///
+    /// Override this to match an upstream system's partitioning.
+    fn partition_hasher(&self) -> PartitionHasher {
+        Box::new(stable_hash)
+    }
+}
+
+/// How a processor's input is split between processor instances, returned from [`ProcessorFactory::partition_by`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessorPartitioning {
+    /// How many processors to build. They run on the node's thread, one operation at a time.
+    pub num_partitions: usize,
+    /// Indexes of the fields records are hashed on to pick their partition.
+    pub key: Vec<usize>,
 }
 
 pub trait Processor: Send + Sync + Debug {
+    /// Called on the processor's thread before it receives any operation, and again after it's rebuilt by supervision.
+    ///
+    /// With `ExecutorOptions::strict_startup_ordering`, sources don't start until this has returned for every processor and sink.
+    fn init(&mut self) -> Result<(), BoxedError> {
+        Ok(())
+    }
+    /// Called before `commit`, so the processor can send operations that belong to the epoch through `fw`.
+    fn before_commit(
+        &mut self,
+        _epoch_details: &Epoch,
+        _record_store: &ProcessorRecordStore,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
     fn commit(&self, epoch_details: &Epoch) -> Result<(), BoxedError>;
     fn process(
         &mut self,
@@ -96,6 +296,67 @@ pub trait Processor: Send + Sync + Debug {
         record_store: &ProcessorRecordStore,
         object: Object,
     ) -> Result<(), BoxedError>;
+
+    /// Called once before processing starts, with a reader for every input port that's connected to a stateful output port.
+    ///
+    /// Processors that look up records by primary key, like joins, can keep the readers for any of their input ports.
+    fn set_record_readers(&mut self, _record_readers: HashMap<PortHandle, InputRecordReader>) {}
+    /// Called when the upstream sources of input port `port` have finished, so no more data will arrive on it.
+    ///
+    /// Processors that buffer data per port, like joins, can flush through `fw` here.
+    fn on_port_closed(
+        &mut self,
+        _port: PortHandle,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+    /// Called when the earliest watermark of the processor's open input ports advances,
+    /// so operations with event times before `watermark` won't arrive anymore.
+    ///
+    /// Windowing processors can close their windows and send the results through `fw` here.
+    /// The watermark is passed on to downstream nodes afterwards.
+    fn on_watermark(
+        &mut self,
+        _watermark: SystemTime,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+    /// Flushes the processor's state storage to disk, so it's durable without replaying RocksDB's write-ahead log.
+    ///
+    /// Called when the pipeline stops gracefully if `ExecutorOptions::flush_on_stop` is set.
+    fn flush(&mut self) -> Result<(), BoxedError> {
+        Ok(())
+    }
+    /// Writes a consistent copy of the state storage provisioned for the processor to `dir`, which doesn't exist yet.
+    ///
+    /// Called after `commit` of every epoch that writes a checkpoint. The copy must be laid out like the storage,
+    /// because it replaces the storage when the executor resumes from the checkpoint. Processors that keep state
+    /// in the storage must implement it, see [`StateEnvironment`].
+    fn snapshot_state(&self, _dir: &Path) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+/// How the executor treats a sink, set with `Dag::add_sink_with_options`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SinkOptions {
+    /// Pause delivery to the sink after repeated failures. Disabled by default.
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+    /// Drop inserts of primary keys the sink already applied, see [`DedupSinkFactory`](crate::dedup::DedupSinkFactory). Disabled by default.
+    pub dedup_by_primary_key: bool,
+    /// Number of most recently applied keys remembered by `dedup_by_primary_key`, all of them if `None`.
+    pub dedup_capacity: Option<usize>,
+}
+
+/// How a sink's input is split between sink instances, returned from [`SinkFactory::partition_by`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkPartitioning {
+    /// How many sinks to build. Each runs on its own thread.
+    pub num_partitions: usize,
+    /// Indexes of the fields records are hashed on to pick their partition.
+    pub key: Vec<usize>,
 }
 
 pub trait SinkFactory: Send + Sync + Debug {
@@ -105,17 +366,146 @@ pub trait SinkFactory: Send + Sync + Debug {
         &self,
         input_schemas: HashMap<PortHandle, Schema>,
     ) -> Result<Box<dyn Sink>, BoxedError>;
+
+    /// Return `Some` to write in parallel to several sinks built by this factory, each owning the records whose key hashes to it.
+    ///
+    /// Operations on the same key keep their order. Read by `Dag::add_sink`.
+    fn partition_by(&self) -> Option<SinkPartitioning> {
+        None
+    }
+
+    /// The hasher used to route records to the partitions of `partition_by`.
+    ///
+    /// Override this to match an upstream system's partitioning.
+    fn partition_hasher(&self) -> PartitionHasher {
+        Box::new(stable_hash)
+    }
+}
+
+/// What a sink did with an epoch, returned from [`Sink::commit_with_decision`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitDecision {
+    /// The epoch is committed.
+    Committed,
+    /// The sink will commit the epoch together with a later one. The executor carries on as if it was committed.
+    Defer,
+    /// The sink is too busy to commit the epoch now.
+    ///
+    /// The executor stops delivering operations to the sink, and asks it to commit the same epoch again after a pause,
+    /// until it returns something else. Meanwhile the sink's input channels fill up, which blocks upstream nodes and sources.
+    Backpressure,
 }
 
 pub trait Sink: Send + Sync + Debug {
+    /// Called on the sink's thread before it receives any operation, to connect to external systems and the like.
+    ///
+    /// With `ExecutorOptions::strict_startup_ordering`, sources don't start until this has returned for every processor and sink.
+    fn init(&mut self) -> Result<(), BoxedError> {
+        Ok(())
+    }
     fn commit(&mut self, epoch_details: &Epoch) -> Result<(), BoxedError>;
+
+    /// Like `commit`, letting the sink defer the commit or push back on its inputs.
+    ///
+    /// The executor calls this instead of `commit`. Override it if the sink batches commits, or can tell when it's overloaded.
+    fn commit_with_decision(
+        &mut self,
+        epoch_details: &Epoch,
+    ) -> Result<CommitDecision, BoxedError> {
+        self.commit(epoch_details)
+            .map(|()| CommitDecision::Committed)
+    }
+
     fn process(
         &mut self,
         from_port: PortHandle,
         record_store: &ProcessorRecordStore,
         op: ProcessorOperation,
     ) -> Result<(), BoxedError>;
+
+    /// Like `process`, also passing `op`'s context: when it happened and was ingested, and the headers its source attached.
+    ///
+    /// The executor calls this instead of `process`. Override it if the sink uses the context.
+    /// Sinks wrapping other sinks must pass the context on.
+    fn process_with_context(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        _context: &OperationContext,
+    ) -> Result<(), BoxedError> {
+        self.process(from_port, record_store, op)
+    }
+
     fn persist(&mut self, queue: &Queue) -> Result<(), BoxedError>;
 
     fn on_source_snapshotting_done(&mut self, connection_name: String) -> Result<(), BoxedError>;
+
+    /// Called when the earliest watermark of the sink's open input ports advances,
+    /// after every operation received before it was passed to the sink.
+    fn on_watermark(&mut self, _watermark: SystemTime) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    /// The source states this sink has durably applied, if it tracks them, like high-water marks kept in the external store.
+    ///
+    /// Read once at startup under `DeliverySemantics::ExactlyOnce` to skip operations replayed from the last checkpoint.
+    /// An operation is skipped if its source table's state here is at or after the operation's identifier,
+    /// so a sink can have applied part of an epoch, or some tables further than others.
+    fn applied_source_states(&self) -> Option<SourceStates> {
+        None
+    }
+
+    /// The source offset this sink has durably applied operations of every source table through, if it tracks a single one,
+    /// like a high-water mark kept in the external store.
+    ///
+    /// Read once at startup under `DeliverySemantics::ExactlyOnce`, like `applied_source_states`.
+    /// An operation is skipped if its identifier is at or before this offset.
+    /// Operations sent by processors carry no offset, so they're only skipped with their whole epoch.
+    fn applied_through(&self) -> Option<OpIdentifier> {
+        None
+    }
+
+    /// Called once after all upstream sources have finished and everything they sent has been processed and committed.
+    ///
+    /// Not called if the executor is stopped before the sources finish. Use it for finalization, like writing a manifest.
+    fn on_end_of_stream(&mut self) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+/// Builds a [`TransformingSink`], added with `Dag::add_transforming_sink`.
+pub trait TransformingSinkFactory: Send + Sync + Debug {
+    fn get_input_ports(&self) -> Vec<PortHandle>;
+    fn get_output_ports(&self) -> Vec<PortHandle>;
+    fn get_output_schema(
+        &self,
+        output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError>;
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn TransformingSink>, BoxedError>;
+    fn type_name(&self) -> String;
+}
+
+/// A sink that also has output ports, so it can forward operations downstream like a processor.
+///
+/// It runs as a processor node: it's not persisted to a queue, and doesn't receive `on_source_snapshotting_done`.
+pub trait TransformingSink: Send + Sync + Debug {
+    fn commit(
+        &mut self,
+        epoch_details: &Epoch,
+        record_store: &ProcessorRecordStore,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError>;
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError>;
 }
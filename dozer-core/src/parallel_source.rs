@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::thread::{self, Builder};
+
+use crossbeam::channel::{bounded, Receiver, Select, Sender};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::models::ingestion_types::IngestionMessage;
+
+use crate::channels::SourceChannelForwarder;
+use crate::errors::ExecutionError;
+use crate::node::{PortHandle, Progress, Source, SourceMode, SourceState};
+
+/// Messages every fetcher can queue before it blocks, if not set with [`ParallelFetchSource::with_channel_capacity`].
+pub const DEFAULT_FETCHER_CHANNEL_CAPACITY: usize = 1024;
+
+/// A source whose data is fetched by several fetchers at once, like ranges of a table. Run it with [`ParallelFetchSource`].
+pub trait ParallelFetch: Send + Sync + Debug {
+    fn num_fetchers(&self) -> usize;
+
+    /// Fetches the share of fetcher `fetcher`, numbering every message it emits.
+    ///
+    /// The sequence numbers of all fetchers together must be 0, 1, 2 and so on, each used once,
+    /// and every fetcher must emit its own in ascending order.
+    fn fetch(
+        &self,
+        fetcher: usize,
+        last_checkpoint: &SourceState,
+        emitter: &mut FetchEmitter,
+    ) -> Result<(), BoxedError>;
+
+    fn mode(&self) -> SourceMode {
+        SourceMode::Streaming
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        None
+    }
+}
+
+/// Passed to [`ParallelFetch::fetch`] to emit messages.
+#[derive(Debug)]
+pub struct FetchEmitter {
+    sender: Sender<Fetched>,
+}
+
+impl FetchEmitter {
+    /// Blocks while the fetcher is too far ahead of the messages forwarded. Fails once the source has quit.
+    pub fn emit(
+        &mut self,
+        seq: u64,
+        message: IngestionMessage,
+        port: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        self.sender
+            .send(Ok((seq, message, port)))
+            .map_err(Into::into)
+    }
+}
+
+type Fetched = Result<(u64, IngestionMessage, PortHandle), BoxedError>;
+
+/// Runs the fetchers of a [`ParallelFetch`] on threads of their own and forwards their messages in sequence.
+///
+/// A message is held back until every message before it has been forwarded. As fetchers emit in ascending order,
+/// at most one message per fetcher is held back, besides the ones queued on its channel.
+/// The source fails if a fetcher fails, or all fetchers finish with a sequence number missing.
+#[derive(Debug)]
+pub struct ParallelFetchSource<F> {
+    fetch: F,
+    channel_capacity: usize,
+}
+
+impl<F: ParallelFetch> ParallelFetchSource<F> {
+    pub fn new(fetch: F) -> Self {
+        Self {
+            fetch,
+            channel_capacity: DEFAULT_FETCHER_CHANNEL_CAPACITY,
+        }
+    }
+
+    pub fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+}
+
+impl<F: ParallelFetch> Source for ParallelFetchSource<F> {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        let num_fetchers = self.fetch.num_fetchers();
+        if num_fetchers == 0 {
+            return Err("A parallel fetch source needs at least one fetcher".into());
+        }
+        thread::scope(|scope| {
+            let mut receivers = Vec::with_capacity(num_fetchers);
+            let mut threads = Vec::with_capacity(num_fetchers);
+            for fetcher in 0..num_fetchers {
+                let (sender, receiver) = bounded(self.channel_capacity);
+                receivers.push(receiver);
+                let (fetch, last_checkpoint) = (&self.fetch, &last_checkpoint);
+                let thread = Builder::new()
+                    .name(format!("source-fetcher-{fetcher}"))
+                    .spawn_scoped(scope, move || {
+                        let mut emitter = FetchEmitter { sender };
+                        if let Err(e) = fetch.fetch(fetcher, last_checkpoint, &mut emitter) {
+                            // Fails if the source already quit, which is reported instead.
+                            let _ = emitter.sender.send(Err(e));
+                        }
+                    })
+                    .map_err(ExecutionError::CannotSpawnWorkerThread)?;
+                threads.push(thread);
+            }
+
+            // Dropping the receivers makes the fetchers' next `emit` fail, so they quit if reassembly did.
+            let result = reassemble(receivers, fw);
+            let mut panicked = None;
+            for (fetcher, thread) in threads.into_iter().enumerate() {
+                if thread.join().is_err() {
+                    panicked.get_or_insert(fetcher);
+                }
+            }
+            match panicked {
+                Some(fetcher) => Err(ExecutionError::FetcherPanicked(fetcher).into()),
+                None => result,
+            }
+        })
+    }
+
+    fn mode(&self) -> SourceMode {
+        self.fetch.mode()
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        self.fetch.progress()
+    }
+}
+
+/// Forwards the fetchers' messages in sequence until every fetcher is done.
+///
+/// The next message is always the first unforwarded one of some fetcher, so only fetchers without a message held back are waited on.
+fn reassemble(
+    receivers: Vec<Receiver<Fetched>>,
+    fw: &mut dyn SourceChannelForwarder,
+) -> Result<(), BoxedError> {
+    let mut buffer = ReorderBuffer::default();
+    let mut waiting = (0..receivers.len()).collect::<Vec<_>>();
+    loop {
+        while let Some((fetcher, message, port)) = buffer.pop_next() {
+            fw.send(message, port)?;
+            waiting.push(fetcher);
+        }
+        if waiting.is_empty() {
+            // Every fetcher still running has a message held back, yet none is next.
+            if !buffer.is_empty() {
+                return Err(ExecutionError::MissingSequenceNumber(buffer.next_seq()).into());
+            }
+            return Ok(());
+        }
+
+        let mut select = Select::new();
+        for fetcher in &waiting {
+            select.recv(&receivers[*fetcher]);
+        }
+        let operation = select.select();
+        let index = operation.index();
+        let fetcher = waiting[index];
+        match operation.recv(&receivers[fetcher]) {
+            Ok(Ok((seq, message, port))) => {
+                if !buffer.push(seq, (fetcher, message, port)) {
+                    return Err(ExecutionError::OutOfOrderFetch { fetcher, seq }.into());
+                }
+                waiting.swap_remove(index);
+            }
+            Ok(Err(e)) => return Err(e),
+            // The fetcher is done.
+            Err(_) => {
+                waiting.swap_remove(index);
+            }
+        }
+    }
+}
+
+/// Items by sequence number, popped in sequence without gaps.
+#[derive(Debug)]
+struct ReorderBuffer<T> {
+    next_seq: u64,
+    pending: BTreeMap<u64, T>,
+}
+
+impl<T> Default for ReorderBuffer<T> {
+    fn default() -> Self {
+        Self {
+            next_seq: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> ReorderBuffer<T> {
+    /// The sequence number of the next item to pop.
+    fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns false if an item with `seq` was pushed before.
+    fn push(&mut self, seq: u64, item: T) -> bool {
+        if seq < self.next_seq || self.pending.contains_key(&seq) {
+            return false;
+        }
+        self.pending.insert(seq, item);
+        true
+    }
+
+    /// Pops the item with the next sequence number, if it was pushed.
+    fn pop_next(&mut self) -> Option<T> {
+        let item = self.pending.remove(&self.next_seq)?;
+        self.next_seq += 1;
+        Some(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, Operation};
+
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        ParallelGeneratorSourceFactory, PARALLEL_GENERATOR_SOURCE_OUTPUT_PORT,
+    };
+    use crate::DagBuilder;
+
+    #[tokio::test]
+    async fn test_run_dag_with_parallel_fetch_source() {
+        let count: u64 = 2_000;
+        let num_fetchers = 4;
+        let sink = RecordingSinkFactory::new()
+            .record_ops()
+            .stop_after(count, Arc::new(AtomicBool::new(true)));
+        let recording = sink.recording();
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                ParallelGeneratorSourceFactory::new(count, num_fetchers),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                PARALLEL_GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let recording = recording.lock();
+        assert_eq!(recording.ops.len() as u64, count);
+        let mut fetchers = vec![];
+        for (seq, recorded) in (0..).zip(recording.ops.iter()) {
+            let Operation::Insert { new } = &recorded.op else {
+                panic!("Expected an insert, got {:?}", recorded.op);
+            };
+            assert_eq!(new.values[0], Field::UInt(seq));
+            fetchers.push(new.values[1].clone());
+        }
+        fetchers.sort();
+        fetchers.dedup();
+        assert_eq!(fetchers.len(), num_fetchers);
+    }
+}
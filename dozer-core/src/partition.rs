@@ -0,0 +1,30 @@
+/// Hashes a record key to pick the partition it's routed to.
+pub type PartitionHasher = Box<dyn Fn(&[u8]) -> u64 + Send + Sync>;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, its output is guaranteed to stay the same across Rust versions and platforms.
+pub fn stable_hash(key: &[u8]) -> u64 {
+    key.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Returns the partition in `0..num_partitions` that `key` is routed to.
+pub fn partition_of(hasher: impl Fn(&[u8]) -> u64, key: &[u8], num_partitions: usize) -> usize {
+    debug_assert!(num_partitions > 0);
+    (hasher(key) % num_partitions as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stable_hash() {
+        assert_eq!(stable_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(partition_of(stable_hash, b"a", 4), 0);
+    }
+}
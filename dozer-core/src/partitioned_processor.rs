@@ -0,0 +1,569 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer, StoreRecord};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::parking_lot::Mutex;
+use dozer_types::types::{Record, Schema};
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::epoch::Epoch;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::{
+    PortHandle, Processor, ProcessorFactory, ProcessorPartitioning, StateBackend, StateEnvironment,
+};
+use crate::partition::{partition_of, PartitionHasher};
+use crate::record_store::InputRecordReader;
+
+/// Builds one processor per partition of `ProcessorFactory::partition_by`, and routes every record to the partition
+/// its key hashes to with `ProcessorFactory::partition_hasher`. Wrapped around processors by `Dag::add_processor`.
+///
+/// Operations on a key reach its partition in order. An update that changes the key is processed as a delete
+/// by the old key's partition and an insert by the new key's partition.
+/// Every partition gets a subdirectory of the processor's RocksDB state directory, and its own part of the checkpoint data,
+/// so the number of partitions can't change between runs resuming from the same checkpoint.
+#[derive(Debug)]
+pub(crate) struct PartitionedProcessorFactory {
+    inner: Box<dyn ProcessorFactory>,
+    partitioning: ProcessorPartitioning,
+}
+
+impl PartitionedProcessorFactory {
+    pub fn new(inner: Box<dyn ProcessorFactory>, partitioning: ProcessorPartitioning) -> Self {
+        Self {
+            inner,
+            partitioning,
+        }
+    }
+
+    fn build_partitions(
+        &self,
+        input_schemas: &HashMap<PortHandle, Schema>,
+        checkpoint_data: Option<Vec<u8>>,
+        mut build: impl FnMut(usize, Option<Vec<u8>>) -> Result<Box<dyn Processor>, BoxedError>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        let ProcessorPartitioning {
+            num_partitions,
+            key,
+        } = &self.partitioning;
+        if *num_partitions == 0 || key.is_empty() {
+            return Err("Partitioned processors need at least one partition and key field".into());
+        }
+        for (port, schema) in input_schemas {
+            if let Some(index) = key.iter().find(|index| **index >= schema.fields.len()) {
+                return Err(format!(
+                    "Partition key field {index} is out of range for the schema on port {port}"
+                )
+                .into());
+            }
+        }
+
+        let checkpoint_data = match checkpoint_data {
+            Some(data) => split_checkpoint_data(&data, *num_partitions)?
+                .into_iter()
+                .map(Some)
+                .collect(),
+            None => vec![None; *num_partitions],
+        };
+        let processors = checkpoint_data
+            .into_iter()
+            .enumerate()
+            .map(|(partition, data)| build(partition, data))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Box::new(PartitionedProcessor {
+            processors,
+            key: key.clone(),
+            hasher: self.inner.partition_hasher(),
+        }))
+    }
+}
+
+impl ProcessorFactory for PartitionedProcessorFactory {
+    fn get_output_schema(
+        &self,
+        output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        self.inner.get_output_schema(output_port, input_schemas)
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.inner.get_input_ports()
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        self.inner.get_output_ports()
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        output_schemas: HashMap<PortHandle, Schema>,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        self.build_partitions(&input_schemas, checkpoint_data, |_, data| {
+            self.inner.build(
+                input_schemas.clone(),
+                output_schemas.clone(),
+                record_store,
+                data,
+            )
+        })
+    }
+
+    fn type_name(&self) -> String {
+        self.inner.type_name()
+    }
+
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        self.inner.state_backend()
+    }
+
+    fn build_with_state(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        output_schemas: HashMap<PortHandle, Schema>,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+        state: StateEnvironment,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        let state_dir = match state {
+            StateEnvironment::RocksDb(path) => Some(path),
+            StateEnvironment::Memory => None,
+            StateEnvironment::Lmdb(_) => {
+                return Err("Partitioned processors can't share an LMDB environment".into())
+            }
+        };
+        self.build_partitions(&input_schemas, checkpoint_data, |partition, data| {
+            let state = match &state_dir {
+                Some(path) => {
+                    let path = path.join(partition.to_string());
+                    std::fs::create_dir_all(&path)?;
+                    StateEnvironment::RocksDb(path)
+                }
+                None => StateEnvironment::Memory,
+            };
+            self.inner.build_with_state(
+                input_schemas.clone(),
+                output_schemas.clone(),
+                record_store,
+                data,
+                state,
+            )
+        })
+    }
+
+    fn requires_total_order(&self) -> bool {
+        self.inner.requires_total_order()
+    }
+}
+
+/// Splits the data `PartitionedProcessor::serialize` wrote into the data of every partition.
+fn split_checkpoint_data(
+    mut data: &[u8],
+    num_partitions: usize,
+) -> Result<Vec<Vec<u8>>, BoxedError> {
+    let mut partitions = vec![];
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err("Truncated partitioned processor checkpoint data".into());
+        }
+        let (len, rest) = data.split_at(8);
+        let len = u64::from_le_bytes(len.try_into().expect("checked above")) as usize;
+        if rest.len() < len {
+            return Err("Truncated partitioned processor checkpoint data".into());
+        }
+        let (partition, rest) = rest.split_at(len);
+        partitions.push(partition.to_vec());
+        data = rest;
+    }
+    if partitions.len() != num_partitions {
+        return Err(format!(
+            "The checkpoint has data of {} partitions, but the processor has {num_partitions}",
+            partitions.len()
+        )
+        .into());
+    }
+    Ok(partitions)
+}
+
+struct PartitionedProcessor {
+    processors: Vec<Box<dyn Processor>>,
+    key: Vec<usize>,
+    hasher: PartitionHasher,
+}
+
+impl std::fmt::Debug for PartitionedProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartitionedProcessor")
+            .field("processors", &self.processors)
+            .field("key", &self.key)
+            .finish()
+    }
+}
+
+impl PartitionedProcessor {
+    fn partition_of(&self, record: &Record) -> usize {
+        partition_of(
+            &self.hasher,
+            &record.get_key(&self.key),
+            self.processors.len(),
+        )
+    }
+
+    fn each(
+        &mut self,
+        mut call: impl FnMut(&mut dyn Processor) -> Result<(), BoxedError>,
+    ) -> Result<(), BoxedError> {
+        for processor in &mut self.processors {
+            call(processor.as_mut())?;
+        }
+        Ok(())
+    }
+}
+
+impl Processor for PartitionedProcessor {
+    fn init(&mut self) -> Result<(), BoxedError> {
+        self.each(|processor| processor.init())
+    }
+
+    fn before_commit(
+        &mut self,
+        epoch_details: &Epoch,
+        record_store: &ProcessorRecordStore,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.each(|processor| processor.before_commit(epoch_details, record_store, fw))
+    }
+
+    fn commit(&self, epoch_details: &Epoch) -> Result<(), BoxedError> {
+        for processor in &self.processors {
+            processor.commit(epoch_details)?;
+        }
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        let (partition, op) = match op {
+            ProcessorOperation::Update { old, new } => {
+                let old_partition = self.partition_of(&record_store.load_record(&old)?);
+                let new_partition = self.partition_of(&record_store.load_record(&new)?);
+                if old_partition == new_partition {
+                    (new_partition, ProcessorOperation::Update { old, new })
+                } else {
+                    self.processors[old_partition].process(
+                        from_port,
+                        record_store,
+                        ProcessorOperation::Delete { old },
+                        fw,
+                    )?;
+                    (new_partition, ProcessorOperation::Insert { new })
+                }
+            }
+            ProcessorOperation::Insert { new } => (
+                self.partition_of(&record_store.load_record(&new)?),
+                ProcessorOperation::Insert { new },
+            ),
+            ProcessorOperation::Delete { old } => (
+                self.partition_of(&record_store.load_record(&old)?),
+                ProcessorOperation::Delete { old },
+            ),
+        };
+        self.processors[partition].process(from_port, record_store, op, fw)
+    }
+
+    /// Writes the length of every partition's data, then the data.
+    fn serialize(
+        &mut self,
+        record_store: &ProcessorRecordStore,
+        mut object: Object,
+    ) -> Result<(), BoxedError> {
+        for processor in &mut self.processors {
+            let data = Arc::new(Mutex::new(vec![]));
+            processor.serialize(record_store, Object::in_memory(data.clone()))?;
+            let data = std::mem::take(&mut *data.lock());
+            object.write(&(data.len() as u64).to_le_bytes())?;
+            object.write(&data)?;
+        }
+        Ok(())
+    }
+
+    fn set_record_readers(&mut self, record_readers: HashMap<PortHandle, InputRecordReader>) {
+        for processor in &mut self.processors {
+            processor.set_record_readers(record_readers.clone());
+        }
+    }
+
+    fn on_port_closed(
+        &mut self,
+        port: PortHandle,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.each(|processor| processor.on_port_closed(port, fw))
+    }
+
+    fn on_watermark(
+        &mut self,
+        watermark: SystemTime,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.each(|processor| processor.on_watermark(watermark, fw))
+    }
+
+    fn flush(&mut self) -> Result<(), BoxedError> {
+        self.each(|processor| processor.flush())
+    }
+
+    /// Snapshots every partition to the subdirectory laid out like its storage.
+    fn snapshot_state(&self, dir: &Path) -> Result<(), BoxedError> {
+        std::fs::create_dir_all(dir)?;
+        for (partition, processor) in self.processors.iter().enumerate() {
+            processor.snapshot_state(&dir.join(partition.to_string()))?;
+        }
+        if std::fs::read_dir(dir)?.next().is_none() {
+            // No partition keeps anything in its storage.
+            std::fs::remove_dir(dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use dozer_types::types::{Field, FieldDefinition, FieldType, Operation, SourceDefinition};
+
+    use crate::DEFAULT_PORT_HANDLE;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct TestForwarder;
+
+    impl ProcessorChannelForwarder for TestForwarder {
+        fn send(&mut self, _op: ProcessorOperation, _port: PortHandle) {}
+    }
+
+    type Routed = Arc<Mutex<Vec<(usize, Operation)>>>;
+
+    /// Records the operations every partition processes, routing on the last byte of the key like a producer partitioning on it.
+    #[derive(Debug, Default)]
+    struct RecordingProcessorFactory {
+        builds: Arc<AtomicUsize>,
+        routed: Routed,
+    }
+
+    impl ProcessorFactory for RecordingProcessorFactory {
+        fn get_output_schema(
+            &self,
+            _output_port: &PortHandle,
+            input_schemas: &HashMap<PortHandle, Schema>,
+        ) -> Result<Schema, BoxedError> {
+            Ok(input_schemas[&DEFAULT_PORT_HANDLE].clone())
+        }
+
+        fn get_input_ports(&self) -> Vec<PortHandle> {
+            vec![DEFAULT_PORT_HANDLE]
+        }
+
+        fn get_output_ports(&self) -> Vec<PortHandle> {
+            vec![DEFAULT_PORT_HANDLE]
+        }
+
+        fn build(
+            &self,
+            _input_schemas: HashMap<PortHandle, Schema>,
+            _output_schemas: HashMap<PortHandle, Schema>,
+            _record_store: &ProcessorRecordStoreDeserializer,
+            checkpoint_data: Option<Vec<u8>>,
+        ) -> Result<Box<dyn Processor>, BoxedError> {
+            let partition = self.builds.fetch_add(1, Ordering::SeqCst);
+            if let Some(data) = checkpoint_data {
+                assert_eq!(data, vec![partition as u8; partition]);
+            }
+            Ok(Box::new(RecordingProcessor {
+                partition,
+                routed: self.routed.clone(),
+            }))
+        }
+
+        fn type_name(&self) -> String {
+            "Recording".to_owned()
+        }
+
+        fn id(&self) -> String {
+            "Recording".to_owned()
+        }
+
+        fn partition_by(&self) -> Option<ProcessorPartitioning> {
+            Some(ProcessorPartitioning {
+                num_partitions: 3,
+                key: vec![0],
+            })
+        }
+
+        fn partition_hasher(&self) -> PartitionHasher {
+            Box::new(|key: &[u8]| *key.last().unwrap() as u64)
+        }
+    }
+
+    #[derive(Debug)]
+    struct RecordingProcessor {
+        partition: usize,
+        routed: Routed,
+    }
+
+    impl Processor for RecordingProcessor {
+        fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn process(
+            &mut self,
+            _from_port: PortHandle,
+            record_store: &ProcessorRecordStore,
+            op: ProcessorOperation,
+            _fw: &mut dyn ProcessorChannelForwarder,
+        ) -> Result<(), BoxedError> {
+            self.routed
+                .lock()
+                .push((self.partition, op.load(record_store)?));
+            Ok(())
+        }
+
+        /// Writes the partition index as many times as it's worth.
+        fn serialize(
+            &mut self,
+            _record_store: &ProcessorRecordStore,
+            mut object: Object,
+        ) -> Result<(), BoxedError> {
+            object.write(&vec![self.partition as u8; self.partition])?;
+            Ok(())
+        }
+    }
+
+    fn schema() -> Schema {
+        Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "id".to_string(),
+                    FieldType::UInt,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            )
+            .clone()
+    }
+
+    fn build(
+        factory: &PartitionedProcessorFactory,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        factory.build(
+            HashMap::from([(DEFAULT_PORT_HANDLE, schema())]),
+            HashMap::from([(DEFAULT_PORT_HANDLE, schema())]),
+            &ProcessorRecordStoreDeserializer::new(Default::default()).unwrap(),
+            checkpoint_data,
+        )
+    }
+
+    fn record(id: u64) -> Record {
+        Record::new(vec![Field::UInt(id)])
+    }
+
+    #[test]
+    fn partitioned_processor_routes_with_custom_hasher() {
+        let inner = RecordingProcessorFactory::default();
+        let routed = inner.routed.clone();
+        let partitioning = inner.partition_by().unwrap();
+        let factory = PartitionedProcessorFactory::new(Box::new(inner), partitioning);
+        let mut processor = build(&factory, None).unwrap();
+
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let insert = |id| ProcessorOperation::Insert {
+            new: record_store.create_record(&record(id)).unwrap(),
+        };
+        for id in [4, 8, 9] {
+            processor
+                .process(
+                    DEFAULT_PORT_HANDLE,
+                    &record_store,
+                    insert(id),
+                    &mut TestForwarder,
+                )
+                .unwrap();
+        }
+        // Moves the key from partition 1 to partition 2.
+        let update = ProcessorOperation::Update {
+            old: record_store.create_record(&record(4)).unwrap(),
+            new: record_store.create_record(&record(5)).unwrap(),
+        };
+        processor
+            .process(
+                DEFAULT_PORT_HANDLE,
+                &record_store,
+                update,
+                &mut TestForwarder,
+            )
+            .unwrap();
+
+        // UInt keys end with the value's last byte, so the hasher puts every id in partition `id % 3`.
+        assert_eq!(
+            *routed.lock(),
+            vec![
+                (1, Operation::Insert { new: record(4) }),
+                (2, Operation::Insert { new: record(8) }),
+                (0, Operation::Insert { new: record(9) }),
+                (1, Operation::Delete { old: record(4) }),
+                (2, Operation::Insert { new: record(5) }),
+            ]
+        );
+    }
+
+    #[test]
+    fn partitioned_processor_splits_checkpoint_data() {
+        let inner = RecordingProcessorFactory::default();
+        let builds = inner.builds.clone();
+        let partitioning = inner.partition_by().unwrap();
+        let factory = PartitionedProcessorFactory::new(Box::new(inner), partitioning);
+        let mut processor = build(&factory, None).unwrap();
+
+        let data = Arc::new(Mutex::new(vec![]));
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        processor
+            .serialize(&record_store, Object::in_memory(data.clone()))
+            .unwrap();
+        let data = std::mem::take(&mut *data.lock());
+
+        // Every partition is built from its own part.
+        builds.store(0, Ordering::SeqCst);
+        build(&factory, Some(data.clone())).unwrap();
+        assert_eq!(builds.load(Ordering::SeqCst), 3);
+
+        let partitioning = ProcessorPartitioning {
+            num_partitions: 2,
+            key: vec![0],
+        };
+        let factory = PartitionedProcessorFactory::new(
+            Box::<RecordingProcessorFactory>::default(),
+            partitioning,
+        );
+        assert!(build(&factory, Some(data)).is_err());
+    }
+}
@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+use std::thread::{Builder, JoinHandle};
+use std::time::SystemTime;
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use dozer_log::storage::Queue;
+use dozer_recordstore::ProcessorRecordStore;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::node::{OpIdentifier, SourceStates, TableState};
+use dozer_types::types::{Operation, Record, Schema};
+
+use crate::epoch::Epoch;
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::node::{CommitDecision, PortHandle, Sink, SinkFactory, SinkPartitioning};
+use crate::partition::{partition_of, PartitionHasher};
+
+/// Operations queued for every partition before the sink node blocks.
+const PARTITION_CHANNEL_CAPACITY: usize = 1024;
+
+/// Builds one sink per partition of `SinkFactory::partition_by`, each running on its own thread,
+/// and routes every record to the partition its key hashes to. Wrapped around sinks by `Dag::add_sink`.
+///
+/// Operations on a key reach its partition in order. An update that changes the key is delivered as a delete
+/// to the old key's partition and an insert to the new key's partition.
+/// Partitions process operations asynchronously, so an error from `process` surfaces from the next commit, failing it.
+/// A source state or offset counts as applied only if every partition applied it,
+/// so under `DeliverySemantics::ExactlyOnce` partitions that got further than others see the epochs in between again.
+#[derive(Debug)]
+pub(crate) struct PartitionedSinkFactory {
+    inner: Box<dyn SinkFactory>,
+    partitioning: SinkPartitioning,
+}
+
+impl PartitionedSinkFactory {
+    pub fn new(inner: Box<dyn SinkFactory>, partitioning: SinkPartitioning) -> Self {
+        Self {
+            inner,
+            partitioning,
+        }
+    }
+}
+
+impl SinkFactory for PartitionedSinkFactory {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.inner.get_input_ports()
+    }
+
+    fn prepare(&self, input_schemas: HashMap<PortHandle, Schema>) -> Result<(), BoxedError> {
+        if self.partitioning.num_partitions == 0 || self.partitioning.key.is_empty() {
+            return Err("Partitioned sinks need at least one partition and key field".into());
+        }
+        for (port, schema) in &input_schemas {
+            if let Some(index) = self
+                .partitioning
+                .key
+                .iter()
+                .find(|index| **index >= schema.fields.len())
+            {
+                return Err(format!(
+                    "Partition key field {index} is out of range for the schema on port {port}"
+                )
+                .into());
+            }
+        }
+        self.inner.prepare(input_schemas)
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Sink>, BoxedError> {
+        let sinks = (0..self.partitioning.num_partitions)
+            .map(|_| self.inner.build(input_schemas.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Box::new(PartitionedSink::new(
+            sinks,
+            self.partitioning.key.clone(),
+            self.inner.partition_hasher(),
+        )?))
+    }
+}
+
+enum Message {
+    Init,
+    Op {
+        port: PortHandle,
+        op: Operation,
+        context: OperationContext,
+    },
+    Commit(Epoch),
+    Persist(Queue),
+    SnapshottingDone(String),
+    Watermark(SystemTime),
+    EndOfStream,
+}
+
+#[derive(Debug)]
+struct Partition {
+    sender: Sender<Message>,
+    /// Replies to every message but `Op`. Messages other than commits reply `Committed` on success.
+    replies: Receiver<Result<CommitDecision, BoxedError>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct PartitionedSink {
+    partitions: Vec<Partition>,
+    key: Vec<usize>,
+    hasher: PartitionHasher,
+    /// The source states all partitions have applied, read before they started.
+    applied: Option<SourceStates>,
+    /// The offset all partitions have applied operations through, read before they started.
+    applied_through: Option<OpIdentifier>,
+    /// The epoch being committed, and the partitions that pushed back on it.
+    pending_commit: Option<(u64, Vec<usize>)>,
+    /// If a partition deferred the epoch being committed.
+    deferred: bool,
+}
+
+impl std::fmt::Debug for PartitionedSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartitionedSink")
+            .field("partitions", &self.partitions)
+            .field("key", &self.key)
+            .field("applied", &self.applied)
+            .field("applied_through", &self.applied_through)
+            .field("pending_commit", &self.pending_commit)
+            .field("deferred", &self.deferred)
+            .finish()
+    }
+}
+
+impl PartitionedSink {
+    fn new(
+        sinks: Vec<Box<dyn Sink>>,
+        key: Vec<usize>,
+        hasher: PartitionHasher,
+    ) -> Result<Self, BoxedError> {
+        let applied = merge_applied(sinks.iter().map(|sink| sink.applied_source_states()));
+        let applied_through = sinks
+            .iter()
+            .map(|sink| sink.applied_through())
+            .reduce(|a, b| a.zip(b).map(|(a, b)| a.min(b)))
+            .flatten();
+        let mut partitions = Vec::with_capacity(sinks.len());
+        for (index, sink) in sinks.into_iter().enumerate() {
+            let (sender, receiver) = bounded(PARTITION_CHANNEL_CAPACITY);
+            let (reply_sender, replies) = bounded(1);
+            let thread = Builder::new()
+                .name(format!("sink-partition-{index}"))
+                .spawn(move || run_partition(sink, receiver, reply_sender))?;
+            partitions.push(Partition {
+                sender,
+                replies,
+                thread: Some(thread),
+            });
+        }
+        Ok(Self {
+            partitions,
+            key,
+            hasher,
+            applied,
+            applied_through,
+            pending_commit: None,
+            deferred: false,
+        })
+    }
+
+    fn partition_of(&self, record: &Record) -> usize {
+        partition_of(
+            &self.hasher,
+            &record.get_key(&self.key),
+            self.partitions.len(),
+        )
+    }
+
+    fn send(&self, partition: usize, message: Message) -> Result<(), BoxedError> {
+        self.partitions[partition]
+            .sender
+            .send(message)
+            .map_err(|_| BoxedError::from(format!("Sink partition {partition} quit")))
+    }
+
+    /// Sends `message` to all of `partitions` so they handle it in parallel, then waits for their replies.
+    fn call(
+        &self,
+        partitions: &[usize],
+        message: impl Fn() -> Message,
+    ) -> Result<Vec<CommitDecision>, BoxedError> {
+        for partition in partitions {
+            self.send(*partition, message())?;
+        }
+        let mut decisions = Vec::with_capacity(partitions.len());
+        let mut first_error: Option<BoxedError> = None;
+        // Wait for every reply, so no partition is left behind on the next call.
+        for partition in partitions {
+            match self.partitions[*partition].replies.recv() {
+                Ok(Ok(decision)) => decisions.push(decision),
+                Ok(Err(e)) => {
+                    first_error.get_or_insert(e);
+                }
+                Err(_) => {
+                    first_error.get_or_insert(format!("Sink partition {partition} quit").into());
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(decisions),
+        }
+    }
+
+    fn call_all(&self, message: impl Fn() -> Message) -> Result<(), BoxedError> {
+        let all = (0..self.partitions.len()).collect::<Vec<_>>();
+        self.call(&all, message).map(|_| ())
+    }
+}
+
+impl Sink for PartitionedSink {
+    fn init(&mut self) -> Result<(), BoxedError> {
+        self.call_all(|| Message::Init)
+    }
+
+    fn commit(&mut self, epoch_details: &Epoch) -> Result<(), BoxedError> {
+        self.commit_with_decision(epoch_details).map(|_| ())
+    }
+
+    /// Commits `epoch_details` in every partition. If any partition pushes back, only those are asked again on the retry.
+    fn commit_with_decision(
+        &mut self,
+        epoch_details: &Epoch,
+    ) -> Result<CommitDecision, BoxedError> {
+        let epoch_id = epoch_details.common_info.id;
+        let partitions = match self.pending_commit.take() {
+            Some((pending_id, partitions)) if pending_id == epoch_id => partitions,
+            _ => {
+                self.deferred = false;
+                (0..self.partitions.len()).collect()
+            }
+        };
+        let decisions = self.call(&partitions, || Message::Commit(epoch_details.clone()))?;
+
+        let mut backpressured = vec![];
+        for (partition, decision) in partitions.into_iter().zip(decisions) {
+            match decision {
+                CommitDecision::Committed => {}
+                CommitDecision::Defer => self.deferred = true,
+                CommitDecision::Backpressure => backpressured.push(partition),
+            }
+        }
+        if !backpressured.is_empty() {
+            self.pending_commit = Some((epoch_id, backpressured));
+            return Ok(CommitDecision::Backpressure);
+        }
+        Ok(if self.deferred {
+            CommitDecision::Defer
+        } else {
+            CommitDecision::Committed
+        })
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<(), BoxedError> {
+        self.process_with_context(from_port, record_store, op, &Default::default())
+    }
+
+    fn process_with_context(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        context: &OperationContext,
+    ) -> Result<(), BoxedError> {
+        let message = |op| Message::Op {
+            port: from_port,
+            op,
+            context: context.clone(),
+        };
+        match op.load(record_store)? {
+            Operation::Update { old, new } => {
+                let (old_partition, new_partition) =
+                    (self.partition_of(&old), self.partition_of(&new));
+                if old_partition == new_partition {
+                    self.send(new_partition, message(Operation::Update { old, new }))
+                } else {
+                    self.send(old_partition, message(Operation::Delete { old }))?;
+                    self.send(new_partition, message(Operation::Insert { new }))
+                }
+            }
+            Operation::Insert { new } => {
+                self.send(self.partition_of(&new), message(Operation::Insert { new }))
+            }
+            Operation::Delete { old } => {
+                self.send(self.partition_of(&old), message(Operation::Delete { old }))
+            }
+        }
+    }
+
+    fn persist(&mut self, queue: &Queue) -> Result<(), BoxedError> {
+        self.call_all(|| Message::Persist(queue.clone()))
+    }
+
+    fn on_source_snapshotting_done(&mut self, connection_name: String) -> Result<(), BoxedError> {
+        self.call_all(|| Message::SnapshottingDone(connection_name.clone()))
+    }
+
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), BoxedError> {
+        self.call_all(|| Message::Watermark(watermark))
+    }
+
+    fn on_end_of_stream(&mut self) -> Result<(), BoxedError> {
+        self.call_all(|| Message::EndOfStream)
+    }
+
+    fn applied_source_states(&self) -> Option<SourceStates> {
+        self.applied.clone()
+    }
+
+    fn applied_through(&self) -> Option<OpIdentifier> {
+        self.applied_through
+    }
+}
+
+/// The source states applied by all of `states`, `None` if any partition doesn't track them.
+///
+/// A table missing from any partition's states isn't applied.
+fn merge_applied(mut states: impl Iterator<Item = Option<SourceStates>>) -> Option<SourceStates> {
+    let mut merged = states.next()??;
+    for other in states {
+        let other = other?;
+        merged.retain(|node_handle, tables| {
+            let Some(other_tables) = other.get(node_handle) else {
+                return false;
+            };
+            tables.retain(|table_name, state| {
+                let Some(other_state) = other_tables.get(table_name) else {
+                    return false;
+                };
+                *state = earliest_state(state, other_state);
+                true
+            });
+            !tables.is_empty()
+        });
+    }
+    Some(merged)
+}
+
+/// The state that covers fewer operations.
+fn earliest_state(a: &TableState, b: &TableState) -> TableState {
+    match (a, b) {
+        (TableState::Restartable(a), TableState::Restartable(b)) => {
+            TableState::Restartable(*a.min(b))
+        }
+        (TableState::NonRestartable, _) | (_, TableState::NonRestartable) => {
+            TableState::NonRestartable
+        }
+        _ => TableState::NotStarted,
+    }
+}
+
+impl Drop for PartitionedSink {
+    fn drop(&mut self) {
+        let threads = self
+            .partitions
+            .drain(..)
+            .filter_map(|mut partition| partition.thread.take())
+            .collect::<Vec<_>>();
+        // The partitions were drained, so their senders are dropped and the threads quit.
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs a partition's sink until the partitioned sink is dropped.
+///
+/// Operations are stored again in a record store of the partition's own, as the executor's isn't shared with it.
+fn run_partition(
+    mut sink: Box<dyn Sink>,
+    receiver: Receiver<Message>,
+    replies: Sender<Result<CommitDecision, BoxedError>>,
+) {
+    let record_store = match ProcessorRecordStore::new(Default::default()) {
+        Ok(record_store) => record_store,
+        Err(e) => {
+            let _ = replies.send(Err(e.into()));
+            return;
+        }
+    };
+    let mut process_error: Option<BoxedError> = None;
+    for message in receiver {
+        let reply = match message {
+            Message::Op { port, op, context } => {
+                if process_error.is_none() {
+                    let result = ProcessorOperation::new(&op, &record_store)
+                        .map_err(BoxedError::from)
+                        .and_then(|op| {
+                            sink.process_with_context(port, &record_store, op, &context)
+                        });
+                    process_error = result.err();
+                }
+                continue;
+            }
+            _ if process_error.is_some() => Err(process_error.take().expect("checked above")),
+            Message::Init => sink.init().map(|()| CommitDecision::Committed),
+            Message::Commit(epoch) => {
+                let decision = sink.commit_with_decision(&epoch);
+                record_store.compact();
+                decision
+            }
+            Message::Persist(queue) => sink.persist(&queue).map(|()| CommitDecision::Committed),
+            Message::SnapshottingDone(connection_name) => sink
+                .on_source_snapshotting_done(connection_name)
+                .map(|()| CommitDecision::Committed),
+            Message::Watermark(watermark) => sink
+                .on_watermark(watermark)
+                .map(|()| CommitDecision::Committed),
+            Message::EndOfStream => sink.on_end_of_stream().map(|()| CommitDecision::Committed),
+        };
+        if replies.send(reply).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::Field;
+
+    use crate::tests::dag_base_run::NoopProcessorFactory;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        GeneratorSourceFactory, OpGenerator, OpMix, GENERATOR_SOURCE_OUTPUT_PORT,
+    };
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    /// Reports the source states it was built with as applied.
+    #[derive(Debug)]
+    struct AppliedStatesSink {
+        applied: Option<SourceStates>,
+    }
+
+    impl Sink for AppliedStatesSink {
+        fn commit(&mut self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn process(
+            &mut self,
+            _from_port: PortHandle,
+            _record_store: &ProcessorRecordStore,
+            _op: ProcessorOperation,
+        ) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn persist(&mut self, _queue: &Queue) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn on_source_snapshotting_done(
+            &mut self,
+            _connection_name: String,
+        ) -> Result<(), BoxedError> {
+            Ok(())
+        }
+
+        fn applied_source_states(&self) -> Option<SourceStates> {
+            self.applied.clone()
+        }
+    }
+
+    fn source_states(tables: &[(&str, TableState)]) -> SourceStates {
+        let tables = tables
+            .iter()
+            .map(|(name, state)| (name.to_string(), *state))
+            .collect();
+        SourceStates::from([(NodeHandle::new(None, "source".to_string()), tables)])
+    }
+
+    fn partitioned_sink(applied: Vec<Option<SourceStates>>) -> PartitionedSink {
+        let sinks = applied
+            .into_iter()
+            .map(|applied| Box::new(AppliedStatesSink { applied }) as Box<dyn Sink>)
+            .collect();
+        PartitionedSink::new(sinks, vec![0], Box::new(crate::partition::stable_hash)).unwrap()
+    }
+
+    #[test]
+    fn partitioned_sink_applied_only_what_every_partition_applied() {
+        let restartable = |txid| TableState::Restartable(OpIdentifier::new(txid, 0));
+        let sink = partitioned_sink(vec![
+            Some(source_states(&[
+                ("a", restartable(5)),
+                ("b", restartable(3)),
+            ])),
+            Some(source_states(&[("a", restartable(2))])),
+            Some(source_states(&[
+                ("a", restartable(7)),
+                ("b", TableState::NotStarted),
+            ])),
+        ]);
+        assert_eq!(
+            sink.applied_source_states(),
+            Some(source_states(&[("a", restartable(2))]))
+        );
+
+        let sink = partitioned_sink(vec![Some(source_states(&[("a", restartable(5))])), None]);
+        assert_eq!(sink.applied_source_states(), None);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_partitioned_sink() {
+        let count: u64 = 5_000;
+        let num_partitions = 4;
+        let op_mix = OpMix {
+            inserts: 3,
+            updates: 2,
+            deletes: 1,
+            key_space: 50,
+        };
+        let latch = Arc::new(AtomicBool::new(true));
+        let sink = RecordingSinkFactory::new()
+            .with_partitions(num_partitions)
+            .record_ops()
+            .stop_after(count, latch.clone());
+        let recording = sink.recording();
+
+        let source_handle = NodeHandle::new(Some(1), 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false).with_op_mix(op_mix),
+            )
+            .processor(proc_handle.clone(), NoopProcessorFactory {})
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let key = |op: &Operation| match op {
+            Operation::Insert { new } | Operation::Update { new, .. } => new.values[0].clone(),
+            Operation::Delete { old } => old.values[0].clone(),
+        };
+        let mut generator = OpGenerator::new(op_mix);
+        let mut expected: HashMap<Field, Vec<Operation>> = HashMap::new();
+        for n in 1..count + 1 {
+            let op = generator.next_op(n);
+            expected.entry(key(&op)).or_default().push(op);
+        }
+
+        let recording = recording.lock();
+        assert_eq!(recording.ops.len() as u64, count);
+        let mut actual: HashMap<Field, Vec<Operation>> = HashMap::new();
+        let mut instances: HashMap<Field, usize> = HashMap::new();
+        for recorded in &recording.ops {
+            let key = recorded.key().clone();
+            // Every key is owned by one partition.
+            assert_eq!(
+                *instances.entry(key.clone()).or_insert(recorded.instance),
+                recorded.instance
+            );
+            actual.entry(key).or_default().push(recorded.op.clone());
+        }
+        // Each partition received the operations on its keys in the order they were sent.
+        assert_eq!(actual, expected);
+        let mut used = instances.values().collect::<Vec<_>>();
+        used.sort();
+        used.dedup();
+        assert!(used.len() > 1);
+        assert!(used.iter().all(|instance| **instance < num_partitions));
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_partitioned_sink_custom_hasher() {
+        let count: u64 = 1_000;
+        let latch = Arc::new(AtomicBool::new(true));
+        // Every key hashes to 6, which is partition 2 of 4.
+        let sink = RecordingSinkFactory::new()
+            .with_partitions(4)
+            .with_hasher(|_| 6)
+            .record_ops()
+            .stop_after(count, latch.clone());
+        let recording = sink.recording();
+
+        let source_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let recording = recording.lock();
+        assert_eq!(recording.ops.len() as u64, count);
+        assert!(recording.ops.iter().all(|recorded| recorded.instance == 2));
+    }
+}
@@ -0,0 +1,197 @@
+use dozer_recordstore::{ProcessorRecordStore, StoreRecord};
+use dozer_types::serde::{Deserialize, Serialize};
+use dozer_types::types::{Record, Schema};
+
+use crate::errors::ExecutionError;
+use crate::executor_operation::ProcessorOperation;
+
+/// Selects and reorders the fields of the records flowing through an edge,
+/// so the downstream input port receives a narrower schema than the upstream output port produces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+pub struct FieldProjection {
+    /// Index of the upstream field that goes to each downstream field.
+    indexes: Vec<usize>,
+}
+
+impl FieldProjection {
+    pub fn new(indexes: Vec<usize>) -> Self {
+        Self { indexes }
+    }
+
+    pub fn indexes(&self) -> &[usize] {
+        &self.indexes
+    }
+
+    /// Returns the projected schema, or `None` if an index is out of `schema`'s range.
+    ///
+    /// The primary key is kept only if all of its fields are projected.
+    pub fn project_schema(&self, schema: &Schema) -> Option<Schema> {
+        let fields = self
+            .indexes
+            .iter()
+            .map(|index| schema.fields.get(*index).cloned())
+            .collect::<Option<Vec<_>>>()?;
+        let primary_index = schema
+            .primary_index
+            .iter()
+            .map(|index| self.indexes.iter().position(|i| i == index))
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+        Some(Schema {
+            fields,
+            primary_index,
+        })
+    }
+
+    pub fn project_record(&self, record: &Record) -> Record {
+        Record {
+            values: self
+                .indexes
+                .iter()
+                .map(|index| record.values[*index].clone())
+                .collect(),
+            lifetime: record.lifetime.clone(),
+        }
+    }
+
+    /// Projects the records of `op`, storing the projected records in `record_store`.
+    pub fn project_operation(
+        &self,
+        op: &ProcessorOperation,
+        record_store: &ProcessorRecordStore,
+    ) -> Result<ProcessorOperation, ExecutionError> {
+        let project = |record| -> Result<_, ExecutionError> {
+            let record = record_store.load_record(record)?;
+            Ok(record_store.create_record(&self.project_record(&record))?)
+        };
+        Ok(match op {
+            ProcessorOperation::Delete { old } => ProcessorOperation::Delete { old: project(old)? },
+            ProcessorOperation::Insert { new } => ProcessorOperation::Insert { new: project(new)? },
+            ProcessorOperation::Update { old, new } => ProcessorOperation::Update {
+                old: project(old)?,
+                new: project(new)?,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, FieldDefinition, FieldType, SourceDefinition};
+
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{ThreeFieldSourceFactory, THREE_FIELD_SOURCE_OUTPUT_PORT};
+    use crate::{Dag, Endpoint};
+
+    use super::*;
+
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        for (name, pk) in [("a", true), ("b", false), ("c", false)] {
+            schema.field(
+                FieldDefinition::new(
+                    name.to_string(),
+                    FieldType::Int,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                pk,
+            );
+        }
+        schema
+    }
+
+    #[test]
+    fn project_schema_reorders_fields_and_primary_key() {
+        let projected = FieldProjection::new(vec![2, 0])
+            .project_schema(&schema())
+            .unwrap();
+        let names = projected
+            .fields
+            .iter()
+            .map(|field| field.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["c", "a"]);
+        assert_eq!(projected.primary_index, vec![1]);
+
+        let projected = FieldProjection::new(vec![1, 2])
+            .project_schema(&schema())
+            .unwrap();
+        assert!(projected.primary_index.is_empty());
+
+        assert!(FieldProjection::new(vec![3])
+            .project_schema(&schema())
+            .is_none());
+    }
+
+    #[test]
+    fn project_operation_projects_records() {
+        let record_store = ProcessorRecordStore::new(Default::default()).unwrap();
+        let record = Record::new(vec![Field::Int(1), Field::Int(2), Field::Int(3)]);
+        let op = ProcessorOperation::Insert {
+            new: record_store.create_record(&record).unwrap(),
+        };
+
+        let ProcessorOperation::Insert { new } = FieldProjection::new(vec![2, 0])
+            .project_operation(&op, &record_store)
+            .unwrap()
+        else {
+            panic!("Projection must keep the operation type");
+        };
+        assert_eq!(
+            record_store.load_record(&new).unwrap(),
+            Record::new(vec![Field::Int(3), Field::Int(1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_field_projection() {
+        let count: u64 = 100;
+
+        let mut dag = Dag::new();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let projected_sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let full_sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        let projected_sink = RecordingSinkFactory::new().record_ops();
+        let projected_recording = projected_sink.recording();
+        let full_sink = RecordingSinkFactory::new().record_ops();
+        let full_recording = full_sink.recording();
+        dag.add_source(
+            source_handle.clone(),
+            Box::new(ThreeFieldSourceFactory::new(count)),
+        );
+        dag.add_sink(projected_sink_handle.clone(), Box::new(projected_sink));
+        dag.add_sink(full_sink_handle.clone(), Box::new(full_sink));
+
+        dag.connect_with_projection(
+            Endpoint::new(source_handle.clone(), THREE_FIELD_SOURCE_OUTPUT_PORT),
+            Endpoint::new(projected_sink_handle, RECORDING_SINK_INPUT_PORT),
+            Some(FieldProjection::new(vec![2, 0])),
+        )
+        .unwrap();
+        dag.connect(
+            Endpoint::new(source_handle, THREE_FIELD_SOURCE_OUTPUT_PORT),
+            Endpoint::new(full_sink_handle, RECORDING_SINK_INPUT_PORT),
+        )
+        .unwrap();
+
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let projected_state = projected_recording.lock().materialize();
+        let full_state = full_recording.lock().materialize();
+        assert_eq!(projected_state.len(), count as usize);
+        assert_eq!(full_state.len(), count as usize);
+        for n in 1..count + 1 {
+            let record = ThreeFieldSourceFactory::record(n);
+            assert_eq!(full_state[&Field::UInt(n)], record);
+            let projected = Record::new(vec![record.values[2].clone(), record.values[0].clone()]);
+            assert_eq!(projected_state[&projected.values[0]], projected);
+        }
+    }
+}
@@ -3,15 +3,18 @@ use crate::checkpoint::serialize::{
     serialize_vec_u8, Cursor, DeserializationError, SerializationError,
 };
 use crate::executor_operation::ProcessorOperation;
+use crate::node::OutputPortType;
 use dozer_log::storage::Object;
 use dozer_recordstore::{
     ProcessorRecord, ProcessorRecordStore, ProcessorRecordStoreDeserializer, RecordStoreError,
     StoreRecord,
 };
+use dozer_types::parking_lot::RwLock;
 use dozer_types::thiserror::{self, Error};
-use dozer_types::types::Schema;
-use std::collections::HashMap;
+use dozer_types::types::{Record, Schema};
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
 
 #[derive(Debug, Error)]
 pub enum RecordWriterError {
@@ -32,6 +35,95 @@ pub trait RecordWriter: Send + Sync {
         record_store: &ProcessorRecordStore,
         object: Object,
     ) -> Result<(), SerializationError>;
+    /// Returns the latest record written with the encoded primary key `key`, unless it's been deleted.
+    fn lookup(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+    ) -> Result<Option<Record>, RecordStoreError>;
+    /// Returns the encoded primary key of `record`, extracted with the primary index of the writer's schema.
+    fn primary_key(&self, record: &Record) -> Vec<u8>;
+    /// Returns the writer's record history, if it keeps one.
+    fn as_record_reader(&self) -> Option<&dyn RecordReader> {
+        None
+    }
+}
+
+/// A record writer shared by the node that writes to it and the downstream processors that read from it.
+pub type SharedRecordWriter = Arc<RwLock<Box<dyn RecordWriter>>>;
+
+/// Looks up the records sent to a processor's input port from a stateful output port, by primary key.
+///
+/// Reflects every operation the upstream node has sent, which can include operations the processor hasn't received yet.
+#[derive(Debug, Clone)]
+pub struct InputRecordReader {
+    writer: SharedRecordWriter,
+}
+
+impl InputRecordReader {
+    pub(crate) fn new(writer: SharedRecordWriter) -> Self {
+        Self { writer }
+    }
+
+    /// Returns the latest record with the encoded primary key `key`, as returned by `Record::get_key`.
+    pub fn lookup(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+    ) -> Result<Option<Record>, RecordStoreError> {
+        self.writer.read().lookup(record_store, key)
+    }
+
+    /// Returns the latest record with the same primary key as `record`, which only needs the primary key fields set.
+    ///
+    /// The key is extracted with the primary index of the input port's schema.
+    pub fn lookup_record(
+        &self,
+        record_store: &ProcessorRecordStore,
+        record: &Record,
+    ) -> Result<Option<Record>, RecordStoreError> {
+        let writer = self.writer.read();
+        writer.lookup(record_store, &writer.primary_key(record))
+    }
+
+    /// Returns `version` of the record with the encoded primary key `key`, see [`RecordReader::get_version`].
+    ///
+    /// Always `None` unless the upstream port is `OutputPortType::StatefulWithVersionedLookup`.
+    pub fn get_version(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+        version: u64,
+    ) -> Result<Option<Record>, RecordStoreError> {
+        match self.writer.read().as_record_reader() {
+            Some(reader) => reader.get_version(record_store, key, version),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the newest version of the record with the encoded primary key `key`, see [`RecordReader::latest_version`].
+    ///
+    /// Always `None` unless the upstream port is `OutputPortType::StatefulWithVersionedLookup`.
+    pub fn latest_version(&self, key: &[u8]) -> Option<u64> {
+        self.writer.read().as_record_reader()?.latest_version(key)
+    }
+}
+
+/// Looks up historical versions of the records a [`RecordWriter`] has written, by primary key.
+///
+/// `key` is the encoded primary key, as returned by `Record::get_key`.
+pub trait RecordReader {
+    /// Returns `version` of the record with `key`, if it's still kept.
+    ///
+    /// A key's first version is 1, and every update adds a version.
+    fn get_version(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+        version: u64,
+    ) -> Result<Option<Record>, RecordStoreError>;
+    /// Returns the newest version of the record with `key`, if it exists.
+    fn latest_version(&self, key: &[u8]) -> Option<u64>;
 }
 
 impl Debug for dyn RecordWriter {
@@ -40,16 +132,23 @@ impl Debug for dyn RecordWriter {
     }
 }
 
+/// Creates the record writer of a stateful output port of type `port_type`.
 pub fn create_record_writer(
     schema: Schema,
+    port_type: OutputPortType,
     record_store: &ProcessorRecordStoreDeserializer,
     checkpoint_data: Option<Vec<u8>>,
 ) -> Result<Box<dyn RecordWriter>, DeserializationError> {
-    let writer = Box::new(PrimaryKeyLookupRecordWriter::new(
-        schema,
-        record_store,
-        checkpoint_data,
-    )?);
+    let writer: Box<dyn RecordWriter> = match port_type {
+        OutputPortType::StatefulWithVersionedLookup { max_versions } => Box::new(
+            VersionedRecordWriter::new(schema, max_versions, record_store, checkpoint_data)?,
+        ),
+        _ => Box::new(PrimaryKeyLookupRecordWriter::new(
+            schema,
+            record_store,
+            checkpoint_data,
+        )?),
+    };
     Ok(writer)
 }
 
@@ -139,4 +238,493 @@ impl RecordWriter for PrimaryKeyLookupRecordWriter {
         }
         Ok(())
     }
+
+    fn lookup(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+    ) -> Result<Option<Record>, RecordStoreError> {
+        self.index
+            .get(key)
+            .map(|record| record_store.load_record(record))
+            .transpose()
+    }
+
+    fn primary_key(&self, record: &Record) -> Vec<u8> {
+        record.get_key(&self.schema.primary_index)
+    }
+}
+
+/// Like [`PrimaryKeyLookupRecordWriter`], but keeps the last `max_versions` versions of every record for [`RecordReader`].
+///
+/// A deleted key's history is dropped, and inserting it again starts from version 1.
+#[derive(Debug)]
+pub(crate) struct VersionedRecordWriter {
+    schema: Schema,
+    max_versions: usize,
+    index: HashMap<Vec<u8>, RecordHistory>,
+}
+
+#[derive(Debug)]
+struct RecordHistory {
+    /// The version of `records[0]`.
+    first_version: u64,
+    /// Oldest first.
+    records: VecDeque<ProcessorRecord>,
+}
+
+impl RecordHistory {
+    fn latest_version(&self) -> u64 {
+        self.first_version + self.records.len() as u64 - 1
+    }
+}
+
+impl VersionedRecordWriter {
+    pub(crate) fn new(
+        schema: Schema,
+        max_versions: usize,
+        record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Self, DeserializationError> {
+        debug_assert!(
+            !schema.primary_index.is_empty(),
+            "VersionedRecordWriter can only be used with a schema that has a primary key."
+        );
+        debug_assert!(
+            max_versions > 0,
+            "At least one version must be kept, which building the DAG checks"
+        );
+
+        let mut index = HashMap::new();
+        if let Some(checkpoint_data) = checkpoint_data {
+            let mut cursor = Cursor::new(&checkpoint_data);
+            let len = deserialize_u64(&mut cursor)?;
+            for _ in 0..len {
+                let key = deserialize_vec_u8(&mut cursor)?.to_vec();
+                let first_version = deserialize_u64(&mut cursor)?;
+                let num_records = deserialize_u64(&mut cursor)?;
+                let mut records = VecDeque::with_capacity(num_records as usize);
+                for _ in 0..num_records {
+                    records.push_back(deserialize_record(&mut cursor, record_store)?);
+                }
+                index.insert(
+                    key,
+                    RecordHistory {
+                        first_version,
+                        records,
+                    },
+                );
+            }
+        }
+
+        Ok(Self {
+            schema,
+            max_versions,
+            index,
+        })
+    }
+
+    fn key(
+        &self,
+        record_store: &ProcessorRecordStore,
+        record: &ProcessorRecord,
+    ) -> Result<Vec<u8>, RecordStoreError> {
+        Ok(record_store
+            .load_record(record)?
+            .get_key(&self.schema.primary_index))
+    }
+
+    fn push_version(&mut self, key: Vec<u8>, record: ProcessorRecord) {
+        let history = self.index.entry(key).or_insert_with(|| RecordHistory {
+            first_version: 1,
+            records: VecDeque::new(),
+        });
+        history.records.push_back(record);
+        if history.records.len() > self.max_versions {
+            history.records.pop_front();
+            history.first_version += 1;
+        }
+    }
+
+    fn latest_record(&self, key: &[u8]) -> Result<ProcessorRecord, RecordWriterError> {
+        self.index
+            .get(key)
+            .and_then(|history| history.records.back().cloned())
+            .ok_or(RecordWriterError::RecordNotFound)
+    }
+}
+
+impl RecordWriter for VersionedRecordWriter {
+    fn write(
+        &mut self,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<ProcessorOperation, RecordWriterError> {
+        match op {
+            ProcessorOperation::Insert { new } => {
+                let new_key = self.key(record_store, &new)?;
+                self.index.remove(&new_key);
+                self.push_version(new_key, new.clone());
+                Ok(ProcessorOperation::Insert { new })
+            }
+            ProcessorOperation::Delete { old } => {
+                let old_key = self.key(record_store, &old)?;
+                let old = self.latest_record(&old_key)?;
+                self.index.remove(&old_key);
+                Ok(ProcessorOperation::Delete { old })
+            }
+            ProcessorOperation::Update { old, new } => {
+                let old_key = self.key(record_store, &old)?;
+                let old = self.latest_record(&old_key)?;
+                let new_key = self.key(record_store, &new)?;
+                if new_key != old_key {
+                    // The primary key changed, which is a delete and an insert.
+                    self.index.remove(&old_key);
+                    self.index.remove(&new_key);
+                }
+                self.push_version(new_key, new.clone());
+                Ok(ProcessorOperation::Update { old, new })
+            }
+        }
+    }
+
+    fn serialize(
+        &self,
+        record_store: &ProcessorRecordStore,
+        mut object: Object,
+    ) -> Result<(), SerializationError> {
+        serialize_u64(self.index.len() as u64, &mut object)?;
+        for (key, history) in &self.index {
+            serialize_vec_u8(key, &mut object)?;
+            serialize_u64(history.first_version, &mut object)?;
+            serialize_u64(history.records.len() as u64, &mut object)?;
+            for record in &history.records {
+                serialize_record(record, record_store, &mut object)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lookup(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+    ) -> Result<Option<Record>, RecordStoreError> {
+        self.index
+            .get(key)
+            .and_then(|history| history.records.back())
+            .map(|record| record_store.load_record(record))
+            .transpose()
+    }
+
+    fn primary_key(&self, record: &Record) -> Vec<u8> {
+        record.get_key(&self.schema.primary_index)
+    }
+
+    fn as_record_reader(&self) -> Option<&dyn RecordReader> {
+        Some(self)
+    }
+}
+
+impl RecordReader for VersionedRecordWriter {
+    fn get_version(
+        &self,
+        record_store: &ProcessorRecordStore,
+        key: &[u8],
+        version: u64,
+    ) -> Result<Option<Record>, RecordStoreError> {
+        let Some(history) = self.index.get(key) else {
+            return Ok(None);
+        };
+        let Some(offset) = version.checked_sub(history.first_version) else {
+            return Ok(None);
+        };
+        history
+            .records
+            .get(offset as usize)
+            .map(|record| record_store.load_record(record))
+            .transpose()
+    }
+
+    fn latest_version(&self, key: &[u8]) -> Option<u64> {
+        self.index.get(key).map(RecordHistory::latest_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::{Field, FieldDefinition, FieldType, SourceDefinition};
+
+    use crate::executor::ExecutorOptions;
+    use crate::tests::processors::{
+        ReaderCheck, RecordingProcessorFactory, RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+        RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+    };
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        DualPortGeneratorSourceFactory, GeneratorSourceFactory, OpMix,
+        DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_1, DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_2,
+        GENERATOR_SOURCE_OUTPUT_PORT,
+    };
+    use crate::{DagBuilder, DEFAULT_PORT_HANDLE};
+
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "id".to_string(),
+                    FieldType::UInt,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                true,
+            )
+            .field(
+                FieldDefinition::new(
+                    "value".to_string(),
+                    FieldType::String,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            )
+            .clone()
+    }
+
+    fn record(id: u64, value: &str) -> Record {
+        Record::new(vec![Field::UInt(id), Field::String(value.to_string())])
+    }
+
+    fn write(
+        writer: &mut VersionedRecordWriter,
+        record_store: &ProcessorRecordStore,
+        old: Option<Record>,
+        new: Record,
+    ) {
+        let new = record_store.create_record(&new).unwrap();
+        let op = match old {
+            Some(old) => ProcessorOperation::Update {
+                old: record_store.create_record(&old).unwrap(),
+                new,
+            },
+            None => ProcessorOperation::Insert { new },
+        };
+        writer.write(record_store, op).unwrap();
+    }
+
+    #[test]
+    fn input_record_reader_looks_up_by_schema_primary_key() {
+        let record_store = ProcessorRecordStoreDeserializer::new(Default::default()).unwrap();
+        let writer: SharedRecordWriter = Arc::new(RwLock::new(
+            create_record_writer(
+                schema(),
+                OutputPortType::StatefulWithPrimaryKeyLookup,
+                &record_store,
+                None,
+            )
+            .unwrap(),
+        ));
+        let record_store = record_store.into_record_store();
+        let reader = InputRecordReader::new(writer.clone());
+
+        for record in [record(1, "a"), record(2, "b")] {
+            let new = record_store.create_record(&record).unwrap();
+            writer
+                .write()
+                .write(&record_store, ProcessorOperation::Insert { new })
+                .unwrap();
+        }
+
+        // Only the primary key field is used for the lookup.
+        assert_eq!(
+            reader.lookup_record(&record_store, &record(2, "")).unwrap(),
+            Some(record(2, "b"))
+        );
+        assert_eq!(
+            reader
+                .lookup_record(&record_store, &record(3, "b"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn versioned_record_writer_keeps_versions() {
+        let record_store = ProcessorRecordStoreDeserializer::new(Default::default()).unwrap();
+        let mut writer = VersionedRecordWriter::new(schema(), 10, &record_store, None).unwrap();
+        let record_store = record_store.into_record_store();
+
+        let versions = [record(1, "a"), record(1, "b"), record(1, "c")];
+        write(&mut writer, &record_store, None, versions[0].clone());
+        write(
+            &mut writer,
+            &record_store,
+            Some(versions[0].clone()),
+            versions[1].clone(),
+        );
+        write(
+            &mut writer,
+            &record_store,
+            Some(versions[1].clone()),
+            versions[2].clone(),
+        );
+
+        let key = versions[0].get_key(&schema().primary_index);
+        assert_eq!(writer.latest_version(&key), Some(3));
+        for (version, expected) in (1..).zip(&versions) {
+            assert_eq!(
+                writer.get_version(&record_store, &key, version).unwrap(),
+                Some(expected.clone())
+            );
+        }
+        assert_eq!(writer.get_version(&record_store, &key, 0).unwrap(), None);
+        assert_eq!(writer.get_version(&record_store, &key, 4).unwrap(), None);
+
+        let other_key = record(2, "a").get_key(&schema().primary_index);
+        assert_eq!(writer.latest_version(&other_key), None);
+    }
+
+    #[test]
+    fn versioned_record_writer_bounds_history() {
+        let record_store = ProcessorRecordStoreDeserializer::new(Default::default()).unwrap();
+        let mut writer = VersionedRecordWriter::new(schema(), 2, &record_store, None).unwrap();
+        let record_store = record_store.into_record_store();
+
+        write(&mut writer, &record_store, None, record(1, "a"));
+        write(
+            &mut writer,
+            &record_store,
+            Some(record(1, "a")),
+            record(1, "b"),
+        );
+        write(
+            &mut writer,
+            &record_store,
+            Some(record(1, "b")),
+            record(1, "c"),
+        );
+
+        let key = record(1, "a").get_key(&schema().primary_index);
+        assert_eq!(writer.latest_version(&key), Some(3));
+        assert_eq!(writer.get_version(&record_store, &key, 1).unwrap(), None);
+        assert_eq!(
+            writer.get_version(&record_store, &key, 2).unwrap(),
+            Some(record(1, "b"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_processor_reads_other_input_port() {
+        let count: u64 = 1_000;
+        let latch = Arc::new(AtomicBool::new(true));
+        let processor = RecordingProcessorFactory::new()
+            .join()
+            .check_readers(ReaderCheck::LookupRightPort);
+        let recording = processor.recording();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        // The source sends every key on its first port before its second port,
+        // so the record has been written to the right port's reader by the time the processor receives it on the left port.
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                DualPortGeneratorSourceFactory::new(count, latch.clone(), true),
+            )
+            .processor(proc_handle.clone(), processor)
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().stop_after(count, latch),
+            )
+            .edge(
+                &source_handle,
+                DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_2,
+                &proc_handle,
+                RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+            )
+            .edge(
+                &source_handle,
+                DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_1,
+                &proc_handle,
+                RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            error_threshold: Some(0),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        assert_eq!(recording.lock().matched, count);
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_processor_reads_record_versions() {
+        let count: u64 = 1_000;
+        let latch = Arc::new(AtomicBool::new(true));
+        let op_mix = OpMix {
+            inserts: 1,
+            updates: 3,
+            deletes: 0,
+            key_space: 10,
+        };
+        let processor = RecordingProcessorFactory::new().check_readers(ReaderCheck::Versions);
+        let recording = processor.recording();
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+        // Keep every version, so none the processor reads has been dropped.
+        let source = GeneratorSourceFactory::new(count, latch.clone(), false)
+            .with_op_mix(op_mix)
+            .with_port_type(OutputPortType::StatefulWithVersionedLookup {
+                max_versions: count as usize,
+            });
+        let dag = DagBuilder::new()
+            .source(source_handle.clone(), source)
+            .processor(proc_handle.clone(), processor)
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().stop_after(count, latch),
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let options = ExecutorOptions {
+            error_threshold: Some(0),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        assert_eq!(recording.lock().matched, count);
+    }
 }
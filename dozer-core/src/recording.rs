@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use dozer_types::bincode;
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::models::ingestion_types::IngestionMessage;
+use dozer_types::parking_lot::Mutex;
+use dozer_types::serde::{Deserialize, Serialize};
+use dozer_types::thiserror::{self, Error};
+use dozer_types::types::Schema;
+
+use crate::channels::SourceChannelForwarder;
+use crate::errors::ExecutionError;
+use crate::executor_operation::{OperationContext, OperationTimestamps};
+use crate::node::{
+    OutputPortDef, OutputPortType, PortHandle, Progress, Source, SourceFactory, SourceMode,
+    SourceState,
+};
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("Cannot open operation log {0:?}: {1}")]
+    Open(PathBuf, #[source] std::io::Error),
+    #[error("Cannot write operation log: {0}")]
+    Write(#[source] bincode::Error),
+    #[error("Cannot read operation log: {0}")]
+    Read(#[source] bincode::Error),
+    #[error("Port {0} is not in the operation log")]
+    UnknownPort(PortHandle),
+}
+
+/// Written at the start of an operation log, so it can be replayed without the recorded source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+struct LogHeader {
+    backfill: bool,
+    ports: Vec<RecordedPort>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+struct RecordedPort {
+    handle: PortHandle,
+    name: String,
+    typ: OutputPortType,
+    schema: Schema,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "dozer_types::serde")]
+struct RecordedMessage {
+    port: PortHandle,
+    message: IngestionMessage,
+    event_time: Option<SystemTime>,
+}
+
+/// Wraps a source factory, journaling every message its sources send to the file at `path`.
+///
+/// The file is truncated when the source is built. Messages are buffered and flushed when the source stops.
+#[derive(Debug)]
+pub struct RecordingSourceFactory {
+    inner: Box<dyn SourceFactory>,
+    path: PathBuf,
+}
+
+impl RecordingSourceFactory {
+    pub fn new(inner: Box<dyn SourceFactory>, path: PathBuf) -> Self {
+        Self { inner, path }
+    }
+}
+
+impl SourceFactory for RecordingSourceFactory {
+    fn get_output_schema(&self, port: &PortHandle) -> Result<Schema, BoxedError> {
+        self.inner.get_output_schema(port)
+    }
+
+    fn get_output_port_name(&self, port: &PortHandle) -> String {
+        self.inner.get_output_port_name(port)
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        self.inner.get_output_ports()
+    }
+
+    fn build(
+        &self,
+        output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        let ports = self
+            .inner
+            .get_output_ports()
+            .into_iter()
+            .map(|port| {
+                Ok(RecordedPort {
+                    handle: port.handle,
+                    name: self.inner.get_output_port_name(&port.handle),
+                    typ: port.typ,
+                    schema: output_schemas
+                        .get(&port.handle)
+                        .ok_or(RecordingError::UnknownPort(port.handle))?
+                        .clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, RecordingError>>()?;
+        let inner = self.inner.build(output_schemas)?;
+        let header = LogHeader {
+            backfill: inner.mode() == SourceMode::Backfill,
+            ports,
+        };
+
+        let file =
+            File::create(&self.path).map_err(|e| RecordingError::Open(self.path.clone(), e))?;
+        let mut writer = BufWriter::new(file);
+        bincode::serialize_into(&mut writer, &header).map_err(RecordingError::Write)?;
+        Ok(Box::new(RecordingSource {
+            inner,
+            writer: Mutex::new(writer),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct RecordingSource {
+    inner: Box<dyn Source>,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl Source for RecordingSource {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        let mut writer = self.writer.lock();
+        let result = self.inner.start(
+            &mut RecordingForwarder {
+                inner: fw,
+                writer: &mut *writer,
+            },
+            last_checkpoint,
+        );
+        writer
+            .flush()
+            .map_err(|e| RecordingError::Write(Box::new(bincode::ErrorKind::Io(e))))?;
+        result
+    }
+
+    fn mode(&self) -> SourceMode {
+        self.inner.mode()
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        self.inner.progress()
+    }
+}
+
+struct RecordingForwarder<'a> {
+    inner: &'a mut dyn SourceChannelForwarder,
+    writer: &'a mut BufWriter<File>,
+}
+
+impl<'a> RecordingForwarder<'a> {
+    fn record(
+        &mut self,
+        message: &IngestionMessage,
+        port: PortHandle,
+        event_time: Option<SystemTime>,
+    ) -> Result<(), ExecutionError> {
+        let recorded = RecordedMessage {
+            port,
+            message: message.clone(),
+            event_time,
+        };
+        bincode::serialize_into(&mut *self.writer, &recorded)
+            .map_err(|e| ExecutionError::Source(Box::new(RecordingError::Write(e))))
+    }
+}
+
+impl<'a> SourceChannelForwarder for RecordingForwarder<'a> {
+    fn send(&mut self, message: IngestionMessage, port: PortHandle) -> Result<(), ExecutionError> {
+        self.record(&message, port, None)?;
+        self.inner.send(message, port)
+    }
+
+    fn send_with_context(
+        &mut self,
+        message: IngestionMessage,
+        port: PortHandle,
+        context: OperationContext,
+    ) -> Result<(), ExecutionError> {
+        // Headers aren't recorded, so replayed operations have none.
+        self.record(&message, port, context.timestamps.event_time)?;
+        self.inner.send_with_context(message, port, context)
+    }
+
+    fn send_watermark(
+        &mut self,
+        watermark: SystemTime,
+        port: PortHandle,
+    ) -> Result<(), ExecutionError> {
+        // Watermarks aren't recorded either, so a replay sends none.
+        self.inner.send_watermark(watermark, port)
+    }
+}
+
+/// Replays an operation log written by [`RecordingSourceFactory`], in the order the messages were sent.
+///
+/// Operations at or before the checkpoint the source starts from are skipped. A truncated last message is ignored.
+#[derive(Debug)]
+pub struct LogReplaySourceFactory {
+    path: PathBuf,
+    header: LogHeader,
+}
+
+impl LogReplaySourceFactory {
+    pub fn new(path: PathBuf) -> Result<Self, RecordingError> {
+        let (header, _) = open_log(&path)?;
+        Ok(Self { path, header })
+    }
+
+    fn port(&self, port: &PortHandle) -> Result<&RecordedPort, RecordingError> {
+        self.header
+            .ports
+            .iter()
+            .find(|recorded| recorded.handle == *port)
+            .ok_or(RecordingError::UnknownPort(*port))
+    }
+}
+
+fn open_log(path: &Path) -> Result<(LogHeader, BufReader<File>), RecordingError> {
+    let file = File::open(path).map_err(|e| RecordingError::Open(path.to_path_buf(), e))?;
+    let mut reader = BufReader::new(file);
+    let header = bincode::deserialize_from(&mut reader).map_err(RecordingError::Read)?;
+    Ok((header, reader))
+}
+
+impl SourceFactory for LogReplaySourceFactory {
+    fn get_output_schema(&self, port: &PortHandle) -> Result<Schema, BoxedError> {
+        Ok(self.port(port)?.schema.clone())
+    }
+
+    fn get_output_port_name(&self, port: &PortHandle) -> String {
+        self.port(port)
+            .map_or_else(|_| port.to_string(), |recorded| recorded.name.clone())
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        self.header
+            .ports
+            .iter()
+            .map(|recorded| OutputPortDef::new(recorded.handle, recorded.typ))
+            .collect()
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(LogReplaySource {
+            path: self.path.clone(),
+            backfill: self.header.backfill,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct LogReplaySource {
+    path: PathBuf,
+    backfill: bool,
+}
+
+impl Source for LogReplaySource {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        let (_, mut reader) = open_log(&self.path)?;
+        loop {
+            let recorded: RecordedMessage = match bincode::deserialize_from(&mut reader) {
+                Ok(recorded) => recorded,
+                Err(e) => match &*e {
+                    bincode::ErrorKind::Io(io) if io.kind() == ErrorKind::UnexpectedEof => break,
+                    _ => return Err(RecordingError::Read(e).into()),
+                },
+            };
+
+            if let IngestionMessage::OperationEvent { id: Some(id), .. } = &recorded.message {
+                if let Some(Some(checkpoint)) = last_checkpoint.get(&recorded.port) {
+                    if id <= checkpoint {
+                        continue;
+                    }
+                }
+            }
+
+            let context = OperationContext {
+                timestamps: OperationTimestamps {
+                    event_time: recorded.event_time,
+                    processing_time: None,
+                },
+                headers: Default::default(),
+            };
+            fw.send_with_context(recorded.message, recorded.port, context)?;
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> SourceMode {
+        if self.backfill {
+            SourceMode::Backfill
+        } else {
+            SourceMode::Streaming
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use dozer_log::tokio;
+    use tempdir::TempDir;
+
+    use crate::tests::run_materializing;
+    use crate::tests::sources::{GeneratorSourceFactory, OpMix};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_replays_recorded_log() {
+        let count: u64 = 1_000;
+        let op_mix = OpMix {
+            inserts: 3,
+            updates: 2,
+            deletes: 1,
+            key_space: 50,
+        };
+        let log_dir = TempDir::new("test_run_dag_replays_recorded_log").unwrap();
+        let log_path = log_dir.path().join("operations.log");
+
+        let latch = Arc::new(AtomicBool::new(true));
+        let recorded = run_materializing(
+            RecordingSourceFactory::new(
+                Box::new(
+                    GeneratorSourceFactory::new(count, latch.clone(), false).with_op_mix(op_mix),
+                ),
+                log_path.clone(),
+            ),
+            count,
+            latch,
+        )
+        .await;
+
+        // The replay source stops by itself once the log is exhausted.
+        let replayed = run_materializing(
+            LogReplaySourceFactory::new(log_path).unwrap(),
+            count,
+            Arc::new(AtomicBool::new(true)),
+        )
+        .await;
+
+        assert!(!recorded.is_empty());
+        assert_eq!(replayed, recorded);
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use dozer_storage::errors::StorageError;
+use dozer_storage::{LmdbVal, RocksdbMap};
+use dozer_types::borrow::{Borrow, IntoOwned};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::models::ingestion_types::IngestionMessage;
+use dozer_types::node::OpIdentifier;
+use dozer_types::types::{Operation, Record, Schema};
+
+use crate::channels::SourceChannelForwarder;
+use crate::node::{OutputPortDef, OutputPortType, PortHandle, Source, SourceFactory, SourceState};
+use crate::DEFAULT_PORT_HANDLE;
+
+/// Streams the entries of a [`RocksdbMap`] in key order, as inserts on `DEFAULT_PORT_HANDLE`.
+///
+/// Each entry is turned into a record by `to_record`, which must match `schema`.
+/// The source quits once it has sent every entry. An entry's position is its offset, so a run restarted
+/// from a checkpoint skips the entries already sent, as long as the map hasn't changed in between.
+#[derive(Debug)]
+pub struct RocksdbMapSourceFactory<K, V> {
+    map: Arc<RocksdbMap<K, V>>,
+    schema: Schema,
+    to_record: fn(K, V) -> Record,
+    range: Option<(K, K)>,
+}
+
+impl<K, V> RocksdbMapSourceFactory<K, V> {
+    pub fn new(map: Arc<RocksdbMap<K, V>>, schema: Schema, to_record: fn(K, V) -> Record) -> Self {
+        Self {
+            map,
+            schema,
+            to_record,
+            range: None,
+        }
+    }
+
+    /// Only sends the entries with keys from `start`, inclusive, to `end`, exclusive.
+    pub fn with_range(mut self, start: K, end: K) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+}
+
+impl<K, V> SourceFactory for RocksdbMapSourceFactory<K, V>
+where
+    K: LmdbVal + Clone + Debug + Send + Sync,
+    V: LmdbVal + Debug + Send + Sync,
+    for<'a> K::Borrowed<'a>: IntoOwned<K>,
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        Ok(self.schema.clone())
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "rocksdb_map".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            DEFAULT_PORT_HANDLE,
+            OutputPortType::Stateless,
+        )]
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(RocksdbMapSource {
+            map: self.map.clone(),
+            to_record: self.to_record,
+            range: self.range.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub struct RocksdbMapSource<K, V> {
+    map: Arc<RocksdbMap<K, V>>,
+    to_record: fn(K, V) -> Record,
+    range: Option<(K, K)>,
+}
+
+impl<K, V> RocksdbMapSource<K, V>
+where
+    K: LmdbVal + Sync,
+    V: LmdbVal + Sync,
+    for<'a> K::Borrowed<'a>: IntoOwned<K>,
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    #[allow(clippy::type_complexity)]
+    fn entries(
+        &self,
+    ) -> Result<Box<dyn Iterator<Item = Result<(K, V), StorageError>> + '_>, StorageError> {
+        Ok(match &self.range {
+            Some((start, end)) => Box::new(self.map.range(start.borrow(), end.borrow())?),
+            None => Box::new(self.map.iter()),
+        })
+    }
+}
+
+impl<K, V> Source for RocksdbMapSource<K, V>
+where
+    K: LmdbVal + Debug + Send + Sync,
+    V: LmdbVal + Debug + Send + Sync,
+    for<'a> K::Borrowed<'a>: IntoOwned<K>,
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        let checkpoint = last_checkpoint.get(&DEFAULT_PORT_HANDLE).copied().flatten();
+        for (position, entry) in self.entries()?.enumerate() {
+            let id = OpIdentifier::new(position as u64, 0);
+            if checkpoint.map_or(false, |checkpoint| id <= checkpoint) {
+                continue;
+            }
+            let (key, value) = entry?;
+            fw.send(
+                IngestionMessage::OperationEvent {
+                    table_index: 0,
+                    op: Operation::Insert {
+                        new: (self.to_record)(key, value),
+                    },
+                    id: Some(id),
+                },
+                DEFAULT_PORT_HANDLE,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use dozer_log::tokio;
+    use dozer_storage::{KeyOrder, RocksdbMapOptions};
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::Field;
+    use tempdir::TempDir;
+
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{
+        generated_value, GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT,
+    };
+    use crate::DagBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_from_rocksdb_map() {
+        let map_dir = TempDir::new("test_run_dag_from_rocksdb_map").unwrap();
+        let map = RocksdbMap::<u64, String>::create_with_options(
+            map_dir.path(),
+            Default::default(),
+            RocksdbMapOptions {
+                key_order: KeyOrder::UnsignedInteger,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let map = Arc::new(map);
+        for n in 0..100 {
+            map.insert(&n, &generated_value(n, 10)).unwrap();
+        }
+        let schema = GeneratorSourceFactory::new(0, Arc::new(AtomicBool::new(true)), false)
+            .get_output_schema(&GENERATOR_SOURCE_OUTPUT_PORT)
+            .unwrap();
+        let to_record = |key: u64, value: String| {
+            Record::new(vec![
+                Field::String(format!("key_{key}")),
+                Field::String(value),
+            ])
+        };
+
+        for (factory, expected) in [
+            (
+                RocksdbMapSourceFactory::new(map.clone(), schema.clone(), to_record),
+                100,
+            ),
+            (
+                RocksdbMapSourceFactory::new(map.clone(), schema.clone(), to_record)
+                    .with_range(10, 60),
+                50,
+            ),
+        ] {
+            let sink = RecordingSinkFactory::new();
+            let received = sink.received();
+            let source_handle = NodeHandle::new(None, "source".to_string());
+            let sink_handle = NodeHandle::new(None, "sink".to_string());
+            let dag = DagBuilder::new()
+                .source(source_handle.clone(), factory)
+                .sink(sink_handle.clone(), sink)
+                .edge(
+                    &source_handle,
+                    DEFAULT_PORT_HANDLE,
+                    &sink_handle,
+                    RECORDING_SINK_INPUT_PORT,
+                )
+                .build()
+                .unwrap();
+            run_dag(dag, Default::default()).await.unwrap();
+
+            assert_eq!(received.load(Ordering::SeqCst), expected);
+        }
+    }
+}
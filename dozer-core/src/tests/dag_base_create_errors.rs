@@ -1,4 +1,5 @@
 use crate::checkpoint::create_checkpoint_for_test;
+use crate::errors::ExecutionError;
 use crate::executor::DagExecutor;
 use crate::node::{
     OutputPortDef, OutputPortType, PortHandle, Processor, ProcessorFactory, Source, SourceFactory,
@@ -314,3 +315,26 @@ async fn test_create_proc_panic() {
         .join()
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_dag_without_source_is_rejected() {
+    let latch = Arc::new(AtomicBool::new(true));
+    let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+    let mut dag = Dag::new();
+    dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+    dag.add_sink(
+        sink_handle.clone(),
+        Box::new(CountingSinkFactory::new(1, latch)),
+    );
+    dag.connect(
+        Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle, COUNTING_SINK_INPUT_PORT),
+    )
+    .unwrap();
+
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    let result = DagExecutor::new(dag, checkpoint, Default::default()).await;
+    assert!(matches!(result, Err(ExecutionError::NoSourceNode)));
+}
@@ -1,17 +1,22 @@
 use crate::channels::ProcessorChannelForwarder;
-use crate::checkpoint::create_checkpoint_for_test;
-use crate::epoch::Epoch;
-use crate::executor::DagExecutor;
+use crate::checkpoint::{create_checkpoint_for_test, OptionCheckpoint};
+use crate::epoch::{Epoch, EpochManagerOptions};
+use crate::errors::ExecutionError;
+use crate::executor::{DagExecutor, ExecutorOptions};
 use crate::executor_operation::ProcessorOperation;
-use crate::node::{PortHandle, Processor, ProcessorFactory};
-use crate::tests::sinks::{CountingSinkFactory, COUNTING_SINK_INPUT_PORT};
+use crate::node::{PortHandle, Processor, ProcessorFactory, StateBackend};
+use crate::tests::processors::{RecordingProcessorFactory, StateCountingProcessorFactory};
+use crate::tests::sinks::{
+    CountingSinkFactory, RecordingSinkFactory, COUNTING_SINK_INPUT_PORT, RECORDING_SINK_INPUT_PORT,
+};
 use crate::tests::sources::{
-    DualPortGeneratorSourceFactory, GeneratorSourceFactory,
+    generated_value, DualPortGeneratorSourceFactory, GeneratorSourceFactory, OpGenerator, OpMix,
     DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_1, DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_2,
     GENERATOR_SOURCE_OUTPUT_PORT,
 };
-use crate::{Dag, Endpoint, DEFAULT_PORT_HANDLE};
-use dozer_log::storage::Object;
+use crate::tests::{checkpoint_every_commit, dir_entries, generator_to_state_counting_dag};
+use crate::{Dag, DagBuilder, Endpoint, DEFAULT_PORT_HANDLE};
+use dozer_log::storage::{InMemoryStorage, Object};
 use dozer_log::tokio;
 use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
 use dozer_types::errors::internal::BoxedError;
@@ -19,10 +24,11 @@ use dozer_types::node::NodeHandle;
 use dozer_types::types::Schema;
 
 use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tempdir::TempDir;
 
 #[derive(Debug)]
 pub(crate) struct NoopProcessorFactory {}
@@ -420,3 +426,584 @@ async fn test_run_dag_1_source_2_ports_stateless() {
         .join()
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_run_dag_wait_for_sink_count() {
+    let count: u64 = 100_000;
+    let latch = Arc::new(AtomicBool::new(true));
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+    let dag = DagBuilder::new()
+        .source(
+            source_handle.clone(),
+            GeneratorSourceFactory::new(count, latch.clone(), false),
+        )
+        .sink(sink_handle.clone(), CountingSinkFactory::new(count, latch))
+        .edge(
+            &source_handle,
+            GENERATOR_SOURCE_OUTPUT_PORT,
+            &sink_handle,
+            COUNTING_SINK_INPUT_PORT,
+        )
+        .build()
+        .unwrap();
+
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    let join_handle = DagExecutor::new(dag, checkpoint, Default::default())
+        .await
+        .unwrap()
+        .start(Arc::new(AtomicBool::new(true)), Default::default())
+        .await
+        .unwrap();
+
+    let timeout = Duration::from_secs(60);
+    let started = Instant::now();
+    join_handle
+        .wait_for_sink_count(&sink_handle, count, timeout)
+        .unwrap();
+    assert!(started.elapsed() < timeout);
+
+    // The source sends no more.
+    assert!(matches!(
+        join_handle.wait_for_sink_count(&sink_handle, count + 1, Duration::from_millis(100)),
+        Err(ExecutionError::SinkCountNotReached { received, .. }) if received == count
+    ));
+    assert!(matches!(
+        join_handle.wait_for_sink_count(&source_handle, 0, timeout),
+        Err(ExecutionError::NodeNotFound(_))
+    ));
+    join_handle.join().unwrap();
+}
+
+#[tokio::test]
+async fn test_run_dag_join_timeout() {
+    let count: u64 = 1_000_000;
+
+    let mut dag = Dag::new();
+    let latch = Arc::new(AtomicBool::new(true));
+    let sink = RecordingSinkFactory::new().stop_after(count, latch.clone());
+    let received = sink.received();
+
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+    dag.add_source(
+        source_handle.clone(),
+        Box::new(GeneratorSourceFactory::new(count, latch, false)),
+    );
+    dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+    dag.add_sink(sink_handle.clone(), Box::new(sink));
+
+    dag.connect(
+        Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+        Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+    )
+    .unwrap();
+
+    dag.connect(
+        Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+    )
+    .unwrap();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    let mut join_handle = DagExecutor::new(dag, checkpoint, Default::default())
+        .await
+        .unwrap()
+        .start(running.clone(), Default::default())
+        .await
+        .unwrap();
+
+    assert!(join_handle
+        .join_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+    // The pipeline is left running.
+    let before = received.load(Ordering::SeqCst);
+    assert!(join_handle
+        .join_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+    assert!(received.load(Ordering::SeqCst) > before);
+
+    running.store(false, Ordering::SeqCst);
+    assert_eq!(
+        join_handle.join_timeout(Duration::from_secs(60)).unwrap(),
+        Some(())
+    );
+}
+
+#[tokio::test]
+async fn test_run_dag_abort() {
+    // Far more than can be processed before the abort.
+    let count: u64 = 100_000_000;
+
+    let mut dag = Dag::new();
+    let latch = Arc::new(AtomicBool::new(true));
+    let sink = RecordingSinkFactory::new();
+    // Shared with the sink only, so the sink thread has quit once this is the last reference.
+    let received = sink.received();
+
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+    dag.add_source(
+        source_handle.clone(),
+        Box::new(GeneratorSourceFactory::new(count, latch.clone(), false)),
+    );
+    dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+    dag.add_sink(sink_handle.clone(), Box::new(sink));
+
+    dag.connect(
+        Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+        Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+    )
+    .unwrap();
+    dag.connect(
+        Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle.clone(), RECORDING_SINK_INPUT_PORT),
+    )
+    .unwrap();
+
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    let join_handle = DagExecutor::new(dag, checkpoint, Default::default())
+        .await
+        .unwrap()
+        .start(Arc::new(AtomicBool::new(true)), Default::default())
+        .await
+        .unwrap();
+    // Abort once operations are flowing all the way to the sink.
+    join_handle
+        .wait_for_sink_count(&sink_handle, 1, Duration::from_secs(60))
+        .unwrap();
+    let abort_handle = join_handle.abort_handle();
+    let join = thread::spawn(move || join_handle.join());
+
+    let aborted_at = Instant::now();
+    abort_handle.abort();
+    let result = join.join().unwrap();
+    assert!(matches!(result, Err(ExecutionError::Aborted)));
+    assert!(aborted_at.elapsed() < Duration::from_secs(1));
+    // The sink thread was joined, so it processes no more.
+    assert_eq!(Arc::strong_count(&received), 1);
+    assert!(received.load(Ordering::SeqCst) < count);
+
+    // Let the source thread quit.
+    latch.store(false, Ordering::SeqCst);
+}
+
+#[tokio::test]
+async fn test_run_dag_op_mix_materializes() {
+    let count: u64 = 1_000;
+    let op_mix = OpMix {
+        inserts: 3,
+        updates: 2,
+        deletes: 1,
+        key_space: 50,
+    };
+
+    let mut dag = Dag::new();
+    let latch = Arc::new(AtomicBool::new(true));
+    let sink = RecordingSinkFactory::new()
+        .record_ops()
+        .stop_after(count, latch.clone());
+    let recording = sink.recording();
+
+    let source_handle = NodeHandle::new(Some(1), 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+    dag.add_source(
+        source_handle.clone(),
+        Box::new(GeneratorSourceFactory::new(count, latch, false).with_op_mix(op_mix)),
+    );
+    dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+    dag.add_sink(sink_handle.clone(), Box::new(sink));
+
+    dag.connect(
+        Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+        Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+    )
+    .unwrap();
+
+    dag.connect(
+        Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle, RECORDING_SINK_INPUT_PORT),
+    )
+    .unwrap();
+
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    DagExecutor::new(dag, checkpoint, Default::default())
+        .await
+        .unwrap()
+        .start(Arc::new(AtomicBool::new(true)), Default::default())
+        .await
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let mut generator = OpGenerator::new(op_mix);
+    for n in 1..count + 1 {
+        generator.next_op(n);
+    }
+    let mut expected = generator.live().cloned().collect::<Vec<_>>();
+    let mut actual = recording
+        .lock()
+        .materialize()
+        .into_values()
+        .collect::<Vec<_>>();
+    expected.sort_by(|a, b| a.values.cmp(&b.values));
+    actual.sort_by(|a, b| a.values.cmp(&b.values));
+    assert!(!expected.is_empty());
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test]
+async fn test_run_dag_with_mixed_state_backends() {
+    let count: u64 = 1_000;
+    let state_dir = TempDir::new("test_run_dag_with_mixed_state_backends").unwrap();
+
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let memory_handle = NodeHandle::new(Some(1), 2.to_string());
+    let rocksdb_handle = NodeHandle::new(Some(1), 3.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 4.to_string());
+    let (checkpoint_temp_dir, _) = create_checkpoint_for_test().await;
+    let checkpoint_dir = checkpoint_temp_dir.path().to_str().unwrap().to_string();
+
+    for run in 1..3 {
+        let latch = Arc::new(AtomicBool::new(true));
+        let memory = StateCountingProcessorFactory::new(StateBackend::Memory);
+        let memory_count = memory.count();
+        let rocksdb = StateCountingProcessorFactory::new(StateBackend::RocksDb);
+        let rocksdb_count = rocksdb.count();
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .processor(memory_handle.clone(), memory)
+            .processor(rocksdb_handle.clone(), rocksdb)
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().stop_after(count, latch),
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &memory_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &memory_handle,
+                DEFAULT_PORT_HANDLE,
+                &rocksdb_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &rocksdb_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let checkpoint = OptionCheckpoint::new(checkpoint_dir.clone(), Default::default())
+            .await
+            .unwrap();
+        DagExecutor::new(dag, checkpoint, checkpoint_every_commit(&state_dir))
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap()
+            .join()
+            .unwrap();
+
+        // Memory state starts over on every run, while RocksDB state is recovered from `state_dir`.
+        assert_eq!(memory_count.load(Ordering::SeqCst), count);
+        assert_eq!(rocksdb_count.load(Ordering::SeqCst), count * run);
+    }
+    assert!(state_dir.path().join(rocksdb_handle.to_string()).exists());
+    assert!(!state_dir.path().join(memory_handle.to_string()).exists());
+}
+
+#[tokio::test]
+async fn test_run_dag_processor_state_follows_checkpoint() {
+    let count: u64 = 1_000;
+    let state_dir = TempDir::new("test_run_dag_processor_state_follows_checkpoint").unwrap();
+    let (temp_dir, _) = create_checkpoint_for_test().await;
+    let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+    let open = || OptionCheckpoint::new(checkpoint_dir.clone(), Default::default());
+    let run = |executor: DagExecutor| async move {
+        executor
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap()
+            .join()
+            .unwrap();
+    };
+
+    let (dag, _) = generator_to_state_counting_dag(count);
+    run(DagExecutor::new(
+        dag,
+        open().await.unwrap(),
+        checkpoint_every_commit(&state_dir),
+    )
+    .await
+    .unwrap())
+    .await;
+    let checkpoint_epoch = open().await.unwrap().epoch_id().unwrap();
+
+    // Commit another run's operations to the state without checkpointing them.
+    let options = ExecutorOptions {
+        state_dir: Some(state_dir.path().to_path_buf()),
+        ..Default::default()
+    };
+    let (dag, state_count) = generator_to_state_counting_dag(count);
+    run(DagExecutor::new(dag, open().await.unwrap(), options)
+        .await
+        .unwrap())
+    .await;
+    assert_eq!(state_count.load(Ordering::SeqCst), count * 2);
+    assert_eq!(open().await.unwrap().epoch_id(), Some(checkpoint_epoch));
+
+    // Sources replay those operations, onto the state restored to the checkpoint.
+    let (dag, state_count) = generator_to_state_counting_dag(count);
+    run(DagExecutor::new(
+        dag,
+        open().await.unwrap(),
+        checkpoint_every_commit(&state_dir),
+    )
+    .await
+    .unwrap())
+    .await;
+    assert_eq!(state_count.load(Ordering::SeqCst), count * 2);
+
+    // Without a checkpoint to resume from, the state starts over.
+    let (_fresh_temp_dir, fresh_checkpoint) = create_checkpoint_for_test().await;
+    let (dag, state_count) = generator_to_state_counting_dag(count);
+    run(
+        DagExecutor::new(dag, fresh_checkpoint, checkpoint_every_commit(&state_dir))
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(state_count.load(Ordering::SeqCst), count);
+}
+
+#[tokio::test]
+async fn test_validate_build_reports_failing_init() {
+    let sent = Arc::new(AtomicU64::new(0));
+    let sink = RecordingSinkFactory::new();
+    let received = sink.received();
+
+    let source_handle = NodeHandle::new(None, "source".to_string());
+    let proc_handle = NodeHandle::new(None, "proc".to_string());
+    let sink_handle = NodeHandle::new(None, "sink".to_string());
+    let dag = DagBuilder::new()
+        .source(
+            source_handle.clone(),
+            GeneratorSourceFactory::new(100, Arc::new(AtomicBool::new(false)), false)
+                .with_sent_counter(sent.clone()),
+        )
+        .processor(
+            proc_handle.clone(),
+            RecordingProcessorFactory::new().fail_init(),
+        )
+        .sink(sink_handle.clone(), sink)
+        .edge(
+            &source_handle,
+            GENERATOR_SOURCE_OUTPUT_PORT,
+            &proc_handle,
+            DEFAULT_PORT_HANDLE,
+        )
+        .edge(
+            &proc_handle,
+            DEFAULT_PORT_HANDLE,
+            &sink_handle,
+            RECORDING_SINK_INPUT_PORT,
+        )
+        .build()
+        .unwrap();
+
+    let temp_dir = TempDir::new("test_validate_build").unwrap();
+    let checkpoint_dir = temp_dir.path().to_str().unwrap().to_string();
+    let error = DagExecutor::validate_build(dag, checkpoint_dir, Default::default())
+        .await
+        .unwrap_err();
+
+    let ExecutionError::NodeInit { node, error } = error else {
+        panic!("Unexpected error {error}");
+    };
+    assert_eq!(node, proc_handle);
+    assert_eq!(error.to_string(), "missing resource");
+    assert_eq!(sent.load(Ordering::SeqCst), 0);
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn test_run_dag_without_checkpointing() {
+    async fn run(checkpointing: bool) -> (TempDir, u64) {
+        let count: u64 = 1_000;
+        let checkpoint_dir = TempDir::new("test_run_dag_without_checkpointing").unwrap();
+        let latch = Arc::new(AtomicBool::new(true));
+        let sink = RecordingSinkFactory::new().stop_after(count, latch.clone());
+        let received = sink.received();
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch, false),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        let checkpoint = OptionCheckpoint::new(
+            checkpoint_dir.path().to_str().unwrap().to_string(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        let options = ExecutorOptions {
+            commit_sz: 10,
+            epoch_manager_options: EpochManagerOptions {
+                max_num_records_before_persist: 10,
+                enable_app_checkpoints: true,
+                ..Default::default()
+            },
+            checkpointing,
+            ..Default::default()
+        };
+        DagExecutor::new(dag, checkpoint, options)
+            .await
+            .unwrap()
+            .start(Arc::new(AtomicBool::new(true)), Default::default())
+            .await
+            .unwrap()
+            .join()
+            .unwrap();
+        (checkpoint_dir, received.load(Ordering::SeqCst))
+    }
+
+    let (checkpoint_dir, received) = run(true).await;
+    assert_eq!(received, 1_000);
+    assert!(!dir_entries(checkpoint_dir.path()).is_empty());
+
+    let (checkpoint_dir, received) = run(false).await;
+    assert_eq!(received, 1_000);
+    assert!(dir_entries(checkpoint_dir.path()).is_empty());
+}
+
+#[tokio::test]
+async fn test_run_dag_restores_exported_node_state() {
+    let count: u64 = 50;
+    let options = || ExecutorOptions {
+        commit_sz: 10,
+        commit_time_threshold: Duration::from_secs(3600),
+        epoch_manager_options: EpochManagerOptions {
+            max_num_records_before_persist: 10,
+            enable_app_checkpoints: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+    // Returns the recording of the indexing processor, whose index is the processor's state.
+    let dag = || {
+        let latch = Arc::new(AtomicBool::new(true));
+        let processor = RecordingProcessorFactory::new().with_index();
+        let recording = processor.recording();
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, latch.clone(), false),
+            )
+            .processor(proc_handle.clone(), processor)
+            .sink(
+                sink_handle.clone(),
+                RecordingSinkFactory::new().stop_after(count, latch),
+            )
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+            )
+            .edge(
+                &proc_handle,
+                DEFAULT_PORT_HANDLE,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        (dag, recording)
+    };
+    let storage = InMemoryStorage::new();
+    let open = || {
+        OptionCheckpoint::with_storage(Box::new(storage.clone()), String::new(), Default::default())
+    };
+
+    let (indexing_dag, recording) = dag();
+    DagExecutor::new(indexing_dag, open().await.unwrap(), options())
+        .await
+        .unwrap()
+        .start(Arc::new(AtomicBool::new(true)), Default::default())
+        .await
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(recording.lock().index.len(), count as usize);
+
+    let executor = DagExecutor::new(dag().0, open().await.unwrap(), options())
+        .await
+        .unwrap();
+    let mut exported = vec![];
+    executor
+        .export_node_state(&proc_handle, &mut exported)
+        .await
+        .unwrap();
+    assert!(matches!(
+        executor.export_node_state(&sink_handle, &mut vec![]).await,
+        Err(ExecutionError::NodeNotFound(_))
+    ));
+
+    // Without a checkpoint the processor starts empty.
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    let (empty_dag, recording) = dag();
+    let executor = DagExecutor::new(empty_dag, checkpoint, options())
+        .await
+        .unwrap();
+    assert!(matches!(
+        executor.export_node_state(&proc_handle, &mut vec![]).await,
+        Err(ExecutionError::NodeStateNotFound(_))
+    ));
+    assert_eq!(recording.lock().index.get("key_7"), None);
+
+    // Restoring rebuilds the processor from the exported state.
+    let executor = executor
+        .restore_node_state(&proc_handle, &mut exported.as_slice())
+        .unwrap();
+    let restored = recording.lock();
+    assert_eq!(restored.index.len(), count as usize);
+    assert_eq!(restored.index.get("key_7"), Some(&generated_value(7, 0)));
+    drop(restored);
+    assert!(matches!(
+        executor.restore_node_state(&sink_handle, &mut exported.as_slice()),
+        Err(ExecutionError::NodeNotFound(_))
+    ));
+}
@@ -1,15 +1,23 @@
 use crate::dag_schemas::{DagHaveSchemas, DagSchemas};
+use crate::errors::ExecutionError;
 use crate::node::{
     OutputPortDef, OutputPortType, PortHandle, Processor, ProcessorFactory, SinkFactory, Source,
     SourceFactory,
 };
+use crate::tests::dag_base_run::NoopProcessorFactory;
+use crate::tests::sinks::{CountingSinkFactory, COUNTING_SINK_INPUT_PORT};
+use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
 use crate::{Dag, Endpoint, DEFAULT_PORT_HANDLE};
 
 use dozer_recordstore::ProcessorRecordStoreDeserializer;
+use dozer_storage::errors::StorageError;
 use dozer_types::errors::internal::BoxedError;
 use dozer_types::node::NodeHandle;
+use dozer_types::serde_json::{json, Value};
 use dozer_types::types::{FieldDefinition, FieldType, Schema, SourceDefinition};
 use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 macro_rules! chk {
     ($stmt:expr) => {
@@ -118,6 +126,44 @@ impl SourceFactory for TestCountriesSourceFactory {
     }
 }
 
+/// A stateful source whose schema has no primary key.
+#[derive(Debug)]
+struct TestKeylessSourceFactory {}
+
+impl SourceFactory for TestKeylessSourceFactory {
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        Ok(Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "message".to_string(),
+                    FieldType::String,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            )
+            .clone())
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "messages".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            DEFAULT_PORT_HANDLE,
+            OutputPortType::StatefulWithPrimaryKeyLookup,
+        )]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        todo!()
+    }
+}
+
 #[derive(Debug)]
 struct TestJoinProcessorFactory {}
 
@@ -163,6 +209,47 @@ impl ProcessorFactory for TestJoinProcessorFactory {
     }
 }
 
+/// Outputs the input field "x", which the users source doesn't have.
+#[derive(Debug)]
+struct TestFieldXProcessorFactory {}
+
+impl ProcessorFactory for TestFieldXProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        let (_, field) = input_schemas[&DEFAULT_PORT_HANDLE].get_field_index("x")?;
+        Ok(Schema::default().field(field.clone(), false).clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        todo!()
+    }
+
+    fn type_name(&self) -> String {
+        "TestFieldX".to_owned()
+    }
+
+    fn id(&self) -> String {
+        "TestFieldX".to_owned()
+    }
+}
+
 #[derive(Debug)]
 struct TestSinkFactory {}
 
@@ -247,3 +334,215 @@ fn test_extract_dag_schemas() {
         5
     );
 }
+
+#[test]
+fn test_describe_dag() {
+    let mut dag = Dag::new();
+    let latch = Arc::new(AtomicBool::new(true));
+
+    let source_handle = NodeHandle::new(Some(1), 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+    dag.add_source(
+        source_handle.clone(),
+        Box::new(GeneratorSourceFactory::new(1, latch.clone(), false)),
+    );
+    dag.add_processor(proc_handle.clone(), Box::new(NoopProcessorFactory {}));
+    dag.add_sink(
+        sink_handle.clone(),
+        Box::new(CountingSinkFactory::new(1, latch)),
+    );
+
+    chk!(dag.connect(
+        Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+        Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+    ));
+    chk!(dag.connect(
+        Endpoint::new(proc_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle, COUNTING_SINK_INPUT_PORT),
+    ));
+
+    let description = chk!(dag.describe());
+    let nodes = description["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 3);
+
+    let ports = |node: &Value, key: &str| {
+        node[key]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|port| port["port"].clone())
+            .collect::<Vec<_>>()
+    };
+    let field_names = |port: &Value| {
+        port["schema"]["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].clone())
+            .collect::<Vec<_>>()
+    };
+    let expected_field_names = vec![json!("id"), json!("value")];
+
+    let source = &nodes[0];
+    assert_eq!(source["handle"], json!({ "ns": 1, "id": "1" }));
+    assert_eq!(source["type"], "source");
+    assert!(ports(source, "input_ports").is_empty());
+    assert_eq!(
+        ports(source, "output_ports"),
+        vec![json!(GENERATOR_SOURCE_OUTPUT_PORT)]
+    );
+    assert_eq!(
+        field_names(&source["output_ports"][0]),
+        expected_field_names
+    );
+
+    let processor = &nodes[1];
+    assert_eq!(processor["type"], "processor");
+    assert_eq!(
+        ports(processor, "input_ports"),
+        vec![json!(DEFAULT_PORT_HANDLE)]
+    );
+    assert_eq!(
+        ports(processor, "output_ports"),
+        vec![json!(DEFAULT_PORT_HANDLE)]
+    );
+    assert_eq!(
+        field_names(&processor["input_ports"][0]),
+        expected_field_names
+    );
+    assert_eq!(
+        field_names(&processor["output_ports"][0]),
+        expected_field_names
+    );
+
+    let sink = &nodes[2];
+    assert_eq!(sink["type"], "sink");
+    assert_eq!(
+        ports(sink, "input_ports"),
+        vec![json!(COUNTING_SINK_INPUT_PORT)]
+    );
+    assert!(ports(sink, "output_ports").is_empty());
+    assert_eq!(field_names(&sink["input_ports"][0]), expected_field_names);
+}
+
+#[test]
+fn test_validate_reports_unreachable_node() {
+    let mut dag = Dag::new();
+    let latch = Arc::new(AtomicBool::new(true));
+
+    let source_handle = NodeHandle::new(Some(1), 1.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+    let isolated_handle = NodeHandle::new(Some(1), 3.to_string());
+
+    dag.add_source(
+        source_handle.clone(),
+        Box::new(GeneratorSourceFactory::new(1, latch.clone(), false)),
+    );
+    dag.add_sink(
+        sink_handle.clone(),
+        Box::new(CountingSinkFactory::new(1, latch)),
+    );
+    chk!(dag.connect(
+        Endpoint::new(source_handle, GENERATOR_SOURCE_OUTPUT_PORT),
+        Endpoint::new(sink_handle, COUNTING_SINK_INPUT_PORT),
+    ));
+    chk!(dag.validate());
+
+    dag.add_processor(isolated_handle.clone(), Box::new(NoopProcessorFactory {}));
+    assert!(matches!(
+        dag.validate(),
+        Err(ExecutionError::UnreachableNode { node }) if node == isolated_handle
+    ));
+    assert!(matches!(
+        DagSchemas::new(dag),
+        Err(ExecutionError::UnreachableNode { .. })
+    ));
+}
+
+#[test]
+fn test_extract_dag_schemas_reports_missing_field() {
+    let mut dag = Dag::new();
+
+    let users_handle = NodeHandle::new(Some(1), 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+    dag.add_source(users_handle.clone(), Box::new(TestUsersSourceFactory {}));
+    dag.add_processor(proc_handle.clone(), Box::new(TestFieldXProcessorFactory {}));
+    dag.add_sink(sink_handle.clone(), Box::new(TestSinkFactory {}));
+
+    chk!(dag.connect(
+        Endpoint::new(users_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+    ));
+    chk!(dag.connect(
+        Endpoint::new(proc_handle.clone(), DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle, DEFAULT_PORT_HANDLE),
+    ));
+
+    assert!(matches!(
+        DagSchemas::new(dag),
+        Err(ExecutionError::MissingField { node, field }) if node == proc_handle && field == "x"
+    ));
+}
+
+#[test]
+fn test_source_output_schema_without_building() {
+    let source = GeneratorSourceFactory::new(1, Arc::new(AtomicBool::new(false)), false);
+    let schema = chk!(source.get_output_schema(&GENERATOR_SOURCE_OUTPUT_PORT));
+    let fields = schema
+        .fields
+        .iter()
+        .map(|field| (field.name.as_str(), field.typ))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![("id", FieldType::String), ("value", FieldType::String)]
+    );
+    assert_eq!(schema.primary_index, vec![0]);
+}
+
+#[test]
+fn test_extract_dag_schemas_requires_primary_key_on_stateful_port() {
+    let mut dag = Dag::new();
+
+    let source_handle = NodeHandle::new(Some(1), 1.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+    dag.add_source(source_handle.clone(), Box::new(TestKeylessSourceFactory {}));
+    dag.add_sink(sink_handle.clone(), Box::new(TestSinkFactory {}));
+    chk!(dag.connect(
+        Endpoint::new(source_handle, DEFAULT_PORT_HANDLE),
+        Endpoint::new(sink_handle, DEFAULT_PORT_HANDLE),
+    ));
+
+    assert!(matches!(
+        DagSchemas::new(dag),
+        Err(ExecutionError::Storage(StorageError::NoPrimaryKey))
+    ));
+}
+
+#[test]
+fn test_extract_dag_schemas_requires_a_version_on_versioned_port() {
+    let mut dag = Dag::new();
+
+    let source_handle = NodeHandle::new(Some(1), 1.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+
+    let source = GeneratorSourceFactory::new(1, Arc::new(AtomicBool::new(false)), false)
+        .with_port_type(OutputPortType::StatefulWithVersionedLookup { max_versions: 0 });
+    dag.add_source(source_handle.clone(), Box::new(source));
+    dag.add_sink(sink_handle.clone(), Box::new(TestSinkFactory {}));
+    chk!(dag.connect(
+        Endpoint::new(source_handle.clone(), GENERATOR_SOURCE_OUTPUT_PORT),
+        Endpoint::new(sink_handle, DEFAULT_PORT_HANDLE),
+    ));
+
+    assert!(matches!(
+        DagSchemas::new(dag),
+        Err(ExecutionError::NoVersionsKept { node, port })
+            if node == source_handle && port == GENERATOR_SOURCE_OUTPUT_PORT
+    ));
+}
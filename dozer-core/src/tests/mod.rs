@@ -1,10 +1,173 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+
+use dozer_types::node::NodeHandle;
+use dozer_types::parking_lot::Mutex;
+use dozer_types::types::Record;
+use tempdir::TempDir;
+
+use crate::checkpoint::create_checkpoint_for_test;
+use crate::epoch::EpochManagerOptions;
+use crate::errors::ExecutionError;
+use crate::executor::{DagExecutor, ExecutorOptions};
+use crate::node::{SourceFactory, StateBackend};
+use crate::tests::processors::StateCountingProcessorFactory;
+use crate::tests::sinks::{RecordingSinkFactory, SinkRecording, RECORDING_SINK_INPUT_PORT};
+use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+use crate::{Dag, DagBuilder, DEFAULT_PORT_HANDLE};
+
 mod app;
 mod checkpoint_ns;
 mod dag_base_create_errors;
 mod dag_base_errors;
-mod dag_base_run;
+pub mod dag_base_run;
 mod dag_ports;
 mod dag_schemas;
 pub mod processors;
 pub mod sinks;
 pub mod sources;
+
+/// Runs `dag` with a new checkpoint until its sources finish.
+pub(crate) async fn run_dag(dag: Dag, options: ExecutorOptions) -> Result<(), ExecutionError> {
+    let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+    DagExecutor::new(dag, checkpoint, options)
+        .await?
+        .start(Arc::new(AtomicBool::new(true)), Default::default())
+        .await?
+        .join()
+}
+
+/// Keeps processor state in `state_dir`, checkpointing every commit so the state is always as of the latest checkpoint.
+pub(crate) fn checkpoint_every_commit(state_dir: &TempDir) -> ExecutorOptions {
+    ExecutorOptions {
+        state_dir: Some(state_dir.path().to_path_buf()),
+        epoch_manager_options: EpochManagerOptions {
+            max_num_records_before_persist: 0,
+            enable_app_checkpoints: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// A generator source feeding processor `2` counting into RocksDB state, then a sink.
+///
+/// Returns the count of the processor, which starts from the count restored from the checkpoint.
+pub(crate) fn generator_to_state_counting_dag(count: u64) -> (Dag, Arc<AtomicU64>) {
+    let latch = Arc::new(AtomicBool::new(true));
+    let processor = StateCountingProcessorFactory::new(StateBackend::RocksDb);
+    let state_count = processor.count();
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+    let dag = DagBuilder::new()
+        .source(
+            source_handle.clone(),
+            GeneratorSourceFactory::new(count, latch.clone(), false),
+        )
+        .processor(proc_handle.clone(), processor)
+        .sink(
+            sink_handle.clone(),
+            RecordingSinkFactory::new().stop_after(count, latch),
+        )
+        .edge(
+            &source_handle,
+            GENERATOR_SOURCE_OUTPUT_PORT,
+            &proc_handle,
+            DEFAULT_PORT_HANDLE,
+        )
+        .edge(
+            &proc_handle,
+            DEFAULT_PORT_HANDLE,
+            &sink_handle,
+            RECORDING_SINK_INPUT_PORT,
+        )
+        .build()
+        .unwrap();
+    (dag, state_count)
+}
+
+/// A generator source feeding sink `2`, which records the operations it receives.
+pub(crate) fn generator_to_materializing_dag(count: u64) -> (Dag, Arc<Mutex<SinkRecording>>) {
+    let source_handle = NodeHandle::new(None, 1.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+    let latch = Arc::new(AtomicBool::new(true));
+    let sink = RecordingSinkFactory::new()
+        .record_ops()
+        .stop_after(count, latch.clone());
+    let recording = sink.recording();
+    let dag = DagBuilder::new()
+        .source(
+            source_handle.clone(),
+            GeneratorSourceFactory::new(count, latch, false),
+        )
+        .sink(sink_handle.clone(), sink)
+        .edge(
+            &source_handle,
+            GENERATOR_SOURCE_OUTPUT_PORT,
+            &sink_handle,
+            RECORDING_SINK_INPUT_PORT,
+        )
+        .build()
+        .unwrap();
+    (dag, recording)
+}
+
+pub(crate) fn dir_entries(dir: &Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Runs `source` through a noop processor into a recording sink, returning the materialized records.
+pub(crate) async fn run_materializing(
+    source: impl SourceFactory + 'static,
+    count: u64,
+    latch: Arc<AtomicBool>,
+) -> Vec<Record> {
+    run_materializing_with_options(source, count, latch, Default::default()).await
+}
+
+pub(crate) async fn run_materializing_with_options(
+    source: impl SourceFactory + 'static,
+    count: u64,
+    latch: Arc<AtomicBool>,
+    options: ExecutorOptions,
+) -> Vec<Record> {
+    let source_handle = NodeHandle::new(Some(1), 1.to_string());
+    let proc_handle = NodeHandle::new(Some(1), 2.to_string());
+    let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+    let sink = RecordingSinkFactory::new()
+        .record_ops()
+        .stop_after(count, latch);
+    let recording = sink.recording();
+    let dag = DagBuilder::new()
+        .source(source_handle.clone(), source)
+        .processor(proc_handle.clone(), dag_base_run::NoopProcessorFactory {})
+        .sink(sink_handle.clone(), sink)
+        .edge(
+            &source_handle,
+            GENERATOR_SOURCE_OUTPUT_PORT,
+            &proc_handle,
+            DEFAULT_PORT_HANDLE,
+        )
+        .edge(
+            &proc_handle,
+            DEFAULT_PORT_HANDLE,
+            &sink_handle,
+            RECORDING_SINK_INPUT_PORT,
+        )
+        .build()
+        .unwrap();
+    run_dag(dag, options).await.unwrap();
+
+    let mut records = recording
+        .lock()
+        .materialize()
+        .into_values()
+        .collect::<Vec<_>>();
+    records.sort_by(|a, b| a.values.cmp(&b.values));
+    records
+}
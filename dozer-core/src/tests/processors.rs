@@ -1,10 +1,26 @@
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use dozer_recordstore::ProcessorRecordStoreDeserializer;
-use dozer_types::{errors::internal::BoxedError, types::Schema};
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
+use dozer_storage::RocksdbMap;
+use dozer_types::{
+    bincode,
+    errors::internal::BoxedError,
+    parking_lot::Mutex,
+    types::{Field, Operation, Record, Schema},
+};
 
 use crate::{
-    node::{PortHandle, Processor, ProcessorFactory},
+    channels::ProcessorChannelForwarder,
+    epoch::Epoch,
+    executor_operation::{OperationContext, ProcessorOperation},
+    node::{PortHandle, Processor, ProcessorFactory, StateBackend, StateEnvironment},
+    record_store::InputRecordReader,
     DEFAULT_PORT_HANDLE,
 };
 
@@ -92,3 +108,613 @@ impl ProcessorFactory for NoInputPortProcessorFactory {
         "NoInput".to_owned()
     }
 }
+
+pub(crate) const RECORDING_PROCESSOR_LEFT_INPUT_PORT: PortHandle = 1;
+pub(crate) const RECORDING_PROCESSOR_RIGHT_INPUT_PORT: PortHandle = 2;
+
+/// An operation processed by a [`RecordingProcessor`].
+#[derive(Debug, Clone)]
+pub(crate) struct ProcessedOp {
+    /// The number of epochs committed before it.
+    pub epoch: u64,
+    pub port: PortHandle,
+    pub op: Operation,
+    pub context: OperationContext,
+}
+
+/// What the processors built by a [`RecordingProcessorFactory`] observed.
+#[derive(Debug, Default)]
+pub(crate) struct ProcessorRecording {
+    /// Every operation processed, in order, if the factory records them.
+    pub ops: Vec<ProcessedOp>,
+    pub closed_ports: Vec<PortHandle>,
+    pub watermarks: Vec<SystemTime>,
+    /// The reads of [`ReaderCheck`] that found the record expected.
+    pub matched: u64,
+    /// The values of the inserted records by key, if the factory indexes them.
+    pub index: HashMap<String, String>,
+}
+
+/// What a [`RecordingProcessor`] reads from its record readers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReaderCheck {
+    /// Looks up the key of every insert on the left port in the right port's reader, expecting the same record.
+    /// Operations on the right port are only read.
+    LookupRightPort,
+    /// Reads back every version of the records it receives from its input port's reader.
+    Versions,
+}
+
+/// Forwards operations, configured with the processor behaviour under test, recording what it sees in [`Self::recording`].
+#[derive(Debug)]
+pub(crate) struct RecordingProcessorFactory {
+    join: bool,
+    fanout: usize,
+    release: Option<Arc<AtomicBool>>,
+    wait_for_right_port: bool,
+    total_order: bool,
+    hold_until_watermark: bool,
+    stop_on_port_closed: Option<Arc<AtomicBool>>,
+    map_inserts: Option<fn(Record) -> Result<Record, BoxedError>>,
+    fail_init: bool,
+    reader_check: Option<ReaderCheck>,
+    record_ops: bool,
+    index: bool,
+    recording: Arc<Mutex<ProcessorRecording>>,
+}
+
+impl RecordingProcessorFactory {
+    pub fn new() -> Self {
+        Self {
+            join: false,
+            fanout: 1,
+            release: None,
+            wait_for_right_port: false,
+            total_order: false,
+            hold_until_watermark: false,
+            stop_on_port_closed: None,
+            map_inserts: None,
+            fail_init: false,
+            reader_check: None,
+            record_ops: false,
+            index: false,
+            recording: Default::default(),
+        }
+    }
+
+    /// Receives on [`RECORDING_PROCESSOR_LEFT_INPUT_PORT`] and [`RECORDING_PROCESSOR_RIGHT_INPUT_PORT`] instead of the default port.
+    pub fn join(mut self) -> Self {
+        self.join = true;
+        self
+    }
+
+    /// Sends every operation `fanout` times with `send_all`.
+    pub fn with_fanout(mut self, fanout: usize) -> Self {
+        self.fanout = fanout;
+        self
+    }
+
+    /// Blocks on every operation until `release` is set.
+    pub fn block_until(mut self, release: Arc<AtomicBool>) -> Self {
+        self.release = Some(release);
+        self
+    }
+
+    /// Joins, and blocks on an operation from the left port until it has received as many from the right port, or `release` is set.
+    pub fn wait_for_right_port(mut self, release: Arc<AtomicBool>) -> Self {
+        self.join = true;
+        self.wait_for_right_port = true;
+        self.release = Some(release);
+        self
+    }
+
+    pub fn with_total_order(mut self) -> Self {
+        self.total_order = true;
+        self
+    }
+
+    /// Holds back operations until the watermark passes, like a window closing.
+    pub fn hold_until_watermark(mut self) -> Self {
+        self.hold_until_watermark = true;
+        self
+    }
+
+    /// Clears `running` once an input port is closed.
+    pub fn stop_on_port_closed(mut self, running: Arc<AtomicBool>) -> Self {
+        self.stop_on_port_closed = Some(running);
+        self
+    }
+
+    /// Forwards inserts as `map` returns them, failing the operation if it fails.
+    pub fn map_inserts(mut self, map: fn(Record) -> Result<Record, BoxedError>) -> Self {
+        self.map_inserts = Some(map);
+        self
+    }
+
+    /// Fails in `init`, like a processor missing a resource it needs.
+    pub fn fail_init(mut self) -> Self {
+        self.fail_init = true;
+        self
+    }
+
+    pub fn check_readers(mut self, check: ReaderCheck) -> Self {
+        self.join |= check == ReaderCheck::LookupRightPort;
+        self.reader_check = Some(check);
+        self
+    }
+
+    /// Records every operation processed in [`ProcessorRecording::ops`].
+    pub fn record_ops(mut self) -> Self {
+        self.record_ops = true;
+        self
+    }
+
+    /// Indexes the values of inserted records by key in [`ProcessorRecording::index`], and checkpoints the index.
+    pub fn with_index(mut self) -> Self {
+        self.index = true;
+        self
+    }
+
+    pub fn recording(&self) -> Arc<Mutex<ProcessorRecording>> {
+        self.recording.clone()
+    }
+
+    fn input_ports(&self) -> Vec<PortHandle> {
+        if self.join {
+            vec![
+                RECORDING_PROCESSOR_LEFT_INPUT_PORT,
+                RECORDING_PROCESSOR_RIGHT_INPUT_PORT,
+            ]
+        } else {
+            vec![DEFAULT_PORT_HANDLE]
+        }
+    }
+}
+
+impl ProcessorFactory for RecordingProcessorFactory {
+    fn type_name(&self) -> String {
+        "Recording".to_owned()
+    }
+
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        Ok(input_schemas[&self.input_ports()[0]].clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.input_ports()
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        if self.index {
+            self.recording.lock().index = match checkpoint_data {
+                Some(data) => bincode::deserialize(&data)?,
+                None => HashMap::new(),
+            };
+        }
+        let primary_index = match self.reader_check {
+            Some(ReaderCheck::LookupRightPort) => input_schemas
+                [&RECORDING_PROCESSOR_RIGHT_INPUT_PORT]
+                .primary_index
+                .clone(),
+            Some(ReaderCheck::Versions) => {
+                input_schemas[&DEFAULT_PORT_HANDLE].primary_index.clone()
+            }
+            None => vec![],
+        };
+        Ok(Box::new(RecordingProcessor {
+            fanout: self.fanout,
+            release: self.release.clone(),
+            wait_for_right_port: self.wait_for_right_port,
+            hold_until_watermark: self.hold_until_watermark,
+            stop_on_port_closed: self.stop_on_port_closed.clone(),
+            map_inserts: self.map_inserts,
+            fail_init: self.fail_init,
+            reader_check: self.reader_check,
+            record_ops: self.record_ops,
+            index: self.index,
+            primary_index,
+            record_readers: HashMap::new(),
+            versions: HashMap::new(),
+            epoch: 0,
+            num_left: 0,
+            num_right: 0,
+            window: vec![],
+            recording: self.recording.clone(),
+        }))
+    }
+
+    fn id(&self) -> String {
+        "Recording".to_owned()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        if self.index {
+            StateBackend::Memory
+        } else {
+            StateBackend::RocksDb
+        }
+    }
+
+    fn requires_total_order(&self) -> bool {
+        self.total_order
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RecordingProcessor {
+    fanout: usize,
+    release: Option<Arc<AtomicBool>>,
+    wait_for_right_port: bool,
+    hold_until_watermark: bool,
+    stop_on_port_closed: Option<Arc<AtomicBool>>,
+    map_inserts: Option<fn(Record) -> Result<Record, BoxedError>>,
+    fail_init: bool,
+    reader_check: Option<ReaderCheck>,
+    record_ops: bool,
+    index: bool,
+    primary_index: Vec<usize>,
+    record_readers: HashMap<PortHandle, InputRecordReader>,
+    /// The versions received of every key, for [`ReaderCheck::Versions`].
+    versions: HashMap<Vec<u8>, u64>,
+    epoch: u64,
+    num_left: u64,
+    num_right: u64,
+    window: Vec<ProcessorOperation>,
+    recording: Arc<Mutex<ProcessorRecording>>,
+}
+
+impl RecordingProcessor {
+    fn needs_records(&self) -> bool {
+        self.record_ops || self.index || self.map_inserts.is_some() || self.reader_check.is_some()
+    }
+
+    fn check_readers(
+        &mut self,
+        check: ReaderCheck,
+        record_store: &ProcessorRecordStore,
+        op: &Operation,
+    ) -> Result<(), BoxedError> {
+        match check {
+            ReaderCheck::LookupRightPort => {
+                let Operation::Insert { new } = op else {
+                    return Err("expected an insert".into());
+                };
+                let key = new.get_key(&self.primary_index);
+                let reader = &self.record_readers[&RECORDING_PROCESSOR_RIGHT_INPUT_PORT];
+                match reader.lookup(record_store, &key)? {
+                    Some(other) if &other == new => self.recording.lock().matched += 1,
+                    other => return Err(format!("looked up {other:?} for {new:?}").into()),
+                }
+            }
+            ReaderCheck::Versions => {
+                let (Operation::Insert { new } | Operation::Update { new, .. }) = op else {
+                    return Err("expected an insert or update".into());
+                };
+                let key = new.get_key(&self.primary_index);
+                let version = self.versions.entry(key.clone()).or_default();
+                *version += 1;
+                // Later versions may have been written already, but this one stays the same.
+                let reader = &self.record_readers[&DEFAULT_PORT_HANDLE];
+                match reader.get_version(record_store, &key, *version)? {
+                    Some(record) if &record == new => self.recording.lock().matched += 1,
+                    other => {
+                        return Err(
+                            format!("read version {version} as {other:?} for {new:?}").into()
+                        )
+                    }
+                }
+                if reader
+                    .latest_version(&key)
+                    .map_or(true, |latest| latest < *version)
+                {
+                    return Err(format!("latest version of {new:?} is before {version}").into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Processor for RecordingProcessor {
+    fn init(&mut self) -> Result<(), BoxedError> {
+        if self.fail_init {
+            return Err("missing resource".into());
+        }
+        Ok(())
+    }
+
+    fn before_commit(
+        &mut self,
+        _epoch_details: &Epoch,
+        _record_store: &ProcessorRecordStore,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.epoch += 1;
+        Ok(())
+    }
+
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        mut op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        if from_port == RECORDING_PROCESSOR_RIGHT_INPUT_PORT {
+            self.num_right += 1;
+        } else {
+            self.num_left += 1;
+        }
+        if let Some(release) = &self.release {
+            while (!self.wait_for_right_port || self.num_right < self.num_left)
+                && !release.load(Ordering::SeqCst)
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        if self.needs_records() {
+            let mut loaded = op.load(record_store)?;
+            if let Some(check) = self.reader_check {
+                if check == ReaderCheck::LookupRightPort
+                    && from_port == RECORDING_PROCESSOR_RIGHT_INPUT_PORT
+                {
+                    return Ok(());
+                }
+                self.check_readers(check, record_store, &loaded)?;
+            }
+            if let (Some(map), Operation::Insert { new }) = (self.map_inserts, &mut loaded) {
+                *new = map(new.clone())?;
+                op = ProcessorOperation::new(&loaded, record_store)?;
+            }
+
+            let mut recording = self.recording.lock();
+            if self.index {
+                if let Operation::Insert { new } = &loaded {
+                    if let [Field::String(key), Field::String(value)] = new.values.as_slice() {
+                        recording.index.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+            if self.record_ops {
+                recording.ops.push(ProcessedOp {
+                    epoch: self.epoch,
+                    port: from_port,
+                    op: loaded,
+                    context: fw.context(),
+                });
+            }
+        }
+
+        if self.hold_until_watermark {
+            self.window.push(op);
+        } else if self.fanout == 1 {
+            fw.send(op, DEFAULT_PORT_HANDLE);
+        } else {
+            fw.send_all(vec![op; self.fanout], DEFAULT_PORT_HANDLE);
+        }
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        object: Object,
+    ) -> Result<(), BoxedError> {
+        if self.index {
+            object.write(&bincode::serialize(&self.recording.lock().index)?)?;
+        }
+        Ok(())
+    }
+
+    fn set_record_readers(&mut self, record_readers: HashMap<PortHandle, InputRecordReader>) {
+        self.record_readers = record_readers;
+    }
+
+    fn on_port_closed(
+        &mut self,
+        port: PortHandle,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.recording.lock().closed_ports.push(port);
+        if let Some(running) = &self.stop_on_port_closed {
+            running.store(false, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn on_watermark(
+        &mut self,
+        watermark: SystemTime,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.recording.lock().watermarks.push(watermark);
+        if self.hold_until_watermark {
+            fw.send_all(std::mem::take(&mut self.window), DEFAULT_PORT_HANDLE);
+        }
+        Ok(())
+    }
+}
+
+/// Counts operations, keeping the count in its state backend, and forwards them.
+///
+/// Starts from the count in the state it's built with, so [`Self::count`] tells what the state was restored from.
+#[derive(Debug)]
+pub(crate) struct StateCountingProcessorFactory {
+    backend: StateBackend,
+    panic_at: Option<u64>,
+    panicked: Arc<AtomicBool>,
+    count: Arc<AtomicU64>,
+    builds: Arc<AtomicU64>,
+}
+
+impl StateCountingProcessorFactory {
+    pub fn new(backend: StateBackend) -> Self {
+        Self {
+            backend,
+            panic_at: None,
+            panicked: Arc::new(AtomicBool::new(false)),
+            count: Arc::new(AtomicU64::new(0)),
+            builds: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Panics once, before counting operation `panic_at`.
+    pub fn panic_once_at(mut self, panic_at: u64) -> Self {
+        self.panic_at = Some(panic_at);
+        self
+    }
+
+    pub fn count(&self) -> Arc<AtomicU64> {
+        self.count.clone()
+    }
+
+    /// The number of processors built.
+    pub fn builds(&self) -> Arc<AtomicU64> {
+        self.builds.clone()
+    }
+}
+
+impl ProcessorFactory for StateCountingProcessorFactory {
+    fn type_name(&self) -> String {
+        "StateCounting".to_owned()
+    }
+
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        Ok(input_schemas.get(&DEFAULT_PORT_HANDLE).unwrap().clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        unreachable!("The executor calls build_with_state")
+    }
+
+    fn id(&self) -> String {
+        "StateCounting".to_owned()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        self.backend
+    }
+
+    fn build_with_state(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+        state: StateEnvironment,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        self.builds.fetch_add(1, Ordering::SeqCst);
+        let map = match state {
+            StateEnvironment::RocksDb(path) => {
+                let map = RocksdbMap::<u64, u64>::create(&path, Default::default())?;
+                self.count
+                    .store(map.get(&0)?.unwrap_or_default(), Ordering::SeqCst);
+                Some(map)
+            }
+            StateEnvironment::Memory => {
+                self.count.store(0, Ordering::SeqCst);
+                None
+            }
+            StateEnvironment::Lmdb(_) => panic!("Lmdb is not used in these tests"),
+        };
+        Ok(Box::new(StateCountingProcessor {
+            map,
+            panic_at: self.panic_at,
+            panicked: self.panicked.clone(),
+            count: self.count.clone(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct StateCountingProcessor {
+    map: Option<RocksdbMap<u64, u64>>,
+    panic_at: Option<u64>,
+    panicked: Arc<AtomicBool>,
+    count: Arc<AtomicU64>,
+}
+
+impl Processor for StateCountingProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        _record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        if self.panic_at == Some(self.count.load(Ordering::SeqCst) + 1)
+            && !self.panicked.swap(true, Ordering::SeqCst)
+        {
+            panic!("processor crashed");
+        }
+        let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(map) = &self.map {
+            map.insert(&0, &count)?;
+        }
+        fw.send(op, DEFAULT_PORT_HANDLE);
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BoxedError> {
+        if let Some(map) = &self.map {
+            map.flush()?;
+        }
+        Ok(())
+    }
+
+    fn snapshot_state(&self, dir: &Path) -> Result<(), BoxedError> {
+        if let Some(map) = &self.map {
+            map.checkpoint(dir)?;
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,28 @@
+use crate::channels::ProcessorChannelForwarder;
 use crate::epoch::Epoch;
-use crate::executor_operation::ProcessorOperation;
-use crate::node::{PortHandle, Sink, SinkFactory};
+use crate::executor_operation::{OperationContext, ProcessorOperation};
+use crate::node::{
+    CommitDecision, PortHandle, Sink, SinkFactory, SinkPartitioning, TransformingSink,
+    TransformingSinkFactory,
+};
+use crate::partition::{stable_hash, PartitionHasher};
 use crate::DEFAULT_PORT_HANDLE;
 use dozer_log::storage::Queue;
-use dozer_recordstore::ProcessorRecordStore;
+use dozer_recordstore::{ProcessorRecordStore, StoreRecord};
 use dozer_types::errors::internal::BoxedError;
-use dozer_types::types::Schema;
+use dozer_types::node::{OpIdentifier, SourceStates};
+use dozer_types::types::{
+    Field, FieldDefinition, FieldType, Operation, Record, Schema, SourceDefinition,
+};
 
 use dozer_types::log::debug;
+use dozer_types::parking_lot::Mutex;
 use std::collections::HashMap;
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 pub(crate) const COUNTING_SINK_INPUT_PORT: PortHandle = 90;
 
@@ -95,6 +106,471 @@ impl Sink for CountingSink {
     }
 }
 
+pub(crate) const RECORDING_SINK_INPUT_PORT: PortHandle = 91;
+pub(crate) const RECORDING_SINK_INPUT_PORT_2: PortHandle = 92;
+
+/// An operation received by a [`RecordingSink`].
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedOp {
+    /// The sink instance that received it, which is its partition if the sink is partitioned.
+    pub instance: usize,
+    pub port: PortHandle,
+    pub op: Operation,
+    pub context: OperationContext,
+}
+
+impl RecordedOp {
+    /// The first field of the record, which generated records are keyed by.
+    pub fn key(&self) -> &Field {
+        match &self.op {
+            Operation::Insert { new } | Operation::Update { new, .. } => &new.values[0],
+            Operation::Delete { old } => &old.values[0],
+        }
+    }
+}
+
+/// What the sinks built by a [`RecordingSinkFactory`] observed.
+#[derive(Debug, Default)]
+pub(crate) struct SinkRecording {
+    /// Every operation received, in order, if the factory records them.
+    pub ops: Vec<RecordedOp>,
+    /// The number of operations in each committed epoch.
+    pub epoch_sizes: Vec<usize>,
+    /// The number of operations received each time the stream ended.
+    pub ends: Vec<u64>,
+    pub watermarks: Vec<SystemTime>,
+    /// When the last sink finished `init`.
+    pub initialized_at: Option<SystemTime>,
+    /// How far the source's sent counter ran ahead of the operations received, at most.
+    pub max_queue_depth: u64,
+    /// The number of commits pushed back.
+    pub backpressured: u64,
+}
+
+impl SinkRecording {
+    /// Applies the recorded operations keyed by their first field, panicking on one that doesn't match the records so far.
+    pub fn materialize(&self) -> HashMap<Field, Record> {
+        let mut state = HashMap::new();
+        for recorded in &self.ops {
+            match &recorded.op {
+                Operation::Insert { new } => {
+                    let existing = state.insert(new.values[0].clone(), new.clone());
+                    assert!(existing.is_none(), "Inserted existing {new:?}");
+                }
+                Operation::Update { old, new } => {
+                    let existing = state.remove(&old.values[0]);
+                    assert_eq!(existing.as_ref(), Some(old), "Updated missing {old:?}");
+                    state.insert(new.values[0].clone(), new.clone());
+                }
+                Operation::Delete { old } => {
+                    let existing = state.remove(&old.values[0]);
+                    assert_eq!(existing.as_ref(), Some(old), "Deleted missing {old:?}");
+                }
+            }
+        }
+        state
+    }
+
+    /// The keys of the operations received on `port`, in order.
+    pub fn keys(&self, port: PortHandle) -> Vec<Field> {
+        self.ops
+            .iter()
+            .filter(|recorded| recorded.port == port)
+            .map(|recorded| recorded.key().clone())
+            .collect()
+    }
+}
+
+/// A sink for checking what the executor delivers, configured with the behaviour under test.
+///
+/// Counts the operations it receives in [`Self::received`], and records commits, watermarks and the end of the stream in [`Self::recording`].
+/// Every sink it builds shares them.
+#[derive(Debug)]
+pub(crate) struct RecordingSinkFactory {
+    input_ports: Vec<PortHandle>,
+    stop_after: Option<(u64, Arc<AtomicBool>)>,
+    record_ops: bool,
+    op_delay: Option<Duration>,
+    init_delay: Option<Duration>,
+    sent: Option<Arc<AtomicU64>>,
+    deferred_commits: Option<(u64, u32)>,
+    applied_source_states: Option<SourceStates>,
+    applied_through: Option<OpIdentifier>,
+    num_partitions: Option<usize>,
+    hasher: Option<fn(&[u8]) -> u64>,
+    num_built: AtomicUsize,
+    received: Arc<AtomicU64>,
+    recording: Arc<Mutex<SinkRecording>>,
+}
+
+impl RecordingSinkFactory {
+    pub fn new() -> Self {
+        Self {
+            input_ports: vec![RECORDING_SINK_INPUT_PORT],
+            stop_after: None,
+            record_ops: false,
+            op_delay: None,
+            init_delay: None,
+            sent: None,
+            deferred_commits: None,
+            applied_source_states: None,
+            applied_through: None,
+            num_partitions: None,
+            hasher: None,
+            num_built: AtomicUsize::new(0),
+            received: Default::default(),
+            recording: Default::default(),
+        }
+    }
+
+    /// Notifies the sender to exit by clearing `running` once `expected` operations are received.
+    pub fn stop_after(mut self, expected: u64, running: Arc<AtomicBool>) -> Self {
+        self.stop_after = Some((expected, running));
+        self
+    }
+
+    /// Records every operation received in [`SinkRecording::ops`].
+    pub fn record_ops(mut self) -> Self {
+        self.record_ops = true;
+        self
+    }
+
+    /// Also receives on [`RECORDING_SINK_INPUT_PORT_2`].
+    pub fn with_second_port(mut self) -> Self {
+        self.input_ports.push(RECORDING_SINK_INPUT_PORT_2);
+        self
+    }
+
+    /// Sleeps for `op_delay` on every operation, to throttle the pipeline.
+    pub fn with_op_delay(mut self, op_delay: Duration) -> Self {
+        self.op_delay = Some(op_delay);
+        self
+    }
+
+    /// Takes `init_delay` to initialize, like a sink connecting to a slow external system.
+    pub fn with_init_delay(mut self, init_delay: Duration) -> Self {
+        self.init_delay = Some(init_delay);
+        self
+    }
+
+    /// Tracks how far the source's `sent` counter runs ahead of it in [`SinkRecording::max_queue_depth`].
+    pub fn with_queue_depth(mut self, sent: Arc<AtomicU64>) -> Self {
+        self.sent = Some(sent);
+        self
+    }
+
+    /// Defers commits until `batch_size` operations are pending, then pushes back `busy_retries` times before committing the batch,
+    /// like a database that's slow to flush.
+    pub fn with_deferred_commits(mut self, batch_size: u64, busy_retries: u32) -> Self {
+        self.deferred_commits = Some((batch_size, busy_retries));
+        self
+    }
+
+    /// Reports `applied` as the source states it has applied.
+    pub fn with_applied_source_states(mut self, applied: SourceStates) -> Self {
+        self.applied_source_states = Some(applied);
+        self
+    }
+
+    /// Reports `applied_through` as the offset it has applied every source through.
+    pub fn with_applied_through(mut self, applied_through: OpIdentifier) -> Self {
+        self.applied_through = Some(applied_through);
+        self
+    }
+
+    /// Splits the input between `num_partitions` sinks by the first field.
+    pub fn with_partitions(mut self, num_partitions: usize) -> Self {
+        self.num_partitions = Some(num_partitions);
+        self
+    }
+
+    /// Routes records between partitions with `hasher` instead of the default.
+    pub fn with_hasher(mut self, hasher: fn(&[u8]) -> u64) -> Self {
+        self.hasher = Some(hasher);
+        self
+    }
+
+    pub fn received(&self) -> Arc<AtomicU64> {
+        self.received.clone()
+    }
+
+    pub fn recording(&self) -> Arc<Mutex<SinkRecording>> {
+        self.recording.clone()
+    }
+}
+
+impl SinkFactory for RecordingSinkFactory {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.input_ports.clone()
+    }
+
+    fn prepare(&self, _input_schemas: HashMap<PortHandle, Schema>) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Sink>, BoxedError> {
+        Ok(Box::new(RecordingSink {
+            instance: self.num_built.fetch_add(1, Ordering::Relaxed),
+            stop_after: self.stop_after.clone(),
+            record_ops: self.record_ops,
+            op_delay: self.op_delay,
+            init_delay: self.init_delay,
+            sent: self.sent.clone(),
+            deferred_commits: self.deferred_commits,
+            applied_source_states: self.applied_source_states.clone(),
+            applied_through: self.applied_through,
+            current: 0,
+            pending: 0,
+            retries: 0,
+            received: self.received.clone(),
+            recording: self.recording.clone(),
+        }))
+    }
+
+    fn partition_by(&self) -> Option<SinkPartitioning> {
+        self.num_partitions.map(|num_partitions| SinkPartitioning {
+            num_partitions,
+            key: vec![0],
+        })
+    }
+
+    fn partition_hasher(&self) -> PartitionHasher {
+        match self.hasher {
+            Some(hasher) => Box::new(hasher),
+            None => Box::new(stable_hash),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RecordingSink {
+    instance: usize,
+    stop_after: Option<(u64, Arc<AtomicBool>)>,
+    record_ops: bool,
+    op_delay: Option<Duration>,
+    init_delay: Option<Duration>,
+    sent: Option<Arc<AtomicU64>>,
+    deferred_commits: Option<(u64, u32)>,
+    applied_source_states: Option<SourceStates>,
+    applied_through: Option<OpIdentifier>,
+    /// Operations received by this sink.
+    current: u64,
+    /// Operations received since the last commit.
+    pending: u64,
+    /// Pushbacks of the pending commit so far.
+    retries: u32,
+    received: Arc<AtomicU64>,
+    recording: Arc<Mutex<SinkRecording>>,
+}
+
+impl Sink for RecordingSink {
+    fn init(&mut self) -> Result<(), BoxedError> {
+        if let Some(init_delay) = self.init_delay {
+            thread::sleep(init_delay);
+        }
+        self.recording.lock().initialized_at = Some(SystemTime::now());
+        Ok(())
+    }
+
+    fn commit(&mut self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        self.recording
+            .lock()
+            .epoch_sizes
+            .push(self.pending as usize);
+        self.pending = 0;
+        self.retries = 0;
+        Ok(())
+    }
+
+    fn commit_with_decision(
+        &mut self,
+        epoch_details: &Epoch,
+    ) -> Result<CommitDecision, BoxedError> {
+        if let Some((batch_size, busy_retries)) = self.deferred_commits {
+            if self.pending < batch_size {
+                return Ok(CommitDecision::Defer);
+            }
+            if self.retries < busy_retries {
+                self.retries += 1;
+                self.recording.lock().backpressured += 1;
+                return Ok(CommitDecision::Backpressure);
+            }
+        }
+        self.commit(epoch_details)
+            .map(|()| CommitDecision::Committed)
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+    ) -> Result<(), BoxedError> {
+        self.process_with_context(from_port, record_store, op, &Default::default())
+    }
+
+    fn process_with_context(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        context: &OperationContext,
+    ) -> Result<(), BoxedError> {
+        if let Some(op_delay) = self.op_delay {
+            thread::sleep(op_delay);
+        }
+        {
+            let mut recording = self.recording.lock();
+            if let Some(sent) = &self.sent {
+                let depth = sent.load(Ordering::SeqCst).saturating_sub(self.current);
+                recording.max_queue_depth = recording.max_queue_depth.max(depth);
+            }
+            if self.record_ops {
+                recording.ops.push(RecordedOp {
+                    instance: self.instance,
+                    port: from_port,
+                    op: op.load(record_store)?,
+                    context: context.clone(),
+                });
+            }
+        }
+        self.current += 1;
+        self.pending += 1;
+
+        let received = self.received.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some((expected, running)) = &self.stop_after {
+            if received == *expected {
+                running.store(false, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self, _queue: &Queue) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn on_source_snapshotting_done(&mut self, _connection_name: String) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn on_watermark(&mut self, watermark: SystemTime) -> Result<(), BoxedError> {
+        self.recording.lock().watermarks.push(watermark);
+        Ok(())
+    }
+
+    fn applied_source_states(&self) -> Option<SourceStates> {
+        self.applied_source_states.clone()
+    }
+
+    fn applied_through(&self) -> Option<OpIdentifier> {
+        self.applied_through
+    }
+
+    fn on_end_of_stream(&mut self) -> Result<(), BoxedError> {
+        self.recording.lock().ends.push(self.current);
+        Ok(())
+    }
+}
+
+pub(crate) const BATCH_COUNTING_SINK_INPUT_PORT: PortHandle = 95;
+pub(crate) const BATCH_COUNTING_SINK_OUTPUT_PORT: PortHandle = 96;
+
+/// Counts the operations it writes, and forwards the number written in each epoch on commit.
+#[derive(Debug)]
+pub(crate) struct BatchCountingSinkFactory {
+    written: Arc<AtomicU64>,
+}
+
+impl BatchCountingSinkFactory {
+    pub fn new(written: Arc<AtomicU64>) -> Self {
+        Self { written }
+    }
+}
+
+impl TransformingSinkFactory for BatchCountingSinkFactory {
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![BATCH_COUNTING_SINK_INPUT_PORT]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![BATCH_COUNTING_SINK_OUTPUT_PORT]
+    }
+
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        _input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        Ok(Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "count".to_string(),
+                    FieldType::UInt,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            )
+            .clone())
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn TransformingSink>, BoxedError> {
+        Ok(Box::new(BatchCountingSink {
+            written: self.written.clone(),
+            current: 0,
+        }))
+    }
+
+    fn type_name(&self) -> String {
+        "BatchCounting".to_owned()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct BatchCountingSink {
+    written: Arc<AtomicU64>,
+    current: u64,
+}
+
+impl TransformingSink for BatchCountingSink {
+    fn commit(
+        &mut self,
+        _epoch_details: &Epoch,
+        record_store: &ProcessorRecordStore,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        if self.current > 0 {
+            let new = record_store.create_record(&Record::new(vec![Field::UInt(self.current)]))?;
+            fw.send(
+                ProcessorOperation::Insert { new },
+                BATCH_COUNTING_SINK_OUTPUT_PORT,
+            );
+            self.current = 0;
+        }
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        _record_store: &ProcessorRecordStore,
+        _op: ProcessorOperation,
+        _fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.written.fetch_add(1, Ordering::SeqCst);
+        self.current += 1;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ConnectivityTestSinkFactory;
 
@@ -1,5 +1,10 @@
 use crate::channels::SourceChannelForwarder;
-use crate::node::{OutputPortDef, OutputPortType, PortHandle, Source, SourceFactory, SourceState};
+use crate::executor_operation::{OperationContext, OperationHeaders, OperationTimestamps};
+use crate::node::{
+    OutputPortDef, OutputPortDefOptions, OutputPortType, PortHandle, Progress, Source,
+    SourceFactory, SourceMode, SourceState,
+};
+use crate::parallel_source::{FetchEmitter, ParallelFetch, ParallelFetchSource};
 use crate::DEFAULT_PORT_HANDLE;
 use dozer_types::errors::internal::BoxedError;
 use dozer_types::models::ingestion_types::IngestionMessage;
@@ -8,20 +13,103 @@ use dozer_types::types::{
     Field, FieldDefinition, FieldType, Operation, Record, Schema, SourceDefinition,
 };
 
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
 
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub(crate) const GENERATOR_SOURCE_OUTPUT_PORT: PortHandle = 100;
 
+/// Relative weights of the operation types generated against a bounded key space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OpMix {
+    pub inserts: u64,
+    pub updates: u64,
+    pub deletes: u64,
+    pub key_space: u64,
+}
+
+/// Deterministically generates operations following an `OpMix`, tracking the records that are live.
+#[derive(Debug)]
+pub(crate) struct OpGenerator {
+    op_mix: OpMix,
+    live: BTreeMap<u64, Record>,
+}
+
+impl OpGenerator {
+    pub fn new(op_mix: OpMix) -> Self {
+        assert!(op_mix.inserts + op_mix.updates + op_mix.deletes > 0);
+        assert!(op_mix.key_space > 0);
+        Self {
+            op_mix,
+            live: BTreeMap::new(),
+        }
+    }
+
+    /// The records that are live after all generated operations are applied.
+    pub fn live(&self) -> impl Iterator<Item = &Record> {
+        self.live.values()
+    }
+
+    /// Generates the `n`th operation. Updates and deletes fall back to inserts if no key is live,
+    /// and inserts fall back to updates if every key is live.
+    pub fn next_op(&mut self, n: u64) -> Operation {
+        let OpMix {
+            inserts,
+            updates,
+            deletes,
+            key_space,
+        } = self.op_mix;
+        let slot = n % (inserts + updates + deletes);
+        let is_full = self.live.len() as u64 == key_space;
+        if self.live.is_empty() || (slot < inserts && !is_full) {
+            let key = (0..key_space)
+                .map(|offset| (n + offset) % key_space)
+                .find(|key| !self.live.contains_key(key))
+                .expect("key space is not full");
+            let new = generated_record(key, n);
+            self.live.insert(key, new.clone());
+            Operation::Insert { new }
+        } else {
+            let key = *self
+                .live
+                .keys()
+                .nth((n % self.live.len() as u64) as usize)
+                .expect("live is not empty");
+            if slot < inserts + updates {
+                let new = generated_record(key, n);
+                let old = self.live.insert(key, new.clone()).expect("key is live");
+                Operation::Update { old, new }
+            } else {
+                let old = self.live.remove(&key).expect("key is live");
+                Operation::Delete { old }
+            }
+        }
+    }
+}
+
+fn generated_record(key: u64, n: u64) -> Record {
+    Record::new(vec![
+        Field::String(format!("key_{key}")),
+        Field::String(format!("value_{n}")),
+    ])
+}
+
 #[derive(Debug)]
 pub(crate) struct GeneratorSourceFactory {
     count: u64,
     running: Arc<AtomicBool>,
-    stateful: bool,
+    port_type: OutputPortType,
+    op_mix: Option<OpMix>,
+    sent: Option<Arc<AtomicU64>>,
+    event_times: bool,
+    event_time_offset: Duration,
+    value_len: usize,
+    port_options: OutputPortDefOptions,
+    headers: bool,
+    watermark_interval: Option<u64>,
 }
 
 impl GeneratorSourceFactory {
@@ -29,9 +117,78 @@ impl GeneratorSourceFactory {
         Self {
             count,
             running: barrier,
-            stateful,
+            port_type: if stateful {
+                OutputPortType::StatefulWithPrimaryKeyLookup
+            } else {
+                OutputPortType::Stateless
+            },
+            op_mix: None,
+            sent: None,
+            event_times: false,
+            event_time_offset: Duration::ZERO,
+            value_len: 0,
+            port_options: Default::default(),
+            headers: false,
+            watermark_interval: None,
         }
     }
+
+    /// Like `with_event_times`, also sending the event time of every `interval`th operation as a watermark after it.
+    pub fn with_watermarks(mut self, interval: u64) -> Self {
+        self.event_times = true;
+        self.watermark_interval = Some(interval);
+        self
+    }
+
+    /// Sends the `n`th operation with header [`GENERATOR_TENANT_HEADER`] set to `generator_tenant(n)`.
+    pub fn with_headers(mut self) -> Self {
+        self.headers = true;
+        self
+    }
+
+    /// Pads inserted values to `value_len` characters.
+    pub fn with_value_len(mut self, value_len: usize) -> Self {
+        self.value_len = value_len;
+        self
+    }
+
+    pub fn with_port_options(mut self, port_options: OutputPortDefOptions) -> Self {
+        self.port_options = port_options;
+        self
+    }
+
+    /// Overrides the port type picked by `stateful`.
+    pub fn with_port_type(mut self, port_type: OutputPortType) -> Self {
+        self.port_type = port_type;
+        self
+    }
+
+    /// Sends the `n`th operation with event time `generator_event_time(n)`.
+    pub fn with_event_times(mut self) -> Self {
+        self.event_times = true;
+        self
+    }
+
+    /// Like `with_event_times`, adding `offset` to every event time.
+    pub fn with_event_time_offset(mut self, offset: Duration) -> Self {
+        self.event_times = true;
+        self.event_time_offset = offset;
+        self
+    }
+
+    /// Generates a mix of inserts, updates and deletes instead of inserting a new key per operation.
+    ///
+    /// The key space starts empty on every start, so this is not meant for restarts from a checkpoint.
+    pub fn with_op_mix(mut self, op_mix: OpMix) -> Self {
+        self.op_mix = Some(op_mix);
+        self
+    }
+
+    /// Counts the operations the source has sent.
+    pub fn with_sent_counter(mut self, sent: Arc<AtomicU64>) -> Self {
+        self.sent = Some(sent);
+        self
+    }
 }
 
 impl SourceFactory for GeneratorSourceFactory {
@@ -63,14 +220,10 @@ impl SourceFactory for GeneratorSourceFactory {
     }
 
     fn get_output_ports(&self) -> Vec<OutputPortDef> {
-        vec![OutputPortDef::new(
-            GENERATOR_SOURCE_OUTPUT_PORT,
-            if self.stateful {
-                OutputPortType::StatefulWithPrimaryKeyLookup
-            } else {
-                OutputPortType::Stateless
-            },
-        )]
+        vec![
+            OutputPortDef::new(GENERATOR_SOURCE_OUTPUT_PORT, self.port_type)
+                .with_options(self.port_options.clone()),
+        ]
     }
 
     fn build(
@@ -79,7 +232,15 @@ impl SourceFactory for GeneratorSourceFactory {
     ) -> Result<Box<dyn Source>, BoxedError> {
         Ok(Box::new(GeneratorSource {
             count: self.count,
+            value_len: self.value_len,
             running: self.running.clone(),
+            op_mix: self.op_mix,
+            sent: self.sent.clone(),
+            event_times: self.event_times,
+            event_time_offset: self.event_time_offset,
+            processed: AtomicU64::new(0),
+            headers: self.headers,
+            watermark_interval: self.watermark_interval,
         }))
     }
 }
@@ -87,7 +248,32 @@ impl SourceFactory for GeneratorSourceFactory {
 #[derive(Debug)]
 pub(crate) struct GeneratorSource {
     count: u64,
+    value_len: usize,
     running: Arc<AtomicBool>,
+    op_mix: Option<OpMix>,
+    sent: Option<Arc<AtomicU64>>,
+    event_times: bool,
+    event_time_offset: Duration,
+    /// Operations sent since this source started, for `progress`.
+    processed: AtomicU64,
+    headers: bool,
+    watermark_interval: Option<u64>,
+}
+
+/// The value `GeneratorSource` inserts with key `key_{n}`.
+pub(crate) fn generated_value(n: u64, value_len: usize) -> String {
+    format!("{:x<value_len$}", format!("value_{n}"))
+}
+
+pub(crate) fn generator_event_time(n: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(n)
+}
+
+pub(crate) const GENERATOR_TENANT_HEADER: &str = "tenant";
+
+/// The tenant header `GeneratorSource` attaches to the `n`th operation.
+pub(crate) fn generator_tenant(n: u64) -> Vec<u8> {
+    format!("tenant_{}", n % 3).into_bytes()
 }
 
 impl Source for GeneratorSource {
@@ -104,20 +290,51 @@ impl Source for GeneratorSource {
             .unwrap_or(OpIdentifier::new(0, 0))
             .txid;
 
+        let mut generator = self.op_mix.map(OpGenerator::new);
         for n in start + 1..(start + self.count + 1) {
-            fw.send(
-                IngestionMessage::OperationEvent {
-                    table_index: 0,
-                    op: Operation::Insert {
-                        new: Record::new(vec![
-                            Field::String(format!("key_{n}")),
-                            Field::String(format!("value_{n}")),
-                        ]),
-                    },
-                    id: Some(OpIdentifier::new(n, 0)),
+            let op = match &mut generator {
+                Some(generator) => generator.next_op(n),
+                None => Operation::Insert {
+                    new: Record::new(vec![
+                        Field::String(format!("key_{n}")),
+                        Field::String(generated_value(n, self.value_len)),
+                    ]),
                 },
-                GENERATOR_SOURCE_OUTPUT_PORT,
-            )?;
+            };
+            let message = IngestionMessage::OperationEvent {
+                table_index: 0,
+                op,
+                id: Some(OpIdentifier::new(n, 0)),
+            };
+            let event_time = self
+                .event_times
+                .then(|| generator_event_time(n) + self.event_time_offset);
+            let headers = if self.headers {
+                OperationHeaders::new(HashMap::from([(
+                    GENERATOR_TENANT_HEADER.to_string(),
+                    generator_tenant(n),
+                )]))
+            } else {
+                Default::default()
+            };
+            let context = OperationContext {
+                timestamps: OperationTimestamps {
+                    event_time,
+                    processing_time: None,
+                },
+                headers,
+            };
+            fw.send_with_context(message, GENERATOR_SOURCE_OUTPUT_PORT, context)?;
+            if let (Some(interval), Some(event_time)) = (self.watermark_interval, event_time) {
+                if n % interval == 0 {
+                    // Later operations have later event times.
+                    fw.send_watermark(event_time, GENERATOR_SOURCE_OUTPUT_PORT)?;
+                }
+            }
+            if let Some(sent) = &self.sent {
+                sent.fetch_add(1, Ordering::SeqCst);
+            }
+            self.processed.fetch_add(1, Ordering::SeqCst);
         }
 
         loop {
@@ -129,6 +346,13 @@ impl Source for GeneratorSource {
 
         Ok(())
     }
+
+    fn progress(&self) -> Option<Progress> {
+        Some(Progress {
+            processed: self.processed.load(Ordering::SeqCst),
+            total: Some(self.count),
+        })
+    }
 }
 
 pub(crate) const DUAL_PORT_GENERATOR_SOURCE_OUTPUT_PORT_1: PortHandle = 1000;
@@ -265,6 +489,290 @@ impl Source for DualPortGeneratorSource {
     }
 }
 
+pub(crate) const BACKFILL_SOURCE_OUTPUT_PORT: PortHandle = 200;
+
+/// Backfills `backfill_count` operations, then streams `streaming_count` operations and quits.
+#[derive(Debug)]
+pub(crate) struct BackfillSourceFactory {
+    backfill_count: u64,
+    streaming_count: u64,
+}
+
+impl BackfillSourceFactory {
+    pub fn new(backfill_count: u64, streaming_count: u64) -> Self {
+        Self {
+            backfill_count,
+            streaming_count,
+        }
+    }
+}
+
+impl SourceFactory for BackfillSourceFactory {
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        Ok(Schema::default()
+            .field(
+                FieldDefinition::new(
+                    "id".to_string(),
+                    FieldType::String,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                true,
+            )
+            .clone())
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "backfill".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            BACKFILL_SOURCE_OUTPUT_PORT,
+            OutputPortType::Stateless,
+        )]
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(BackfillSource {
+            backfill_count: self.backfill_count,
+            streaming_count: self.streaming_count,
+        }))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct BackfillSource {
+    backfill_count: u64,
+    streaming_count: u64,
+}
+
+impl Source for BackfillSource {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        _last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        for n in 1..(self.backfill_count + self.streaming_count + 1) {
+            fw.send(
+                IngestionMessage::OperationEvent {
+                    table_index: 0,
+                    op: Operation::Insert {
+                        new: Record::new(vec![Field::String(format!("key_{n}"))]),
+                    },
+                    id: Some(OpIdentifier::new(n, 0)),
+                },
+                BACKFILL_SOURCE_OUTPUT_PORT,
+            )?;
+            if n == self.backfill_count {
+                fw.send(
+                    IngestionMessage::SnapshottingDone,
+                    BACKFILL_SOURCE_OUTPUT_PORT,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mode(&self) -> SourceMode {
+        SourceMode::Backfill
+    }
+}
+
+pub(crate) const THREE_FIELD_SOURCE_OUTPUT_PORT: PortHandle = 300;
+
+/// Inserts `count` records with an `id`, `a` and `b` field, and quits.
+#[derive(Debug)]
+pub(crate) struct ThreeFieldSourceFactory {
+    count: u64,
+}
+
+impl ThreeFieldSourceFactory {
+    pub fn new(count: u64) -> Self {
+        Self { count }
+    }
+
+    pub fn record(n: u64) -> Record {
+        Record::new(vec![
+            Field::UInt(n),
+            Field::String(format!("a_{n}")),
+            Field::String(format!("b_{n}")),
+        ])
+    }
+}
+
+impl SourceFactory for ThreeFieldSourceFactory {
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        let mut schema = Schema::default();
+        schema.field(
+            FieldDefinition::new(
+                "id".to_string(),
+                FieldType::UInt,
+                false,
+                SourceDefinition::Dynamic,
+            ),
+            true,
+        );
+        for name in ["a", "b"] {
+            schema.field(
+                FieldDefinition::new(
+                    name.to_string(),
+                    FieldType::String,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                false,
+            );
+        }
+        Ok(schema)
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "three_field".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            THREE_FIELD_SOURCE_OUTPUT_PORT,
+            OutputPortType::Stateless,
+        )]
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(ThreeFieldSource { count: self.count }))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ThreeFieldSource {
+    count: u64,
+}
+
+impl Source for ThreeFieldSource {
+    fn start(
+        &self,
+        fw: &mut dyn SourceChannelForwarder,
+        _last_checkpoint: SourceState,
+    ) -> Result<(), BoxedError> {
+        for n in 1..(self.count + 1) {
+            fw.send(
+                IngestionMessage::OperationEvent {
+                    table_index: 0,
+                    op: Operation::Insert {
+                        new: ThreeFieldSourceFactory::record(n),
+                    },
+                    id: Some(OpIdentifier::new(n, 0)),
+                },
+                THREE_FIELD_SOURCE_OUTPUT_PORT,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) const PARALLEL_GENERATOR_SOURCE_OUTPUT_PORT: PortHandle = 400;
+
+/// Inserts `count` records with an `id`, the sequence number, and the `fetcher` that fetched it, and quits.
+///
+/// Fetcher `i` of `num_fetchers` fetches every id congruent to `i`, sleeping a varying time before each,
+/// so the fetchers finish their records out of sequence.
+#[derive(Debug)]
+pub(crate) struct ParallelGeneratorSourceFactory {
+    count: u64,
+    num_fetchers: usize,
+}
+
+impl ParallelGeneratorSourceFactory {
+    pub fn new(count: u64, num_fetchers: usize) -> Self {
+        Self {
+            count,
+            num_fetchers,
+        }
+    }
+}
+
+impl SourceFactory for ParallelGeneratorSourceFactory {
+    fn get_output_schema(&self, _port: &PortHandle) -> Result<Schema, BoxedError> {
+        let mut schema = Schema::default();
+        for (name, primary_key) in [("id", true), ("fetcher", false)] {
+            schema.field(
+                FieldDefinition::new(
+                    name.to_string(),
+                    FieldType::UInt,
+                    false,
+                    SourceDefinition::Dynamic,
+                ),
+                primary_key,
+            );
+        }
+        Ok(schema)
+    }
+
+    fn get_output_port_name(&self, _port: &PortHandle) -> String {
+        "parallel_generator".to_string()
+    }
+
+    fn get_output_ports(&self) -> Vec<OutputPortDef> {
+        vec![OutputPortDef::new(
+            PARALLEL_GENERATOR_SOURCE_OUTPUT_PORT,
+            OutputPortType::Stateless,
+        )]
+    }
+
+    fn build(
+        &self,
+        _output_schemas: HashMap<PortHandle, Schema>,
+    ) -> Result<Box<dyn Source>, BoxedError> {
+        Ok(Box::new(ParallelFetchSource::new(ParallelGenerator {
+            count: self.count,
+            num_fetchers: self.num_fetchers,
+        })))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ParallelGenerator {
+    count: u64,
+    num_fetchers: usize,
+}
+
+impl ParallelFetch for ParallelGenerator {
+    fn num_fetchers(&self) -> usize {
+        self.num_fetchers
+    }
+
+    fn fetch(
+        &self,
+        fetcher: usize,
+        _last_checkpoint: &SourceState,
+        emitter: &mut FetchEmitter,
+    ) -> Result<(), BoxedError> {
+        for seq in (fetcher as u64..self.count).step_by(self.num_fetchers) {
+            thread::sleep(Duration::from_micros(
+                (seq * 7919 + fetcher as u64 * 104729) % 500,
+            ));
+            emitter.emit(
+                seq,
+                IngestionMessage::OperationEvent {
+                    table_index: 0,
+                    op: Operation::Insert {
+                        new: Record::new(vec![Field::UInt(seq), Field::UInt(fetcher as u64)]),
+                    },
+                    id: Some(OpIdentifier::new(seq, 0)),
+                },
+                PARALLEL_GENERATOR_SOURCE_OUTPUT_PORT,
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ConnectivityTestSourceFactory;
 
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::types::{Operation, Record, Schema};
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::epoch::Epoch;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::{PortHandle, Processor, ProcessorFactory, StateBackend};
+use crate::DEFAULT_PORT_HANDLE;
+
+pub type RecordTransform = Arc<dyn Fn(&mut Record) + Send + Sync>;
+
+/// Named record transforms, so the same transform can be defined once and referenced by many processors.
+///
+/// Transforms must keep records in the schema they're applied to.
+#[derive(Clone, Default)]
+pub struct TransformRegistry {
+    transforms: HashMap<String, RecordTransform>,
+}
+
+impl TransformRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `transform` as `name`, replacing the transform registered as `name` before, if any.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        transform: impl Fn(&mut Record) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.transforms.insert(name.into(), Arc::new(transform));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RecordTransform> {
+        self.transforms.get(name)
+    }
+
+    /// Returns the transforms registered as `names`, in order, or an error naming the first one that isn't registered.
+    fn resolve(&self, names: &[String]) -> Result<Vec<RecordTransform>, BoxedError> {
+        names
+            .iter()
+            .map(|name| {
+                self.get(name)
+                    .cloned()
+                    .ok_or_else(|| BoxedError::from(format!("Transform {name} isn't registered")))
+            })
+            .collect()
+    }
+}
+
+impl Debug for TransformRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = self.transforms.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_struct("TransformRegistry")
+            .field("transforms", &names)
+            .finish()
+    }
+}
+
+/// Applies transforms of a [`TransformRegistry`], referenced by name, to every record it receives, in the order they're named.
+///
+/// Both records of an update are transformed. Naming a transform that isn't registered fails the DAG's validation.
+#[derive(Debug)]
+pub struct TransformProcessorFactory {
+    registry: Arc<TransformRegistry>,
+    names: Vec<String>,
+}
+
+impl TransformProcessorFactory {
+    pub fn new(
+        registry: Arc<TransformRegistry>,
+        names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            registry,
+            names: names.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl ProcessorFactory for TransformProcessorFactory {
+    fn get_output_schema(
+        &self,
+        _output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        self.registry.resolve(&self.names)?;
+        Ok(input_schemas[&DEFAULT_PORT_HANDLE].clone())
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        vec![DEFAULT_PORT_HANDLE]
+    }
+
+    fn build(
+        &self,
+        _input_schemas: HashMap<PortHandle, Schema>,
+        _output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        Ok(Box::new(TransformProcessor {
+            transforms: self.registry.resolve(&self.names)?,
+        }))
+    }
+
+    fn type_name(&self) -> String {
+        "Transform".to_owned()
+    }
+
+    fn id(&self) -> String {
+        "Transform".to_owned()
+    }
+
+    fn state_backend(&self) -> StateBackend {
+        StateBackend::Memory
+    }
+}
+
+struct TransformProcessor {
+    transforms: Vec<RecordTransform>,
+}
+
+impl Debug for TransformProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransformProcessor")
+            .field("transforms", &self.transforms.len())
+            .finish()
+    }
+}
+
+impl TransformProcessor {
+    fn transform(&self, record: &mut Record) {
+        for transform in &self.transforms {
+            transform(record);
+        }
+    }
+}
+
+impl Processor for TransformProcessor {
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        _from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        let mut op = op.load(record_store)?;
+        match &mut op {
+            Operation::Insert { new } => self.transform(new),
+            Operation::Delete { old } => self.transform(old),
+            Operation::Update { old, new } => {
+                self.transform(old);
+                self.transform(new);
+            }
+        }
+        fw.send(
+            ProcessorOperation::new(&op, record_store)?,
+            DEFAULT_PORT_HANDLE,
+        );
+        Ok(())
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::types::Field;
+
+    use crate::checkpoint::create_checkpoint_for_test;
+    use crate::executor::DagExecutor;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_dag_with_registered_transform() {
+        let count: u64 = 100;
+
+        let mut registry = TransformRegistry::new();
+        registry.register("uppercase_name", |record: &mut Record| {
+            if let Field::String(name) = &mut record.values[0] {
+                *name = name.to_uppercase();
+            }
+        });
+        let registry = Arc::new(registry);
+
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let proc_handle = NodeHandle::new(Some(1), 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = |transform: &str| {
+            let latch = Arc::new(AtomicBool::new(true));
+            let sink = RecordingSinkFactory::new()
+                .record_ops()
+                .stop_after(count, latch.clone());
+            let recording = sink.recording();
+            let dag = DagBuilder::new()
+                .source(
+                    source_handle.clone(),
+                    GeneratorSourceFactory::new(count, latch, false),
+                )
+                .processor(
+                    proc_handle.clone(),
+                    TransformProcessorFactory::new(registry.clone(), [transform]),
+                )
+                .sink(sink_handle.clone(), sink)
+                .edge(
+                    &source_handle,
+                    GENERATOR_SOURCE_OUTPUT_PORT,
+                    &proc_handle,
+                    DEFAULT_PORT_HANDLE,
+                )
+                .edge(
+                    &proc_handle,
+                    DEFAULT_PORT_HANDLE,
+                    &sink_handle,
+                    RECORDING_SINK_INPUT_PORT,
+                )
+                .build()
+                .unwrap();
+            (dag, recording)
+        };
+
+        let (uppercase, recording) = dag("uppercase_name");
+        run_dag(uppercase, Default::default()).await.unwrap();
+        let recording = recording.lock();
+        assert_eq!(recording.ops.len() as u64, count);
+        for (n, recorded) in (1..).zip(recording.ops.iter()) {
+            let Operation::Insert { new } = &recorded.op else {
+                panic!("Expected an insert, got {:?}", recorded.op);
+            };
+            assert_eq!(new.values[0], Field::String(format!("KEY_{n}")));
+        }
+
+        // Referencing a transform that isn't registered fails validation.
+        let (lowercase, _) = dag("lowercase_name");
+        let (_temp_dir, checkpoint) = create_checkpoint_for_test().await;
+        assert!(DagExecutor::new(lowercase, checkpoint, Default::default())
+            .await
+            .is_err());
+    }
+}
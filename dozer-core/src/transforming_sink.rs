@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use dozer_log::storage::Object;
+use dozer_recordstore::{ProcessorRecordStore, ProcessorRecordStoreDeserializer};
+use dozer_types::errors::internal::BoxedError;
+use dozer_types::types::Schema;
+
+use crate::channels::ProcessorChannelForwarder;
+use crate::epoch::Epoch;
+use crate::executor_operation::ProcessorOperation;
+use crate::node::{
+    PortHandle, Processor, ProcessorFactory, TransformingSink, TransformingSinkFactory,
+};
+
+/// Runs a [`TransformingSink`] as a processor.
+#[derive(Debug)]
+pub(crate) struct TransformingSinkProcessorFactory(pub Box<dyn TransformingSinkFactory>);
+
+impl ProcessorFactory for TransformingSinkProcessorFactory {
+    fn get_output_schema(
+        &self,
+        output_port: &PortHandle,
+        input_schemas: &HashMap<PortHandle, Schema>,
+    ) -> Result<Schema, BoxedError> {
+        self.0.get_output_schema(output_port, input_schemas)
+    }
+
+    fn get_input_ports(&self) -> Vec<PortHandle> {
+        self.0.get_input_ports()
+    }
+
+    fn get_output_ports(&self) -> Vec<PortHandle> {
+        self.0.get_output_ports()
+    }
+
+    fn build(
+        &self,
+        input_schemas: HashMap<PortHandle, Schema>,
+        output_schemas: HashMap<PortHandle, Schema>,
+        _record_store: &ProcessorRecordStoreDeserializer,
+        _checkpoint_data: Option<Vec<u8>>,
+    ) -> Result<Box<dyn Processor>, BoxedError> {
+        Ok(Box::new(TransformingSinkProcessor(
+            self.0.build(input_schemas, output_schemas)?,
+        )))
+    }
+
+    fn type_name(&self) -> String {
+        self.0.type_name()
+    }
+
+    fn id(&self) -> String {
+        self.0.type_name()
+    }
+}
+
+#[derive(Debug)]
+struct TransformingSinkProcessor(Box<dyn TransformingSink>);
+
+impl Processor for TransformingSinkProcessor {
+    fn before_commit(
+        &mut self,
+        epoch_details: &Epoch,
+        record_store: &ProcessorRecordStore,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.0.commit(epoch_details, record_store, fw)
+    }
+
+    fn commit(&self, _epoch_details: &Epoch) -> Result<(), BoxedError> {
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        from_port: PortHandle,
+        record_store: &ProcessorRecordStore,
+        op: ProcessorOperation,
+        fw: &mut dyn ProcessorChannelForwarder,
+    ) -> Result<(), BoxedError> {
+        self.0.process(from_port, record_store, op, fw)
+    }
+
+    fn serialize(
+        &mut self,
+        _record_store: &ProcessorRecordStore,
+        _object: Object,
+    ) -> Result<(), BoxedError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+
+    use crate::executor::ExecutorOptions;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{
+        BatchCountingSinkFactory, RecordingSinkFactory, BATCH_COUNTING_SINK_INPUT_PORT,
+        BATCH_COUNTING_SINK_OUTPUT_PORT, RECORDING_SINK_INPUT_PORT,
+    };
+    use crate::tests::sources::{BackfillSourceFactory, BACKFILL_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    #[tokio::test]
+    async fn test_run_dag_with_transforming_sink() {
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let transforming_sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 3.to_string());
+
+        let written = Arc::new(AtomicU64::new(0));
+        let sink = RecordingSinkFactory::new();
+        let recording = sink.recording();
+        let dag = DagBuilder::new()
+            .source(source_handle.clone(), BackfillSourceFactory::new(0, 100))
+            .transforming_sink(
+                transforming_sink_handle.clone(),
+                BatchCountingSinkFactory::new(written.clone()),
+            )
+            .sink(sink_handle.clone(), sink)
+            .edge(
+                &source_handle,
+                BACKFILL_SOURCE_OUTPUT_PORT,
+                &transforming_sink_handle,
+                BATCH_COUNTING_SINK_INPUT_PORT,
+            )
+            .edge(
+                &transforming_sink_handle,
+                BATCH_COUNTING_SINK_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+
+        // Only commit on batch size. The source never finishes backfilling.
+        let options = ExecutorOptions {
+            backfill_commit_sz: 10,
+            backfill_commit_time_threshold: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        run_dag(dag, options).await.unwrap();
+
+        assert_eq!(written.load(Ordering::SeqCst), 100);
+        // One count event is forwarded with each of the ten batches.
+        let epoch_sizes = recording.lock().epoch_sizes.clone();
+        assert_eq!(epoch_sizes[..10], [1; 10]);
+        assert_eq!(epoch_sizes.iter().sum::<usize>(), 10);
+    }
+}
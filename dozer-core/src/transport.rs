@@ -0,0 +1,234 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam::channel::{
+    bounded, unbounded, Receiver, RecvError, SendError, Sender, TryRecvError,
+};
+
+use crate::executor_operation::ExecutorOperation;
+
+/// Creates the channels that carry messages through edges, set per output port with `OutputPortDefOptions::transport`.
+pub trait ChannelTransport: Send + Sync + Debug {
+    /// Creates a channel of an edge, holding up to `capacity` messages, or any number if `None`.
+    ///
+    /// Nodes send and receive through the returned endpoints. Both may be shared by several threads.
+    fn channel(
+        &self,
+        capacity: Option<usize>,
+    ) -> (Arc<dyn TransportSender>, Arc<dyn TransportReceiver>);
+}
+
+/// The sending end of a channel created by a [`ChannelTransport`].
+pub trait TransportSender: Send + Sync + Debug {
+    /// Sends `op`, blocking while the channel is full. Fails with `op` if the receiving end is gone.
+    fn send(&self, op: ExecutorOperation) -> Result<(), SendError<ExecutorOperation>>;
+}
+
+/// The receiving end of a channel created by a [`ChannelTransport`].
+pub trait TransportReceiver: Send + Sync + Debug {
+    /// Blocks until a message arrives. Fails once the channel is empty and all senders are gone.
+    fn recv(&self) -> Result<ExecutorOperation, RecvError>;
+
+    fn try_recv(&self) -> Result<ExecutorOperation, TryRecvError>;
+
+    /// Number of messages queued, for metrics and debugging.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The in-process channel the messages arrive on, if any.
+    ///
+    /// The executor waits on these together with the other inputs of a node. Receivers without one are polled
+    /// with [`try_recv`](Self::try_recv) every [`POLL_INTERVAL`] while the node waits.
+    fn as_crossbeam(&self) -> Option<&Receiver<ExecutorOperation>> {
+        None
+    }
+}
+
+/// How often receivers without [`TransportReceiver::as_crossbeam`] are polled while their node waits.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+impl TransportSender for Sender<ExecutorOperation> {
+    fn send(&self, op: ExecutorOperation) -> Result<(), SendError<ExecutorOperation>> {
+        Sender::send(self, op)
+    }
+}
+
+impl TransportReceiver for Receiver<ExecutorOperation> {
+    fn recv(&self) -> Result<ExecutorOperation, RecvError> {
+        Receiver::recv(self)
+    }
+
+    fn try_recv(&self) -> Result<ExecutorOperation, TryRecvError> {
+        Receiver::try_recv(self)
+    }
+
+    fn len(&self) -> usize {
+        Receiver::len(self)
+    }
+
+    fn as_crossbeam(&self) -> Option<&Receiver<ExecutorOperation>> {
+        Some(self)
+    }
+}
+
+/// Carries messages through in-process channels. The default transport.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InProcessTransport;
+
+impl ChannelTransport for InProcessTransport {
+    fn channel(
+        &self,
+        capacity: Option<usize>,
+    ) -> (Arc<dyn TransportSender>, Arc<dyn TransportReceiver>) {
+        let (sender, receiver) = match capacity {
+            Some(capacity) => bounded(capacity),
+            None => unbounded(),
+        };
+        (Arc::new(sender), Arc::new(receiver))
+    }
+}
+
+/// The transport of an edge, [`InProcessTransport`] by default.
+#[derive(Debug, Clone, Default)]
+pub struct EdgeTransport(Option<Arc<dyn ChannelTransport>>);
+
+impl EdgeTransport {
+    pub fn new(transport: impl ChannelTransport + 'static) -> Self {
+        Self(Some(Arc::new(transport)))
+    }
+
+    pub(crate) fn channel(
+        &self,
+        capacity: Option<usize>,
+    ) -> (Arc<dyn TransportSender>, Arc<dyn TransportReceiver>) {
+        match &self.0 {
+            Some(transport) => transport.channel(capacity),
+            None => InProcessTransport.channel(capacity),
+        }
+    }
+}
+
+/// Transports may hold state, so two custom ones are only equal if they're clones of each other.
+impl PartialEq for EdgeTransport {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for EdgeTransport {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use dozer_log::tokio;
+    use dozer_types::node::NodeHandle;
+    use dozer_types::parking_lot::Mutex;
+
+    use crate::node::OutputPortDefOptions;
+    use crate::tests::run_dag;
+    use crate::tests::sinks::{RecordingSinkFactory, RECORDING_SINK_INPUT_PORT};
+    use crate::tests::sources::{GeneratorSourceFactory, GENERATOR_SOURCE_OUTPUT_PORT};
+    use crate::DagBuilder;
+
+    use super::*;
+
+    /// Records every message sent, and hides the in-process channel from the receiving node, so it's polled.
+    #[derive(Debug)]
+    struct RecordingTransport {
+        messages: Arc<Mutex<Vec<ExecutorOperation>>>,
+    }
+
+    impl ChannelTransport for RecordingTransport {
+        fn channel(
+            &self,
+            capacity: Option<usize>,
+        ) -> (Arc<dyn TransportSender>, Arc<dyn TransportReceiver>) {
+            let (sender, receiver) = InProcessTransport.channel(capacity);
+            (
+                Arc::new(RecordingSender {
+                    sender,
+                    messages: self.messages.clone(),
+                }),
+                Arc::new(PolledReceiver(receiver)),
+            )
+        }
+    }
+
+    #[derive(Debug)]
+    struct RecordingSender {
+        sender: Arc<dyn TransportSender>,
+        messages: Arc<Mutex<Vec<ExecutorOperation>>>,
+    }
+
+    impl TransportSender for RecordingSender {
+        fn send(&self, op: ExecutorOperation) -> Result<(), SendError<ExecutorOperation>> {
+            self.messages.lock().push(op.clone());
+            self.sender.send(op)
+        }
+    }
+
+    #[derive(Debug)]
+    struct PolledReceiver(Arc<dyn TransportReceiver>);
+
+    impl TransportReceiver for PolledReceiver {
+        fn recv(&self) -> Result<ExecutorOperation, RecvError> {
+            self.0.recv()
+        }
+
+        fn try_recv(&self) -> Result<ExecutorOperation, TryRecvError> {
+            self.0.try_recv()
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_dag_with_custom_transport() {
+        let count: u64 = 1_000;
+        let messages = Arc::new(Mutex::new(vec![]));
+        let source_handle = NodeHandle::new(None, 1.to_string());
+        let sink_handle = NodeHandle::new(Some(1), 2.to_string());
+        let dag = DagBuilder::new()
+            .source(
+                source_handle.clone(),
+                GeneratorSourceFactory::new(count, Arc::new(AtomicBool::new(false)), false)
+                    .with_port_options(OutputPortDefOptions {
+                        transport: EdgeTransport::new(RecordingTransport {
+                            messages: messages.clone(),
+                        }),
+                        ..Default::default()
+                    }),
+            )
+            .sink(sink_handle.clone(), RecordingSinkFactory::new())
+            .edge(
+                &source_handle,
+                GENERATOR_SOURCE_OUTPUT_PORT,
+                &sink_handle,
+                RECORDING_SINK_INPUT_PORT,
+            )
+            .build()
+            .unwrap();
+        run_dag(dag, Default::default()).await.unwrap();
+
+        let messages = messages.lock();
+        let num_ops = messages
+            .iter()
+            .filter(|message| matches!(message, ExecutorOperation::Op { .. }))
+            .count();
+        assert_eq!(num_ops, count as usize);
+        assert!(messages
+            .iter()
+            .any(|message| matches!(message, ExecutorOperation::Terminate)));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    num::NonZeroU16,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use dozer_types::{
+    bytes::Bytes,
+    grpc_types::internal::{self, storage_response},
+    parking_lot::Mutex,
+    tonic::async_trait,
+};
+use futures_util::{stream::BoxStream, StreamExt};
+
+use super::{Error, ListObjectsOutput, ListedObject, Storage};
+
+/// Keeps objects in memory, for tests and for embedding a pipeline without a file system.
+///
+/// Clones share the same objects, so a clone can be used to reopen what another one wrote.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStorage {
+    objects: Arc<Mutex<BTreeMap<String, (Bytes, SystemTime)>>>,
+    /// Map from upload id to the key and the parts uploaded so far.
+    multipart_uploads: Arc<Mutex<HashMap<String, (String, BTreeMap<NonZeroU16, Vec<u8>>)>>>,
+    next_upload_id: Arc<AtomicU64>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for InMemoryStorage {
+    /// There's no in memory storage in the protocol, so it's described as local storage without a root.
+    fn describe(&self) -> storage_response::Storage {
+        storage_response::Storage::Local(internal::LocalStorage {
+            root: String::new(),
+        })
+    }
+
+    async fn put_object(&self, key: String, data: Vec<u8>) -> Result<(), Error> {
+        self.objects
+            .lock()
+            .insert(key, (data.into(), SystemTime::now()));
+        Ok(())
+    }
+
+    async fn create_multipart_upload(&self, key: String) -> Result<String, Error> {
+        let upload_id = self
+            .next_upload_id
+            .fetch_add(1, Ordering::SeqCst)
+            .to_string();
+        self.multipart_uploads
+            .lock()
+            .insert(upload_id.clone(), (key, BTreeMap::new()));
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        key: String,
+        upload_id: String,
+        part_number: NonZeroU16,
+        data: Vec<u8>,
+    ) -> Result<String, Error> {
+        let mut multipart_uploads = self.multipart_uploads.lock();
+        match multipart_uploads.get_mut(&upload_id) {
+            Some((upload_key, parts)) if *upload_key == key => {
+                parts.insert(part_number, data);
+                Ok(part_number.to_string())
+            }
+            _ => Err(Error::UploadNotFound { key, upload_id }),
+        }
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: String,
+        upload_id: String,
+        mut parts: Vec<(NonZeroU16, String)>,
+    ) -> Result<(), Error> {
+        parts.sort();
+
+        let uploaded = {
+            let mut multipart_uploads = self.multipart_uploads.lock();
+            match multipart_uploads.get(&upload_id) {
+                Some((upload_key, _)) if *upload_key == key => {
+                    multipart_uploads
+                        .remove(&upload_id)
+                        .expect("We just checked it exists")
+                        .1
+                }
+                _ => return Err(Error::UploadNotFound { key, upload_id }),
+            }
+        };
+
+        let mut data = vec![];
+        for part_number in parts.into_iter().map(|(part_number, _)| part_number) {
+            let part = uploaded.get(&part_number).ok_or(Error::UploadNotFound {
+                key: key.clone(),
+                upload_id: upload_id.clone(),
+            })?;
+            data.extend_from_slice(part);
+        }
+        self.put_object(key, data).await
+    }
+
+    async fn list_objects(
+        &self,
+        prefix: String,
+        _continuation_token: Option<String>,
+    ) -> Result<ListObjectsOutput, Error> {
+        let objects = self
+            .objects
+            .lock()
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(key, (_, last_modified))| ListedObject {
+                key: key.clone(),
+                last_modified: *last_modified,
+            })
+            .collect();
+        Ok(ListObjectsOutput {
+            objects,
+            continuation_token: None,
+        })
+    }
+
+    async fn get_object(
+        &self,
+        key: String,
+    ) -> Result<BoxStream<Result<Bytes, std::io::Error>>, Error> {
+        let data = match self.objects.lock().get(&key) {
+            Some((data, _)) => data.clone(),
+            None => return Err(Error::FileSystem(key, std::io::ErrorKind::NotFound.into())),
+        };
+        Ok(futures_util::stream::once(async move { Ok(data) }).boxed())
+    }
+
+    async fn delete_objects(&self, keys: Vec<String>) -> Result<(), Error> {
+        if keys.is_empty() {
+            return Err(Error::EmptyDeleteObjectsRequest);
+        }
+
+        let mut objects = self.objects.lock();
+        for key in keys {
+            objects.remove(&key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_storage_basic() {
+        super::super::tests::test_storage_basic(&InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_storage_multipart() {
+        super::super::tests::test_storage_multipart(&InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_storage_prefix() {
+        super::super::tests::test_storage_prefix(&InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_storage_empty_multipart() {
+        super::super::tests::test_storage_empty_multipart(&InMemoryStorage::new()).await;
+    }
+}
@@ -123,6 +123,10 @@ mod local;
 
 pub use local::LocalStorage;
 
+mod memory;
+
+pub use memory::InMemoryStorage;
+
 mod queue;
 pub use queue::Queue;
 
@@ -1,44 +1,76 @@
+use std::sync::Arc;
+
 use dozer_types::log::error;
+use dozer_types::parking_lot::Mutex;
 use tokio::sync::mpsc::error::SendError;
 
 use super::queue::Queue;
 
 #[derive(Debug)]
 pub struct Object {
-    queue: Queue,
+    /// `None` for objects that are only written to `copy`.
+    queue: Option<Queue>,
     key: String,
     data: Vec<u8>,
+    copy: Option<Arc<Mutex<Vec<u8>>>>,
 }
 
 impl Object {
     pub fn new(queue: Queue, key: String) -> Result<Self, SendError<String>> {
         queue.create_upload(key.clone())?;
         Ok(Self {
-            queue,
+            queue: Some(queue),
             key,
             data: vec![],
+            copy: None,
         })
     }
 
+    /// An object that uploads nothing, only appending everything written to `copy`.
+    ///
+    /// Lets a writer split its object between several writers, by giving each one of these and writing their data to it.
+    pub fn in_memory(copy: Arc<Mutex<Vec<u8>>>) -> Self {
+        Self {
+            queue: None,
+            key: String::new(),
+            data: vec![],
+            copy: Some(copy),
+        }
+    }
+
+    /// Also appends everything written to `copy`, so the data can be read back without downloading it.
+    pub fn with_copy(mut self, copy: Arc<Mutex<Vec<u8>>>) -> Self {
+        self.copy = Some(copy);
+        self
+    }
+
     pub fn write(&mut self, data: &[u8]) -> Result<(), SendError<String>> {
+        if let Some(copy) = &self.copy {
+            copy.lock().extend_from_slice(data);
+        }
+        let Some(queue) = &self.queue else {
+            return Ok(());
+        };
         self.data.extend_from_slice(data);
         if self.data.len() >= 100 * 1024 * 1024 {
-            self.queue
-                .upload_chunk(self.key.clone(), std::mem::take(&mut self.data))?;
+            queue.upload_chunk(self.key.clone(), std::mem::take(&mut self.data))?;
         }
         Ok(())
     }
 
-    pub fn queue(&self) -> &Queue {
-        &self.queue
+    /// The queue the object is uploaded through, `None` for `in_memory` objects.
+    pub fn queue(&self) -> Option<&Queue> {
+        self.queue.as_ref()
     }
 
     fn drop(&mut self) -> Result<(), SendError<String>> {
+        let Some(queue) = &self.queue else {
+            return Ok(());
+        };
         if !self.data.is_empty() {
-            self.queue
-                .upload_chunk(self.key.clone(), std::mem::take(&mut self.data))?;
+            queue.upload_chunk(self.key.clone(), std::mem::take(&mut self.data))?;
         }
-        self.queue.complete_upload(std::mem::take(&mut self.key))?;
+        queue.complete_upload(std::mem::take(&mut self.key))?;
         Ok(())
     }
 }
@@ -77,4 +109,14 @@ mod tests {
             vec![0; num_bytes]
         );
     }
+
+    #[test]
+    fn in_memory_object_should_only_write_copy() {
+        let copy = Arc::new(Mutex::new(vec![]));
+        let mut object = Object::in_memory(copy.clone());
+        object.write(&[1, 2]).unwrap();
+        object.write(&[3]).unwrap();
+        drop(object);
+        assert_eq!(*copy.lock(), vec![1, 2, 3]);
+    }
 }
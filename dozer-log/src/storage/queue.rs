@@ -17,7 +17,7 @@ use tokio::{
     task::JoinHandle,
 };
 
-use super::Storage;
+use super::{LocalStorage, Storage};
 
 #[derive(Debug, Clone)]
 pub struct Queue {
@@ -61,6 +61,35 @@ impl Queue {
         self.send_request(key, RequestKind::UploadObject(data))
     }
 
+    /// Deletes every object whose key starts with `prefix`, after the requests queued before it.
+    pub fn delete_prefix(
+        &self,
+        prefix: String,
+    ) -> Result<oneshot::Receiver<String>, SendError<String>> {
+        self.send_request(prefix, RequestKind::DeletePrefix)
+    }
+
+    /// Copies every object whose key starts with `prefix` to a local storage rooted at `destination`, without the prefix,
+    /// after the requests queued before it.
+    ///
+    /// Unlike the other requests, this one is sent without blocking the thread.
+    pub async fn copy_prefix(
+        &self,
+        prefix: String,
+        destination: String,
+    ) -> Result<oneshot::Receiver<String>, SendError<String>> {
+        let (return_sender, return_receiver) = oneshot::channel();
+        self.sender
+            .send(Request {
+                key: prefix,
+                kind: RequestKind::CopyPrefix { destination },
+                return_sender,
+            })
+            .await
+            .map_err(|e| SendError(e.0.key))?;
+        Ok(return_receiver)
+    }
+
     fn send_request(
         &self,
         key: String,
@@ -91,6 +120,8 @@ enum RequestKind {
     UploadChunk(Vec<u8>),
     CompleteUpload,
     UploadObject(Vec<u8>),
+    DeletePrefix,
+    CopyPrefix { destination: String },
 }
 
 struct MultipartUpload {
@@ -190,6 +221,46 @@ async fn handle_request(
         RequestKind::UploadObject(data) => {
             storage.put_object(key.to_string(), data).await?;
         }
+        RequestKind::DeletePrefix => {
+            let mut continuation_token = None;
+            loop {
+                let objects = storage
+                    .list_objects(key.to_string(), continuation_token)
+                    .await?;
+                let keys = objects
+                    .objects
+                    .into_iter()
+                    .map(|object| object.key)
+                    .collect::<Vec<_>>();
+                if !keys.is_empty() {
+                    storage.delete_objects(keys).await?;
+                }
+                continuation_token = objects.continuation_token;
+                if continuation_token.is_none() {
+                    break;
+                }
+            }
+        }
+        RequestKind::CopyPrefix { destination } => {
+            let destination = LocalStorage::new(destination).await?;
+            let mut continuation_token = None;
+            loop {
+                let objects = storage
+                    .list_objects(key.to_string(), continuation_token)
+                    .await?;
+                for object in objects.objects {
+                    let data = storage.download_object(object.key.clone()).await?;
+                    let relative_key = object.key[key.len()..].trim_start_matches('/');
+                    destination
+                        .put_object(relative_key.to_string(), data)
+                        .await?;
+                }
+                continuation_token = objects.continuation_token;
+                if continuation_token.is_none() {
+                    break;
+                }
+            }
+        }
     }
     Ok(())
 }
@@ -237,6 +308,72 @@ mod tests {
         assert!(multipart_uploads.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_handle_request_delete_prefix() {
+        let (_temp_dir, storage) = create_temp_dir_local_storage().await;
+        let mut multipart_uploads = HashMap::new();
+        for key in ["a/1", "a/2", "b/1"] {
+            storage.put_object(key.to_string(), vec![0]).await.unwrap();
+        }
+        handle_request(
+            &*storage,
+            &mut multipart_uploads,
+            "a/",
+            RequestKind::DeletePrefix,
+        )
+        .await
+        .unwrap();
+        let keys = storage
+            .list_objects(String::new(), None)
+            .await
+            .unwrap()
+            .objects
+            .into_iter()
+            .map(|object| object.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["b/1"]);
+    }
+
+    #[tokio::test]
+    async fn test_handle_request_copy_prefix() {
+        let (_temp_dir, storage) = create_temp_dir_local_storage().await;
+        let (destination_dir, destination) = create_temp_dir_local_storage().await;
+        let mut multipart_uploads = HashMap::new();
+        for key in ["a/1", "a/b/2", "b/1"] {
+            storage
+                .put_object(key.to_string(), key.as_bytes().to_vec())
+                .await
+                .unwrap();
+        }
+        handle_request(
+            &*storage,
+            &mut multipart_uploads,
+            "a",
+            RequestKind::CopyPrefix {
+                destination: destination_dir.path().to_str().unwrap().to_string(),
+            },
+        )
+        .await
+        .unwrap();
+        let mut keys = destination
+            .list_objects(String::new(), None)
+            .await
+            .unwrap()
+            .objects
+            .into_iter()
+            .map(|object| object.key)
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["1", "b/2"]);
+        assert_eq!(
+            destination
+                .download_object("b/2".to_string())
+                .await
+                .unwrap(),
+            b"a/b/2"
+        );
+    }
+
     #[tokio::test]
     async fn test_handle_request_upload_already_exists() {
         let (_temp_dir, storage) = create_temp_dir_local_storage().await;
@@ -6,9 +6,9 @@ use std::{
 
 use dozer_types::{bincode, parking_lot::RwLock, types::Field};
 
-use crate::RecordStoreError;
+use crate::{RecordStoreError, SchemaVersions};
 
-use super::{RecordRef, RecordRefInner};
+use super::{FieldRef, RecordRef, RecordRefInner};
 
 pub trait StoreRecord {
     fn store_record(&self, record: &RecordRef) -> Result<(), RecordStoreError>;
@@ -23,6 +23,8 @@ pub trait StoreRecord {
 #[derive(Debug)]
 pub struct ProcessorRecordStore {
     inner: RwLock<ProcessorRecordStoreInner>,
+    /// Live records are always of the current version, which slices are tagged with.
+    versions: Arc<SchemaVersions>,
 }
 
 #[derive(Debug, Default)]
@@ -38,6 +40,7 @@ impl ProcessorRecordStore {
     pub fn new() -> Result<Self, RecordStoreError> {
         Ok(Self {
             inner: RwLock::new(Default::default()),
+            versions: Default::default(),
         })
     }
 
@@ -46,6 +49,8 @@ impl ProcessorRecordStore {
     }
 
     /// Returns the serialized data and the `start` for next `serialize_slice` call.
+    ///
+    /// The data is tagged with the current schema version.
     pub fn serialize_slice(&self, start: usize) -> Result<(Vec<u8>, usize), RecordStoreError> {
         let inner = self.inner.read();
         let slice = inner
@@ -53,11 +58,12 @@ impl ProcessorRecordStore {
             .range(start..)
             .filter_map(|(&id, weak)| weak.upgrade().map(|record| (id, RecordRef(record))))
             .collect::<Vec<_>>();
-        let data =
-            bincode::serialize(&slice).map_err(|e| RecordStoreError::SerializationError {
-                typ: "[(usize, RecordRef)]",
+        let data = bincode::serialize(&(self.versions.current_version(), slice)).map_err(|e| {
+            RecordStoreError::SerializationError {
+                typ: "(u32, [(usize, RecordRef)])",
                 reason: Box::new(e),
-            })?;
+            }
+        })?;
         Ok((data, inner.next_index))
     }
 
@@ -70,6 +76,27 @@ impl ProcessorRecordStore {
             .expect("RecordRef not found in ProcessorRecordStore") as u64
     }
 
+    /// Returns a deserializer holding the records that are live now at their indices, sharing them instead of copying.
+    pub fn pin(&self) -> ProcessorRecordStoreDeserializer {
+        let inner = self.inner.read();
+        let records = inner
+            .records
+            .iter()
+            .filter_map(|(&id, weak)| weak.upgrade().map(|record| (id, RecordRef(record))))
+            .collect::<BTreeMap<_, _>>();
+        let record_pointer_to_index = records
+            .iter()
+            .map(|(&id, record)| (record.id(), id))
+            .collect();
+        ProcessorRecordStoreDeserializer {
+            inner: RwLock::new(ProcessorRecordStoreDeserializerInner {
+                records,
+                record_pointer_to_index,
+            }),
+            versions: self.versions.clone(),
+        }
+    }
+
     pub fn vacuum(&self) {
         let mut inner = self.inner.write();
         let inner = inner.deref_mut();
@@ -112,6 +139,7 @@ impl StoreRecord for ProcessorRecordStore {
 #[derive(Debug)]
 pub struct ProcessorRecordStoreDeserializer {
     inner: RwLock<ProcessorRecordStoreDeserializerInner>,
+    versions: Arc<SchemaVersions>,
 }
 
 #[derive(Debug)]
@@ -127,15 +155,28 @@ impl ProcessorRecordStoreDeserializer {
                 records: BTreeMap::new(),
                 record_pointer_to_index: HashMap::new(),
             }),
+            versions: Default::default(),
         })
     }
 
+    /// Migrates the records of slices tagged with older versions to the current one of `versions` when they're deserialized.
+    pub fn with_schema_versions(mut self, versions: Arc<SchemaVersions>) -> Self {
+        self.versions = versions;
+        self
+    }
+
     pub fn deserialize_and_extend(&self, data: &[u8]) -> Result<(), RecordStoreError> {
-        let slice: Vec<(usize, RecordRef)> =
-            bincode::deserialize(data).map_err(|e| RecordStoreError::DeserializationError {
-                typ: "[(usize, RecordRef)]",
+        let (version, mut slice): (u32, Vec<(usize, RecordRef)>) = bincode::deserialize(data)
+            .map_err(|e| RecordStoreError::DeserializationError {
+                typ: "(u32, [(usize, RecordRef)])",
                 reason: Box::new(e),
             })?;
+        if version != self.versions.current_version() {
+            for (_, record) in &mut slice {
+                let values = record.load().iter().map(FieldRef::cloned).collect();
+                *record = RecordRef::new(self.versions.migrate(version, values)?);
+            }
+        }
 
         let mut inner = self.inner.write();
 
@@ -175,6 +216,7 @@ impl ProcessorRecordStoreDeserializer {
                     .collect(),
                 record_pointer_to_index: inner.record_pointer_to_index,
             }),
+            versions: self.versions,
         }
     }
 }
@@ -269,4 +311,21 @@ mod tests {
             next_index as u64
         );
     }
+
+    #[test]
+    fn test_pin_keeps_dropped_records() {
+        let record_store = ProcessorRecordStore::new().unwrap();
+        let record = record_store.create_ref(&[Field::Int(0)]).unwrap();
+        let index = record_store.serialize_ref(&record);
+        let pinned = record_store.pin();
+
+        let weak = Arc::downgrade(&record.0);
+        drop(record);
+        record_store.vacuum();
+        assert!(weak.upgrade().is_some());
+
+        let record = pinned.deserialize_ref(index).unwrap();
+        assert_eq!(record.load()[0].cloned(), Field::Int(0));
+        assert_eq!(pinned.into_record_store().serialize_ref(&record), index);
+    }
 }
@@ -1,3 +1,6 @@
+use std::path::Path;
+use std::sync::Arc;
+
 use dozer_types::{
     bincode,
     errors::internal::BoxedError,
@@ -31,6 +34,8 @@ pub enum RecordStoreError {
     RocksdbRecordNotFound(u64),
     #[error("Bincode error: {0}")]
     Bincode(#[from] bincode::Error),
+    #[error("Unknown schema version: {0}")]
+    UnknownSchemaVersion(u32),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -107,11 +112,19 @@ pub enum ProcessorRecordStore {
 
 impl ProcessorRecordStore {
     pub fn new(record_store: RecordStore) -> Result<Self, RecordStoreError> {
+        Self::new_in(record_store, None)
+    }
+
+    /// Like `new`, but a RocksDB store keeps its files under `spill_dir` if set.
+    pub fn new_in(
+        record_store: RecordStore,
+        spill_dir: Option<&Path>,
+    ) -> Result<Self, RecordStoreError> {
         match record_store {
             RecordStore::InMemory => Ok(Self::InMemory(in_memory::ProcessorRecordStore::new()?)),
-            RecordStore::Rocksdb(config) => {
-                Ok(Self::Rocksdb(rocksdb::ProcessorRecordStore::new(config)?))
-            }
+            RecordStore::Rocksdb(config) => Ok(Self::Rocksdb(rocksdb::ProcessorRecordStore::new(
+                config, spill_dir,
+            )?)),
         }
     }
 
@@ -148,11 +161,35 @@ impl ProcessorRecordStore {
         bincode::serialize(&record)
     }
 
+    /// Returns a deserializer of the records that are live now, so records serialized now can still be deserialized
+    /// after they're dropped. Records are shared, not copied. `None` for the RocksDB store.
+    pub fn pin(&self) -> Option<ProcessorRecordStoreDeserializer> {
+        match self {
+            Self::InMemory(store) => Some(ProcessorRecordStoreDeserializer::InMemory(store.pin())),
+            Self::Rocksdb(_) => None,
+        }
+    }
+
     pub fn compact(&self) {
         if let Self::InMemory(store) = self {
             store.vacuum();
         }
     }
+
+    /// Size of the records held in memtables that `flush` can spill to disk. Always 0 for the in memory store.
+    pub fn memtable_size(&self) -> Result<usize, RecordStoreError> {
+        match self {
+            Self::InMemory(_) => Ok(0),
+            Self::Rocksdb(store) => store.memtable_size(),
+        }
+    }
+
+    pub fn flush(&self) -> Result<(), RecordStoreError> {
+        match self {
+            Self::InMemory(_) => Ok(()),
+            Self::Rocksdb(store) => store.flush(),
+        }
+    }
 }
 
 impl StoreRecord for ProcessorRecordStore {
@@ -182,13 +219,31 @@ pub enum ProcessorRecordStoreDeserializer {
 
 impl ProcessorRecordStoreDeserializer {
     pub fn new(record_store: RecordStore) -> Result<Self, RecordStoreError> {
+        Self::new_in(record_store, None)
+    }
+
+    /// Like `new`, but a RocksDB store keeps its files under `spill_dir` if set.
+    pub fn new_in(
+        record_store: RecordStore,
+        spill_dir: Option<&Path>,
+    ) -> Result<Self, RecordStoreError> {
         match record_store {
             RecordStore::InMemory => Ok(Self::InMemory(
                 in_memory::ProcessorRecordStoreDeserializer::new()?,
             )),
-            RecordStore::Rocksdb(config) => {
-                Ok(Self::Rocksdb(rocksdb::ProcessorRecordStore::new(config)?))
-            }
+            RecordStore::Rocksdb(config) => Ok(Self::Rocksdb(rocksdb::ProcessorRecordStore::new(
+                config, spill_dir,
+            )?)),
+        }
+    }
+
+    /// Migrates records deserialized from slices tagged with older schema versions to the current one of `versions`.
+    ///
+    /// The RocksDB store doesn't serialize its records into slices, so it ignores `versions`.
+    pub fn with_schema_versions(self, versions: Arc<SchemaVersions>) -> Self {
+        match self {
+            Self::InMemory(store) => Self::InMemory(store.with_schema_versions(versions)),
+            Self::Rocksdb(store) => Self::Rocksdb(store),
         }
     }
 
@@ -253,6 +308,8 @@ fn load_in_memory_record_ref(record_ref: &in_memory::RecordRef) -> Vec<Field> {
 
 mod in_memory;
 mod rocksdb;
+mod schema_evolution;
+pub use schema_evolution::{append_field, Migration, SchemaVersions};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(crate = "dozer_types::serde")]
@@ -315,4 +372,59 @@ mod tests {
         // TODO: enable this test when serialization is implemented for rocksdb
         // test_record_serialization_roundtrip_impl(RecordStore::Rocksdb);
     }
+
+    fn nullable_column_added() -> Arc<SchemaVersions> {
+        let mut versions = SchemaVersions::new();
+        versions.register(append_field(Field::Null));
+        Arc::new(versions)
+    }
+
+    #[test]
+    fn test_record_serialization_migrates_older_schema_version() {
+        let record_store = ProcessorRecordStore::new(RecordStore::InMemory).unwrap();
+        let record = record_store.create_record(&test_record()).unwrap();
+        let serialized_record = record_store.serialize_record(&record).unwrap();
+        let data = record_store.serialize_slice(0).unwrap().0;
+
+        let record_store = ProcessorRecordStoreDeserializer::new(RecordStore::InMemory)
+            .unwrap()
+            .with_schema_versions(nullable_column_added());
+        record_store.deserialize_and_extend(&data).unwrap();
+        let deserialized_record = record_store.deserialize_record(&serialized_record).unwrap();
+        let record_store = record_store.into_record_store();
+
+        let mut expected = test_record();
+        expected.values.push(Field::Null);
+        assert_eq!(
+            record_store.load_record(&deserialized_record).unwrap(),
+            expected
+        );
+
+        // Records written after the migration are already in the current version and read back unchanged.
+        let mut record = test_record();
+        record.values.push(Field::Int(5));
+        let new_record = record_store.create_record(&record).unwrap();
+        let serialized_record = record_store.serialize_record(&new_record).unwrap();
+        let data = record_store.serialize_slice(0).unwrap().0;
+
+        let deserializer = ProcessorRecordStoreDeserializer::new(RecordStore::InMemory)
+            .unwrap()
+            .with_schema_versions(nullable_column_added());
+        deserializer.deserialize_and_extend(&data).unwrap();
+        let deserialized_record = deserializer.deserialize_record(&serialized_record).unwrap();
+        assert_eq!(
+            deserializer
+                .into_record_store()
+                .load_record(&deserialized_record)
+                .unwrap(),
+            record
+        );
+
+        // A store that doesn't know the version can't read the slice.
+        let deserializer = ProcessorRecordStoreDeserializer::new(RecordStore::InMemory).unwrap();
+        assert!(matches!(
+            deserializer.deserialize_and_extend(&data),
+            Err(RecordStoreError::UnknownSchemaVersion(2))
+        ));
+    }
 }
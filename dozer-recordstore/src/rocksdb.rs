@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::sync::atomic::AtomicU64;
 
 use dozer_storage::RocksdbMap;
@@ -15,9 +16,14 @@ pub struct ProcessorRecordStore {
 }
 
 impl ProcessorRecordStore {
-    pub fn new(config: RocksdbConfig) -> Result<Self, RecordStoreError> {
-        let temp_dir = TempDir::new("rocksdb_processor_record_store")
-            .map_err(RecordStoreError::FailedToCreateTempDir)?;
+    /// Creates the store in a temporary directory under `spill_dir`, or under the system temp dir if `None`.
+    pub fn new(config: RocksdbConfig, spill_dir: Option<&Path>) -> Result<Self, RecordStoreError> {
+        let prefix = "rocksdb_processor_record_store";
+        let temp_dir = match spill_dir {
+            Some(spill_dir) => TempDir::new_in(spill_dir, prefix),
+            None => TempDir::new(prefix),
+        }
+        .map_err(RecordStoreError::FailedToCreateTempDir)?;
         let records = RocksdbMap::<u64, Vec<Field>>::create(temp_dir.path(), config)?;
 
         Ok(Self {
@@ -31,6 +37,14 @@ impl ProcessorRecordStore {
         self.next_id.load(std::sync::atomic::Ordering::SeqCst) as usize
     }
 
+    pub fn memtable_size(&self) -> Result<usize, RecordStoreError> {
+        Ok(self.records.memtable_size()?)
+    }
+
+    pub fn flush(&self) -> Result<(), RecordStoreError> {
+        Ok(self.records.flush()?)
+    }
+
     pub fn create_ref(&self, values: &[Field]) -> Result<u64, RecordStoreError> {
         let id = self
             .next_id
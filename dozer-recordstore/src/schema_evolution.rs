@@ -0,0 +1,67 @@
+use std::fmt::{self, Debug, Formatter};
+
+use dozer_types::types::Field;
+
+use crate::RecordStoreError;
+
+/// Migrates a record's values from one schema version to the next.
+pub type Migration = Box<dyn Fn(Vec<Field>) -> Vec<Field> + Send + Sync>;
+
+/// Returns a migration that appends a field with `default` value, e.g. `Field::Null` for a new nullable column.
+pub fn append_field(default: Field) -> Migration {
+    Box::new(move |mut values| {
+        values.push(default.clone());
+        values
+    })
+}
+
+/// The schema versions of a record store. Versions start at 1 and every registered migration bumps the version by 1.
+///
+/// The in-memory store tags the records it serializes into checkpoints with the current version, and migrates records
+/// tagged with older versions forward when they're deserialized, see `ProcessorRecordStoreDeserializer::with_schema_versions`.
+/// Migrations apply to every record in the store, so they must tell the records they apply to apart by their values.
+#[derive(Default)]
+pub struct SchemaVersions {
+    /// `migrations[i]` migrates records from version `i + 1` to version `i + 2`.
+    migrations: Vec<Migration>,
+}
+
+impl Debug for SchemaVersions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SchemaVersions")
+            .field("current_version", &self.current_version())
+            .finish()
+    }
+}
+
+impl SchemaVersions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The version new records are written with.
+    pub fn current_version(&self) -> u32 {
+        self.migrations.len() as u32 + 1
+    }
+
+    /// Registers the migration from the current version to a new version, and returns the new version.
+    pub fn register(&mut self, migration: Migration) -> u32 {
+        self.migrations.push(migration);
+        self.current_version()
+    }
+
+    /// Migrates `values` of a record written with `version` to the current version.
+    pub(crate) fn migrate(
+        &self,
+        version: u32,
+        mut values: Vec<Field>,
+    ) -> Result<Vec<Field>, RecordStoreError> {
+        if version == 0 || version > self.current_version() {
+            return Err(RecordStoreError::UnknownSchemaVersion(version));
+        }
+        for migration in &self.migrations[version as usize - 1..] {
+            values = migration(values);
+        }
+        Ok(values)
+    }
+}
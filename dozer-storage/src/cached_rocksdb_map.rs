@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, HashMap};
+
+use dozer_types::borrow::IntoOwned;
+use dozer_types::parking_lot::Mutex;
+
+use crate::errors::StorageError;
+use crate::{BorrowEncode, Encode, LmdbVal, RocksdbMap};
+
+/// A [`RocksdbMap`] with a least recently used cache of values in front of `get`.
+///
+/// `insert` and `remove` write through to the map and invalidate the key's entry. Absent keys are cached too.
+/// The map is never read or written with the cache locked, so a miss doesn't hold up hits of other keys.
+/// Writes that bypass the wrapper, like ones in a [`RocksdbTransaction`](crate::RocksdbTransaction), aren't seen by the cache,
+/// so call [`clear_cache`](Self::clear_cache) after them.
+#[derive(Debug)]
+pub struct CachedRocksdbMap<K, V> {
+    map: RocksdbMap<K, V>,
+    cache: Mutex<LruCache<V>>,
+}
+
+impl<K: BorrowEncode, V: LmdbVal + Clone> CachedRocksdbMap<K, V>
+where
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    /// Caches the values of at most `capacity` keys of `map`.
+    pub fn new(map: RocksdbMap<K, V>, capacity: usize) -> Self {
+        Self {
+            map,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn count(&self) -> Result<usize, StorageError> {
+        self.map.count()
+    }
+
+    pub fn get(&self, key: K::Encode<'_>) -> Result<Option<V>, StorageError> {
+        let key = key.encode()?;
+        let cache_key = key.as_ref().to_vec();
+        let fill = {
+            let mut cache = self.cache.lock();
+            if let Some(value) = cache.get(&cache_key) {
+                return Ok(value);
+            }
+            cache.start_fill(cache_key.clone())
+        };
+        let value = self.map.get_encoded(key);
+        let mut cache = self.cache.lock();
+        match &value {
+            Ok(value) => cache.finish_fill(cache_key, fill, value.clone()),
+            Err(_) => cache.cancel_fill(&cache_key, fill),
+        }
+        value
+    }
+
+    pub fn contains(&self, key: K::Encode<'_>) -> Result<bool, StorageError> {
+        self.get(key).map(|value| value.is_some())
+    }
+
+    pub fn insert(&self, key: K::Encode<'_>, value: V::Encode<'_>) -> Result<(), StorageError> {
+        let key = key.encode()?;
+        let cache_key = key.as_ref().to_vec();
+        let result = self.map.insert_encoded(key, value);
+        // Invalidating after the write rejects fills that may have read the map before it.
+        self.cache.lock().invalidate(&cache_key);
+        result
+    }
+
+    pub fn remove(&self, key: K::Encode<'_>) -> Result<(), StorageError> {
+        let key = key.encode()?;
+        let cache_key = key.as_ref().to_vec();
+        let result = self.map.remove_encoded(key);
+        self.cache.lock().invalidate(&cache_key);
+        result
+    }
+
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.map.flush()
+    }
+
+    /// Number of keys whose values are cached.
+    pub fn cache_len(&self) -> usize {
+        self.cache.lock().len()
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.lock().clear();
+    }
+
+    /// The underlying map, for reads that don't go through the cache, like `iter` and `range`.
+    pub fn inner(&self) -> &RocksdbMap<K, V> {
+        &self.map
+    }
+}
+
+/// Values by encoded key, evicting the least recently used one when full.
+#[derive(Debug)]
+struct LruCache<V> {
+    capacity: usize,
+    /// The value and last use of every key.
+    entries: HashMap<Vec<u8>, (Option<V>, u64)>,
+    /// Keys by last use, least recent first.
+    uses: BTreeMap<u64, Vec<u8>>,
+    next_use: u64,
+    /// The latest fill started for every key being read from the map, until it's finished or the key is invalidated.
+    fills: HashMap<Vec<u8>, u64>,
+    next_fill: u64,
+}
+
+impl<V: Clone> LruCache<V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            uses: BTreeMap::new(),
+            next_use: 0,
+            fills: HashMap::new(),
+            next_fill: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// `Some` with the cached value if `key` is cached, which may be `None` if the key is absent from the map.
+    fn get(&mut self, key: &[u8]) -> Option<Option<V>> {
+        let next_use = self.next_use;
+        let (value, last_use) = self.entries.get_mut(key)?;
+        let key = self.uses.remove(&*last_use).expect("Every entry has a use");
+        *last_use = next_use;
+        self.uses.insert(next_use, key);
+        self.next_use += 1;
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Option<V>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() == self.capacity {
+            let (_, evicted) = self.uses.pop_first().expect("The cache is full");
+            self.entries.remove(&evicted);
+        }
+        self.uses.insert(self.next_use, key.clone());
+        self.entries.insert(key, (value, self.next_use));
+        self.next_use += 1;
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((_, last_use)) = self.entries.remove(key) {
+            self.uses.remove(&last_use);
+        }
+    }
+
+    /// Starts filling `key` with a value about to be read from the map, returning the fill to pass to `finish_fill`.
+    fn start_fill(&mut self, key: Vec<u8>) -> u64 {
+        let fill = self.next_fill;
+        self.next_fill += 1;
+        self.fills.insert(key, fill);
+        fill
+    }
+
+    /// Caches `value` as read by `fill`, unless `key` was invalidated or another fill started since.
+    fn finish_fill(&mut self, key: Vec<u8>, fill: u64, value: Option<V>) {
+        if self.fills.get(&key) == Some(&fill) {
+            self.fills.remove(&key);
+            self.insert(key, value);
+        }
+    }
+
+    fn cancel_fill(&mut self, key: &[u8], fill: u64) {
+        if self.fills.get(key) == Some(&fill) {
+            self.fills.remove(key);
+        }
+    }
+
+    /// Removes `key`'s value and rejects the fills of `key` started so far.
+    fn invalidate(&mut self, key: &[u8]) {
+        self.remove(key);
+        self.fills.remove(key);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.uses.clear();
+        self.fills.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_cached_rocksdb_map_invalidates_on_write() {
+        let temp_dir = TempDir::new("test_cached_rocksdb_map_invalidates_on_write").unwrap();
+        let map = RocksdbMap::<u64, String>::create(temp_dir.path(), Default::default()).unwrap();
+        let map = CachedRocksdbMap::new(map, 2);
+        for key in 0..3 {
+            map.insert(&key, &format!("value {key}")).unwrap();
+        }
+
+        for _ in 0..3 {
+            assert_eq!(map.get(&1).unwrap(), Some("value 1".to_string()));
+        }
+        assert_eq!(map.cache_len(), 1);
+
+        map.insert(&1, &"new value 1".to_string()).unwrap();
+        assert_eq!(map.get(&1).unwrap(), Some("new value 1".to_string()));
+        map.remove(&1).unwrap();
+        assert_eq!(map.get(&1).unwrap(), None);
+        assert!(!map.contains(&1).unwrap());
+
+        // Key 1 was used more recently than key 0, so key 0 is evicted for key 2.
+        assert_eq!(map.get(&0).unwrap(), Some("value 0".to_string()));
+        map.get(&1).unwrap();
+        assert_eq!(map.get(&2).unwrap(), Some("value 2".to_string()));
+        assert_eq!(map.cache_len(), 2);
+        map.inner()
+            .insert(&1, &"uncached value 1".to_string())
+            .unwrap();
+        assert_eq!(map.get(&1).unwrap(), None);
+        map.clear_cache();
+        assert_eq!(map.get(&1).unwrap(), Some("uncached value 1".to_string()));
+    }
+
+    #[test]
+    fn test_lru_cache_rejects_invalidated_fills() {
+        let mut cache = LruCache::new(2);
+
+        // A write lands between reading the map and caching what was read.
+        let fill = cache.start_fill(b"a".to_vec());
+        cache.invalidate(b"a");
+        cache.finish_fill(b"a".to_vec(), fill, Some("old a"));
+        assert_eq!(cache.get(b"a"), None);
+
+        // Of two concurrent fills only the later one, which may have read a later value, is cached.
+        let first = cache.start_fill(b"b".to_vec());
+        let second = cache.start_fill(b"b".to_vec());
+        cache.finish_fill(b"b".to_vec(), second, Some("new b"));
+        cache.finish_fill(b"b".to_vec(), first, Some("old b"));
+        assert_eq!(cache.get(b"b"), Some(Some("new b")));
+
+        let fill = cache.start_fill(b"c".to_vec());
+        cache.clear();
+        cache.finish_fill(b"c".to_vec(), fill, None);
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -16,6 +16,10 @@ pub enum StorageError {
         create_thread_id: ThreadId,
         commit_thread_id: ThreadId,
     },
+    #[error("No savepoint in the current transaction")]
+    NoSavepoint,
+    #[error("Schema of a stateful port has no primary key")]
+    NoPrimaryKey,
 
     #[error("Unable to deserialize type: {} - Reason: {}", typ, reason.to_string())]
     DeserializationError {
@@ -34,6 +38,24 @@ pub enum StorageError {
 
     #[error("Rocksdb error: {0}")]
     Rocksdb(#[from] rocksdb::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl StorageError {
+    /// Whether retrying the operation that returned this error may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            StorageError::Rocksdb(e) => matches!(
+                e.kind(),
+                rocksdb::ErrorKind::Busy
+                    | rocksdb::ErrorKind::TryAgain
+                    | rocksdb::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -18,7 +18,14 @@ pub use lmdb_counter::LmdbCounter;
 mod lmdb_option;
 pub use lmdb_option::LmdbOption;
 mod rocksdb_map;
-pub use rocksdb_map::RocksdbMap;
+pub use rocksdb_map::{
+    CompactionCallback, CompactionInfo, KeyComparator, KeyOrder, ReplayReport, RetryOptions,
+    RocksdbDatabase, RocksdbMap, RocksdbMapOptions, RocksdbTransaction, WalSync,
+};
+mod cached_rocksdb_map;
+pub use cached_rocksdb_map::CachedRocksdbMap;
+mod sharded_rocksdb_map;
+pub use sharded_rocksdb_map::{ShardedRocksdbMap, VIRTUAL_NODES_PER_SHARD};
 
 #[cfg(test)]
 mod tests;
@@ -179,6 +179,24 @@ mod u64 {
     unsafe impl LmdbVal for u64 {}
 }
 
+impl<'a> Encode<'a> for &'a i64 {
+    fn encode(self) -> Result<Encoded<'a>, StorageError> {
+        Ok(Encoded::U8x8(self.to_le_bytes()))
+    }
+}
+
+impl BorrowEncode for i64 {
+    type Encode<'a> = &'a i64;
+}
+
+impl Decode for i64 {
+    fn decode(bytes: &[u8]) -> Result<Cow<Self>, StorageError> {
+        Ok(Cow::Owned(i64::from_le_bytes(bytes.try_into().unwrap())))
+    }
+}
+
+unsafe impl LmdbVal for i64 {}
+
 impl<'a> Encode<'a> for &'a [u8] {
     fn encode(self) -> Result<Encoded<'a>, StorageError> {
         Ok(Encoded::Borrowed(self))
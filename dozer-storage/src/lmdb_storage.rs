@@ -130,6 +130,8 @@ impl LmdbEnvironmentManager {
 
 #[derive(Debug)]
 pub struct RwLmdbEnvironment {
+    /// Nested transactions of `inner`, innermost last. Declared first so they're dropped before their parents.
+    savepoints: Vec<RwTransaction<'static>>,
     inner: Option<(RwTransaction<'static>, ThreadId)>,
     env: Arc<Environment>,
 }
@@ -143,6 +145,7 @@ impl LmdbEnvironment for RwLmdbEnvironment {
 impl RwLmdbEnvironment {
     fn new(env: Environment) -> Result<Self, StorageError> {
         Ok(Self {
+            savepoints: vec![],
             inner: None,
             env: Arc::new(env),
         })
@@ -179,12 +182,53 @@ impl RwLmdbEnvironment {
                 });
             }
 
+            for savepoint in self.savepoints.drain(..).rev() {
+                savepoint.commit()?;
+            }
             txn.commit()?;
         }
         Ok(())
     }
 
+    /// Marks a point in the current transaction that `rollback_to_savepoint` can undo changes back to.
+    ///
+    /// Savepoints nest. Changes made after one are kept if it's released, or when the transaction is committed.
+    pub fn savepoint(&mut self) -> Result<(), StorageError> {
+        self.txn_mut()?;
+        let parent = match self.savepoints.last_mut() {
+            Some(savepoint) => savepoint,
+            None => &mut self.inner.as_mut().expect("transaction was just begun").0,
+        };
+        let nested = parent.begin_nested_txn()?;
+        // SAFETY:
+        // - The parent isn't used while `nested` is alive, because `txn_mut` returns the innermost savepoint.
+        // - Savepoints are dropped or committed before their parents.
+        let nested =
+            unsafe { std::mem::transmute::<RwTransaction<'_>, RwTransaction<'static>>(nested) };
+        self.savepoints.push(nested);
+        Ok(())
+    }
+
+    /// Discards the changes made since the latest savepoint, and the savepoint.
+    pub fn rollback_to_savepoint(&mut self) -> Result<(), StorageError> {
+        let savepoint = self.savepoints.pop().ok_or(StorageError::NoSavepoint)?;
+        savepoint.abort();
+        Ok(())
+    }
+
+    /// Keeps the changes made since the latest savepoint as part of the enclosing transaction, and removes the savepoint.
+    pub fn release_savepoint(&mut self) -> Result<(), StorageError> {
+        let savepoint = self.savepoints.pop().ok_or(StorageError::NoSavepoint)?;
+        savepoint.commit()?;
+        Ok(())
+    }
+
     pub fn txn_mut(&mut self) -> Result<&mut RwTransaction, StorageError> {
+        if let Some(savepoint) = self.savepoints.last_mut() {
+            // SAFETY:
+            // - Transmute savepoint back to its actual lifetime.
+            return Ok(unsafe { std::mem::transmute(savepoint) });
+        }
         if let Some((txn, _)) = self.inner.as_mut() {
             // SAFETY:
             // - Transmute txn back to its actual lifetime.
@@ -308,4 +352,32 @@ mod tests {
         .unwrap();
         ro_env.open_database(db_name).unwrap();
     }
+
+    #[test]
+    fn test_rollback_to_savepoint() {
+        let temp_dir = TempDir::new("test").unwrap();
+        let mut rw_env = LmdbEnvironmentManager::create_rw(
+            temp_dir.path(),
+            "test",
+            LmdbEnvironmentOptions::default(),
+        )
+        .unwrap();
+        let db = rw_env
+            .create_database(Some("db"), DatabaseFlags::empty())
+            .unwrap();
+
+        rw_env.put(db, b"kept", b"1").unwrap();
+        rw_env.savepoint().unwrap();
+        rw_env.put(db, b"rolled_back", b"2").unwrap();
+        assert_eq!(rw_env.get(db, b"rolled_back").unwrap(), Some(&b"2"[..]));
+        rw_env.rollback_to_savepoint().unwrap();
+        assert!(matches!(
+            rw_env.rollback_to_savepoint(),
+            Err(StorageError::NoSavepoint)
+        ));
+        rw_env.commit().unwrap();
+
+        assert_eq!(rw_env.get(db, b"kept").unwrap(), Some(&b"1"[..]));
+        assert_eq!(rw_env.get(db, b"rolled_back").unwrap(), None);
+    }
 }
@@ -1,15 +1,297 @@
+use std::cmp::Ordering;
+use std::ffi::CStr;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
 
-use rocksdb::{BlockBasedOptions, Cache, Options, DB};
+use rocksdb::checkpoint::Checkpoint;
+use rocksdb::compaction_filter::CompactionFilter;
+use rocksdb::compaction_filter_factory::{CompactionFilterContext, CompactionFilterFactory};
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamily, ColumnFamilyDescriptor, CompactionDecision, Direction,
+    IngestExternalFileOptions, IteratorMode, MergeOperands, Options, ReadOptions, SstFileWriter,
+    WriteBatch, WriteOptions, DB, DEFAULT_COLUMN_FAMILY_NAME,
+};
 
-use dozer_types::borrow::IntoOwned;
+use dozer_types::borrow::{Borrow, Cow, IntoOwned};
 use dozer_types::models::app_config::RocksdbConfig;
+use dozer_types::parking_lot::RwLock;
 
-use crate::{errors::StorageError, BorrowEncode, Encode, LmdbVal};
+use crate::{errors::StorageError, BorrowEncode, Encode, Encoded, LmdbVal};
+
+#[derive(Clone, Default)]
+pub struct RocksdbMapOptions {
+    /// If set, transient RocksDB errors are retried before being returned.
+    pub retry: Option<RetryOptions>,
+    /// How keys are stored, which decides the order of `iter` and `range`.
+    pub key_order: KeyOrder,
+    /// How writes are made durable.
+    pub wal_sync: WalSync,
+    /// Orders stored keys instead of their bytes, which decides the order of `iter` and `range`.
+    ///
+    /// It compares keys as stored, after `key_order` is applied.
+    pub comparator: Option<KeyComparator>,
+    /// Bits per key of a bloom filter on the keys in every table file, so `get` and `contains` of absent keys skip reading them.
+    ///
+    /// No filter is used if not set. 10 bits per key give about 1% false positives.
+    pub bloom_bits_per_key: Option<i32>,
+    /// Called on RocksDB's compaction thread whenever a compaction of the map finishes, see [`CompactionInfo`].
+    ///
+    /// It should return quickly, as the compaction isn't complete until it does.
+    pub on_compaction: Option<CompactionCallback>,
+    /// Prepended to every stored key, so maps with different prefixes can share a database with isolated keys.
+    ///
+    /// `iter` and `range` only see keys with the prefix, and return them with it stripped, and `count` is exact
+    /// instead of estimated. No map's prefix may start with another's, and `comparator` compares prefixed keys.
+    /// With a `comparator`, `iter` and `count` scan the keys of every prefix, as it may not keep a prefix's keys together.
+    pub key_prefix: Vec<u8>,
+}
+
+pub type CompactionCallback = Arc<dyn Fn(CompactionInfo) + Send + Sync>;
+
+impl std::fmt::Debug for RocksdbMapOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RocksdbMapOptions")
+            .field("retry", &self.retry)
+            .field("key_order", &self.key_order)
+            .field("wal_sync", &self.wal_sync)
+            .field("comparator", &self.comparator)
+            .field("bloom_bits_per_key", &self.bloom_bits_per_key)
+            .field("on_compaction", &self.on_compaction.is_some())
+            .field("key_prefix", &self.key_prefix)
+            .finish()
+    }
+}
+
+/// What a [`RocksdbMap`] compaction did, passed to `RocksdbMapOptions::on_compaction`.
+///
+/// The rocksdb crate doesn't expose RocksDB's event listeners, so this is gathered by a compaction filter that keeps
+/// every entry. A compaction split into subcompactions is reported once per subcompaction.
+/// Compactions whose input has no entries aren't reported. Use [`RocksdbMap::num_files_at_level`] for file counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionInfo {
+    /// If it was requested, like by [`RocksdbMap::compact_range`], instead of scheduled by RocksDB.
+    pub is_manual: bool,
+    /// If its input was all table files of the map.
+    pub is_full: bool,
+    /// Number of entries read, excluding the versions and deletions RocksDB dropped before filtering.
+    pub num_entries: u64,
+    /// Levels of the table files the entries were read from, ascending.
+    pub input_levels: Vec<u32>,
+}
+
+struct CompactionListener {
+    callback: CompactionCallback,
+}
+
+impl CompactionFilterFactory for CompactionListener {
+    type Filter = CompactionCounter;
+
+    fn create(&mut self, context: CompactionFilterContext) -> Self::Filter {
+        CompactionCounter {
+            callback: self.callback.clone(),
+            info: CompactionInfo {
+                is_manual: context.is_manual_compaction,
+                is_full: context.is_full_compaction,
+                num_entries: 0,
+                input_levels: vec![],
+            },
+        }
+    }
+
+    fn name(&self) -> &CStr {
+        compaction_listener_name()
+    }
+}
+
+/// Lives as long as one compaction, counting what it reads and reporting it when dropped.
+struct CompactionCounter {
+    callback: CompactionCallback,
+    info: CompactionInfo,
+}
+
+impl CompactionFilter for CompactionCounter {
+    fn filter(&mut self, level: u32, _key: &[u8], _value: &[u8]) -> CompactionDecision {
+        self.info.num_entries += 1;
+        if let Err(index) = self.info.input_levels.binary_search(&level) {
+            self.info.input_levels.insert(index, level);
+        }
+        CompactionDecision::Keep
+    }
+
+    fn name(&self) -> &CStr {
+        compaction_listener_name()
+    }
+}
+
+impl Drop for CompactionCounter {
+    fn drop(&mut self) {
+        if self.info.num_entries > 0 {
+            (self.callback)(self.info.clone());
+        }
+    }
+}
+
+fn compaction_listener_name() -> &'static CStr {
+    CStr::from_bytes_with_nul(b"dozer_compaction_listener\0").expect("valid C string")
+}
+
+/// A custom order of a [`RocksdbMap`]'s keys.
+///
+/// RocksDB records the comparator's name, so a map must always be opened with the comparator it was created with.
+/// Keys that compare equal are the same key, so the order must be total over distinct keys.
+#[derive(Clone)]
+pub struct KeyComparator {
+    name: String,
+    compare: Arc<dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync>,
+}
+
+impl KeyComparator {
+    pub fn new(
+        name: impl Into<String>,
+        compare: impl Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            compare: Arc::new(compare),
+        }
+    }
+}
+
+impl KeyComparator {
+    fn set(&self, options: &mut Options) {
+        let compare = self.compare.clone();
+        options.set_comparator(
+            self.name.as_str(),
+            Box::new(move |left: &[u8], right: &[u8]| compare(left, right)),
+        );
+    }
+}
+
+impl std::fmt::Debug for KeyComparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyComparator")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// How a [`RocksdbMap`] uses the write-ahead log, trading durability for write throughput.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalSync {
+    /// Every write is synced to disk before it returns. Writes survive a machine crash.
+    EverySync,
+    /// Writes go to the log through the OS buffer. They survive a process crash, but may be lost on a machine crash.
+    #[default]
+    Async,
+    /// Writes skip the log. Writes that haven't been flushed are lost on any crash.
+    Disabled,
+}
+
+impl WalSync {
+    /// Returns the more durable of `self` and `other`.
+    fn max_durability(self, other: WalSync) -> WalSync {
+        let rank = |wal_sync| match wal_sync {
+            WalSync::Disabled => 0,
+            WalSync::Async => 1,
+            WalSync::EverySync => 2,
+        };
+        if rank(other) > rank(self) {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn write_options(self) -> WriteOptions {
+        let mut options = WriteOptions::default();
+        match self {
+            WalSync::EverySync => options.set_sync(true),
+            WalSync::Async => {}
+            WalSync::Disabled => options.disable_wal(true),
+        }
+        options
+    }
+}
+
+/// How a [`RocksdbMap`] stores keys.
+///
+/// RocksDB orders keys by their bytes. Integers are encoded little-endian, so they don't sort numerically as is.
+/// The integer orders store keys so they do. They must only be used with keys of that type, and
+/// a map must always be opened with the order it was created with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyOrder {
+    /// Keys are stored as encoded.
+    #[default]
+    Encoded,
+    /// For `u32` and `u64` keys: Keys are stored big-endian.
+    UnsignedInteger,
+    /// For `i64` keys: Keys are stored big-endian with the sign bit flipped, so negative keys come first.
+    SignedInteger,
+}
+
+impl KeyOrder {
+    fn store<'a>(self, key: Encoded<'a>) -> Encoded<'a> {
+        match self {
+            KeyOrder::Encoded => key,
+            KeyOrder::UnsignedInteger | KeyOrder::SignedInteger => {
+                let mut stored = key.as_ref().to_vec();
+                stored.reverse();
+                if self == KeyOrder::SignedInteger {
+                    if let Some(first) = stored.first_mut() {
+                        *first ^= 0x80;
+                    }
+                }
+                Encoded::Vec(stored)
+            }
+        }
+    }
+
+    fn load(self, stored: &[u8]) -> Encoded<'_> {
+        match self {
+            KeyOrder::Encoded => Encoded::Borrowed(stored),
+            KeyOrder::UnsignedInteger | KeyOrder::SignedInteger => {
+                let mut key = stored.to_vec();
+                if self == KeyOrder::SignedInteger {
+                    if let Some(first) = key.first_mut() {
+                        *first ^= 0x80;
+                    }
+                }
+                key.reverse();
+                Encoded::Vec(key)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryOptions {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled on every following retry.
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct RocksdbMap<K, V> {
-    db: DB,
+    /// Shared with the other maps of a [`RocksdbDatabase`].
+    db: Arc<DB>,
+    /// The column family holding the map, the default one unless the map is in a [`RocksdbDatabase`].
+    column_family: String,
+    options: RocksdbMapOptions,
+    /// Taken for writing by `upsert`, and for reading by other writes, so nothing is written between its read and write.
+    write_lock: RwLock<()>,
     _key: std::marker::PhantomData<K>,
     _value: std::marker::PhantomData<V>,
 }
@@ -19,35 +301,74 @@ where
     for<'a> V::Borrowed<'a>: IntoOwned<V>,
 {
     pub fn create(path: &Path, config: RocksdbConfig) -> Result<Self, StorageError> {
-        let mut options = Options::default();
-        options.create_if_missing(true);
-
-        if let Some(block_cache_size) = config.block_cache_size {
-            let mut block_options = BlockBasedOptions::default();
-            let cache = Cache::new_lru_cache(block_cache_size);
-            block_options.set_block_cache(&cache);
+        Self::create_with_options(path, config, Default::default())
+    }
 
-            options.set_block_based_table_factory(&block_options);
-        }
+    pub fn create_with_options(
+        path: &Path,
+        config: RocksdbConfig,
+        map_options: RocksdbMapOptions,
+    ) -> Result<Self, StorageError> {
+        Self::open(path, config, Options::default(), map_options)
+    }
 
+    fn open(
+        path: &Path,
+        config: RocksdbConfig,
+        mut options: Options,
+        map_options: RocksdbMapOptions,
+    ) -> Result<Self, StorageError> {
+        options.create_if_missing(true);
+        set_map_options(&mut options, config, &map_options);
         let db = DB::open(&options, path)?;
-        Ok(Self {
-            db,
-            _key: std::marker::PhantomData,
-            _value: std::marker::PhantomData,
-        })
+        Ok(Self::new(
+            Arc::new(db),
+            DEFAULT_COLUMN_FAMILY_NAME.to_string(),
+            map_options,
+        ))
+    }
+
+    /// Replays the write-ahead log of the map at `path`, which may have been left behind by an unclean shutdown.
+    ///
+    /// The database is opened like `create_with_options` opens it, so `config` and `map_options` must be the ones
+    /// the map was created with. Operations that were written but not flushed are recovered and counted,
+    /// then flushed so they're persisted. The database must not be open elsewhere.
+    pub fn replay_wal(
+        path: &Path,
+        config: RocksdbConfig,
+        map_options: &RocksdbMapOptions,
+    ) -> Result<ReplayReport, StorageError> {
+        let mut options = Options::default();
+        set_map_options(&mut options, config, map_options);
+        replay_wal(path, options)
     }
 
     pub fn count(&self) -> Result<usize, StorageError> {
+        if !self.options.key_prefix.is_empty() {
+            // The estimate is of the whole column family.
+            let mut count = 0;
+            for entry in self.prefixed_entries(&self.options.key_prefix) {
+                entry?;
+                count += 1;
+            }
+            return Ok(count);
+        }
         Ok(self
-            .db
-            .property_int_value("rocksdb.estimate-num-keys")?
+            .retry(|| {
+                self.db
+                    .property_int_value_cf(self.cf(), "rocksdb.estimate-num-keys")
+            })?
             .expect("rocksdb.estimate-num-keys") as usize)
     }
 
     pub fn get(&self, key: K::Encode<'_>) -> Result<Option<V>, StorageError> {
-        let key = key.encode()?;
-        let value = self.db.get_pinned(key)?;
+        self.get_encoded(key.encode()?)
+    }
+
+    /// Like `get`, with the key already encoded.
+    pub(crate) fn get_encoded(&self, key: Encoded<'_>) -> Result<Option<V>, StorageError> {
+        let key = self.store_key(key);
+        let value = self.retry(|| self.db.get_pinned_cf(self.cf(), &key))?;
         if let Some(value) = value {
             let value = V::decode(&value)?;
             Ok(Some(value.into_owned()))
@@ -56,26 +377,1247 @@ where
         }
     }
 
+    /// Passes the value of `key` to `f` while it's pinned in RocksDB, without copying it into an owned `V`.
+    pub fn with_value<R>(
+        &self,
+        key: K::Encode<'_>,
+        f: impl FnOnce(Option<V::Borrowed<'_>>) -> R,
+    ) -> Result<R, StorageError> {
+        let key = self.encode_key(key)?;
+        let Some(value) = self.retry(|| self.db.get_pinned_cf(self.cf(), &key))? else {
+            return Ok(f(None));
+        };
+        match V::decode(&value)? {
+            Cow::Borrowed(value) => Ok(f(Some(value))),
+            Cow::Owned(value) => Ok(f(Some(value.borrow()))),
+        }
+    }
+
     pub fn contains(&self, key: K::Encode<'_>) -> Result<bool, StorageError> {
-        let key = key.encode()?;
-        let value = self.db.get_pinned(key)?;
+        self.contains_encoded(key.encode()?)
+    }
+
+    pub(crate) fn contains_encoded(&self, key: Encoded<'_>) -> Result<bool, StorageError> {
+        let key = self.store_key(key);
+        let value = self.retry(|| self.db.get_pinned_cf(self.cf(), &key))?;
         Ok(value.is_some())
     }
 
     pub fn insert(&self, key: K::Encode<'_>, value: V::Encode<'_>) -> Result<(), StorageError> {
+        self.insert_encoded(key.encode()?, value)
+    }
+
+    pub(crate) fn insert_encoded(
+        &self,
+        key: Encoded<'_>,
+        value: V::Encode<'_>,
+    ) -> Result<(), StorageError> {
+        let _guard = self.write_lock.read();
+        self.put_encoded(key, value)
+    }
+
+    /// Stores `value` for `key`, or `merge(existing, value)` if `key` has a value, and returns what was stored.
+    ///
+    /// Atomic with respect to other writes through the map, unlike a `get` followed by an `insert`.
+    /// Writes in a [`RocksdbTransaction`] aren't synchronized with it.
+    pub fn upsert(
+        &self,
+        key: K::Encode<'_>,
+        value: V,
+        merge: impl Fn(&V, &V) -> V,
+    ) -> Result<V, StorageError> {
         let key = key.encode()?;
+        let _guard = self.write_lock.write();
+        let value = match self.get_encoded(Encoded::Borrowed(key.as_ref()))? {
+            Some(existing) => merge(&existing, &value),
+            None => value,
+        };
+        self.put_encoded(key, value.borrow())?;
+        Ok(value)
+    }
+
+    fn put_encoded(&self, key: Encoded<'_>, value: V::Encode<'_>) -> Result<(), StorageError> {
+        let key = self.store_key(key);
         let value = value.encode()?;
-        self.db.put(key, value)?;
-        Ok(())
+        let write_options = self.options.wal_sync.write_options();
+        self.retry(|| self.db.put_cf_opt(self.cf(), &key, &value, &write_options))
     }
 
     pub fn remove(&self, key: K::Encode<'_>) -> Result<(), StorageError> {
-        let key = key.encode()?;
-        self.db.delete(key)?;
-        Ok(())
+        self.remove_encoded(key.encode()?)
+    }
+
+    pub(crate) fn remove_encoded(&self, key: Encoded<'_>) -> Result<(), StorageError> {
+        let key = self.store_key(key);
+        let _guard = self.write_lock.read();
+        let write_options = self.options.wal_sync.write_options();
+        self.retry(|| self.db.delete_cf_opt(self.cf(), &key, &write_options))
     }
 
     pub fn flush(&self) -> Result<(), StorageError> {
-        self.db.flush().map_err(Into::into)
+        self.retry(|| self.db.flush_cf(self.cf()))
+    }
+
+    /// Bulk loads entries by writing them to a table file and ingesting it into RocksDB, which is far faster than inserting them one by one.
+    ///
+    /// Keys and values must be encoded like `K` and `V`. Entries must be sorted by key in the map's order,
+    /// which is the order of the encoded bytes unless `key_order` or `comparator` is set, without duplicate keys.
+    /// Otherwise an error is returned and nothing is ingested.
+    /// Ingested entries replace existing ones with the same keys, and are durable once this returns.
+    pub fn ingest_sorted<I: Iterator<Item = (Vec<u8>, Vec<u8>)>>(
+        &self,
+        sorted: I,
+    ) -> Result<(), StorageError> {
+        static NEXT_FILE_ID: AtomicU64 = AtomicU64::new(0);
+        let file_id = NEXT_FILE_ID.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = self.db.path().join(format!("ingest_{file_id}.sst"));
+
+        let result = self.write_and_ingest(sorted, &path);
+        if result.is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        result
+    }
+
+    fn write_and_ingest(
+        &self,
+        sorted: impl Iterator<Item = (Vec<u8>, Vec<u8>)>,
+        path: &Path,
+    ) -> Result<(), StorageError> {
+        let mut options = Options::default();
+        if let Some(comparator) = &self.options.comparator {
+            comparator.set(&mut options);
+        }
+        let mut writer = SstFileWriter::create(&options);
+        writer.open(path)?;
+        let mut written = false;
+        for (key, value) in sorted {
+            let key = self.store_key(Encoded::Vec(key));
+            writer.put(key, value)?;
+            written = true;
+        }
+        if !written {
+            // RocksDB can't finish an empty table file.
+            drop(writer);
+            let _ = std::fs::remove_file(path);
+            return Ok(());
+        }
+        writer.finish()?;
+
+        let mut ingest_options = IngestExternalFileOptions::default();
+        ingest_options.set_move_files(true);
+        self.retry(|| {
+            self.db
+                .ingest_external_file_cf_opts(self.cf(), &ingest_options, vec![path])
+        })
+    }
+
+    /// Compacts all table files of the map, blocking until it's done.
+    pub fn compact_range(&self) {
+        self.db
+            .compact_range_cf(self.cf(), None::<&[u8]>, None::<&[u8]>);
+    }
+
+    /// Number of table files at `level`, where level 0 has the files flushed from memtables.
+    pub fn num_files_at_level(&self, level: usize) -> Result<u64, StorageError> {
+        let property = format!("rocksdb.num-files-at-level{level}");
+        Ok(self
+            .retry(|| self.db.property_int_value_cf(self.cf(), &property))?
+            .unwrap_or_default())
+    }
+
+    /// Approximate size of the active and unflushed memtables, in bytes.
+    pub fn memtable_size(&self) -> Result<usize, StorageError> {
+        Ok(self
+            .retry(|| {
+                self.db
+                    .property_int_value_cf(self.cf(), "rocksdb.cur-size-all-mem-tables")
+            })?
+            .expect("rocksdb.cur-size-all-mem-tables") as usize)
+    }
+}
+
+impl<K: LmdbVal, V: LmdbVal> RocksdbMap<K, V>
+where
+    for<'a> K::Borrowed<'a>: IntoOwned<K>,
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    /// Iterates all entries in key order, see [`KeyOrder`].
+    ///
+    /// Entries are read lazily from a RocksDB iterator, which sees the map as it was when `iter` was called.
+    /// Writes made while iterating aren't visible. The iterator is `Send`, so it can be consumed on another thread.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V), StorageError>> + Send + '_
+    where
+        K: Sync,
+        V: Sync,
+    {
+        self.decode_entries(self.prefixed_entries(&self.options.key_prefix))
+    }
+
+    /// Iterates the entries with keys from `start`, inclusive, to `end`, exclusive, in key order.
+    pub fn range(
+        &self,
+        start: K::Encode<'_>,
+        end: K::Encode<'_>,
+    ) -> Result<impl Iterator<Item = Result<(K, V), StorageError>> + Send + '_, StorageError>
+    where
+        K: Sync,
+        V: Sync,
+    {
+        let start = self.encode_key(start)?;
+        let end = self.encode_key(end)?.as_ref().to_vec();
+        // RocksDB compares the upper bound with the map's comparator, if it has one.
+        let mut read_options = ReadOptions::default();
+        read_options.set_iterate_upper_bound(end);
+        let entries = self.db.iterator_cf_opt(
+            self.cf(),
+            read_options,
+            IteratorMode::From(start.as_ref(), Direction::Forward),
+        );
+        Ok(self.decode_entries(entries))
+    }
+
+    fn decode_entries<'a>(
+        &'a self,
+        entries: impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>> + Send + 'a,
+    ) -> impl Iterator<Item = Result<(K, V), StorageError>> + Send + 'a
+    where
+        K: Sync,
+        V: Sync,
+    {
+        entries.map(move |entry| {
+            let (key, value) = entry?;
+            let key = &key[self.options.key_prefix.len()..];
+            let key = K::decode(self.options.key_order.load(key).as_ref())?.into_owned();
+            let value = V::decode(&value)?.into_owned();
+            Ok((key, value))
+        })
+    }
+}
+
+impl<K: BorrowEncode, V> RocksdbMap<K, V> {
+    fn encode_key<'a>(&self, key: K::Encode<'a>) -> Result<Encoded<'a>, StorageError> {
+        Ok(self.store_key(key.encode()?))
+    }
+}
+
+impl<K, V> RocksdbMap<K, V> {
+    fn new(db: Arc<DB>, column_family: String, options: RocksdbMapOptions) -> Self {
+        Self {
+            db,
+            column_family,
+            options,
+            write_lock: RwLock::new(()),
+            _key: std::marker::PhantomData,
+            _value: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns `key` as it's stored, with `key_order` and `key_prefix` applied.
+    fn store_key<'a>(&self, key: Encoded<'a>) -> Encoded<'a> {
+        let key = self.options.key_order.store(key);
+        if self.options.key_prefix.is_empty() {
+            return key;
+        }
+        let mut stored = self.options.key_prefix.clone();
+        stored.extend_from_slice(key.as_ref());
+        Encoded::Vec(stored)
+    }
+
+    /// Writes a consistent copy of the database the map is in to `path`, which must not exist, that can be opened like the original.
+    ///
+    /// Table files are hard linked if `path` is on the same file system, so it's cheap even for large maps.
+    pub fn checkpoint(&self, path: &Path) -> Result<(), StorageError> {
+        Checkpoint::new(&*self.db)?.create_checkpoint(path)?;
+        Ok(())
+    }
+
+    /// Iterates the stored entries whose keys start with `prefix`.
+    ///
+    /// Byte-wise, those keys are adjacent, so it seeks to the first and stops after the last.
+    /// A comparator may put other keys between them, so with one it scans all keys.
+    fn prefixed_entries<'a>(
+        &'a self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>> + Send + 'a {
+        let bytewise = self.options.comparator.is_none();
+        let has_prefix = move |entry: &Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>| {
+            entry
+                .as_ref()
+                .map_or(true, |(key, _)| key.starts_with(prefix))
+        };
+        let mode = if bytewise {
+            IteratorMode::From(prefix, Direction::Forward)
+        } else {
+            IteratorMode::Start
+        };
+        self.db
+            .iterator_cf(self.cf(), mode)
+            .take_while(move |entry| !bytewise || has_prefix(entry))
+            .filter(move |entry| bytewise || has_prefix(entry))
+    }
+
+    fn cf(&self) -> &ColumnFamily {
+        self.db
+            .cf_handle(&self.column_family)
+            .expect("column families are never dropped")
+    }
+
+    fn retry<T>(
+        &self,
+        mut f: impl FnMut() -> Result<T, rocksdb::Error>,
+    ) -> Result<T, StorageError> {
+        retry_transient(
+            self.options.retry.as_ref(),
+            StorageError::is_transient,
+            || f().map_err(Into::into),
+        )
+    }
+}
+
+/// Sets what `map_options` and `config` configure when RocksDB is opened.
+fn set_map_options(options: &mut Options, config: RocksdbConfig, map_options: &RocksdbMapOptions) {
+    if let Some(comparator) = &map_options.comparator {
+        comparator.set(options);
+    }
+    if let Some(callback) = &map_options.on_compaction {
+        options.set_compaction_filter_factory(CompactionListener {
+            callback: callback.clone(),
+        });
+    }
+    let cache = config.block_cache_size.map(Cache::new_lru_cache);
+    set_block_options(options, cache.as_ref(), map_options.bloom_bits_per_key);
+}
+
+/// Sets the block cache and bloom filter of table files, if any.
+fn set_block_options(
+    options: &mut Options,
+    cache: Option<&Cache>,
+    bloom_bits_per_key: Option<i32>,
+) {
+    if cache.is_none() && bloom_bits_per_key.is_none() {
+        return;
+    }
+    let mut block_options = BlockBasedOptions::default();
+    if let Some(cache) = cache {
+        block_options.set_block_cache(cache);
+    }
+    if let Some(bits_per_key) = bloom_bits_per_key {
+        block_options.set_bloom_filter(bits_per_key as f64, false);
+    }
+    options.set_block_based_table_factory(&block_options);
+}
+
+/// A RocksDB database holding several [`RocksdbMap`]s, each in its own column family,
+/// so they can be written atomically with a [`RocksdbTransaction`].
+///
+/// Column families share the block cache set in the config, and are otherwise configured with RocksDB's defaults.
+#[derive(Debug, Clone)]
+pub struct RocksdbDatabase {
+    db: Arc<DB>,
+}
+
+impl RocksdbDatabase {
+    /// Opens the database at `path` with the column families named `column_families`, creating what's missing.
+    ///
+    /// Column families created by earlier runs must all be listed.
+    pub fn open(
+        path: &Path,
+        config: RocksdbConfig,
+        column_families: &[&str],
+    ) -> Result<Self, StorageError> {
+        let cache = config.block_cache_size.map(Cache::new_lru_cache);
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        set_block_options(&mut options, cache.as_ref(), None);
+
+        let descriptors = column_families.iter().map(|name| {
+            let mut cf_options = Options::default();
+            set_block_options(&mut cf_options, cache.as_ref(), None);
+            ColumnFamilyDescriptor::new(*name, cf_options)
+        });
+        let db = DB::open_cf_descriptors(&options, path, descriptors)?;
+        Ok(Self { db: Arc::new(db) })
+    }
+
+    /// Returns the map in column family `column_family`.
+    ///
+    /// Only the `retry`, `key_order`, `wal_sync` and `key_prefix` options apply, the others configure RocksDB when it's opened.
+    /// Maps of one column family must always be used with the same `K`, `V` and `key_order`.
+    pub fn map<K, V>(
+        &self,
+        column_family: &str,
+        options: RocksdbMapOptions,
+    ) -> Result<RocksdbMap<K, V>, StorageError> {
+        if self.db.cf_handle(column_family).is_none() {
+            return Err(StorageError::InvalidArgument(format!(
+                "Column family {column_family} wasn't opened"
+            )));
+        }
+        if options.comparator.is_some()
+            || options.bloom_bits_per_key.is_some()
+            || options.on_compaction.is_some()
+        {
+            return Err(StorageError::InvalidArgument(
+                "Maps in a database can't set comparator, bloom_bits_per_key or on_compaction"
+                    .to_string(),
+            ));
+        }
+        Ok(RocksdbMap::new(
+            self.db.clone(),
+            column_family.to_string(),
+            options,
+        ))
+    }
+
+    /// Starts a transaction writing to maps of this database.
+    pub fn transaction(&self) -> RocksdbTransaction<'_> {
+        RocksdbTransaction {
+            db: &self.db,
+            batch: WriteBatch::default(),
+            wal_sync: WalSync::Disabled,
+        }
+    }
+}
+
+/// Writes to several maps of a [`RocksdbDatabase`] that are applied atomically on [`commit`](Self::commit).
+///
+/// Nothing is visible to reads, including reads through the maps, until then. Dropping the transaction discards its writes.
+pub struct RocksdbTransaction<'a> {
+    db: &'a Arc<DB>,
+    batch: WriteBatch,
+    /// The most durable `wal_sync` of the maps written to, which the commit uses.
+    wal_sync: WalSync,
+}
+
+impl RocksdbTransaction<'_> {
+    pub fn insert<K: BorrowEncode, V: BorrowEncode>(
+        &mut self,
+        map: &RocksdbMap<K, V>,
+        key: K::Encode<'_>,
+        value: V::Encode<'_>,
+    ) -> Result<(), StorageError> {
+        self.check_map(map)?;
+        let key = map.encode_key(key)?;
+        let value = value.encode()?;
+        self.batch.put_cf(map.cf(), key, value);
+        Ok(())
+    }
+
+    pub fn remove<K: BorrowEncode, V>(
+        &mut self,
+        map: &RocksdbMap<K, V>,
+        key: K::Encode<'_>,
+    ) -> Result<(), StorageError> {
+        self.check_map(map)?;
+        let key = map.encode_key(key)?;
+        self.batch.delete_cf(map.cf(), key);
+        Ok(())
+    }
+
+    /// Number of writes in the transaction.
+    pub fn len(&self) -> usize {
+        self.batch.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.is_empty()
+    }
+
+    /// Applies all writes atomically. If this fails, none of them are applied.
+    pub fn commit(self) -> Result<(), StorageError> {
+        let write_options = self.wal_sync.write_options();
+        Ok(self.db.write_opt(self.batch, &write_options)?)
+    }
+
+    fn check_map<K, V>(&mut self, map: &RocksdbMap<K, V>) -> Result<(), StorageError> {
+        if !Arc::ptr_eq(self.db, &map.db) {
+            return Err(StorageError::InvalidArgument(
+                "Map isn't in the transaction's database".to_string(),
+            ));
+        }
+        self.wal_sync = self.wal_sync.max_durability(map.options.wal_sync);
+        Ok(())
+    }
+}
+
+/// Calls `f` until it succeeds, returns an error that's not transient, or runs out of retries.
+fn retry_transient<T, E>(
+    options: Option<&RetryOptions>,
+    is_transient: impl Fn(&E) -> bool,
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut num_retries = 0;
+    let mut backoff = options.map(|options| options.initial_backoff);
+    loop {
+        match (f(), options, backoff) {
+            (Err(e), Some(options), Some(current_backoff))
+                if num_retries < options.max_retries && is_transient(&e) =>
+            {
+                sleep(current_backoff);
+                num_retries += 1;
+                backoff = Some(current_backoff * 2);
+            }
+            (result, _, _) => return result,
+        }
+    }
+}
+
+/// What [`RocksdbMap::replay_wal`] recovered from a write-ahead log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReplayReport {
+    /// Number of write batches found in the log.
+    pub num_batches: usize,
+    /// Number of operations in those batches.
+    pub num_operations: usize,
+    /// Sequence number of the last recovered operation.
+    pub last_sequence_number: Option<u64>,
+}
+
+fn replay_wal(path: &Path, mut options: Options) -> Result<ReplayReport, StorageError> {
+    // Keep recovered operations in the log, so we can count them.
+    options.set_avoid_flush_during_recovery(true);
+    let db = DB::open(&options, path)?;
+
+    let mut report = ReplayReport::default();
+    for batch in db.get_updates_since(0)? {
+        let (sequence_number, batch) = batch?;
+        if batch.is_empty() {
+            continue;
+        }
+        report.num_batches += 1;
+        report.num_operations += batch.len();
+        report.last_sequence_number = Some(sequence_number + batch.len() as u64 - 1);
+    }
+
+    db.flush()?;
+    Ok(report)
+}
+
+impl<K: BorrowEncode> RocksdbMap<K, i64> {
+    /// Creates a map whose values can be incremented with `merge_add` without reading them first.
+    pub fn create_with_add_merge(
+        path: &Path,
+        config: RocksdbConfig,
+        map_options: RocksdbMapOptions,
+    ) -> Result<Self, StorageError> {
+        let mut options = Options::default();
+        options.set_merge_operator_associative("add", add_merge);
+        Self::open(path, config, options, map_options)
+    }
+
+    /// Adds `delta` to the value of `key`, treating a missing value as 0.
+    ///
+    /// The map must have been created with `create_with_add_merge`.
+    pub fn merge_add(&self, key: K::Encode<'_>, delta: i64) -> Result<(), StorageError> {
+        let key = self.encode_key(key)?;
+        let write_options = self.options.wal_sync.write_options();
+        let _guard = self.write_lock.read();
+        self.retry(|| {
+            self.db
+                .merge_cf_opt(self.cf(), &key, delta.to_le_bytes(), &write_options)
+        })
+    }
+}
+
+fn add_merge(_key: &[u8], existing: Option<&[u8]>, operands: &MergeOperands) -> Option<Vec<u8>> {
+    let decode = |bytes: &[u8]| bytes.try_into().ok().map(i64::from_le_bytes);
+    let mut total = existing.map_or(Some(0), decode)?;
+    for operand in operands {
+        total = total.wrapping_add(decode(operand)?);
+    }
+    Some(total.to_le_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_rocksdb_map_integer_key_order() {
+        let temp_dir = TempDir::new("test_rocksdb_map_integer_key_order").unwrap();
+        let options = RocksdbMapOptions {
+            key_order: KeyOrder::UnsignedInteger,
+            ..Default::default()
+        };
+        let map = RocksdbMap::<u64, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+
+        // Insert 1..1000 out of order.
+        for i in 0..999u64 {
+            let key = i * 7919 % 999 + 1;
+            map.insert(&key, &(key * 2)).unwrap();
+        }
+
+        let entries = map.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let expected = (1..1000u64).map(|key| (key, key * 2)).collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+        assert_eq!(map.get(&500).unwrap(), Some(1000));
+
+        let keys = map
+            .range(&255, &260)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![255, 256, 257, 258, 259]);
+    }
+
+    #[test]
+    fn test_rocksdb_map_ingest_sorted() {
+        let temp_dir = TempDir::new("test_rocksdb_map_ingest_sorted").unwrap();
+        let options = RocksdbMapOptions {
+            key_order: KeyOrder::UnsignedInteger,
+            ..Default::default()
+        };
+        let map = RocksdbMap::<u64, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+        map.insert(&0, &1).unwrap();
+
+        let encode = |n: u64| n.to_le_bytes().to_vec();
+        map.ingest_sorted((0..1000u64).map(|key| (encode(key), encode(key * 2))))
+            .unwrap();
+
+        let entries = map.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        let expected = (0..1000u64).map(|key| (key, key * 2)).collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+        assert_eq!(map.get(&500).unwrap(), Some(1000));
+        assert_eq!(map.get(&1000).unwrap(), None);
+
+        // Unsorted entries are rejected.
+        assert!(map
+            .ingest_sorted(
+                [2000u64, 1500]
+                    .into_iter()
+                    .map(|key| (encode(key), encode(key)))
+            )
+            .is_err());
+        assert_eq!(map.get(&2000).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rocksdb_map_iter_reads_snapshot() {
+        let temp_dir = TempDir::new("test_rocksdb_map_iter_reads_snapshot").unwrap();
+        let map =
+            Arc::new(RocksdbMap::<u64, u64>::create(temp_dir.path(), Default::default()).unwrap());
+        let num_entries = 100_000u64;
+        for i in 0..num_entries {
+            map.insert(&i, &i).unwrap();
+        }
+
+        let iter = map.iter();
+        let writer = {
+            let map = map.clone();
+            thread::spawn(move || {
+                // Overwrite existing entries and add new ones while the iterator is consumed.
+                for i in 0..num_entries {
+                    map.insert(&i, &(i + 1)).unwrap();
+                    map.insert(&(num_entries + i), &0).unwrap();
+                }
+            })
+        };
+        let count = thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    let mut count = 0;
+                    for entry in iter {
+                        let (key, value) = entry.unwrap();
+                        assert_eq!(key, value);
+                        count += 1;
+                    }
+                    count
+                })
+                .join()
+                .unwrap()
+        });
+        writer.join().unwrap();
+
+        assert_eq!(count, num_entries);
+        assert_eq!(map.iter().count() as u64, num_entries * 2);
+    }
+
+    /// Set in the process `test_rocksdb_map_wal_sync` runs itself as, to the directory to write the map to.
+    const WAL_SYNC_CRASH_DIR: &str = "DOZER_TEST_WAL_SYNC_CRASH_DIR";
+    const WAL_SYNC_CRASH_MODE: &str = "DOZER_TEST_WAL_SYNC_CRASH_MODE";
+
+    fn wal_sync_map(path: &Path, wal_sync: WalSync) -> RocksdbMap<u64, u64> {
+        let options = RocksdbMapOptions {
+            wal_sync,
+            ..Default::default()
+        };
+        RocksdbMap::create_with_options(path, Default::default(), options).unwrap()
+    }
+
+    #[test]
+    fn test_rocksdb_map_wal_sync() {
+        let modes = [
+            ("every_sync", WalSync::EverySync),
+            ("async", WalSync::Async),
+            ("disabled", WalSync::Disabled),
+        ];
+        if let Ok(path) = std::env::var(WAL_SYNC_CRASH_DIR) {
+            // The crashing process: write, then abort without flushing or closing the map.
+            let mode = std::env::var(WAL_SYNC_CRASH_MODE).unwrap();
+            let (_, wal_sync) = modes.iter().find(|(name, _)| *name == mode).unwrap();
+            let map = wal_sync_map(Path::new(&path), *wal_sync);
+            for i in 0..100 {
+                map.insert(&i, &(i * 2)).unwrap();
+            }
+            map.remove(&0).unwrap();
+            std::process::abort();
+        }
+
+        for (mode, wal_sync) in modes {
+            let temp_dir = TempDir::new("test_rocksdb_map_wal_sync").unwrap();
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "rocksdb_map::tests::test_rocksdb_map_wal_sync"])
+                .env(WAL_SYNC_CRASH_DIR, temp_dir.path())
+                .env(WAL_SYNC_CRASH_MODE, mode)
+                .output()
+                .unwrap()
+                .status;
+            assert!(
+                !status.success(),
+                "The process writing with {mode} didn't crash"
+            );
+
+            // Logged writes are replayed after a process crash, the ones that skipped the log are lost.
+            // Only a machine crash would lose the writes of `Async` that `EverySync` keeps.
+            let map = wal_sync_map(temp_dir.path(), wal_sync);
+            if wal_sync == WalSync::Disabled {
+                assert_eq!(map.iter().count(), 0);
+            } else {
+                assert_eq!(map.get(&0).unwrap(), None);
+                for i in 1..100 {
+                    assert_eq!(map.get(&i).unwrap(), Some(i * 2));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rocksdb_map_comparator() {
+        let temp_dir = TempDir::new("test_rocksdb_map_comparator").unwrap();
+        // Keys are `(u64, u64)` tuples, stored big-endian. Order by the second element, then the first.
+        let encode = |first: u64, second: u64| [first.to_be_bytes(), second.to_be_bytes()].concat();
+        let options = RocksdbMapOptions {
+            comparator: Some(KeyComparator::new("second_then_first", |a, b| {
+                (&a[8..], &a[..8]).cmp(&(&b[8..], &b[..8]))
+            })),
+            ..Default::default()
+        };
+        let map = RocksdbMap::<Vec<u8>, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+
+        let keys = [(1, 3), (2, 1), (3, 2), (4, 1), (0, 3)];
+        for (first, second) in keys {
+            map.insert(&encode(first, second), &first).unwrap();
+        }
+
+        let firsts = map.iter().map(|entry| entry.unwrap().1).collect::<Vec<_>>();
+        assert_eq!(firsts, vec![2, 4, 3, 0, 1]);
+        assert_eq!(map.get(&encode(3, 2)).unwrap(), Some(3));
+
+        // `(4, 1)` sorts before `(0, 3)` with the comparator, but after it byte-wise.
+        let firsts = map
+            .range(&encode(4, 1), &encode(0, 3))
+            .unwrap()
+            .map(|entry| entry.unwrap().1)
+            .collect::<Vec<_>>();
+        assert_eq!(firsts, vec![4, 3]);
+    }
+
+    #[test]
+    fn test_rocksdb_map_bloom_filter() {
+        let temp_dir = TempDir::new("test_rocksdb_map_bloom_filter").unwrap();
+        let options = RocksdbMapOptions {
+            bloom_bits_per_key: Some(10),
+            ..Default::default()
+        };
+        let map = RocksdbMap::<u64, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+
+        for key in (0..1000).step_by(2) {
+            map.insert(&key, &key).unwrap();
+        }
+        // The filter is built with table files, so check keys both in the memtable and on disk.
+        for flushed in [false, true] {
+            if flushed {
+                map.flush().unwrap();
+            }
+            for key in 0..1000 {
+                assert_eq!(map.contains(&key).unwrap(), key % 2 == 0, "key {key}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rocksdb_map_on_compaction() {
+        let temp_dir = TempDir::new("test_rocksdb_map_on_compaction").unwrap();
+        let compactions = Arc::new(std::sync::Mutex::new(vec![]));
+        let options = RocksdbMapOptions {
+            on_compaction: Some(Arc::new({
+                let compactions = compactions.clone();
+                move |info| compactions.lock().unwrap().push(info)
+            })),
+            ..Default::default()
+        };
+        let map = RocksdbMap::<u64, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+
+        // Three overlapping table files, fewer than trigger an automatic compaction.
+        for file in 0..3u64 {
+            for key in (file..300).step_by(3) {
+                map.insert(&key, &key).unwrap();
+            }
+            map.flush().unwrap();
+        }
+        assert_eq!(map.num_files_at_level(0).unwrap(), 3);
+        assert!(compactions.lock().unwrap().is_empty());
+
+        map.compact_range();
+        let compactions = compactions.lock().unwrap();
+        assert!(!compactions.is_empty());
+        assert!(compactions.iter().all(|info| info.is_manual));
+        assert!(compactions.iter().all(|info| info.input_levels == vec![0]));
+        assert_eq!(
+            compactions.iter().map(|info| info.num_entries).sum::<u64>(),
+            300
+        );
+        assert_eq!(map.num_files_at_level(0).unwrap(), 0);
+        assert!((1..7).any(|level| map.num_files_at_level(level).unwrap() > 0));
+        assert_eq!(map.iter().count(), 300);
+    }
+
+    #[test]
+    fn test_rocksdb_map_with_value() {
+        let temp_dir = TempDir::new("test_rocksdb_map_with_value").unwrap();
+        let map = RocksdbMap::<u64, String>::create(temp_dir.path(), Default::default()).unwrap();
+        map.insert(&1, "hello").unwrap();
+
+        // The closure gets a `&str` pointing into the pinned value.
+        let len = map.with_value(&1, |value: Option<&str>| value.map(str::len));
+        assert_eq!(len.unwrap(), Some(5));
+        let len = map.with_value(&2, |value: Option<&str>| value.map(str::len));
+        assert_eq!(len.unwrap(), None);
+    }
+
+    #[test]
+    fn test_rocksdb_map_signed_integer_key_order() {
+        let temp_dir = TempDir::new("test_rocksdb_map_signed_integer_key_order").unwrap();
+        let options = RocksdbMapOptions {
+            key_order: KeyOrder::SignedInteger,
+            ..Default::default()
+        };
+        let map = RocksdbMap::<i64, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+
+        let keys = [3, -1, i64::MIN, 0, i64::MAX, -300, 256];
+        for key in keys {
+            map.insert(&key, &0).unwrap();
+        }
+
+        let mut expected = keys.to_vec();
+        expected.sort();
+        let entries = map.iter().map(|entry| entry.unwrap().0).collect::<Vec<_>>();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_rocksdb_map_merge_add() {
+        let temp_dir = TempDir::new("test_rocksdb_map_merge_add").unwrap();
+        let map = Arc::new(
+            RocksdbMap::<u64, i64>::create_with_add_merge(
+                temp_dir.path(),
+                Default::default(),
+                Default::default(),
+            )
+            .unwrap(),
+        );
+        assert_eq!(map.get(&0).unwrap(), None);
+
+        let num_threads = 8;
+        let num_deltas = 1000;
+        let handles = (0..num_threads)
+            .map(|thread_index| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for i in 0..num_deltas {
+                        let delta = if i % 3 == 0 { -i } else { i + thread_index };
+                        map.merge_add(&0, delta).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let expected = (0..num_threads)
+            .flat_map(|thread_index| {
+                (0..num_deltas).map(move |i| if i % 3 == 0 { -i } else { i + thread_index })
+            })
+            .sum::<i64>();
+        assert_eq!(map.get(&0).unwrap(), Some(expected));
+
+        map.insert(&1, &5).unwrap();
+        map.merge_add(&1, -7).unwrap();
+        assert_eq!(map.get(&1).unwrap(), Some(-2));
+    }
+
+    #[test]
+    fn test_rocksdb_map_merge_add_with_options() {
+        let temp_dir = TempDir::new("test_rocksdb_map_merge_add_with_options").unwrap();
+        let map = RocksdbMap::<u64, i64>::create_with_add_merge(
+            temp_dir.path(),
+            Default::default(),
+            RocksdbMapOptions {
+                key_order: KeyOrder::UnsignedInteger,
+                key_prefix: b"counts/".to_vec(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for key in [256, 1, 2] {
+            map.merge_add(&key, 1).unwrap();
+        }
+        map.merge_add(&1, 2).unwrap();
+
+        // Keys are stored big-endian with the prefix, so they're iterated in numeric order.
+        let entries = map.iter().map(|entry| entry.unwrap()).collect::<Vec<_>>();
+        assert_eq!(entries, vec![(1, 3), (2, 1), (256, 1)]);
+        assert_eq!(map.count().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_rocksdb_map_upsert() {
+        let temp_dir = TempDir::new("test_rocksdb_map_upsert").unwrap();
+        let map = Arc::new(
+            RocksdbMap::<u64, Vec<u8>>::create(temp_dir.path(), Default::default()).unwrap(),
+        );
+        let append =
+            |existing: &Vec<u8>, new: &Vec<u8>| [existing.as_slice(), new.as_slice()].concat();
+
+        assert_eq!(map.upsert(&0, vec![1, 2], append).unwrap(), vec![1, 2]);
+        map.upsert(&0, vec![3], append).unwrap();
+        map.upsert(&0, vec![], append).unwrap();
+        assert_eq!(
+            map.upsert(&0, vec![4, 5], append).unwrap(),
+            vec![1, 2, 3, 4, 5]
+        );
+        assert_eq!(map.get(&0).unwrap(), Some(vec![1, 2, 3, 4, 5]));
+
+        // Concurrent upserts of a key don't lose each other's values.
+        let num_threads = 8u8;
+        let num_upserts = 100;
+        let handles = (0..num_threads)
+            .map(|thread_index| {
+                let map = map.clone();
+                thread::spawn(move || {
+                    for _ in 0..num_upserts {
+                        map.upsert(&1, vec![thread_index], append).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let mut value = map.get(&1).unwrap().unwrap();
+        value.sort();
+        let expected = (0..num_threads)
+            .flat_map(|thread_index| std::iter::repeat(thread_index).take(num_upserts))
+            .collect::<Vec<_>>();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn test_replay_wal_recovers_unflushed_writes() {
+        let temp_dir = TempDir::new("test_replay_wal_recovers_unflushed_writes").unwrap();
+        let num_records = 100;
+        // The log is replayed with the map's comparator, which RocksDB refuses to open the database without.
+        let options = RocksdbMapOptions {
+            comparator: Some(KeyComparator::new("reversed", |a, b| b.cmp(a))),
+            ..Default::default()
+        };
+        {
+            let map = RocksdbMap::<u64, u64>::create_with_options(
+                temp_dir.path(),
+                Default::default(),
+                options.clone(),
+            )
+            .unwrap();
+            for i in 0..num_records {
+                map.insert(&i, &(i * 2)).unwrap();
+            }
+            map.remove(&0).unwrap();
+            // Dropped without flushing, so the writes only live in the log.
+        }
+
+        let report =
+            RocksdbMap::<u64, u64>::replay_wal(temp_dir.path(), Default::default(), &options)
+                .unwrap();
+        assert_eq!(report.num_batches, num_records as usize + 1);
+        assert_eq!(report.num_operations, num_records as usize + 1);
+        assert_eq!(report.last_sequence_number, Some(num_records + 1));
+
+        let map = RocksdbMap::<u64, u64>::create_with_options(
+            temp_dir.path(),
+            Default::default(),
+            options,
+        )
+        .unwrap();
+        assert_eq!(map.get(&0).unwrap(), None);
+        for i in 1..num_records {
+            assert_eq!(map.get(&i).unwrap(), Some(i * 2));
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum FlakyError {
+        Transient,
+        Fatal,
+    }
+
+    /// Fails with `error` for the first `num_failures` calls.
+    struct Flaky {
+        num_failures: u32,
+        error: fn() -> FlakyError,
+        num_calls: u32,
+    }
+
+    impl Flaky {
+        fn new(num_failures: u32, error: fn() -> FlakyError) -> Self {
+            Self {
+                num_failures,
+                error,
+                num_calls: 0,
+            }
+        }
+
+        fn call(&mut self) -> Result<u32, FlakyError> {
+            self.num_calls += 1;
+            if self.num_calls <= self.num_failures {
+                Err((self.error)())
+            } else {
+                Ok(self.num_calls)
+            }
+        }
+    }
+
+    fn is_transient(error: &FlakyError) -> bool {
+        *error == FlakyError::Transient
+    }
+
+    fn retry_options(max_retries: u32) -> RetryOptions {
+        RetryOptions {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_retry_transient_succeeds() {
+        let mut flaky = Flaky::new(2, || FlakyError::Transient);
+        let result = retry_transient(Some(&retry_options(3)), is_transient, || flaky.call());
+        assert_eq!(result, Ok(3));
+    }
+
+    #[test]
+    fn test_retry_transient_gives_up() {
+        let mut flaky = Flaky::new(2, || FlakyError::Transient);
+        let result = retry_transient(Some(&retry_options(1)), is_transient, || flaky.call());
+        assert_eq!(result, Err(FlakyError::Transient));
+        assert_eq!(flaky.num_calls, 2);
+
+        let mut flaky = Flaky::new(2, || FlakyError::Transient);
+        let result = retry_transient(None, is_transient, || flaky.call());
+        assert_eq!(result, Err(FlakyError::Transient));
+        assert_eq!(flaky.num_calls, 1);
+    }
+
+    #[test]
+    fn test_retry_transient_surfaces_fatal_error() {
+        let mut flaky = Flaky::new(2, || FlakyError::Fatal);
+        let result = retry_transient(Some(&retry_options(3)), is_transient, || flaky.call());
+        assert_eq!(result, Err(FlakyError::Fatal));
+        assert_eq!(flaky.num_calls, 1);
+    }
+
+    #[test]
+    fn test_rocksdb_map_checkpoint() {
+        let temp_dir = TempDir::new("test_rocksdb_map_checkpoint").unwrap();
+        let map = RocksdbMap::<u64, u64>::create(&temp_dir.path().join("map"), Default::default())
+            .unwrap();
+        map.insert(&1, &10).unwrap();
+
+        let checkpoint_path = temp_dir.path().join("checkpoint");
+        map.checkpoint(&checkpoint_path).unwrap();
+        map.insert(&2, &20).unwrap();
+
+        let checkpoint =
+            RocksdbMap::<u64, u64>::create(&checkpoint_path, Default::default()).unwrap();
+        assert_eq!(checkpoint.get(&1).unwrap(), Some(10));
+        assert_eq!(checkpoint.get(&2).unwrap(), None);
+        assert!(map.checkpoint(&checkpoint_path).is_err());
+    }
+
+    #[test]
+    fn test_rocksdb_map_key_prefix() {
+        let temp_dir = TempDir::new("test_rocksdb_map_key_prefix").unwrap();
+        let database =
+            RocksdbDatabase::open(temp_dir.path(), Default::default(), &["data"]).unwrap();
+        let map = |prefix: &[u8]| {
+            let options = RocksdbMapOptions {
+                key_order: KeyOrder::UnsignedInteger,
+                key_prefix: prefix.to_vec(),
+                ..Default::default()
+            };
+            database.map::<u64, String>("data", options).unwrap()
+        };
+        let (users, orders) = (map(b"users/"), map(b"orders/"));
+
+        for key in 0..5 {
+            users.insert(&key, &format!("user {key}")).unwrap();
+            orders.insert(&key, &format!("order {key}")).unwrap();
+        }
+        orders.remove(&3).unwrap();
+
+        assert_eq!(users.get(&3).unwrap(), Some("user 3".to_string()));
+        assert_eq!(orders.get(&3).unwrap(), None);
+        assert_eq!(orders.get(&1).unwrap(), Some("order 1".to_string()));
+        assert_eq!(users.count().unwrap(), 5);
+        assert_eq!(orders.count().unwrap(), 4);
+
+        let entries = users.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            entries,
+            (0..5)
+                .map(|key| (key, format!("user {key}")))
+                .collect::<Vec<_>>()
+        );
+        let keys = orders
+            .range(&1, &4)
+            .unwrap()
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(keys, vec![1, 2]);
+
+        // Unprefixed, the keys of both maps are visible.
+        assert_eq!(map(b"").iter().count(), 9);
+    }
+
+    #[test]
+    fn test_rocksdb_map_key_prefix_with_comparator() {
+        let temp_dir = TempDir::new("test_rocksdb_map_key_prefix_with_comparator").unwrap();
+        // Compares the last bytes first, so the keys of both prefixes interleave.
+        let comparator = KeyComparator::new("reversed_bytes", |a: &[u8], b: &[u8]| {
+            a.iter().rev().cmp(b.iter().rev())
+        });
+        let open = |prefix: &[u8]| {
+            let options = RocksdbMapOptions {
+                comparator: Some(comparator.clone()),
+                key_prefix: prefix.to_vec(),
+                ..Default::default()
+            };
+            RocksdbMap::<u64, u64>::create_with_options(
+                temp_dir.path(),
+                Default::default(),
+                options,
+            )
+            .unwrap()
+        };
+
+        {
+            let users = open(b"users/");
+            for key in 0..5 {
+                users.insert(&key, &key).unwrap();
+            }
+        }
+        {
+            let orders = open(b"orders/");
+            for key in 0..5 {
+                orders.insert(&key, &(key + 10)).unwrap();
+            }
+        }
+
+        let users = open(b"users/");
+        assert_eq!(users.count().unwrap(), 5);
+        let entries = users.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries, (0..5).map(|key| (key, key)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_rocksdb_transaction_across_column_families() {
+        let temp_dir = TempDir::new("test_rocksdb_transaction_across_column_families").unwrap();
+        let database =
+            RocksdbDatabase::open(temp_dir.path(), Default::default(), &["orders", "totals"])
+                .unwrap();
+        let orders = database
+            .map::<u64, String>("orders", Default::default())
+            .unwrap();
+        let totals = database
+            .map::<u64, u64>("totals", Default::default())
+            .unwrap();
+        orders.insert(&0, "stale").unwrap();
+
+        let write = |fail: bool| -> Result<(), StorageError> {
+            let mut transaction = database.transaction();
+            transaction.insert(&orders, &1, "order")?;
+            transaction.remove(&orders, &0)?;
+            transaction.insert(&totals, &1, &100)?;
+            assert_eq!(transaction.len(), 3);
+            // Nothing is visible before the commit.
+            assert_eq!(orders.get(&1)?, None);
+            assert_eq!(totals.get(&1)?, None);
+            if fail {
+                return Err(StorageError::InvalidArgument("fail".to_string()));
+            }
+            transaction.commit()
+        };
+
+        assert!(write(true).is_err());
+        assert_eq!(orders.get(&0).unwrap(), Some("stale".to_string()));
+        assert_eq!(orders.get(&1).unwrap(), None);
+        assert_eq!(totals.get(&1).unwrap(), None);
+
+        write(false).unwrap();
+        assert_eq!(orders.get(&0).unwrap(), None);
+        assert_eq!(orders.get(&1).unwrap(), Some("order".to_string()));
+        assert_eq!(totals.get(&1).unwrap(), Some(100));
+
+        // Maps of another database can't join the transaction.
+        let other_dir = TempDir::new("test_rocksdb_transaction_other").unwrap();
+        let other = RocksdbMap::<u64, u64>::create(other_dir.path(), Default::default()).unwrap();
+        assert!(database.transaction().insert(&other, &1, &1).is_err());
     }
 }
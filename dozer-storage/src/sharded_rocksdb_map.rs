@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use dozer_types::borrow::{Borrow, IntoOwned};
+use dozer_types::models::app_config::RocksdbConfig;
+
+use crate::errors::StorageError;
+use crate::{BorrowEncode, Encode, Encoded, LmdbVal, RocksdbMap, RocksdbMapOptions};
+
+/// Points every shard has on the hash ring. More points spread keys more evenly between shards.
+pub const VIRTUAL_NODES_PER_SHARD: usize = 128;
+
+/// A map split over several [`RocksdbMap`]s, each in its own database in a subdirectory.
+///
+/// Keys are assigned to shards by consistent hashing of their encoded bytes, so changing the number of shards
+/// with [`reshard`](Self::reshard) only moves about the share of keys that the added or removed shards own.
+/// The map must always be opened with the number of shards it was created or last resharded with.
+/// `iter` isn't in key order across shards.
+#[derive(Debug)]
+pub struct ShardedRocksdbMap<K, V> {
+    dir: PathBuf,
+    config: RocksdbConfig,
+    options: RocksdbMapOptions,
+    ring: HashRing,
+    shards: Vec<RocksdbMap<K, V>>,
+}
+
+impl<K: BorrowEncode, V: LmdbVal> ShardedRocksdbMap<K, V>
+where
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    pub fn create(
+        dir: &Path,
+        config: RocksdbConfig,
+        num_shards: usize,
+    ) -> Result<Self, StorageError> {
+        Self::create_with_options(dir, config, num_shards, Default::default())
+    }
+
+    /// Opens or creates `num_shards` shards in `dir`, every one with `options`.
+    pub fn create_with_options(
+        dir: &Path,
+        config: RocksdbConfig,
+        num_shards: usize,
+        options: RocksdbMapOptions,
+    ) -> Result<Self, StorageError> {
+        if num_shards == 0 {
+            return Err(StorageError::InvalidArgument(
+                "A sharded map needs at least one shard".to_string(),
+            ));
+        }
+        let mut map = Self {
+            dir: dir.to_path_buf(),
+            config,
+            options,
+            ring: HashRing::new(num_shards),
+            shards: Vec::with_capacity(num_shards),
+        };
+        for shard in 0..num_shards {
+            let shard = map.open_shard(shard)?;
+            map.shards.push(shard);
+        }
+        Ok(map)
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The shard that owns `key`.
+    pub fn shard_of(&self, key: K::Encode<'_>) -> Result<usize, StorageError> {
+        Ok(self.ring.shard_of(key.encode()?.as_ref()))
+    }
+
+    pub fn count(&self) -> Result<usize, StorageError> {
+        self.shards.iter().map(|shard| shard.count()).sum()
+    }
+
+    pub fn get(&self, key: K::Encode<'_>) -> Result<Option<V>, StorageError> {
+        let key = key.encode()?;
+        self.shard(&key).get_encoded(key)
+    }
+
+    pub fn contains(&self, key: K::Encode<'_>) -> Result<bool, StorageError> {
+        let key = key.encode()?;
+        self.shard(&key).contains_encoded(key)
+    }
+
+    pub fn insert(&self, key: K::Encode<'_>, value: V::Encode<'_>) -> Result<(), StorageError> {
+        let key = key.encode()?;
+        self.shard(&key).insert_encoded(key, value)
+    }
+
+    pub fn remove(&self, key: K::Encode<'_>) -> Result<(), StorageError> {
+        let key = key.encode()?;
+        self.shard(&key).remove_encoded(key)
+    }
+
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.shards.iter().try_for_each(|shard| shard.flush())
+    }
+
+    fn shard(&self, key: &Encoded) -> &RocksdbMap<K, V> {
+        &self.shards[self.ring.shard_of(key.as_ref())]
+    }
+
+    fn shard_path(&self, shard: usize) -> PathBuf {
+        self.dir.join(format!("shard_{shard}"))
+    }
+
+    fn open_shard(&self, shard: usize) -> Result<RocksdbMap<K, V>, StorageError> {
+        RocksdbMap::create_with_options(&self.shard_path(shard), self.config, self.options.clone())
+    }
+}
+
+impl<K: LmdbVal, V: LmdbVal> ShardedRocksdbMap<K, V>
+where
+    for<'a> K::Borrowed<'a>: IntoOwned<K>,
+    for<'a> V::Borrowed<'a>: IntoOwned<V>,
+{
+    /// Iterates all entries, shard by shard, each in key order.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V), StorageError>> + Send + '_
+    where
+        K: Sync,
+        V: Sync,
+    {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    /// Changes the number of shards to `num_shards`, moving the keys whose shard changes. Returns how many moved.
+    ///
+    /// A key is written to its new shard before it's removed from the old one, so it's never lost, but a reshard
+    /// interrupted by a crash can leave copies behind. Removed shards' directories are deleted once they're empty.
+    pub fn reshard(&mut self, num_shards: usize) -> Result<usize, StorageError>
+    where
+        K: Sync,
+        V: Sync,
+    {
+        if num_shards == 0 {
+            return Err(StorageError::InvalidArgument(
+                "A sharded map needs at least one shard".to_string(),
+            ));
+        }
+        for shard in self.shards.len()..num_shards {
+            let shard = self.open_shard(shard)?;
+            self.shards.push(shard);
+        }
+
+        let ring = HashRing::new(num_shards);
+        let mut num_moved = 0;
+        for (shard, map) in self.shards.iter().enumerate() {
+            for entry in map.iter() {
+                let (key, value) = entry?;
+                let key = key.borrow().encode()?;
+                let target = ring.shard_of(key.as_ref());
+                if target != shard {
+                    self.shards[target]
+                        .insert_encoded(Encoded::Borrowed(key.as_ref()), value.borrow())?;
+                    map.remove_encoded(key)?;
+                    num_moved += 1;
+                }
+            }
+        }
+
+        while self.shards.len() > num_shards {
+            let shard = self.shards.len() - 1;
+            // Close the database before deleting it.
+            drop(self.shards.pop());
+            std::fs::remove_dir_all(self.shard_path(shard))?;
+        }
+        self.ring = ring;
+        Ok(num_moved)
+    }
+}
+
+/// Maps hashes of keys to shards, placing every shard at `VIRTUAL_NODES_PER_SHARD` points on a ring.
+///
+/// A key belongs to the shard of the first point at or after its hash. Points don't depend on the number of shards,
+/// so adding a shard only takes over keys from the points just before its own.
+#[derive(Debug, Clone)]
+struct HashRing {
+    points: BTreeMap<u64, usize>,
+}
+
+impl HashRing {
+    fn new(num_shards: usize) -> Self {
+        let mut points = BTreeMap::new();
+        for shard in 0..num_shards {
+            for virtual_node in 0..VIRTUAL_NODES_PER_SHARD {
+                // On the unlikely collision the lower shard keeps the point, whatever the number of shards.
+                points
+                    .entry(hash(format!("{shard}/{virtual_node}").as_bytes()))
+                    .or_insert(shard);
+            }
+        }
+        Self { points }
+    }
+
+    fn shard_of(&self, key: &[u8]) -> usize {
+        let hash = hash(key);
+        let (_, shard) = self
+            .points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .expect("The ring has at least one shard");
+        *shard
+    }
+}
+
+/// FNV-1a, with the finalizer of splitmix64 so similar inputs land far apart on the ring.
+///
+/// Stable across runs and platforms, as shards are chosen by it.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_sharded_rocksdb_map_reshard() {
+        let temp_dir = TempDir::new("test_sharded_rocksdb_map_reshard").unwrap();
+        let mut map =
+            ShardedRocksdbMap::<u64, String>::create(temp_dir.path(), Default::default(), 3)
+                .unwrap();
+        let num_keys = 10_000u64;
+        for key in 0..num_keys {
+            map.insert(&key, &format!("value {key}")).unwrap();
+        }
+        let shards_before = (0..num_keys)
+            .map(|key| map.shard_of(&key).unwrap())
+            .collect::<Vec<_>>();
+        assert!((0..3).all(|shard| shards_before.contains(&shard)));
+
+        let num_moved = map.reshard(4).unwrap();
+
+        assert_eq!(map.num_shards(), 4);
+        assert_eq!(map.count().unwrap(), num_keys as usize);
+        for key in 0..num_keys {
+            assert_eq!(map.get(&key).unwrap(), Some(format!("value {key}")));
+        }
+        let num_changed = (0..num_keys)
+            .filter(|key| map.shard_of(key).unwrap() != shards_before[*key as usize])
+            .count();
+        assert_eq!(num_moved, num_changed);
+        // Keys only move to the new shard, which owns about a quarter of them.
+        assert!((0..num_keys)
+            .filter(|key| map.shard_of(key).unwrap() != shards_before[*key as usize])
+            .all(|key| map.shard_of(&key).unwrap() == 3));
+        let moved_fraction = num_moved as f64 / num_keys as f64;
+        assert!(
+            (0.15..0.35).contains(&moved_fraction),
+            "{moved_fraction} of keys moved"
+        );
+
+        // Back to 3 shards, the keys return to where they were.
+        assert_eq!(map.reshard(3).unwrap(), num_moved);
+        assert!(!temp_dir.path().join("shard_3").exists());
+        for key in 0..num_keys {
+            assert_eq!(map.shard_of(&key).unwrap(), shards_before[key as usize]);
+            assert_eq!(map.get(&key).unwrap(), Some(format!("value {key}")));
+        }
+    }
+}
@@ -13,7 +13,7 @@ use crate::{
 
 use super::equal_default;
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 /// All possible kinds of `IngestionMessage`.
 pub enum IngestionMessage {
     /// A CDC event.
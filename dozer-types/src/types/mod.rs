@@ -80,6 +80,10 @@ impl Schema {
         Self::default()
     }
 
+    pub fn builder() -> SchemaBuilder {
+        SchemaBuilder::default()
+    }
+
     pub fn field(&mut self, f: FieldDefinition, pk: bool) -> &mut Self {
         self.fields.push(f);
         if pk {
@@ -122,6 +126,51 @@ impl Schema {
     }
 }
 
+/// Builds a [`Schema`] of dynamic fields, see [`Schema::builder`].
+#[derive(Clone, Debug, Default)]
+pub struct SchemaBuilder {
+    fields: Vec<FieldDefinition>,
+    primary_index: Vec<usize>,
+}
+
+impl SchemaBuilder {
+    pub fn field(mut self, name: impl Into<String>, typ: FieldType, nullable: bool) -> Self {
+        self.fields.push(FieldDefinition::new(
+            name.into(),
+            typ,
+            nullable,
+            SourceDefinition::Dynamic,
+        ));
+        self
+    }
+
+    /// Sets the indexes of the fields forming the primary key, replacing any set before.
+    pub fn primary_key(mut self, indices: impl IntoIterator<Item = usize>) -> Self {
+        self.primary_index = indices.into_iter().collect();
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If a primary key index is out of range of the fields.
+    pub fn build(self) -> Schema {
+        if let Some(index) = self
+            .primary_index
+            .iter()
+            .find(|index| **index >= self.fields.len())
+        {
+            panic!(
+                "Primary key index {index} is out of range of {} fields",
+                self.fields.len()
+            );
+        }
+        Schema {
+            fields: self.fields,
+            primary_index: self.primary_index,
+        }
+    }
+}
+
 impl Display for Schema {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let table = self.print();
@@ -257,6 +306,36 @@ pub enum Operation {
     Update { old: Record, new: Record },
 }
 
+impl Operation {
+    pub fn insert(new: Record) -> Self {
+        Operation::Insert { new }
+    }
+
+    pub fn update(old: Record, new: Record) -> Self {
+        Operation::Update { old, new }
+    }
+
+    pub fn delete(old: Record) -> Self {
+        Operation::Delete { old }
+    }
+
+    /// The record after the operation, `None` for deletes.
+    pub fn new_record(&self) -> Option<&Record> {
+        match self {
+            Operation::Insert { new } | Operation::Update { new, .. } => Some(new),
+            Operation::Delete { .. } => None,
+        }
+    }
+
+    /// The record before the operation, `None` for inserts.
+    pub fn old_record(&self) -> Option<&Record> {
+        match self {
+            Operation::Delete { old } | Operation::Update { old, .. } => Some(old),
+            Operation::Insert { .. } => None,
+        }
+    }
+}
+
 // Helpful in interacting with external systems during ingestion and querying
 // For example, nanoseconds can overflow.
 #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -1,4 +1,7 @@
-use crate::types::{field_test_cases, DozerDuration, DozerPoint, Field, TimeUnit};
+use crate::types::{
+    field_test_cases, DozerDuration, DozerPoint, Field, FieldDefinition, FieldType, Operation,
+    Record, Schema, SourceDefinition, TimeUnit,
+};
 use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use ordered_float::OrderedFloat;
 use rust_decimal::Decimal;
@@ -567,3 +570,64 @@ fn test_to_conversion() {
     assert!(field.to_duration().is_some());
     assert!(field.to_null().is_some());
 }
+
+#[test]
+fn test_operation_helpers() {
+    let old = Record::new(vec![Field::UInt(1)]);
+    let new = Record::new(vec![Field::UInt(2)]);
+
+    let insert = Operation::insert(new.clone());
+    assert_eq!(insert, Operation::Insert { new: new.clone() });
+    assert_eq!(insert.new_record(), Some(&new));
+    assert_eq!(insert.old_record(), None);
+
+    let update = Operation::update(old.clone(), new.clone());
+    assert_eq!(update.new_record(), Some(&new));
+    assert_eq!(update.old_record(), Some(&old));
+
+    let delete = Operation::delete(old.clone());
+    assert_eq!(delete, Operation::Delete { old: old.clone() });
+    assert_eq!(delete.new_record(), None);
+    assert_eq!(delete.old_record(), Some(&old));
+}
+
+#[test]
+fn test_schema_builder() {
+    let built = Schema::builder()
+        .field("id", FieldType::UInt, false)
+        .field("name", FieldType::String, true)
+        .primary_key([0])
+        .build();
+
+    let mut expected = Schema::new();
+    expected
+        .field(
+            FieldDefinition::new(
+                "id".to_string(),
+                FieldType::UInt,
+                false,
+                SourceDefinition::Dynamic,
+            ),
+            true,
+        )
+        .field(
+            FieldDefinition::new(
+                "name".to_string(),
+                FieldType::String,
+                true,
+                SourceDefinition::Dynamic,
+            ),
+            false,
+        );
+    assert_eq!(built, expected);
+}
+
+#[test]
+#[should_panic(expected = "Primary key index 2 is out of range of 2 fields")]
+fn test_schema_builder_rejects_out_of_range_primary_key() {
+    Schema::builder()
+        .field("id", FieldType::UInt, false)
+        .field("name", FieldType::String, true)
+        .primary_key([2])
+        .build();
+}